@@ -1,6 +1,9 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // this will build the proto and put the code inside target/debug/rpts01/out/..
-    tonic_build::compile_protos("proto/rpts01.proto")?;
+    // the User message is owned by the rpts-domain crate, so we reuse its generated type.
+    tonic_build::configure()
+        .extern_path(".rpts.domain", "::rpts_domain::proto")
+        .compile(&["proto/rpts01.proto"], &["proto", "../rpts-domain/proto"])?;
 
     // this would allow us to configure how to build the proto files.
     // for instance, by only generating the server and compiling the proto to a specific folder
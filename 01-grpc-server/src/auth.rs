@@ -0,0 +1,369 @@
+use crate::proto::PermissionDeniedDetails;
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use prost::Message;
+use serde::Deserialize;
+use std::{collections::HashMap, sync::Arc};
+use tonic::{
+    body::BoxBody,
+    codegen::{http, BoxFuture, Context, Poll, Service},
+    transport::{Body, NamedService},
+    Code, Request, Status,
+};
+
+/// Requirements a caller must meet to invoke a method.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    /// Anyone can call the method, even without a token.
+    Public,
+    /// A valid token holding all these scopes is required.
+    Scopes(&'static [&'static str]),
+}
+
+/// Authorization policies of every method in the server.
+/// Methods not listed here are denied.
+pub const POLICIES: &[(&str, Policy)] = &[
+    ("/rpts01.Rpts/SayHi", Policy::Public),
+    ("/rpts01.Rpts/GetUser", Policy::Scopes(&["users:read"])),
+];
+
+/// Header where the authorized calls carry the tenant of their caller to the service.
+/// Whatever the client sends in it is replaced.
+pub const TENANT_HEADER: &str = "x-rpts-tenant";
+
+/// Claims we expect in the bearer tokens.
+/// The expiration (`exp`) is checked while validating the token.
+#[derive(Debug, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    /// Space separated list of scopes, as in OAuth 2.0.
+    #[serde(default)]
+    pub scope: String,
+    /// Client company of the caller, whose users are the only ones it sees.
+    pub tenant: Option<String>,
+}
+
+impl Claims {
+    fn has_scope(&self, scope: &str) -> bool {
+        self.scope.split_whitespace().any(|s| s == scope)
+    }
+}
+
+/// Validates the token of a request and evaluates the policy of the called method.
+pub struct Authorizer {
+    key: DecodingKey<'static>,
+    validation: Validation,
+    policies: HashMap<&'static str, Policy>,
+    default_tenant: Option<String>,
+}
+
+impl Authorizer {
+    /// Builds an authorizer for HS256 tokens signed with the given secret.
+    pub fn new(secret: &str, policies: &[(&'static str, Policy)]) -> Self {
+        Self {
+            key: DecodingKey::from_secret(secret.as_bytes()).into_static(),
+            validation: Validation::new(Algorithm::HS256),
+            policies: policies.iter().cloned().collect(),
+            default_tenant: None,
+        }
+    }
+
+    /// Puts the tokens without a tenant in this one, instead of rejecting them.
+    pub fn with_default_tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.default_tenant = Some(tenant.into());
+        self
+    }
+
+    /// Builds an authorizer with the default [POLICIES] and the secret
+    /// found in the [JWT_SECRET] env var.
+    /// The tokens without a tenant belong to the one in the [DEFAULT_TENANT] env var, if any.
+    pub fn from_env() -> Result<Self, std::env::VarError> {
+        let secret = std::env::var("JWT_SECRET")?;
+        let authorizer = Self::new(&secret, POLICIES);
+        Ok(match std::env::var("DEFAULT_TENANT") {
+            Ok(tenant) => authorizer.with_default_tenant(tenant),
+            Err(_) => authorizer,
+        })
+    }
+
+    /// Authorizes the call to a method. Returns the validated claims, if any.
+    pub fn authorize(
+        &self,
+        method: &str,
+        headers: &http::HeaderMap,
+    ) -> Result<Option<Claims>, Status> {
+        let scopes = match self.policies.get(method) {
+            Some(Policy::Public) => return Ok(None),
+            Some(Policy::Scopes(scopes)) => scopes,
+            None => return Err(permission_denied(method, &[])),
+        };
+        let mut claims = self.validate(headers)?;
+        claims.tenant = claims.tenant.or_else(|| self.default_tenant.clone());
+        if claims.tenant.is_none() {
+            return Err(Status::unauthenticated("The token has no tenant"));
+        }
+        let missing_scopes: Vec<&str> = scopes
+            .iter()
+            .filter(|scope| !claims.has_scope(scope))
+            .cloned()
+            .collect();
+        if missing_scopes.is_empty() {
+            Ok(Some(claims))
+        } else {
+            Err(permission_denied(method, &missing_scopes))
+        }
+    }
+
+    fn validate(&self, headers: &http::HeaderMap) -> Result<Claims, Status> {
+        let token = headers
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("Missing bearer token"))?;
+        decode::<Claims>(token, &self.key, &self.validation)
+            .map(|data| data.claims)
+            .map_err(|_| Status::unauthenticated("The token is invalid"))
+    }
+}
+
+fn permission_denied(method: &str, missing_scopes: &[&str]) -> Status {
+    let message = if missing_scopes.is_empty() {
+        format!("Method {} is not allowed", method)
+    } else {
+        format!("Missing scopes: {}", missing_scopes.join(" "))
+    };
+    let details = PermissionDeniedDetails {
+        method: method.to_string(),
+        missing_scopes: missing_scopes.iter().map(|s| s.to_string()).collect(),
+    };
+    let mut buf = Vec::with_capacity(details.encoded_len());
+    details
+        .encode(&mut buf)
+        .expect("Vec<u8> has enough capacity");
+    Status::with_details(Code::PermissionDenied, message, buf.into())
+}
+
+/// Wraps a generated gRPC server, authorizing every call before it reaches it.
+/// Interceptors can't see which method is being called, so this works at the http level.
+#[derive(Clone)]
+pub struct Authorized<S> {
+    inner: S,
+    authorizer: Arc<Authorizer>,
+}
+
+impl<S> Authorized<S> {
+    pub fn new(inner: S, authorizer: Arc<Authorizer>) -> Self {
+        Self { inner, authorizer }
+    }
+}
+
+impl<S: NamedService> NamedService for Authorized<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S> Service<http::Request<Body>> for Authorized<S>
+where
+    S: Service<http::Request<Body>, Response = http::Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<Body>) -> Self::Future {
+        let authorized = self
+            .authorizer
+            .authorize(req.uri().path(), req.headers())
+            .and_then(|claims| {
+                req.headers_mut().remove(TENANT_HEADER);
+                if let Some(tenant) = claims.and_then(|claims| claims.tenant) {
+                    let tenant = http::HeaderValue::from_str(&tenant)
+                        .map_err(|_| Status::unauthenticated("The tenant is invalid"))?;
+                    req.headers_mut().insert(TENANT_HEADER, tenant);
+                }
+                Ok(())
+            });
+        match authorized {
+            Ok(()) => Box::pin(self.inner.call(req)),
+            Err(status) => Box::pin(async move { Ok(status.to_http()) }),
+        }
+    }
+}
+
+/// The tenant of the caller of an authorized call.
+pub fn tenant<T>(request: &Request<T>) -> Result<String, Status> {
+    request
+        .metadata()
+        .get(TENANT_HEADER)
+        .and_then(|tenant| tenant.to_str().ok())
+        .map(|tenant| tenant.to_string())
+        .ok_or_else(|| Status::unauthenticated("The caller has no tenant"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde::Serialize;
+
+    const SECRET: &str = "my_secret";
+
+    #[derive(Serialize)]
+    struct TestClaims<'a> {
+        sub: &'a str,
+        exp: usize,
+        scope: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tenant: Option<&'a str>,
+    }
+
+    fn headers(scope: &str, secret: &str) -> http::HeaderMap {
+        headers_of("acme", scope, secret)
+    }
+
+    fn headers_of(
+        tenant: impl Into<Option<&'static str>>,
+        scope: &str,
+        secret: &str,
+    ) -> http::HeaderMap {
+        let claims = TestClaims {
+            sub: "user",
+            exp: 10_000_000_000,
+            scope,
+            tenant: tenant.into(),
+        };
+        let key = EncodingKey::from_secret(secret.as_bytes());
+        let token = encode(&Header::default(), &claims, &key).unwrap();
+        let mut headers = http::HeaderMap::new();
+        headers.insert(
+            "authorization",
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        headers
+    }
+
+    fn authorizer() -> Authorizer {
+        Authorizer::new(SECRET, POLICIES)
+    }
+
+    #[test]
+    fn public_methods_need_no_token() {
+        let result = authorizer().authorize("/rpts01.Rpts/SayHi", &http::HeaderMap::new());
+        assert!(result.unwrap().is_none());
+    }
+
+    #[test]
+    fn scoped_methods_work_with_the_scope() {
+        let headers = headers("users:write users:read", SECRET);
+        let claims = authorizer()
+            .authorize("/rpts01.Rpts/GetUser", &headers)
+            .unwrap()
+            .unwrap();
+        assert_eq!(claims.sub, "user");
+        assert_eq!(claims.tenant.unwrap(), "acme");
+    }
+
+    #[test]
+    fn scoped_methods_reject_tokens_without_tenant() {
+        let headers = headers_of(None, "users:read", SECRET);
+        let status = authorizer()
+            .authorize("/rpts01.Rpts/GetUser", &headers)
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+
+    #[test]
+    fn tokens_without_tenant_take_the_default_one() {
+        let headers = headers_of(None, "users:read", SECRET);
+        let claims = authorizer()
+            .with_default_tenant("default")
+            .authorize("/rpts01.Rpts/GetUser", &headers)
+            .unwrap()
+            .unwrap();
+        assert_eq!(claims.tenant.unwrap(), "default");
+    }
+
+    #[test]
+    fn scoped_methods_require_a_token() {
+        let status = authorizer()
+            .authorize("/rpts01.Rpts/GetUser", &http::HeaderMap::new())
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+
+    #[test]
+    fn scoped_methods_reject_invalid_tokens() {
+        let headers = headers("users:read", "another_secret");
+        let status = authorizer()
+            .authorize("/rpts01.Rpts/GetUser", &headers)
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+
+    #[test]
+    fn scoped_methods_report_missing_scopes() {
+        let headers = headers("users:write", SECRET);
+        let status = authorizer()
+            .authorize("/rpts01.Rpts/GetUser", &headers)
+            .err()
+            .unwrap();
+        let details = PermissionDeniedDetails::decode(status.details()).unwrap();
+
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert_eq!(details.method, "/rpts01.Rpts/GetUser");
+        assert_eq!(details.missing_scopes, vec!["users:read".to_string()]);
+    }
+
+    #[test]
+    fn unknown_methods_are_denied() {
+        let headers = headers("users:read users:write", SECRET);
+        let status = authorizer()
+            .authorize("/rpts01.Rpts/DropUsers", &headers)
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), Code::PermissionDenied);
+    }
+
+    /// Answers with the tenant header the service got.
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Service<http::Request<Body>> for Echo {
+        type Response = http::Response<BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: http::Request<Body>) -> Self::Future {
+            let mut res = http::Response::new(BoxBody::empty());
+            if let Some(tenant) = req.headers().get(TENANT_HEADER) {
+                res.headers_mut().insert(TENANT_HEADER, tenant.clone());
+            }
+            Box::pin(async move { Ok(res) })
+        }
+    }
+
+    #[tokio::test]
+    async fn the_service_gets_the_tenant_of_the_token() {
+        let mut service = Authorized::new(Echo, Arc::new(authorizer()));
+        let mut req = http::Request::new(Body::empty());
+        *req.uri_mut() = "/rpts01.Rpts/GetUser".parse().unwrap();
+        *req.headers_mut() = headers("users:read", SECRET);
+        // a client can't choose its tenant
+        req.headers_mut()
+            .insert(TENANT_HEADER, "another".parse().unwrap());
+
+        let res = service.call(req).await.unwrap();
+
+        assert_eq!(res.headers()[TENANT_HEADER], "acme");
+    }
+}
@@ -1,16 +1,15 @@
-use crate::proto::User;
 use anyhow::Result;
-use prost_types::{Timestamp};
-
+use rpts_domain::{CustomData, User};
 use sqlx::{
     types::chrono::{DateTime, NaiveDate, Utc},
-    PgPool,
+    PgPool, Postgres, Transaction,
 };
-use std::collections::HashMap;
+use std::convert::TryFrom;
 
 #[tonic::async_trait]
 pub trait Repository {
-    async fn get_user(&self, name: &str) -> Result<User>;
+    /// Gets a user by name, among the users of the tenant.
+    async fn get_user(&self, name: &str, tenant: &str) -> Result<User>;
 }
 
 pub struct PostgresRepository {
@@ -19,22 +18,37 @@ pub struct PostgresRepository {
 
 impl PostgresRepository {
     pub async fn build(conn_str: &str) -> Result<Self> {
-        let pool = PgPool::new(conn_str).await?;
+        let pool = PgPool::connect(conn_str).await?;
         Ok(Self { pool })
     }
+
+    /// Starts a transaction seeing the rows of the tenant, as the row-level security
+    /// of the users only lets through the tenant in `app.tenant_id`.
+    async fn begin(&self, tenant: &str) -> Result<Transaction<'_, Postgres>> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!("SELECT set_config('app.tenant_id', $1, true)", tenant)
+            .execute(&mut tx)
+            .await?;
+        Ok(tx)
+    }
 }
 
 #[tonic::async_trait]
 #[allow(clippy::empty_line_after_outer_attr)]
 impl Repository for PostgresRepository {
-    async fn get_user(&self, name: &str) -> Result<User> {
-        sqlx::query_as!(
+    async fn get_user(&self, name: &str, tenant: &str) -> Result<User> {
+        let mut tx = self.begin(tenant).await?;
+        // names are only unique within a tenant
+        let raw_user = sqlx::query_as!(
           RawUser, 
-          "SELECT id, name, birth_date, created_at, updated_at, custom_data FROM users where name = $1", 
+          "SELECT id, name, birth_date, created_at, updated_at, custom_data FROM users where tenant_id = $1 AND name = $2 AND deleted_at IS NULL", 
+          tenant,
           name
         )
-        .fetch_one(&self.pool)
-        .await.map(RawUser::into).map_err(sqlx::Error::into)
+        .fetch_one(&mut tx)
+        .await?;
+        tx.commit().await?;
+        User::try_from(raw_user)
     }
 }
 
@@ -42,43 +56,30 @@ impl Repository for PostgresRepository {
 struct RawUser {
     pub id: uuid::Uuid,
     pub name: String,
-    pub birth_date: Option<NaiveDate>,
+    pub birth_date: NaiveDate,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
-    pub custom_data: serde_json::Value,
-}
-
-impl From<RawUser> for User {
-    fn from(raw_user: RawUser) -> Self {
-        Self {
-            id: raw_user.id.to_string(),
-            name: raw_user.name,
-            birth_date: naive_to_timestamp(raw_user.birth_date),
-            created_at: to_timestamp(raw_user.created_at),
-            updated_at: to_timestamp(raw_user.updated_at),
-            custom_data: to_map(&raw_user.custom_data),
-        }
-    }
+    pub custom_data: Option<serde_json::Value>,
 }
 
-fn to_map(json: &serde_json::Value) -> HashMap<String, i64> {
-    let mut map = HashMap::new();
-    for (key, val) in json.as_object().unwrap() {
-            map.insert(key.to_owned(), val.as_i64().unwrap());
-    }
-    map
-}
+impl TryFrom<RawUser> for User {
+    type Error = anyhow::Error;
 
-fn to_timestamp(datetime: Option<DateTime<Utc>>) -> Option<Timestamp> {
-    datetime.map(|dt| Timestamp {
-        seconds: dt.timestamp(),
-        nanos: 0,
-    })
-}
+    fn try_from(raw_user: RawUser) -> Result<Self> {
+        let custom_data = raw_user
+            .custom_data
+            .map(serde_json::from_value::<CustomData>)
+            .transpose()?;
 
-fn naive_to_timestamp(date: Option<NaiveDate>) -> Option<Timestamp> {
-    date.map(|d| Timestamp {
-        seconds: d.and_hms(0, 0, 0).timestamp(),
-        nanos: 0,
-    })
+        Ok(Self {
+            id: Some(raw_user.id),
+            name: raw_user.name,
+            birth_date: raw_user.birth_date,
+            created_at: raw_user.created_at,
+            updated_at: raw_user.updated_at,
+            custom_data,
+            // deleted users are never read
+            deleted_at: None,
+        })
+    }
 }
@@ -1,11 +1,13 @@
+mod auth;
 mod data;
 mod proto;
 mod service;
 
+use auth::{Authorized, Authorizer};
 use proto::rpts_server::RptsServer;
 use service::Rpts01Service;
-use std::env;
-use tonic::{metadata::MetadataValue, transport::Server, Request, Status};
+use std::{env, sync::Arc};
+use tonic::transport::Server;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -15,23 +17,23 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let addr = address.parse()?;
     let conn_str = &env::var("DATABASE_URL")?;
     let repository = data::PostgresRepository::build(conn_str).await?;
+
+    // `rpts01 migrate [up|status|dry-run]` manages the schema instead of serving
+    if let Some(command) = rpts_migrations::Command::from_args(env::args())? {
+        rpts_migrations::run(&repository.pool, command).await?;
+        return Ok(());
+    }
+    // refuse to start if the database schema doesn't match the one we expect
+    rpts_migrations::ensure_up_to_date(&repository.pool).await?;
+
     let rpts01_service = Rpts01Service { repository };
+    // every method is authorized according to the policies declared in the auth module
+    let authorizer = Arc::new(Authorizer::from_env()?);
 
     Server::builder()
-        .add_service(RptsServer::with_interceptor(rpts01_service, interceptor))
+        .add_service(Authorized::new(RptsServer::new(rpts01_service), authorizer))
         .serve(addr)
         .await?;
 
     Ok(())
 }
-
-fn interceptor(req: Request<()>) -> Result<Request<()>, Status> {
-    let token = MetadataValue::from_str("Bearer myjwttoken").unwrap();
-    match req.metadata().get("authorization") {
-        Some(t) if t == token => {
-            println!("The token is valid!");
-            Ok(req)
-        }
-        _ => Err(Status::unauthenticated("The token is invalid")),
-    }
-}
@@ -1,7 +1,9 @@
 use crate::{
+    auth,
     data::Repository,
-    proto::{rpts_server::Rpts, HiRequest, HiResponse, User, UserRequest},
+    proto::{rpts_server::Rpts, HiRequest, HiResponse, UserRequest},
 };
+use rpts_domain::proto::User;
 use tonic::{Request, Response, Status};
 
 #[allow(clippy::module_name_repetitions)]
@@ -19,14 +21,95 @@ impl<T: Repository + Send + Sync + 'static> Rpts for Rpts01Service<T> {
     }
 
     async fn get_user(&self, request: Request<UserRequest>) -> Result<Response<User>, Status> {
+        let tenant = auth::tenant(&request)?;
         let name = request.into_inner().name;
 
         self.repository
-            .get_user(&name)
+            .get_user(&name, &tenant)
             .await
-            .map(Response::new)
+            .map(|user| Response::new(user.into()))
             .map_err(|e| {
                 Status::not_found(format!("No user with name {} exists. Error: {:?}", name, e))
             })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{auth::TENANT_HEADER, data::PostgresRepository};
+    use tonic::Code;
+
+    /// The service on the database of `DATABASE_URL`, migrated with `cargo make db-migrate`.
+    async fn service() -> Rpts01Service<PostgresRepository> {
+        dotenv::dotenv().ok();
+        let conn_str = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let repository = PostgresRepository::build(&conn_str).await.unwrap();
+        rpts_migrations::ensure_up_to_date(&repository.pool)
+            .await
+            .unwrap();
+        Rpts01Service { repository }
+    }
+
+    fn user_request(name: &str, tenant: &str) -> Request<UserRequest> {
+        let mut request = Request::new(UserRequest {
+            name: name.to_string(),
+        });
+        request
+            .metadata_mut()
+            .insert(TENANT_HEADER, tenant.parse().unwrap());
+        request
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the database, run with `cargo test -- --include-ignored`"]
+    async fn get_user_reads_the_seeded_user() {
+        let user = service()
+            .await
+            .get_user(user_request("Roberto", "default"))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(user.name, "Roberto");
+        assert!(!user.id.is_empty());
+        // the seed has no random number, only points
+        assert_eq!(user.custom_data.get("random"), Some(&0));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the database, run with `cargo test -- --include-ignored`"]
+    async fn get_user_only_reads_the_users_of_the_tenant() {
+        let status = service()
+            .await
+            .get_user(user_request("Roberto", "acme"))
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(status.code(), Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn get_user_requires_a_tenant() {
+        struct Unreachable;
+
+        #[tonic::async_trait]
+        impl Repository for Unreachable {
+            async fn get_user(&self, _: &str, _: &str) -> anyhow::Result<rpts_domain::User> {
+                unreachable!("the repository is never called without a tenant")
+            }
+        }
+
+        let service = Rpts01Service {
+            repository: Unreachable,
+        };
+        let request = Request::new(UserRequest {
+            name: "Roberto".to_string(),
+        });
+
+        let status = service.get_user(request).await.err().unwrap();
+
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+}
@@ -0,0 +1,13 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // the User message is owned by the rpts-domain crate, so we reuse its generated type.
+    tonic_build::configure()
+        .build_client(false)
+        .extern_path(".rpts.domain", "::rpts_domain::proto")
+        .compile(&["proto/rpts02.proto"], &["proto", "../rpts-domain/proto"])?;
+    // the receiver of the exported spans, for the tests of the telemetry.
+    tonic_build::configure()
+        .build_client(false)
+        .compile(&["proto/otlp_trace.proto"], &["proto"])?;
+    println!("## Proto files have been compiled");
+    Ok(())
+}
@@ -0,0 +1,120 @@
+use super::{required_var, roles, AuthError, AuthProvider, Caller, Credentials};
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+
+/// Authenticates the callers with static API keys, meant for service to service calls.
+/// Only the digests of the keys are kept in memory.
+#[derive(Debug)]
+pub struct ApiKeysProvider {
+    keys: Vec<(Vec<u8>, Caller)>,
+}
+
+impl ApiKeysProvider {
+    /// Builds the provider out of `(key, caller)` pairs.
+    pub fn new(keys: Vec<(String, Caller)>) -> Self {
+        Self {
+            keys: keys
+                .into_iter()
+                .map(|(key, caller)| (digest(&key), caller))
+                .collect(),
+        }
+    }
+
+    /// Builds the provider from [AUTH_API_KEYS], as `key:caller` pairs separated by commas.
+    /// The roles and the tenant of the caller can follow, like `key:caller:admin+support:acme`.
+    pub fn from_env() -> Result<Self, AuthError> {
+        let keys = required_var("AUTH_API_KEYS")?
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| parse_entry(entry.trim()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::new(keys))
+    }
+}
+
+fn parse_entry(entry: &str) -> Result<(String, Caller), AuthError> {
+    let parts: Vec<&str> = entry.splitn(4, ':').collect();
+    match parts[..] {
+        [key, caller, ..] if !key.is_empty() && !caller.is_empty() => {
+            let mut caller = Caller::new(caller);
+            if let Some(groups) = parts.get(2) {
+                caller = caller.with_roles(roles(groups.split('+')));
+            }
+            if let Some(tenant) = parts.get(3).filter(|tenant| !tenant.is_empty()) {
+                caller = caller.with_tenant(*tenant);
+            }
+            Ok((key.to_string(), caller))
+        }
+        _ => Err(AuthError::Configuration(
+            "AUTH_API_KEYS must be a list of key:caller pairs".to_string(),
+        )),
+    }
+}
+
+fn digest(key: &str) -> Vec<u8> {
+    Sha256::digest(key.as_bytes()).to_vec()
+}
+
+#[async_trait]
+impl AuthProvider for ApiKeysProvider {
+    async fn authenticate(&self, credentials: &Credentials) -> Result<Caller, AuthError> {
+        let key = credentials
+            .api_key
+            .as_deref()
+            .ok_or(AuthError::MissingCredentials("API key"))?;
+        let digest = digest(key);
+        self.keys
+            .iter()
+            .find(|(known, _)| *known == digest)
+            .map(|(_, caller)| caller.clone())
+            .ok_or(AuthError::InvalidCredentials)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Role;
+    use super::*;
+
+    fn api_key(key: &str) -> Credentials {
+        Credentials {
+            bearer: None,
+            api_key: Some(key.to_string()),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn known_keys_are_authenticated_as_their_caller() {
+        let provider = ApiKeysProvider::new(vec![
+            ("key-1".to_string(), Caller::new("billing")),
+            ("key-2".to_string(), Caller::new("reports")),
+        ]);
+
+        let caller = provider.authenticate(&api_key("key-2")).await.unwrap();
+
+        assert_eq!(caller, Caller::new("reports"));
+        assert!(matches!(
+            provider.authenticate(&api_key("key-3")).await,
+            Err(AuthError::InvalidCredentials)
+        ));
+        assert!(matches!(
+            provider.authenticate(&Credentials::default()).await,
+            Err(AuthError::MissingCredentials(_))
+        ));
+    }
+
+    #[test]
+    fn entries_can_have_roles() {
+        let (key, caller) = parse_entry("key-1:billing:admin+support").unwrap();
+
+        assert_eq!(key, "key-1");
+        assert_eq!(
+            caller,
+            Caller::new("billing").with_roles(vec![Role::Admin, Role::Support])
+        );
+        let (_, caller) = parse_entry("key-2:reports::acme").unwrap();
+        assert_eq!(caller, Caller::new("reports").with_tenant("acme"));
+        assert!(parse_entry("key-1").is_err());
+        assert!(parse_entry(":billing").is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use super::{jwks::fetch, required_var, AuthError, JwksProvider};
+use std::env;
+
+/// Verifies the tokens of an AWS Cognito user pool, which is just an OpenID Connect provider
+/// telling its ID and access tokens apart with the `token_use` claim.
+pub fn cognito(
+    jwks: &str,
+    region: &str,
+    pool_id: &str,
+    client_id: &str,
+    verify_access_token: bool,
+) -> Result<JwksProvider, AuthError> {
+    let issuer = format!("https://cognito-idp.{}.amazonaws.com/{}", region, pool_id);
+    if verify_access_token {
+        // access tokens have no audience, but the client they were issued to
+        Ok(JwksProvider::new(jwks, Some(issuer), None)?
+            .require_claim("token_use", "access")
+            .require_claim("client_id", client_id))
+    } else {
+        Ok(
+            JwksProvider::new(jwks, Some(issuer), Some(client_id.to_string()))?
+                .require_claim("token_use", "id"),
+        )
+    }
+}
+
+/// Builds the provider from the env variables:
+/// [COGNITO_REGION], [COGNITO_POOLID], [COGNITO_CLIENTID], [COGNITO_VERIFY_ACCESSTOKEN]
+/// and [AUTH_DEFAULT_TENANT].
+pub async fn cognito_from_env() -> Result<JwksProvider, AuthError> {
+    let region = required_var("COGNITO_REGION")?;
+    let pool_id = required_var("COGNITO_POOLID")?;
+    let client_id = required_var("COGNITO_CLIENTID")?;
+    let verify_access_token = env::var("COGNITO_VERIFY_ACCESSTOKEN").map_or(false, |v| v == "true");
+    let url = format!(
+        "https://cognito-idp.{}.amazonaws.com/{}/.well-known/jwks.json",
+        region, pool_id
+    );
+    let jwks = fetch(&url).await?;
+    Ok(
+        cognito(&jwks, &region, &pool_id, &client_id, verify_access_token)?
+            .with_refresh_url(url)
+            .with_default_tenant_from_env(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::jwks::tests::{token, JWKS};
+    use super::super::{AuthProvider, Caller, Credentials};
+    use super::*;
+    use serde_json::json;
+
+    const ISSUER: &str = "https://cognito-idp.eu-west-1.amazonaws.com/my-pool";
+
+    fn bearer(token: String) -> Credentials {
+        Credentials {
+            bearer: Some(token),
+            api_key: None,
+        }
+    }
+
+    #[actix_rt::test]
+    async fn id_tokens_are_verified() {
+        let provider = cognito(JWKS, "eu-west-1", "my-pool", "my-client", false).unwrap();
+        let id = token(json!({
+            "sub": "a", "iss": ISSUER, "aud": "my-client", "token_use": "id",
+            "custom:company": "acme"
+        }));
+        let access = token(json!({
+            "sub": "a", "iss": ISSUER, "client_id": "my-client", "token_use": "access",
+            "custom:company": "acme"
+        }));
+
+        assert_eq!(
+            provider.authenticate(&bearer(id)).await.unwrap(),
+            Caller::new("a").with_tenant("acme")
+        );
+        assert!(provider.authenticate(&bearer(access)).await.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn access_tokens_are_verified_against_the_client() {
+        let provider = cognito(JWKS, "eu-west-1", "my-pool", "my-client", true).unwrap();
+        let access = token(json!({
+            "sub": "a", "iss": ISSUER, "client_id": "my-client", "token_use": "access",
+            "custom:company": "acme"
+        }));
+        let other = token(json!({
+            "sub": "a", "iss": ISSUER, "client_id": "other", "token_use": "access",
+            "custom:company": "acme"
+        }));
+        let no_company = token(
+            json!({"sub": "a", "iss": ISSUER, "client_id": "my-client", "token_use": "access"}),
+        );
+
+        assert!(provider.authenticate(&bearer(access)).await.is_ok());
+        assert!(provider.authenticate(&bearer(other)).await.is_err());
+        assert!(provider.authenticate(&bearer(no_company)).await.is_err());
+    }
+}
@@ -0,0 +1,42 @@
+use super::{roles, AuthError, AuthProvider, Caller, Credentials};
+use async_trait::async_trait;
+use std::env;
+
+/// Ignores the credentials and makes every request with the same caller.
+/// Only meant for local development.
+#[derive(Debug)]
+pub struct DevProvider {
+    caller: Caller,
+}
+
+impl DevProvider {
+    pub fn new(caller: Caller) -> Self {
+        Self { caller }
+    }
+
+    /// The caller is [AUTH_DEV_USER] with the comma separated [AUTH_DEV_ROLES],
+    /// or [Caller::disabled] if not set, in the [AUTH_DEV_TENANT] if any.
+    pub fn from_env() -> Self {
+        let mut caller = match env::var("AUTH_DEV_USER") {
+            Ok(user) => Caller::new(user).with_roles(roles(
+                env::var("AUTH_DEV_ROLES").unwrap_or_default().split(','),
+            )),
+            Err(_) => Caller::disabled(),
+        };
+        if let Ok(tenant) = env::var("AUTH_DEV_TENANT") {
+            caller = caller.with_tenant(tenant);
+        }
+        tracing::warn!(
+            "Authentication in development mode, every caller is {:?}",
+            caller
+        );
+        Self::new(caller)
+    }
+}
+
+#[async_trait]
+impl AuthProvider for DevProvider {
+    async fn authenticate(&self, _: &Credentials) -> Result<Caller, AuthError> {
+        Ok(self.caller.clone())
+    }
+}
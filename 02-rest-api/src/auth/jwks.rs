@@ -0,0 +1,487 @@
+use super::{required_var, roles, AuthError, AuthProvider, Caller, Credentials};
+use crate::telemetry;
+use async_trait::async_trait;
+use futures::channel::oneshot;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant},
+};
+use tracing::{instrument, Span};
+use tracing_futures::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// A JSON Web Key Set, as served by the `jwks_uri` of an OpenID Connect provider.
+#[derive(Debug, Deserialize)]
+struct KeySet {
+    keys: Vec<Key>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Key {
+    kid: String,
+    kty: String,
+    n: Option<String>,
+    e: Option<String>,
+}
+
+/// Least time between two fetches of the key set, whatever the tokens with unknown keys.
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+type Keys = HashMap<String, DecodingKey<'static>>;
+
+/// Reads the RSA keys of a key set, by id.
+fn parse_keys(jwks: &str) -> Result<Keys, AuthError> {
+    let set: KeySet = serde_json::from_str(jwks)
+        .map_err(|e| AuthError::Configuration(format!("Invalid key set: {}", e)))?;
+    let keys = set
+        .keys
+        .into_iter()
+        .filter(|key| key.kty == "RSA")
+        .filter_map(|key| match (key.n, key.e) {
+            (Some(n), Some(e)) => Some((
+                key.kid,
+                DecodingKey::from_rsa_components(&n, &e).into_static(),
+            )),
+            _ => None,
+        })
+        .collect::<Keys>();
+    if keys.is_empty() {
+        return Err(AuthError::Configuration(
+            "The key set has no RSA keys".to_string(),
+        ));
+    }
+    Ok(keys)
+}
+
+/// Where the key set is fetched again from, once its keys are rotated.
+#[derive(Debug)]
+struct Refresh {
+    url: String,
+    last: Mutex<Option<Instant>>,
+}
+
+/// Claim with the groups of the caller, unless `AUTH_ROLES_CLAIM` says otherwise.
+pub const DEFAULT_ROLES_CLAIM: &str = "cognito:groups";
+/// Claim with the tenant of the caller, unless `AUTH_TENANT_CLAIM` says otherwise.
+pub const DEFAULT_TENANT_CLAIM: &str = "custom:company";
+
+/// Verifies the RS256 tokens signed with the keys of a JSON Web Key Set.
+/// The caller is the subject of the token, with the roles in its groups claim
+/// and the tenant in its tenant claim. Tokens without tenant are rejected,
+/// unless a default tenant is set for them with [JwksProvider::with_default_tenant].
+#[derive(Debug)]
+pub struct JwksProvider {
+    keys: RwLock<Keys>,
+    /// Fetches the keys again when a token is signed by an unknown one, if set.
+    refresh: Option<Refresh>,
+    validation: Validation,
+    /// Claims that must have the given value, like the `token_use` of Cognito.
+    required_claims: Vec<(String, Value)>,
+    roles_claim: String,
+    tenant_claim: String,
+    /// Tenant of the tokens without tenant claim, if they're accepted at all.
+    default_tenant: Option<String>,
+}
+
+impl JwksProvider {
+    /// Builds the provider out of the JSON of a key set.
+    pub fn new(
+        jwks: &str,
+        issuer: Option<String>,
+        audience: Option<String>,
+    ) -> Result<Self, AuthError> {
+        let keys = parse_keys(jwks)?;
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.iss = issuer;
+        if let Some(audience) = audience {
+            validation.set_audience(&[audience]);
+        }
+        Ok(Self {
+            keys: RwLock::new(keys),
+            refresh: None,
+            validation,
+            required_claims: vec![],
+            roles_claim: DEFAULT_ROLES_CLAIM.to_string(),
+            tenant_claim: DEFAULT_TENANT_CLAIM.to_string(),
+            default_tenant: None,
+        })
+    }
+
+    /// Only accepts the tokens with the claim set to the value.
+    pub fn require_claim(mut self, name: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.required_claims.push((name.into(), value.into()));
+        self
+    }
+
+    /// Reads the roles from this claim, either a list of groups or a space separated string.
+    pub fn with_roles_claim(mut self, claim: impl Into<String>) -> Self {
+        self.roles_claim = claim.into();
+        self
+    }
+
+    /// Reads the tenant from this claim.
+    pub fn with_tenant_claim(mut self, claim: impl Into<String>) -> Self {
+        self.tenant_claim = claim.into();
+        self
+    }
+
+    /// Fetches the key set again from the url when a token is signed by an unknown key,
+    /// so the rotated keys are known, but no more than once per [REFRESH_INTERVAL].
+    pub fn with_refresh_url(mut self, url: impl Into<String>) -> Self {
+        self.refresh = Some(Refresh {
+            url: url.into(),
+            last: Mutex::new(None),
+        });
+        self
+    }
+
+    /// Accepts the tokens without tenant claim, as callers of this tenant.
+    /// Only meant for the deployments with a single client company.
+    pub fn with_default_tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.default_tenant = Some(tenant.into());
+        self
+    }
+
+    /// Sets the default tenant in [AUTH_DEFAULT_TENANT], if any.
+    pub(super) fn with_default_tenant_from_env(self) -> Self {
+        match env::var("AUTH_DEFAULT_TENANT") {
+            Ok(tenant) => self.with_default_tenant(tenant),
+            Err(_) => self,
+        }
+    }
+
+    /// Builds the provider from the env variables:
+    /// [AUTH_JWKS_FILE] or [AUTH_JWKS_URL], [AUTH_ISSUER], [AUTH_AUDIENCE],
+    /// [AUTH_ROLES_CLAIM], [AUTH_TENANT_CLAIM] and [AUTH_DEFAULT_TENANT].
+    pub async fn from_env() -> Result<Self, AuthError> {
+        let (jwks, url) = match env::var("AUTH_JWKS_FILE") {
+            Ok(path) => (
+                std::fs::read_to_string(&path).map_err(|e| {
+                    AuthError::Configuration(format!("Unable to read {}: {}", path, e))
+                })?,
+                None,
+            ),
+            Err(_) => {
+                let url = required_var("AUTH_JWKS_URL")?;
+                (fetch(&url).await?, Some(url))
+            }
+        };
+        let mut provider = Self::new(
+            &jwks,
+            env::var("AUTH_ISSUER").ok(),
+            env::var("AUTH_AUDIENCE").ok(),
+        )?;
+        if let Some(url) = url {
+            provider = provider.with_refresh_url(url);
+        }
+        if let Ok(claim) = env::var("AUTH_ROLES_CLAIM") {
+            provider = provider.with_roles_claim(claim);
+        }
+        if let Ok(claim) = env::var("AUTH_TENANT_CLAIM") {
+            provider = provider.with_tenant_claim(claim);
+        }
+        Ok(provider.with_default_tenant_from_env())
+    }
+
+    fn key(&self, kid: &str) -> Option<DecodingKey<'static>> {
+        self.keys.read().unwrap().get(kid).cloned()
+    }
+
+    /// Finds the key, fetching the key set again if it's unknown and it's time to.
+    async fn find_key(&self, kid: &str) -> Option<DecodingKey<'static>> {
+        if let Some(key) = self.key(kid) {
+            return Some(key);
+        }
+        let refresh = self.refresh.as_ref()?;
+        {
+            let mut last = refresh.last.lock().unwrap();
+            if last.map_or(false, |last| last.elapsed() < REFRESH_INTERVAL) {
+                return None;
+            }
+            // taken before fetching, so the concurrent requests don't fetch too
+            *last = Some(Instant::now());
+        }
+        let fetched = fetch_on_arbiter(refresh.url.clone()).await;
+        match fetched.and_then(|jwks| parse_keys(&jwks)) {
+            Ok(keys) => *self.keys.write().unwrap() = keys,
+            Err(e) => tracing::warn!("Unable to refresh the keys: {}", e),
+        }
+        self.key(kid)
+    }
+}
+
+/// Downloads the key set published by a provider, within the current trace.
+#[instrument(fields(otel.kind = "client", http.method = "GET", http.url = url))]
+pub(super) async fn fetch(url: &str) -> Result<String, AuthError> {
+    let fail = |e: &dyn std::fmt::Display| {
+        AuthError::Configuration(format!("Unable to fetch the key set at {}: {}", url, e))
+    };
+    let mut request = actix_web::client::Client::default().get(url);
+    for (name, value) in telemetry::inject(&Span::current().context()) {
+        request = request.header(name.as_str(), value);
+    }
+    let mut response = request.send().await.map_err(|e| fail(&e))?;
+    if !response.status().is_success() {
+        return Err(fail(&response.status()));
+    }
+    let body = response.body().await.map_err(|e| fail(&e))?;
+    String::from_utf8(body.to_vec()).map_err(|e| fail(&e))
+}
+
+/// Fetches the key set on the arbiter of the thread, since the futures of the actix client
+/// can't be sent across threads, unlike the ones of the authentication.
+async fn fetch_on_arbiter(url: String) -> Result<String, AuthError> {
+    let (sender, receiver) = oneshot::channel();
+    let span = Span::current();
+    actix_rt::Arbiter::current().exec_fn(move || {
+        actix_rt::spawn(async move {
+            let _ = sender.send(fetch(&url).instrument(span).await);
+        })
+    });
+    receiver
+        .await
+        .map_err(|_| AuthError::Configuration("The key set fetch was canceled".to_string()))?
+}
+
+#[async_trait]
+impl AuthProvider for JwksProvider {
+    async fn authenticate(&self, credentials: &Credentials) -> Result<Caller, AuthError> {
+        let token = credentials
+            .bearer
+            .as_deref()
+            .ok_or(AuthError::MissingCredentials("bearer token"))?;
+        let header = decode_header(token).map_err(|_| AuthError::InvalidCredentials)?;
+        let kid = header.kid.ok_or(AuthError::InvalidCredentials)?;
+        let key = self
+            .find_key(&kid)
+            .await
+            .ok_or(AuthError::InvalidCredentials)?;
+        let claims = decode::<Map<String, Value>>(token, &key, &self.validation)
+            .map_err(|e| {
+                tracing::debug!("Invalid token: {}", e);
+                AuthError::InvalidCredentials
+            })?
+            .claims;
+        if self
+            .required_claims
+            .iter()
+            .any(|(name, value)| claims.get(name) != Some(value))
+        {
+            return Err(AuthError::InvalidCredentials);
+        }
+        let groups: Vec<&str> = match claims.get(&self.roles_claim) {
+            Some(Value::Array(groups)) => groups.iter().filter_map(|g| g.as_str()).collect(),
+            Some(Value::String(groups)) => groups.split_whitespace().collect(),
+            _ => vec![],
+        };
+        let tenant = claims
+            .get(&self.tenant_claim)
+            .and_then(|tenant| tenant.as_str())
+            .or_else(|| self.default_tenant.as_deref())
+            .ok_or_else(|| {
+                tracing::debug!("Token without {} claim", self.tenant_claim);
+                AuthError::InvalidCredentials
+            })?;
+        claims
+            .get("sub")
+            .and_then(|sub| sub.as_str())
+            .map(|sub| {
+                Caller::new(sub)
+                    .with_roles(roles(groups))
+                    .with_tenant(tenant)
+            })
+            .ok_or(AuthError::InvalidCredentials)
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::super::Role;
+    use super::*;
+    use actix_web::{web, App, HttpResponse};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    pub const JWKS: &str = include_str!("testdata/jwks.json");
+    pub const ISSUER: &str = "https://issuer.test";
+
+    /// Signs the claims with the key of [JWKS], expiring in an hour.
+    pub fn token(claims: Value) -> String {
+        token_of("test-key", claims)
+    }
+
+    /// Signs the claims with the private key of [JWKS], under another key id.
+    fn token_of(kid: &str, claims: Value) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.to_string());
+        let mut claims = claims;
+        claims["exp"] = json!(chrono::Utc::now().timestamp() + 3600);
+        let key = EncodingKey::from_rsa_pem(include_bytes!("testdata/private.pem")).unwrap();
+        encode(&header, &claims, &key).unwrap()
+    }
+
+    fn bearer(token: String) -> Credentials {
+        Credentials {
+            bearer: Some(token),
+            api_key: None,
+        }
+    }
+
+    fn provider() -> JwksProvider {
+        JwksProvider::new(JWKS, Some(ISSUER.to_string()), Some("my-api".to_string())).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn valid_tokens_are_authenticated_as_their_subject() {
+        let token = token(json!({
+            "sub": "roberto", "iss": ISSUER, "aud": "my-api", "custom:company": "acme"
+        }));
+
+        let caller = provider().authenticate(&bearer(token)).await.unwrap();
+
+        assert_eq!(caller, Caller::new("roberto").with_tenant("acme"));
+    }
+
+    #[actix_rt::test]
+    async fn roles_are_read_from_the_groups() {
+        let grouped = token(json!({
+            "sub": "roberto", "iss": ISSUER, "aud": "my-api", "custom:company": "acme",
+            "cognito:groups": ["support", "marketing"]
+        }));
+        let scoped = token(json!({
+            "sub": "roberto", "iss": ISSUER, "aud": "my-api", "custom:company": "acme",
+            "scope": "admin openid"
+        }));
+
+        let caller = provider().authenticate(&bearer(grouped)).await.unwrap();
+        let scoped_caller = provider()
+            .with_roles_claim("scope")
+            .authenticate(&bearer(scoped))
+            .await
+            .unwrap();
+
+        assert_eq!(caller.roles, vec![Role::Support]);
+        assert_eq!(scoped_caller.roles, vec![Role::Admin]);
+    }
+
+    #[actix_rt::test]
+    async fn tenants_are_read_from_the_claims() {
+        let acme = token(json!({
+            "sub": "roberto", "iss": ISSUER, "aud": "my-api", "custom:company": "acme"
+        }));
+
+        assert_eq!(
+            provider().authenticate(&bearer(acme)).await.unwrap().tenant,
+            "acme"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn tokens_without_tenant_are_rejected() {
+        let no_company = token(json!({"sub": "roberto", "iss": ISSUER, "aud": "my-api"}));
+
+        assert!(matches!(
+            provider().authenticate(&bearer(no_company)).await,
+            Err(AuthError::InvalidCredentials)
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn tokens_without_tenant_can_get_the_default_one() {
+        let no_company = token(json!({"sub": "roberto", "iss": ISSUER, "aud": "my-api"}));
+
+        let caller = provider()
+            .with_default_tenant("acme")
+            .authenticate(&bearer(no_company))
+            .await
+            .unwrap();
+
+        assert_eq!(caller.tenant, "acme");
+    }
+
+    #[actix_rt::test]
+    async fn tokens_of_other_issuers_or_audiences_are_rejected() {
+        let provider = provider();
+        for claims in vec![
+            json!({"sub": "roberto", "iss": "https://other.test", "aud": "my-api"}),
+            json!({"sub": "roberto", "iss": ISSUER, "aud": "other-api"}),
+        ] {
+            let result = provider.authenticate(&bearer(token(claims))).await;
+            assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+        }
+    }
+
+    #[actix_rt::test]
+    async fn tokens_must_have_the_required_claims() {
+        let provider = provider().require_claim("token_use", "id");
+
+        let valid = token(json!({
+            "sub": "a", "iss": ISSUER, "aud": "my-api", "custom:company": "acme", "token_use": "id"
+        }));
+        let invalid = token(json!({
+            "sub": "a", "iss": ISSUER, "aud": "my-api", "custom:company": "acme",
+            "token_use": "access"
+        }));
+
+        assert!(provider.authenticate(&bearer(valid)).await.is_ok());
+        assert!(provider.authenticate(&bearer(invalid)).await.is_err());
+    }
+
+    async fn serve_jwks(fetches: web::Data<AtomicUsize>) -> HttpResponse {
+        fetches.fetch_add(1, Ordering::SeqCst);
+        HttpResponse::Ok().body(JWKS)
+    }
+
+    #[actix_rt::test]
+    async fn rotated_keys_are_fetched_again_but_not_too_often() {
+        let fetches = web::Data::new(AtomicUsize::new(0));
+        let served = fetches.clone();
+        let server = actix_web::test::start(move || {
+            App::new()
+                .app_data(served.clone())
+                .route("/jwks.json", web::get().to(serve_jwks))
+        });
+        // the provider only knows the key before the rotation
+        let provider = JwksProvider::new(
+            &JWKS.replace("test-key", "old-key"),
+            Some(ISSUER.to_string()),
+            Some("my-api".to_string()),
+        )
+        .unwrap()
+        .with_refresh_url(server.url("/jwks.json"));
+        let claims = json!({
+            "sub": "roberto", "iss": ISSUER, "aud": "my-api", "custom:company": "acme"
+        });
+
+        let rotated = provider.authenticate(&bearer(token(claims.clone()))).await;
+        let unknown = provider
+            .authenticate(&bearer(token_of("unknown-key", claims)))
+            .await;
+
+        assert_eq!(rotated.unwrap().user.as_deref(), Some("roberto"));
+        assert!(matches!(unknown, Err(AuthError::InvalidCredentials)));
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[actix_rt::test]
+    async fn tampered_or_missing_tokens_are_rejected() {
+        let provider = provider();
+        let mut tampered = token(json!({"sub": "roberto", "iss": ISSUER, "aud": "my-api"}));
+        tampered.push('x');
+
+        assert!(matches!(
+            provider.authenticate(&bearer(tampered)).await,
+            Err(AuthError::InvalidCredentials)
+        ));
+        assert!(matches!(
+            provider.authenticate(&Credentials::default()).await,
+            Err(AuthError::MissingCredentials(_))
+        ));
+    }
+}
@@ -0,0 +1,372 @@
+//! Authentication of the callers of the API, whatever the front-end.
+//!
+//! An [AuthProvider] turns the credentials of a request into a [Caller].
+//! The provider is picked by the `AUTH_PROVIDER` env var:
+//!
+//! - `cognito` (default): ID or access tokens of an AWS Cognito user pool.
+//! - `jwks`: tokens of any OpenID Connect provider, verified with its JSON Web Key Set.
+//! - `api_keys`: static API keys, sent in the `X-Api-Key` header.
+//! - `dev`: no credentials at all, every request is made by the same caller.
+//!
+//! The [Role]s of the callers come from the groups of their tokens, or the configuration
+//! of the API keys, and are checked by the policy of the service.
+mod api_keys;
+mod cognito;
+mod dev;
+mod jwks;
+
+use crate::problem::Problem;
+use actix_web::{dev::Payload, http::StatusCode, web, FromRequest, HttpRequest};
+use async_trait::async_trait;
+use futures::future::{ok, FutureExt, LocalBoxFuture};
+use rpts_domain::redact::Secret;
+use std::{env, str::FromStr, sync::Arc};
+use uuid::Uuid;
+
+pub use api_keys::ApiKeysProvider;
+pub use cognito::cognito_from_env;
+pub use dev::DevProvider;
+pub use jwks::JwksProvider;
+
+/// Header with the API keys of the `api_keys` provider.
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+/// Tenant of the callers that don't tell theirs.
+pub const DEFAULT_TENANT: &str = "default";
+
+/// Roles granted to a caller, on top of the access to its own user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Can do anything with any user.
+    Admin,
+    /// Can read any user, but not write them.
+    Support,
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Role::Admin),
+            "support" => Ok(Role::Support),
+            other => Err(format!("Unknown role: {}", other)),
+        }
+    }
+}
+
+/// Reads the roles out of a list of groups, ignoring the groups that aren't roles.
+pub fn roles<'a>(groups: impl IntoIterator<Item = &'a str>) -> Vec<Role> {
+    groups
+        .into_iter()
+        .filter_map(|group| group.trim().parse().ok())
+        .collect()
+}
+
+/// The authenticated caller of a request.
+/// Anonymous callers, without id nor roles, aren't allowed to do anything.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Caller {
+    /// Id of the caller, the subject of its token.
+    pub user: Option<String>,
+    pub roles: Vec<Role>,
+    /// The client company of the caller. It only sees the users of its tenant.
+    pub tenant: String,
+}
+
+impl Default for Caller {
+    fn default() -> Self {
+        Self {
+            user: None,
+            roles: vec![],
+            tenant: DEFAULT_TENANT.to_string(),
+        }
+    }
+}
+
+impl Caller {
+    pub fn new(user: impl Into<String>) -> Self {
+        Self {
+            user: Some(user.into()),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_roles(mut self, roles: Vec<Role>) -> Self {
+        self.roles = roles;
+        self
+    }
+
+    pub fn with_tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.tenant = tenant.into();
+        self
+    }
+
+    /// The caller of every request when the authentication is disabled:
+    /// an admin of the default tenant without id, only meant for local development.
+    pub fn disabled() -> Self {
+        Self {
+            roles: vec![Role::Admin],
+            ..Self::default()
+        }
+    }
+
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
+    }
+
+    /// The id of the caller's own user: its subject, if it's a UUID like the ones of Cognito.
+    pub fn user_id(&self) -> Option<Uuid> {
+        self.user.as_deref().and_then(|user| user.parse().ok())
+    }
+}
+
+/// The credentials sent along with a request.
+#[derive(Clone, Default, PartialEq)]
+pub struct Credentials {
+    /// The token of an `Authorization: Bearer <token>` header.
+    pub bearer: Option<String>,
+    pub api_key: Option<String>,
+}
+
+/// Only tells which credentials were sent, never their values.
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("bearer", &self.bearer.as_ref().map(Secret))
+            .field("api_key", &self.api_key.as_ref().map(Secret))
+            .finish()
+    }
+}
+
+impl Credentials {
+    /// Reads the credentials from a header lookup, so any front-end can use them.
+    fn from_lookup<'a>(get: impl Fn(&str) -> Option<&'a str>) -> Self {
+        Self {
+            bearer: get("authorization")
+                .and_then(|v| v.strip_prefix("Bearer "))
+                .map(|v| v.trim().to_string()),
+            api_key: get(API_KEY_HEADER).map(|v| v.trim().to_string()),
+        }
+    }
+
+    pub fn from_headers(headers: &actix_web::http::HeaderMap) -> Self {
+        Self::from_lookup(|name| headers.get(name).and_then(|v| v.to_str().ok()))
+    }
+
+    pub fn from_metadata(metadata: &tonic::metadata::MetadataMap) -> Self {
+        Self::from_lookup(|name| {
+            metadata
+                .get(name.to_lowercase().as_str())
+                .and_then(|v| v.to_str().ok())
+        })
+    }
+}
+
+/// Errors authenticating a caller.
+#[derive(Debug, thiserror::Error)]
+pub enum AuthError {
+    #[error("Missing credentials: {0}")]
+    MissingCredentials(&'static str),
+    #[error("The credentials are invalid")]
+    InvalidCredentials,
+    #[error("Invalid authentication configuration: {0}")]
+    Configuration(String),
+}
+
+impl From<AuthError> for Problem {
+    fn from(err: AuthError) -> Self {
+        match err {
+            AuthError::Configuration(_) => Problem::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "authentication-error",
+                "The authentication is misconfigured",
+            ),
+            _ => Problem::new(StatusCode::UNAUTHORIZED, "unauthenticated", err),
+        }
+    }
+}
+
+impl From<AuthError> for tonic::Status {
+    fn from(err: AuthError) -> Self {
+        match err {
+            AuthError::Configuration(_) => tonic::Status::internal("Authentication Error"),
+            _ => tonic::Status::unauthenticated(err.to_string()),
+        }
+    }
+}
+
+/// Authenticates the callers out of their credentials.
+#[async_trait]
+pub trait AuthProvider: Send + Sync + std::fmt::Debug {
+    async fn authenticate(&self, credentials: &Credentials) -> Result<Caller, AuthError>;
+}
+
+/// The provider shared by every front-end.
+#[derive(Debug, Clone)]
+pub struct Authenticator(Arc<dyn AuthProvider>);
+
+impl Authenticator {
+    pub fn new(provider: impl AuthProvider + 'static) -> Self {
+        Self(Arc::new(provider))
+    }
+
+    /// Trusts every request, as made by [Caller::disabled].
+    pub fn disabled() -> Self {
+        Self::new(DevProvider::new(Caller::disabled()))
+    }
+
+    /// Builds the provider named by the `AUTH_PROVIDER` env var.
+    /// Cognito is kept as the default, and `COGNITO_ENABLED=false` still disables it.
+    pub async fn from_env() -> Result<Self, AuthError> {
+        let provider = env::var("AUTH_PROVIDER").unwrap_or_else(|_| "cognito".to_string());
+        match provider.as_str() {
+            "cognito" if env::var("COGNITO_ENABLED").map_or(false, |v| v == "false") => {
+                Ok(Self::disabled())
+            }
+            "cognito" => Ok(Self::new(cognito_from_env().await?)),
+            "jwks" => Ok(Self::new(JwksProvider::from_env().await?)),
+            "api_keys" => Ok(Self::new(ApiKeysProvider::from_env()?)),
+            "dev" => Ok(Self::new(DevProvider::from_env())),
+            other => Err(AuthError::Configuration(format!(
+                "Unknown AUTH_PROVIDER: {}",
+                other
+            ))),
+        }
+    }
+
+    pub async fn authenticate(&self, credentials: &Credentials) -> Result<Caller, AuthError> {
+        self.0.authenticate(credentials).await
+    }
+}
+
+/// Reads the env var the provider can't do without.
+fn required_var(name: &str) -> Result<String, AuthError> {
+    env::var(name).map_err(|_| AuthError::Configuration(format!("{} is not set", name)))
+}
+
+/// Callers are authenticated by the [Authenticator] in the app data,
+/// unless a middleware, like the rate limiter, already did and kept them in the request.
+impl FromRequest for Caller {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        if let Some(caller) = req.extensions().get::<Caller>() {
+            return ok(caller.clone()).boxed_local();
+        }
+        let req = req.clone();
+        async move {
+            let authenticator = req
+                .app_data::<web::Data<Authenticator>>()
+                .ok_or_else(|| AuthError::Configuration("No authenticator".to_string()));
+            let credentials = Credentials::from_headers(req.headers());
+            let caller = match authenticator {
+                Ok(authenticator) => authenticator.authenticate(&credentials).await,
+                Err(err) => Err(err),
+            };
+            caller.map_err(|err| {
+                tracing::warn!("Authentication failed: {}", err);
+                Problem::from(err).for_request(&req).into()
+            })
+        }
+        .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn credentials_are_read_from_the_headers() {
+        let req = TestRequest::default()
+            .header("Authorization", "Bearer my.token")
+            .header(API_KEY_HEADER, "my-key")
+            .to_http_request();
+
+        let credentials = Credentials::from_headers(req.headers());
+
+        assert_eq!(credentials.bearer.as_deref(), Some("my.token"));
+        assert_eq!(credentials.api_key.as_deref(), Some("my-key"));
+    }
+
+    #[test]
+    fn credentials_are_read_from_the_metadata() {
+        let mut metadata = tonic::metadata::MetadataMap::new();
+        metadata.insert("authorization", "Bearer my.token".parse().unwrap());
+
+        let credentials = Credentials::from_metadata(&metadata);
+
+        assert_eq!(credentials.bearer.as_deref(), Some("my.token"));
+        assert_eq!(credentials.api_key, None);
+    }
+
+    #[test]
+    fn credentials_are_never_shown() {
+        let credentials = Credentials {
+            bearer: Some("my.token".to_string()),
+            api_key: None,
+        };
+
+        assert_eq!(
+            format!("{:?}", credentials),
+            "Credentials { bearer: Some([REDACTED]), api_key: None }"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn callers_are_extracted_with_the_authenticator() {
+        let authenticator = Authenticator::new(DevProvider::new(Caller::new("dev")));
+        let (req, mut payload) = TestRequest::default()
+            .app_data(web::Data::new(authenticator))
+            .to_http_parts();
+
+        let caller = Caller::from_request(&req, &mut payload).await.unwrap();
+
+        assert_eq!(caller, Caller::new("dev"));
+    }
+
+    #[test]
+    fn only_uuid_subjects_are_user_ids() {
+        let id = Uuid::new_v4();
+
+        assert_eq!(Caller::new(id.to_string()).user_id(), Some(id));
+        assert_eq!(Caller::new("reports").user_id(), None);
+        assert_eq!(Caller::disabled().user_id(), None);
+    }
+
+    #[actix_rt::test]
+    async fn callers_kept_in_the_request_arent_authenticated_again() {
+        let (req, mut payload) = TestRequest::default().to_http_parts();
+        req.extensions_mut().insert(Caller::new("limited"));
+
+        let caller = Caller::from_request(&req, &mut payload).await.unwrap();
+
+        assert_eq!(caller, Caller::new("limited"));
+    }
+
+    #[test]
+    fn roles_ignore_unknown_groups() {
+        assert_eq!(
+            roles(vec!["support", "marketing", "admin"]),
+            vec![Role::Support, Role::Admin]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn failed_authentications_are_unauthenticated_problems() {
+        let authenticator = Authenticator::new(ApiKeysProvider::new(vec![]));
+        let (req, mut payload) = TestRequest::default()
+            .app_data(web::Data::new(authenticator))
+            .to_http_parts();
+
+        let err = Caller::from_request(&req, &mut payload)
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(err.as_error::<Problem>().unwrap().status, 401);
+    }
+}
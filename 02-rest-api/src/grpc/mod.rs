@@ -0,0 +1,459 @@
+mod proto {
+    #![allow(clippy::all, clippy::pedantic, clippy::nursery)]
+    tonic::include_proto!("rpts02");
+}
+
+use crate::auth::{Authenticator, Caller, Credentials};
+use crate::models::{CustomData, User};
+use crate::problem::RequestId;
+use crate::telemetry;
+use crate::v1::service::{Service, ServiceError, USER_NOT_FOUND};
+use proto::{users_service_server::UsersService, UpdateCustomDataRequest, UserIdRequest};
+use rpts_domain::proto::User as ProtoUser;
+use std::{convert::TryFrom, sync::Arc};
+use tonic::{metadata::MetadataMap, Request, Response, Status};
+use tracing::{self as log, instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
+
+pub use proto::users_service_server::UsersServiceServer;
+
+/// gRPC front-end exposing the operations of any [Service].
+#[derive(Debug)]
+pub struct UsersGrpc<S: Service> {
+    svc: Arc<S>,
+    authenticator: Authenticator,
+}
+
+impl<S: Service> UsersGrpc<S> {
+    /// Builds a new UsersGrpc
+    pub fn new(svc: Arc<S>, authenticator: Authenticator) -> Self {
+        Self { svc, authenticator }
+    }
+
+    /// Authenticates the caller with the credentials in the metadata of the request.
+    async fn caller(&self, metadata: &MetadataMap) -> Result<Caller, Status> {
+        let credentials = Credentials::from_metadata(metadata);
+        Ok(self.authenticator.authenticate(&credentials).await?)
+    }
+}
+
+impl From<ServiceError> for Status {
+    fn from(err: ServiceError) -> Self {
+        match err {
+            ServiceError::Unauthorized => Status::permission_denied(err.to_string()),
+            ServiceError::DuplicateName | ServiceError::AlreadySignedUp => {
+                Status::already_exists(err.to_string())
+            }
+            ServiceError::PreconditionFailed | ServiceError::ForeignKeyViolation => {
+                Status::failed_precondition(err.to_string())
+            }
+            ServiceError::SerializationFailure => Status::aborted(err.to_string()),
+            ServiceError::DbError(sqlx::Error::RowNotFound) => Status::not_found(USER_NOT_FOUND),
+            ServiceError::DbError(_) => Status::internal("Database Error"),
+            ServiceError::InvalidUser(_)
+            | ServiceError::CheckViolation
+            | ServiceError::IdempotencyKeyReused
+            | ServiceError::InvalidQuery(_)
+            | ServiceError::InvalidPatch(_) => Status::invalid_argument(err.to_string()),
+        }
+    }
+}
+
+/// Continues the trace of the caller, from the `traceparent` and `tracestate` metadata.
+fn continue_trace(metadata: &MetadataMap) {
+    Span::current().set_parent(&telemetry::extract(|name| {
+        metadata.get(name).and_then(|v| v.to_str().ok())
+    }));
+}
+
+/// The id of the request, as set by the client or a proxy, to record it in the history of the users.
+/// Invalid ids are replaced by a new one.
+fn request_id(metadata: &MetadataMap) -> Option<String> {
+    metadata
+        .get("x-request-id")
+        .map(|v| {
+            v.to_str()
+                .ok()
+                .and_then(RequestId::parse)
+                .unwrap_or_else(RequestId::generate)
+        })
+        .map(|RequestId(id)| id)
+}
+
+fn parse_id(id: &str) -> Result<Uuid, Status> {
+    Uuid::parse_str(id).map_err(|e| Status::invalid_argument(format!("Invalid user id: {}", e)))
+}
+
+fn to_response(svc_result: Result<User, ServiceError>) -> Result<Response<ProtoUser>, Status> {
+    svc_result
+        .map(|user| Response::new(user.into()))
+        .map_err(|err| {
+            log::error!("gRPC request failed: {}", err);
+            err.into()
+        })
+}
+
+#[tonic::async_trait]
+impl<S: Service + 'static> UsersService for UsersGrpc<S> {
+    #[instrument(
+        skip(self, request),
+        fields(
+            otel.kind = "server",
+            rpc.system = "grpc",
+            rpc.service = "rpts02.UsersService",
+            rpc.method = "GetUser"
+        )
+    )]
+    async fn get_user(
+        &self,
+        request: Request<UserIdRequest>,
+    ) -> Result<Response<ProtoUser>, Status> {
+        continue_trace(request.metadata());
+        let caller = self.caller(request.metadata()).await?;
+        let id = parse_id(&request.get_ref().id)?;
+        to_response(self.svc.get_user(&id, caller, false).await)
+    }
+
+    #[instrument(
+        skip(self, request),
+        fields(
+            otel.kind = "server",
+            rpc.system = "grpc",
+            rpc.service = "rpts02.UsersService",
+            rpc.method = "CreateUser"
+        )
+    )]
+    async fn create_user(
+        &self,
+        request: Request<ProtoUser>,
+    ) -> Result<Response<ProtoUser>, Status> {
+        continue_trace(request.metadata());
+        let caller = self.caller(request.metadata()).await?;
+        let request_id = request_id(request.metadata());
+        let user = User::try_from(request.into_inner())
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        to_response(self.svc.create_user(user, caller, request_id).await)
+    }
+
+    #[instrument(
+        skip(self, request),
+        fields(
+            otel.kind = "server",
+            rpc.system = "grpc",
+            rpc.service = "rpts02.UsersService",
+            rpc.method = "UpdateCustomData"
+        )
+    )]
+    async fn update_custom_data(
+        &self,
+        request: Request<UpdateCustomDataRequest>,
+    ) -> Result<Response<ProtoUser>, Status> {
+        continue_trace(request.metadata());
+        let caller = self.caller(request.metadata()).await?;
+        let request_id = request_id(request.metadata());
+        let request = request.into_inner();
+        let id = parse_id(&request.id)?;
+        let custom_data = CustomData::try_from(request.custom_data)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        to_response(
+            self.svc
+                .update_user(&id, caller, custom_data, None, request_id)
+                .await,
+        )
+    }
+
+    #[instrument(
+        skip(self, request),
+        fields(
+            otel.kind = "server",
+            rpc.system = "grpc",
+            rpc.service = "rpts02.UsersService",
+            rpc.method = "DeleteUser"
+        )
+    )]
+    async fn delete_user(
+        &self,
+        request: Request<UserIdRequest>,
+    ) -> Result<Response<ProtoUser>, Status> {
+        continue_trace(request.metadata());
+        let caller = self.caller(request.metadata()).await?;
+        let id = parse_id(&request.get_ref().id)?;
+        let request_id = request_id(request.metadata());
+        to_response(self.svc.delete_user(&id, caller, None, request_id).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::ApiKeysProvider;
+    use crate::v1::mocks::MockSvc;
+    use tonic::Code;
+
+    fn grpc(mock_svc: MockSvc) -> UsersGrpc<MockSvc> {
+        UsersGrpc::new(Arc::new(mock_svc), Authenticator::disabled())
+    }
+
+    #[actix_rt::test]
+    async fn get_user_works() {
+        let user_id = Uuid::new_v4();
+        let user_name = "my_name";
+
+        let mut mock_svc = MockSvc::default();
+        mock_svc
+            .expect_sync_get_user()
+            .returning(move |user_id, _caller, _| {
+                let mut user = User::default();
+                user.id = Some(*user_id);
+                user.name = user_name.to_string();
+                Ok(user)
+            });
+
+        let request = Request::new(UserIdRequest {
+            id: user_id.to_string(),
+        });
+        let user = grpc(mock_svc).get_user(request).await.unwrap().into_inner();
+
+        assert_eq!(user.id, user_id.to_string());
+        assert_eq!(user.name, user_name);
+    }
+
+    #[actix_rt::test]
+    async fn get_user_rejects_unauthenticated_callers() {
+        let mut mock_svc = MockSvc::default();
+        mock_svc.expect_sync_get_user().never();
+
+        let authenticator = Authenticator::new(ApiKeysProvider::new(vec![]));
+        let request = Request::new(UserIdRequest {
+            id: Uuid::new_v4().to_string(),
+        });
+        let status = UsersGrpc::new(Arc::new(mock_svc), authenticator)
+            .get_user(request)
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+
+    #[actix_rt::test]
+    async fn get_user_forwards_the_caller() {
+        let mut mock_svc = MockSvc::default();
+        mock_svc
+            .expect_sync_get_user()
+            .withf(|_, caller, _| caller.user.as_deref() == Some("billing"))
+            .returning(|_, _, _| Ok(User::default()));
+
+        let authenticator = Authenticator::new(ApiKeysProvider::new(vec![(
+            "key".into(),
+            Caller::new("billing"),
+        )]));
+        let mut request = Request::new(UserIdRequest {
+            id: Uuid::new_v4().to_string(),
+        });
+        request
+            .metadata_mut()
+            .insert("x-api-key", "key".parse().unwrap());
+        let result = UsersGrpc::new(Arc::new(mock_svc), authenticator)
+            .get_user(request)
+            .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn get_user_rejects_invalid_ids() {
+        let mut mock_svc = MockSvc::default();
+        mock_svc.expect_sync_get_user().never();
+
+        let request = Request::new(UserIdRequest {
+            id: "not-a-uuid".to_string(),
+        });
+        let status = grpc(mock_svc).get_user(request).await.err().unwrap();
+
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[actix_rt::test]
+    async fn get_user_maps_err_to_permission_denied() {
+        let mut mock_svc = MockSvc::default();
+        mock_svc
+            .expect_sync_get_user()
+            .returning(|_, _, _| Err(ServiceError::Unauthorized));
+
+        let request = Request::new(UserIdRequest {
+            id: Uuid::new_v4().to_string(),
+        });
+        let status = grpc(mock_svc).get_user(request).await.err().unwrap();
+
+        assert_eq!(status.code(), Code::PermissionDenied);
+    }
+
+    #[actix_rt::test]
+    async fn get_user_maps_err_to_not_found() {
+        let mut mock_svc = MockSvc::default();
+        mock_svc
+            .expect_sync_get_user()
+            .returning(|_, _, _| Err(ServiceError::DbError(sqlx::Error::RowNotFound)));
+
+        let request = Request::new(UserIdRequest {
+            id: Uuid::new_v4().to_string(),
+        });
+        let status = grpc(mock_svc).get_user(request).await.err().unwrap();
+
+        assert_eq!(status.code(), Code::NotFound);
+        assert_eq!(status.message(), "User not found");
+    }
+
+    #[actix_rt::test]
+    async fn get_user_maps_err_to_internal() {
+        let mut mock_svc = MockSvc::default();
+        mock_svc
+            .expect_sync_get_user()
+            .returning(|_, _, _| Err(ServiceError::DbError(sqlx::Error::PoolTimedOut)));
+
+        let request = Request::new(UserIdRequest {
+            id: Uuid::new_v4().to_string(),
+        });
+        let status = grpc(mock_svc).get_user(request).await.err().unwrap();
+
+        assert_eq!(status.code(), Code::Internal);
+        assert_eq!(status.message(), "Database Error");
+    }
+
+    #[actix_rt::test]
+    async fn create_user_works() {
+        let mut user = User::default();
+        user.name = "my_name".to_string();
+
+        let mut mock_svc = MockSvc::default();
+        mock_svc
+            .expect_sync_create_user()
+            .returning(|mut user, _, _| {
+                user.id = Some(Uuid::new_v4());
+                Ok(user)
+            });
+
+        let request = Request::new(ProtoUser::from(user));
+        let created = grpc(mock_svc)
+            .create_user(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert!(!created.id.is_empty());
+        assert_eq!(created.name, "my_name");
+    }
+
+    #[actix_rt::test]
+    async fn create_user_rejects_users_without_birth_date() {
+        let mut mock_svc = MockSvc::default();
+        mock_svc.expect_sync_create_user().never();
+
+        let mut user = ProtoUser::from(User::default());
+        user.birth_date = None;
+        let status = grpc(mock_svc)
+            .create_user(Request::new(user))
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[actix_rt::test]
+    async fn update_custom_data_works() {
+        let user_id = Uuid::new_v4();
+        let random = 74444;
+
+        let mut mock_svc = MockSvc::default();
+        mock_svc.expect_sync_update_user().returning(
+            move |user_id, _caller, custom_data, _expected, _| {
+                let mut user = User::default();
+                user.id = Some(*user_id);
+                user.custom_data = Some(custom_data);
+                Ok(user)
+            },
+        );
+
+        let request = Request::new(UpdateCustomDataRequest {
+            id: user_id.to_string(),
+            custom_data: CustomData { random }.into(),
+        });
+        let user = grpc(mock_svc)
+            .update_custom_data(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(user.id, user_id.to_string());
+        assert_eq!(user.custom_data.get("random"), Some(&i64::from(random)));
+    }
+
+    #[actix_rt::test]
+    async fn delete_user_works() {
+        let user_id = Uuid::new_v4();
+
+        let mut mock_svc = MockSvc::default();
+        mock_svc
+            .expect_sync_delete_user()
+            .returning(move |user_id, _caller, _expected, _| {
+                let mut user = User::default();
+                user.id = Some(*user_id);
+                Ok(user)
+            });
+
+        let request = Request::new(UserIdRequest {
+            id: user_id.to_string(),
+        });
+        let user = grpc(mock_svc)
+            .delete_user(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(user.id, user_id.to_string());
+    }
+
+    #[actix_rt::test]
+    async fn delete_user_forwards_the_request_id() {
+        let mut mock_svc = MockSvc::default();
+        mock_svc
+            .expect_sync_delete_user()
+            .withf(|_, _, _, request_id| request_id.as_deref() == Some("my-request"))
+            .returning(|_, _, _, _| Ok(User::default()));
+
+        let mut request = Request::new(UserIdRequest {
+            id: Uuid::new_v4().to_string(),
+        });
+        request
+            .metadata_mut()
+            .insert("x-request-id", "my-request".parse().unwrap());
+        let result = grpc(mock_svc).delete_user(request).await;
+
+        assert!(result.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn delete_user_replaces_invalid_request_ids() {
+        let mut mock_svc = MockSvc::default();
+        mock_svc
+            .expect_sync_delete_user()
+            .withf(|_, _, _, request_id| {
+                let request_id = request_id.as_deref().unwrap();
+                request_id != "my request" && RequestId::parse(request_id).is_some()
+            })
+            .returning(|_, _, _, _| Ok(User::default()));
+
+        let mut request = Request::new(UserIdRequest {
+            id: Uuid::new_v4().to_string(),
+        });
+        request
+            .metadata_mut()
+            .insert("x-request-id", "my request".parse().unwrap());
+        let result = grpc(mock_svc).delete_user(request).await;
+
+        assert!(result.is_ok());
+    }
+}
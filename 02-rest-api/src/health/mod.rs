@@ -0,0 +1,360 @@
+//! Health of the instance, for the orchestrator and for humans.
+//!
+//! - `/health/live` only says the process is up, so it's restarted if it doesn't answer.
+//! - `/health/ready` checks every [Dependency], so traffic only goes to the instances that can serve it.
+//! - `/health/status` reports every check in detail, with its latency.
+//! - `/health` is kept as it was, always `ok`.
+mod postgres;
+
+pub use postgres::PostgresCheck;
+
+use actix_web::{web, HttpResponse};
+use async_trait::async_trait;
+use futures::future::join_all;
+use serde::Serialize;
+use std::{
+    env,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Milliseconds a dependency has to answer, unless `HEALTH_TIMEOUT_MS` says otherwise.
+pub const DEFAULT_TIMEOUT_MS: u64 = 1000;
+
+/// Health of a dependency, from the best to the worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Up,
+    /// Working, but close to stop doing it. The instance is still ready.
+    Degraded,
+    /// Not working, so the instance isn't ready.
+    Down,
+}
+
+/// Outcome of checking a dependency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    pub fn up(detail: impl Into<String>) -> Self {
+        Self {
+            status: Status::Up,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn degraded(detail: impl Into<String>) -> Self {
+        Self {
+            status: Status::Degraded,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn down(detail: impl Into<String>) -> Self {
+        Self {
+            status: Status::Down,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Something the instance needs to serve the requests.
+#[async_trait]
+pub trait Dependency: std::fmt::Debug + Send + Sync {
+    /// Name of the dependency in the reports.
+    fn name(&self) -> &str;
+    /// Checks the dependency, which is down if it takes longer than the timeout.
+    async fn check(&self) -> Check;
+}
+
+/// Check of a dependency, as reported.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DependencyReport {
+    pub name: String,
+    pub status: Status,
+    pub latency_ms: u64,
+    pub detail: String,
+}
+
+/// Health of the instance, the worst of its dependencies.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    pub status: Status,
+    pub version: &'static str,
+    pub dependencies: Vec<DependencyReport>,
+}
+
+impl Report {
+    /// Whether the instance can serve the requests.
+    pub fn is_ready(&self) -> bool {
+        self.status != Status::Down
+    }
+}
+
+/// Checks the dependencies of the instance.
+#[derive(Debug, Clone)]
+pub struct Health {
+    dependencies: Vec<Arc<dyn Dependency>>,
+    timeout: Duration,
+}
+
+impl Health {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            dependencies: vec![],
+            timeout,
+        }
+    }
+
+    /// Builds a health without dependencies, with the timeout in [HEALTH_TIMEOUT_MS].
+    pub fn from_env() -> Result<Self, String> {
+        let timeout = match env::var("HEALTH_TIMEOUT_MS") {
+            Ok(v) => v.parse().map_err(|_| {
+                format!(
+                    "HEALTH_TIMEOUT_MS must be a number of milliseconds, not {}",
+                    v
+                )
+            })?,
+            Err(_) => DEFAULT_TIMEOUT_MS,
+        };
+        Ok(Self::new(Duration::from_millis(timeout)))
+    }
+
+    pub fn with(mut self, dependency: impl Dependency + 'static) -> Self {
+        self.dependencies.push(Arc::new(dependency));
+        self
+    }
+
+    /// Checks every dependency at the same time.
+    pub async fn report(&self) -> Report {
+        let dependencies = join_all(
+            self.dependencies
+                .iter()
+                .map(|dependency| self.check(dependency.as_ref())),
+        )
+        .await;
+        Report {
+            status: dependencies
+                .iter()
+                .map(|dependency| dependency.status)
+                .max()
+                .unwrap_or(Status::Up),
+            version: env!("CARGO_PKG_VERSION"),
+            dependencies,
+        }
+    }
+
+    async fn check(&self, dependency: &dyn Dependency) -> DependencyReport {
+        let start = Instant::now();
+        let check = actix_rt::time::timeout(self.timeout, dependency.check())
+            .await
+            .unwrap_or_else(|_| {
+                Check::down(format!("No answer after {} ms", self.timeout.as_millis()))
+            });
+        DependencyReport {
+            name: dependency.name().to_string(),
+            status: check.status,
+            latency_ms: start.elapsed().as_millis() as u64,
+            detail: check.detail,
+        }
+    }
+}
+
+/// Health endpoints
+pub fn endpoint(cfg: &mut web::ServiceConfig) {
+    cfg.route("/health", web::get().to(handler))
+        .route("/health/live", web::get().to(live))
+        .route("/health/ready", web::get().to(ready))
+        .route("/health/status", web::get().to(status));
+}
+
+async fn handler() -> HttpResponse {
+    HttpResponse::Ok().body("ok")
+}
+
+async fn live() -> HttpResponse {
+    HttpResponse::Ok().body("ok")
+}
+
+/// `503 Service Unavailable` if any dependency is down, naming them.
+async fn ready(health: web::Data<Health>) -> HttpResponse {
+    let report = health.report().await;
+    if report.is_ready() {
+        return HttpResponse::Ok().body("ready");
+    }
+    let down: Vec<_> = report
+        .dependencies
+        .iter()
+        .filter(|dependency| dependency.status == Status::Down)
+        .map(|dependency| dependency.name.as_str())
+        .collect();
+    HttpResponse::ServiceUnavailable().body(format!("not ready: {}", down.join(", ")))
+}
+
+/// The report of every dependency, with `503 Service Unavailable` if not ready.
+async fn status(health: web::Data<Health>) -> HttpResponse {
+    let report = health.report().await;
+    if report.is_ready() {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, App};
+
+    /// A dependency answering the same, after the delay.
+    #[derive(Debug)]
+    struct Fake {
+        name: &'static str,
+        check: Check,
+        delay: Duration,
+    }
+
+    impl Fake {
+        fn new(name: &'static str, check: Check) -> Self {
+            Self {
+                name,
+                check,
+                delay: Duration::from_millis(0),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Dependency for Fake {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn check(&self) -> Check {
+            actix_rt::time::delay_for(self.delay).await;
+            self.check.clone()
+        }
+    }
+
+    async fn get(health: Health, uri: &str) -> (StatusCode, String) {
+        let mut app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(health))
+                .configure(endpoint),
+        )
+        .await;
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let res = test::call_service(&mut app, req).await;
+        let status = res.status();
+        let body = test::read_body(res).await;
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn health() -> Health {
+        Health::new(Duration::from_millis(50))
+    }
+
+    #[actix_rt::test]
+    async fn health_handler_works() {
+        let res: HttpResponse = handler().await;
+        assert!(res.status().is_success());
+    }
+
+    #[actix_rt::test]
+    async fn health_handler_integration_works() {
+        let svc = App::new().route("/health", web::get().to(handler));
+        let mut app = test::init_service(svc).await;
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert!(res.status().is_success());
+    }
+
+    #[actix_rt::test]
+    async fn live_ignores_the_dependencies() {
+        let health = health().with(Fake::new("postgres", Check::down("gone")));
+
+        let (status, body) = get(health, "/health/live").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "ok");
+    }
+
+    #[actix_rt::test]
+    async fn ready_if_every_dependency_is_up() {
+        let health = health().with(Fake::new("postgres", Check::up("fine")));
+
+        let (status, body) = get(health, "/health/ready").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "ready");
+    }
+
+    #[actix_rt::test]
+    async fn ready_if_a_dependency_is_degraded() {
+        let health = health()
+            .with(Fake::new("postgres", Check::degraded("busy")))
+            .with(Fake::new("other", Check::up("fine")));
+
+        let (status, _) = get(health, "/health/ready").await;
+
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn not_ready_if_a_dependency_is_down() {
+        let health = health()
+            .with(Fake::new("postgres", Check::down("gone")))
+            .with(Fake::new("other", Check::up("fine")));
+
+        let (status, body) = get(health, "/health/ready").await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body, "not ready: postgres");
+    }
+
+    #[actix_rt::test]
+    async fn slow_dependencies_are_down() {
+        let slow = Fake {
+            delay: Duration::from_secs(5),
+            ..Fake::new("postgres", Check::up("too late"))
+        };
+
+        let report = health().with(slow).report().await;
+
+        assert_eq!(report.status, Status::Down);
+        assert_eq!(report.dependencies[0].detail, "No answer after 50 ms");
+    }
+
+    #[actix_rt::test]
+    async fn status_reports_every_dependency() {
+        let health = health()
+            .with(Fake::new("postgres", Check::degraded("busy")))
+            .with(Fake::new("other", Check::up("fine")));
+
+        let (status, body) = get(health, "/health/status").await;
+        let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["status"], "degraded");
+        assert_eq!(report["dependencies"][0]["name"], "postgres");
+        assert_eq!(report["dependencies"][0]["status"], "degraded");
+        assert_eq!(report["dependencies"][0]["detail"], "busy");
+        assert!(report["dependencies"][0]["latency_ms"].is_u64());
+        assert_eq!(report["dependencies"][1]["status"], "up");
+    }
+
+    #[actix_rt::test]
+    async fn status_is_unavailable_if_not_ready() {
+        let health = health().with(Fake::new("postgres", Check::down("gone")));
+
+        let (status, body) = get(health, "/health/status").await;
+        let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(report["status"], "down");
+    }
+}
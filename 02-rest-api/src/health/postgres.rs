@@ -0,0 +1,94 @@
+use super::{Check, Dependency};
+use async_trait::async_trait;
+use sqlx::PgPool;
+use tracing as log;
+
+/// Share of the connections in use from which the pool is degraded.
+const SATURATION_WARNING: f64 = 0.8;
+
+/// Checks the database: a round-trip, the connections left in the pool and the schema version.
+#[derive(Debug)]
+pub struct PostgresCheck {
+    pool: PgPool,
+    max_connections: u32,
+}
+
+impl PostgresCheck {
+    pub fn new(pool: PgPool, max_connections: u32) -> Self {
+        Self {
+            pool,
+            max_connections,
+        }
+    }
+}
+
+/// Degraded if every connection is in use, or close to.
+/// A busy pool is never down: the requests wait for a connection, and taking every instance
+/// out of the traffic during a spike would turn it into an outage.
+fn saturation(in_use: u32, max_connections: u32) -> Check {
+    let detail = format!("{} of {} connections in use", in_use, max_connections);
+    if f64::from(in_use) >= f64::from(max_connections) * SATURATION_WARNING {
+        Check::degraded(detail)
+    } else {
+        Check::up(detail)
+    }
+}
+
+#[async_trait]
+impl Dependency for PostgresCheck {
+    fn name(&self) -> &str {
+        "postgres"
+    }
+
+    async fn check(&self) -> Check {
+        // measured before the round-trip takes a connection of its own
+        let in_use = self.pool.size().saturating_sub(self.pool.num_idle() as u32);
+        let pool = saturation(in_use, self.max_connections);
+        // the round-trip would wait for a connection until the check times out
+        if in_use >= self.max_connections {
+            return pool;
+        }
+        // the errors can name the hosts of the database, so they are only logged
+        if let Err(e) = sqlx::query("SELECT 1").execute(&self.pool).await {
+            log::error!("The database health check failed: {}", e);
+            return Check::down("The database doesn't answer");
+        }
+        if let Err(e) = rpts_migrations::ensure_up_to_date(&self.pool).await {
+            log::error!("The database schema health check failed: {}", e);
+            return Check::down("The database schema isn't the expected one");
+        }
+        Check {
+            detail: format!(
+                "{}, schema at version {}",
+                pool.detail,
+                rpts_migrations::expected_version()
+            ),
+            ..pool
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::Status;
+
+    #[test]
+    fn pool_is_up_with_connections_to_spare() {
+        let check = saturation(3, 10);
+        assert_eq!(check.status, Status::Up);
+        assert_eq!(check.detail, "3 of 10 connections in use");
+    }
+
+    #[test]
+    fn pool_is_degraded_when_almost_saturated() {
+        assert_eq!(saturation(8, 10).status, Status::Degraded);
+    }
+
+    #[test]
+    fn pool_is_only_degraded_when_saturated() {
+        let check = saturation(10, 10);
+        assert_eq!(check.status, Status::Degraded);
+        assert_eq!(check.detail, "10 of 10 connections in use");
+    }
+}
@@ -1,51 +1,39 @@
-/// Executes the code in case the user is authorized to access the resource.
-macro_rules! authorized {
-    ($user_id: expr, $caller_id: expr) => {{
-        if let Some(caller_id) = $caller_id {
-            if $user_id.to_string() != caller_id {
-                return Err(ServiceError::Unauthorized);
-            }
-        }
-    }};
-}
-
-/// Expands to the typical error request handling
-macro_rules! svc_err {
-    ($err: expr) => {{
-        match $err {
-            ServiceError::Unauthorized => Err(error::ErrorUnauthorized($err)),
-            ServiceError::DbError(sqlx::Error::RowNotFound) => Err(error::ErrorNotFound($err)),
-            ServiceError::DbError(_) => Err(error::ErrorInternalServerError("Database Error")),
-        }
-    }};
-}
-
-/// Handles the service response
+/// Handles the service response, tagging the user with its version.
+/// Errors are rendered as problems pointing to the request.
 macro_rules! svc_response {
-    ($svc_call: expr, $response_type: expr, $location: expr, $err_msg: expr) => {{
+    ($svc_call: expr, $response_type: expr, $req: expr, $err_msg: expr) => {{
         match $svc_call {
             Ok(svc_resp) => {
-                let response = $response_type.header("Location", $location).json(svc_resp);
+                let response = $response_type
+                    .header("Location", $req.path())
+                    .header("ETag", crate::v1::etag::Version::of(&svc_resp).etag())
+                    .json(svc_resp);
                 Ok(response)
             }
             Err(err) => {
                 log::error!("{}: {}", $err_msg, err);
-                svc_err!(err)
+                Err(crate::problem::Problem::from(err).for_request(&$req))
             }
         }
     }};
 }
 
-/// Wraps the result of the query into a measured block and returns the result
+/// Wraps the result of the query into a measured block and returns the result.
+/// The time taken is observed by the metrics, if any, labeled with the outcome.
 macro_rules! measure_query {
-    ($method: literal, $block: block) => {{
+    ($metrics: expr, $method: literal, $block: block) => {{
         let now = Instant::now();
-        let result = $block;
+        // run apart, so the errors returned early by `?` are measured too
+        let result: sqlx::Result<_> = async { $block }.await;
+        let elapsed = now.elapsed();
         log::debug!(
             "{} user query & deserialization took {} ms",
             $method,
-            now.elapsed().as_millis()
+            elapsed.as_millis()
         );
+        if let Some(metrics) = &$metrics {
+            metrics.observe($method, &result, elapsed);
+        }
         result
     }};
 }
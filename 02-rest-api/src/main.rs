@@ -1,75 +1,198 @@
 #[macro_use]
 mod macros;
+mod auth;
+mod grpc;
 mod health;
+mod metrics;
 mod models;
+mod openapi;
+mod problem;
+mod rate_limit;
+mod telemetry;
 mod v1;
 
 use actix_cors::Cors;
-use actix_web::{
-    middleware::{self, Logger},
-    web, App, HttpServer,
-};
-use actix_web_middleware_cognito::{Cognito, CognitoValidator};
+use actix_web::{middleware, web, App, HttpServer};
 use actix_web_prom::PrometheusMetrics;
 use middleware::Compress;
-use std::sync::Arc;
+use std::time::Duration;
 use tracing as log;
+use v1::cache::CachingRepository;
 use v1::repository::PostgresRepository;
-use v1::service::Rpts02Service;
+use v1::service::{Rpts02Service, Service};
 
 const PORT: &str = "3000";
+const GRPC_PORT: &str = "50052";
+/// Days the deleted users are kept before being purged, unless `USERS_RETENTION_DAYS` says otherwise.
+const RETENTION_DAYS: i64 = 30;
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
     // set up
     dotenv::dotenv().ok();
-    // structured logging and distributed tracing
-    tracing_subscriber::fmt()
-        .with_ansi(true)
-        .json()
-        .flatten_event(true)
-        .with_target(true)
-        .with_span_list(true)
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .with_timer(tracing_subscriber::fmt::time::ChronoUtc::rfc3339())
-        .init();
-    // cognito validator initialized here to avoid building it too many times
+    // structured logging and distributed tracing, exporting the spans if configured
+    let _telemetry = telemetry::init().unwrap_or_else(|e| panic!("🔥 {}", e));
+    // authentication provider shared by the REST and gRPC front-ends
     // this reads from env variables to be built, so you'll have to restart the server
     // for changes to take effect.
-    let cognito_validator =
-        Arc::new(CognitoValidator::create().expect("Error generating Cognito Validator"));
-    // metrics for Prometheus
-    let prometheus = PrometheusMetrics::new("rpts02_api", Some("/metrics"), None);
-    // instantiate a database connection pool
+    let authenticator = auth::Authenticator::from_env()
+        .await
+        .unwrap_or_else(|e| panic!("🔥 {}", e));
+    // metrics for Prometheus, served along with the API unless they have a port of their own
+    let metrics_port = metrics::port_from_env().unwrap_or_else(|e| panic!("🔥 {}", e));
+    let metrics_path = if metrics_port.is_some() {
+        None
+    } else {
+        Some("/metrics")
+    };
+    let prometheus = PrometheusMetrics::new(metrics::NAMESPACE, metrics_path, None);
+    // instantiate a database connection pool, timing its queries
+    let query_metrics = metrics::QueryMetrics::register(&prometheus.registry)
+        .unwrap_or_else(|e| panic!("🔥 {}", e));
     let repository = PostgresRepository::build_from_env()
         .await
-        .expect("Error initializing Database connection pool");
-    // creating the service layer
-    let svc = Rpts02Service::new(repository);
+        .expect("Error initializing Database connection pool")
+        .with_metrics(query_metrics);
+    // `rpts02 migrate [up|status|dry-run]` manages the schema instead of serving
+    let migrate_command = rpts_migrations::Command::from_args(std::env::args())
+        .unwrap_or_else(|e| panic!("🔥 {}", e));
+    if let Some(command) = migrate_command {
+        return rpts_migrations::run(&repository.pool, command)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e));
+    }
+    // refuse to start if the database schema doesn't match the one we expect
+    rpts_migrations::ensure_up_to_date(&repository.pool)
+        .await
+        .unwrap_or_else(|e| panic!("🔥 Database schema mismatch: {}", e));
+    // limiting the requests of each caller, with the buckets in memory or in the database
+    let rate_limiter = rate_limit::RateLimiter::from_env(&repository.pool, authenticator.clone())
+        .unwrap_or_else(|e| panic!("🔥 {}", e));
+    // checking the dependencies for the readiness and status endpoints
+    let health = health::Health::from_env()
+        .unwrap_or_else(|e| panic!("🔥 {}", e))
+        .with(health::PostgresCheck::new(
+            repository.pool.clone(),
+            v1::repository::MAX_CONNECTIONS,
+        ));
+    let health = web::Data::new(health);
+    // sampling the connection pool for the metrics
+    let pool_metrics =
+        metrics::PoolMetrics::register(&prometheus.registry).unwrap_or_else(|e| panic!("🔥 {}", e));
+    let sampled_pool = repository.pool.clone();
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(metrics::POOL_SAMPLE_INTERVAL);
+        loop {
+            interval.tick().await;
+            pool_metrics.sample(&sampled_pool);
+        }
+    });
+    // caching the users read, unless disabled, with its hits and misses in the metrics
+    let repository = CachingRepository::from_env(repository, &prometheus.registry)
+        .unwrap_or_else(|e| panic!("🔥 {}", e));
+    // creating the service layer, counting what's done with the users
+    let user_metrics =
+        metrics::UserMetrics::register(&prometheus.registry).unwrap_or_else(|e| panic!("🔥 {}", e));
+    let svc = Rpts02Service::new(repository).with_metrics(user_metrics);
     // let svc = ServiceInjector::new(svc);
     let svc = web::Data::new(svc);
 
+    // purging the users deleted before the retention period, every hour
+    let retention = std::env::var("USERS_RETENTION_DAYS")
+        .ok()
+        .map(|v| {
+            v.parse()
+                .expect("USERS_RETENTION_DAYS must be a number of days")
+        })
+        .unwrap_or(RETENTION_DAYS);
+    let purge_svc = svc.clone();
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            match purge_svc
+                .purge_deleted_users(chrono::Duration::days(retention))
+                .await
+            {
+                Ok(purged) => log::info!("🧹 Purged {} deleted users", purged),
+                Err(e) => log::error!("🔥 Error purging the deleted users: {}", e),
+            }
+        }
+    });
+
+    // forgetting the buckets of the callers gone quiet
+    let sweep_limiter = rate_limiter.clone();
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = sweep_limiter.sweep().await {
+                log::error!("🔥 Error sweeping the rate limit buckets: {}", e);
+            }
+        }
+    });
+
+    // starting the gRPC front-end on top of the same service, without the rate limits of /v1
+    let grpc_users = grpc::UsersGrpc::new(svc.clone().into_inner(), authenticator.clone());
+    let grpc_address = format!("0.0.0.0:{}", GRPC_PORT)
+        .parse()
+        .expect("Invalid gRPC address");
+    actix_rt::spawn(async move {
+        log::info!("🚀 gRPC server started at port {}!", GRPC_PORT);
+        if let Err(e) = tonic::transport::Server::builder()
+            .add_service(grpc::UsersServiceServer::new(grpc_users))
+            .serve(grpc_address)
+            .await
+        {
+            log::error!("🔥 gRPC server stopped: {}", e);
+        }
+    });
+
+    // serving the metrics on their own port, if any, out of reach of the callers
+    if let Some(port) = metrics_port {
+        let registry = web::Data::new(prometheus.registry.clone());
+        let metrics_server = HttpServer::new(move || {
+            App::new()
+                .app_data(registry.clone())
+                .route("/metrics", web::get().to(metrics::endpoint))
+        })
+        .workers(1)
+        .bind(format!("0.0.0.0:{}", port))
+        .unwrap_or_else(|_| panic!("🔥 Couldn't start the metrics server at port {}", port))
+        .run();
+        actix_rt::spawn(async move {
+            log::info!("📈 Metrics server started at port {}!", port);
+            if let Err(e) = metrics_server.await {
+                log::error!("🔥 Metrics server stopped: {}", e);
+            }
+        });
+    }
+
     // starting the server
     HttpServer::new(move || {
         log::trace!("🚀 Server thread started at port {}!", PORT);
-        // cognito middleware
-        let cognito = Cognito::new(cognito_validator.clone());
         // cors middleware
-        let cors = Cors::default().allowed_methods(vec!["GET", "POST", "PUT", "DELETE"]);
+        let cors = Cors::default().allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"]);
 
         // set up the app
         App::new()
             .wrap(prometheus.clone())
-            .wrap(Logger::default())
+            .wrap(telemetry::access_log())
             .wrap(Compress::default())
             .wrap(cors)
+            // outermost, so the span covers the whole request
+            .wrap(telemetry::Tracing)
             .service(
-                web::scope("/v1")
-                    .wrap(cognito)
+                web::scope(v1::PATH)
+                    .wrap(rate_limiter.clone())
+                    .app_data(web::Data::new(authenticator.clone()))
                     .app_data(svc.clone())
-                    .configure(v1::api::<Rpts02Service<PostgresRepository>>),
+                    .configure(v1::api::<Rpts02Service<CachingRepository<PostgresRepository>>>),
             )
+            .app_data(health.clone())
             .configure(health::endpoint)
+            .configure(openapi::endpoint)
     })
     .bind(format!("0.0.0.0:{}", PORT))
     .unwrap_or_else(|_| panic!("🔥 Couldn't start the server at port {}", PORT))
@@ -0,0 +1,363 @@
+//! Metrics for Prometheus, besides the HTTP ones of `actix-web-prom`.
+//!
+//! They're all in the same registry, under the `rpts02_api` namespace:
+//! - `db_query_duration_seconds`, the queries of the repository by `operation` and `outcome`.
+//! - `db_pool_acquire_seconds`, the time the transactions of the queries waited for a connection.
+//! - `db_pool_connections` and `db_pool_idle_connections`, sampled from the pool.
+//! - `users_created_total`, `users_deleted_total` and `authorization_failures_total`, by `action`.
+//!
+//! `/metrics` is served along with the API, unless [METRICS_PORT] gives it a port of its own,
+//! which can be kept out of reach of the callers.
+use actix_web::{web, HttpResponse};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use sqlx::PgPool;
+use std::{env, fmt, time::Duration};
+use tracing as log;
+
+/// Prefix of every metric of the API.
+pub const NAMESPACE: &str = "rpts02_api";
+/// Time between two samples of the connection pool.
+pub const POOL_SAMPLE_INTERVAL: Duration = Duration::from_secs(15);
+/// Upper bounds of the buckets of the query durations, in seconds.
+const QUERY_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// The port serving `/metrics` apart from the API, if [METRICS_PORT] is set.
+pub fn port_from_env() -> Result<Option<u16>, String> {
+    env::var("METRICS_PORT")
+        .ok()
+        .map(|v| {
+            v.parse()
+                .map_err(|_| format!("METRICS_PORT must be a port number, not {}", v))
+        })
+        .transpose()
+}
+
+/// The metrics of the registry, in the text format of Prometheus.
+pub async fn endpoint(registry: web::Data<Registry>) -> HttpResponse {
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    match encoder.encode(&registry.gather(), &mut buffer) {
+        Ok(()) => HttpResponse::Ok()
+            .content_type(encoder.format_type())
+            .body(buffer),
+        Err(e) => {
+            log::error!("Unable to encode the metrics: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// How a query ended: `ok`, `not_found` if no row matched, or `error`.
+pub fn outcome<T>(result: &sqlx::Result<T>) -> &'static str {
+    match result {
+        Ok(_) => "ok",
+        Err(sqlx::Error::RowNotFound) => "not_found",
+        Err(_) => "error",
+    }
+}
+
+/// Times the queries of the repository, and their wait for a connection.
+#[derive(Clone)]
+pub struct QueryMetrics {
+    duration: HistogramVec,
+    acquire: Histogram,
+}
+
+impl QueryMetrics {
+    /// Registers the `rpts02_api_db_query_duration_seconds` histogram,
+    /// labeled with the `operation` and its `outcome`,
+    /// and the `rpts02_api_db_pool_acquire_seconds` one.
+    pub fn register(registry: &Registry) -> prometheus::Result<Self> {
+        let duration = HistogramVec::new(
+            HistogramOpts::new(
+                "db_query_duration_seconds",
+                "Time taken by the queries of the users, deserialization included",
+            )
+            .namespace(NAMESPACE)
+            .buckets(QUERY_BUCKETS.to_vec()),
+            &["operation", "outcome"],
+        )?;
+        let acquire = Histogram::with_opts(
+            HistogramOpts::new(
+                "db_pool_acquire_seconds",
+                "Time waited for a connection of the pool by the queries",
+            )
+            .namespace(NAMESPACE)
+            .buckets(QUERY_BUCKETS.to_vec()),
+        )?;
+        registry.register(Box::new(duration.clone()))?;
+        registry.register(Box::new(acquire.clone()))?;
+        Ok(Self { duration, acquire })
+    }
+
+    /// Observes the wait for a connection, whether one was acquired or the pool timed out.
+    pub fn acquired(&self, elapsed: Duration) {
+        self.acquire.observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe<T>(&self, operation: &str, result: &sqlx::Result<T>, elapsed: Duration) {
+        self.duration
+            .with_label_values(&[operation, outcome(result)])
+            .observe(elapsed.as_secs_f64());
+    }
+}
+
+impl fmt::Debug for QueryMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("QueryMetrics")
+    }
+}
+
+/// Gauges of the connection pool, sampled every [POOL_SAMPLE_INTERVAL].
+#[derive(Clone)]
+pub struct PoolMetrics {
+    connections: IntGauge,
+    idle: IntGauge,
+}
+
+impl PoolMetrics {
+    /// Registers the `rpts02_api_db_pool_connections` and `rpts02_api_db_pool_idle_connections`
+    /// gauges.
+    pub fn register(registry: &Registry) -> prometheus::Result<Self> {
+        let connections = IntGauge::with_opts(
+            Opts::new("db_pool_connections", "Connections open in the pool").namespace(NAMESPACE),
+        )?;
+        let idle = IntGauge::with_opts(
+            Opts::new(
+                "db_pool_idle_connections",
+                "Connections of the pool waiting to be used",
+            )
+            .namespace(NAMESPACE),
+        )?;
+        registry.register(Box::new(connections.clone()))?;
+        registry.register(Box::new(idle.clone()))?;
+        Ok(Self { connections, idle })
+    }
+
+    /// Samples the connections of the pool, without taking any.
+    pub fn sample(&self, pool: &PgPool) {
+        self.record(pool.size(), pool.num_idle());
+    }
+
+    fn record(&self, connections: u32, idle: usize) {
+        self.connections.set(i64::from(connections));
+        self.idle.set(idle as i64);
+    }
+}
+
+impl fmt::Debug for PoolMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PoolMetrics")
+    }
+}
+
+/// Counts what the callers do with the users.
+#[derive(Clone)]
+pub struct UserMetrics {
+    created: IntCounter,
+    deleted: IntCounter,
+    authorization_failures: IntCounterVec,
+}
+
+impl UserMetrics {
+    /// Registers the `rpts02_api_users_created_total`, `rpts02_api_users_deleted_total`
+    /// and `rpts02_api_authorization_failures_total` counters, the last one labeled with the `action`.
+    pub fn register(registry: &Registry) -> prometheus::Result<Self> {
+        let created = IntCounter::with_opts(
+            Opts::new(
+                "users_created_total",
+                "Users created, by the API or signing up",
+            )
+            .namespace(NAMESPACE),
+        )?;
+        let deleted = IntCounter::with_opts(
+            Opts::new("users_deleted_total", "Users deleted, before being purged")
+                .namespace(NAMESPACE),
+        )?;
+        let authorization_failures = IntCounterVec::new(
+            Opts::new(
+                "authorization_failures_total",
+                "Actions the callers weren't allowed to perform",
+            )
+            .namespace(NAMESPACE),
+            &["action"],
+        )?;
+        registry.register(Box::new(created.clone()))?;
+        registry.register(Box::new(deleted.clone()))?;
+        registry.register(Box::new(authorization_failures.clone()))?;
+        Ok(Self {
+            created,
+            deleted,
+            authorization_failures,
+        })
+    }
+
+    pub fn created(&self) {
+        self.created.inc();
+    }
+
+    pub fn deleted(&self) {
+        self.deleted.inc();
+    }
+
+    pub fn denied(&self, action: &str) {
+        self.authorization_failures
+            .with_label_values(&[action])
+            .inc();
+    }
+}
+
+impl fmt::Debug for UserMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("UserMetrics")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    /// The value of the metric with the labels, if it was ever recorded.
+    fn value(registry: &Registry, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        registry
+            .gather()
+            .into_iter()
+            .filter(|family| family.get_name() == name)
+            .flat_map(|family| family.get_metric().to_vec())
+            .find(|metric| {
+                labels.iter().all(|(name, value)| {
+                    metric
+                        .get_label()
+                        .iter()
+                        .any(|label| label.get_name() == *name && label.get_value() == *value)
+                })
+            })
+            .map(|metric| {
+                if metric.has_histogram() {
+                    metric.get_histogram().get_sample_count() as f64
+                } else if metric.has_gauge() {
+                    metric.get_gauge().get_value()
+                } else {
+                    metric.get_counter().get_value()
+                }
+            })
+    }
+
+    #[test]
+    fn queries_are_observed_by_outcome() {
+        let registry = Registry::new();
+        let metrics = QueryMetrics::register(&registry).unwrap();
+        let elapsed = Duration::from_millis(3);
+
+        metrics.observe("get", &Ok(()), elapsed);
+        metrics.observe("get", &Ok(()), elapsed);
+        metrics.observe::<()>("get", &Err(sqlx::Error::RowNotFound), elapsed);
+        metrics.observe::<()>("update", &Err(sqlx::Error::PoolTimedOut), elapsed);
+
+        let name = "rpts02_api_db_query_duration_seconds";
+        let count = |operation, outcome| {
+            value(
+                &registry,
+                name,
+                &[("operation", operation), ("outcome", outcome)],
+            )
+        };
+        assert_eq!(count("get", "ok"), Some(2.0));
+        assert_eq!(count("get", "not_found"), Some(1.0));
+        assert_eq!(count("update", "error"), Some(1.0));
+        assert_eq!(count("update", "ok"), None);
+    }
+
+    #[test]
+    fn waits_for_connections_are_observed() {
+        let registry = Registry::new();
+        let metrics = QueryMetrics::register(&registry).unwrap();
+
+        metrics.acquired(Duration::from_millis(2));
+        metrics.acquired(Duration::from_secs(30));
+
+        assert_eq!(
+            value(&registry, "rpts02_api_db_pool_acquire_seconds", &[]),
+            Some(2.0)
+        );
+    }
+
+    #[test]
+    fn pool_gauges_are_set() {
+        let registry = Registry::new();
+        let metrics = PoolMetrics::register(&registry).unwrap();
+
+        metrics.record(7, 2);
+
+        assert_eq!(
+            value(&registry, "rpts02_api_db_pool_connections", &[]),
+            Some(7.0)
+        );
+        assert_eq!(
+            value(&registry, "rpts02_api_db_pool_idle_connections", &[]),
+            Some(2.0)
+        );
+    }
+
+    #[test]
+    fn users_and_authorization_failures_are_counted() {
+        let registry = Registry::new();
+        let metrics = UserMetrics::register(&registry).unwrap();
+
+        metrics.created();
+        metrics.created();
+        metrics.deleted();
+        metrics.denied("delete");
+
+        assert_eq!(
+            value(&registry, "rpts02_api_users_created_total", &[]),
+            Some(2.0)
+        );
+        assert_eq!(
+            value(&registry, "rpts02_api_users_deleted_total", &[]),
+            Some(1.0)
+        );
+        assert_eq!(
+            value(
+                &registry,
+                "rpts02_api_authorization_failures_total",
+                &[("action", "delete")]
+            ),
+            Some(1.0)
+        );
+    }
+
+    #[test]
+    fn metrics_are_registered_once() {
+        let registry = Registry::new();
+        UserMetrics::register(&registry).unwrap();
+
+        assert!(UserMetrics::register(&registry).is_err());
+    }
+
+    #[actix_rt::test]
+    async fn endpoint_renders_the_registry() {
+        let registry = Registry::new();
+        UserMetrics::register(&registry).unwrap().created();
+        let mut app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(registry))
+                .route("/metrics", web::get().to(endpoint)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/metrics").to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert!(res.status().is_success());
+        let body = test::read_body(res).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(body.contains("rpts02_api_users_created_total 1"));
+    }
+}
@@ -1,33 +1,291 @@
+pub use rpts_domain::{CustomData, User, Validate, Violation};
+
+use crate::v1::listing::MAX_LIMIT;
 use chrono::{DateTime, NaiveDate, Utc};
+use rpts_domain::{redact::Sensitive, redacted_debug};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use sqlx::types::Json;
-use uuid::Uuid;
+use serde_json::Value;
+
+/// Changes to apply to any field of a user.
+#[derive(Clone, PartialEq)]
+pub enum UserPatch {
+    /// `application/merge-patch+json`, as in RFC 7396.
+    Merge(Value),
+    /// `application/json-patch+json`, as in RFC 6902.
+    Json(json_patch::Patch),
+}
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
-pub struct User {
-    pub id: Option<Uuid>,
-    pub name: String,
-    pub birth_date: NaiveDate,
-    pub created_at: Option<DateTime<Utc>>,
-    pub updated_at: Option<DateTime<Utc>>,
-    pub custom_data: Option<Json<CustomData>>,
+/// The values of the patches are as sensitive as the users.
+impl std::fmt::Debug for UserPatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UserPatch::Merge(patch) => f.debug_tuple("Merge").field(&Sensitive(patch)).finish(),
+            UserPatch::Json(patch) => f.debug_tuple("Json").field(&Sensitive(patch)).finish(),
+        }
+    }
 }
 
-impl Default for User {
+impl UserPatch {
+    pub const MERGE_CONTENT_TYPE: &'static str = "application/merge-patch+json";
+    pub const JSON_CONTENT_TYPE: &'static str = "application/json-patch+json";
+
+    /// Applies the patch to a copy of the user.
+    /// The id and timestamps are kept, as they're not for the callers to change.
+    pub fn apply(&self, user: &User) -> Result<User, String> {
+        let mut doc = serde_json::to_value(user).map_err(|e| e.to_string())?;
+        match self {
+            UserPatch::Merge(patch) => json_patch::merge(&mut doc, patch),
+            UserPatch::Json(patch) => {
+                json_patch::patch(&mut doc, patch).map_err(|e| e.to_string())?
+            }
+        }
+        let mut patched: User = serde_json::from_value(doc).map_err(|e| e.to_string())?;
+        patched.id = user.id;
+        patched.created_at = user.created_at;
+        patched.updated_at = user.updated_at;
+        patched.deleted_at = user.deleted_at;
+        Ok(patched)
+    }
+}
+
+/// Fields the users can be sorted by.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    Name,
+    CreatedAt,
+    BirthDate,
+}
+
+impl Default for SortField {
     fn default() -> Self {
-        Self {
-            id: None,
-            name: String::default(),
-            birth_date: NaiveDate::from_ymd(1977, 03, 10),
-            created_at: None,
-            updated_at: None,
-            custom_data: None,
+        SortField::CreatedAt
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        SortOrder::Asc
+    }
+}
+
+/// Query string of the users collection: `GET /v1/users`.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ListQuery {
+    /// Size of the page, 20 by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 1, max = "MAX_LIMIT"))]
+    pub limit: Option<u32>,
+    /// Opaque position returned in a previous page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    /// Field to sort by, `created_at` by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<SortField>,
+    /// Sort order, `asc` by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<SortOrder>,
+    /// Only the users whose name starts with it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name_prefix: Option<String>,
+    /// Inclusive lower bound of the birth date.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub born_after: Option<NaiveDate>,
+    /// Inclusive upper bound of the birth date.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub born_before: Option<NaiveDate>,
+    /// Adds the number of matching users.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub include_total: bool,
+    /// Lists the deleted users too. Only for admins.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub include_deleted: bool,
+}
+
+// the cursors hold the sort key of a user
+redacted_debug!(ListQuery {
+    limit,
+    #[sensitive]
+    cursor,
+    sort,
+    order,
+    #[sensitive]
+    name_prefix,
+    #[sensitive]
+    born_after,
+    #[sensitive]
+    born_before,
+    include_total,
+    include_deleted,
+});
+
+/// Query string of a single user: `GET /v1/users/{id}`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ReadQuery {
+    /// Finds the user even if it was deleted. Only for admins.
+    #[serde(default)]
+    pub include_deleted: bool,
+}
+
+/// Kinds of changes recorded in the history of a user.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Create,
+    Update,
+    Delete,
+    Restore,
+}
+
+impl Operation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operation::Create => "create",
+            Operation::Update => "update",
+            Operation::Delete => "delete",
+            Operation::Restore => "restore",
+        }
+    }
+}
+
+impl std::str::FromStr for Operation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "create" => Ok(Operation::Create),
+            "update" => Ok(Operation::Update),
+            "delete" => Ok(Operation::Delete),
+            "restore" => Ok(Operation::Restore),
+            _ => Err(format!("Unknown operation: {}", s)),
         }
     }
 }
 
-#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, Default)]
-#[serde(rename_all = "camelCase")]
-pub struct CustomData {
-    pub random: u32,
+/// A change of a user, as recorded in the audit trail.
+#[derive(Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AuditRecord {
+    pub id: i64,
+    pub user_id: uuid::Uuid,
+    /// Id of the caller who made the change, if authenticated.
+    pub actor: Option<String>,
+    pub operation: Operation,
+    /// The fields that changed, as `{"field": {"before": ..., "after": ...}}`.
+    pub changes: Value,
+    pub request_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+redacted_debug!(AuditRecord {
+    id,
+    user_id,
+    actor,
+    operation,
+    #[sensitive]
+    changes,
+    request_id,
+    created_at,
+});
+
+/// Query string of the history of a user: `GET /v1/users/{id}/history`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct HistoryQuery {
+    /// Size of the page, 20 by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 1, max = "MAX_LIMIT"))]
+    pub limit: Option<u32>,
+    /// Opaque position returned in a previous page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+}
+
+/// A page of the history of a user, the latest changes first.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct HistoryPage {
+    pub items: Vec<AuditRecord>,
+    pub next_cursor: Option<String>,
+}
+
+/// A page of the users collection.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct UserPage {
+    pub items: Vec<User>,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+    /// Users matching the filters, only if requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn user() -> User {
+        let mut user = User::default();
+        user.id = Some(uuid::Uuid::new_v4());
+        user.name = "Roberto".to_string();
+        user.custom_data = Some(CustomData { random: 10 });
+        user
+    }
+
+    #[test]
+    fn merge_patch_works() {
+        let user = user();
+        let patch = UserPatch::Merge(json!({"name": "Rob", "custom_data": null}));
+
+        let patched = patch.apply(&user).unwrap();
+
+        assert_eq!(patched.name, "Rob");
+        assert_eq!(patched.birth_date, user.birth_date);
+        assert_eq!(patched.custom_data, None);
+    }
+
+    #[test]
+    fn json_patch_works() {
+        let user = user();
+        let patch = serde_json::from_value(json!([
+            {"op": "test", "path": "/name", "value": "Roberto"},
+            {"op": "replace", "path": "/birth_date", "value": "1980-01-01"},
+            {"op": "replace", "path": "/custom_data/random", "value": 20}
+        ]))
+        .unwrap();
+
+        let patched = UserPatch::Json(patch).apply(&user).unwrap();
+
+        assert_eq!(patched.name, "Roberto");
+        assert_eq!(patched.birth_date.to_string(), "1980-01-01");
+        assert_eq!(patched.custom_data, Some(CustomData { random: 20 }));
+    }
+
+    #[test]
+    fn patches_keep_the_id() {
+        let user = user();
+        let patch = UserPatch::Merge(json!({"id": uuid::Uuid::new_v4()}));
+        assert_eq!(patch.apply(&user).unwrap().id, user.id);
+    }
+
+    #[test]
+    fn failed_json_patches_return_err() {
+        let patch = serde_json::from_value(json!([
+            {"op": "test", "path": "/name", "value": "Someone else"}
+        ]))
+        .unwrap();
+        assert!(UserPatch::Json(patch).apply(&user()).is_err());
+    }
+
+    #[test]
+    fn patches_resulting_in_invalid_users_return_err() {
+        let patch = UserPatch::Merge(json!({"birth_date": "not a date"}));
+        assert!(patch.apply(&user()).is_err());
+    }
 }
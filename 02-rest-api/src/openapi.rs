@@ -0,0 +1,623 @@
+//! OpenAPI 3 description of the REST API.
+//!
+//! The document is served at `/openapi.json` and browsed with the Swagger UI at `/docs`.
+//! The schemas of the models and the query parameters are derived from their types,
+//! and the tests check the paths against the routes of [v1::api], so it can't drift from the code.
+use crate::auth;
+use crate::models::{CustomData, HistoryPage, HistoryQuery, ListQuery, ReadQuery, User, UserPage};
+use crate::problem::{self, Problem};
+use crate::v1::{self, idempotency};
+use actix_web::{web, HttpResponse};
+use schemars::{gen::SchemaSettings, JsonSchema};
+use serde_json::{json, Map, Value};
+
+/// JSON schemas as in OpenAPI 3.0, with the optional fields as nullable.
+fn settings() -> SchemaSettings {
+    SchemaSettings::openapi3()
+}
+
+/// The schemas of the models in the components of the document,
+/// derived from their types along with the ones they refer to.
+fn schemas() -> Value {
+    let mut gen = settings().into_generator();
+    gen.subschema_for::<User>();
+    gen.subschema_for::<CustomData>();
+    gen.subschema_for::<UserPage>();
+    gen.subschema_for::<HistoryPage>();
+    gen.subschema_for::<Problem>();
+    json!(gen.take_definitions())
+}
+
+/// A reference to the schema of a model, which must be in [schemas].
+fn schema_ref<T: JsonSchema>() -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", T::schema_name()) })
+}
+
+/// The query parameters of the handlers reading a `web::Query<T>`, from the fields of `T`.
+fn query<T: JsonSchema>() -> Vec<Value> {
+    let schema = settings()
+        .with(|settings| settings.inline_subschemas = true)
+        .into_generator()
+        .into_root_schema_for::<T>()
+        .schema;
+    let object = schema.object.unwrap_or_default();
+    let required = object.required;
+    object
+        .properties
+        .into_iter()
+        .map(|(name, property)| {
+            let property = property.into_object();
+            let description = property
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.description.clone())
+                .unwrap_or_default();
+            json!({
+                "in": "query",
+                "name": name,
+                "required": required.contains(&name),
+                "schema": property,
+                "description": description
+            })
+        })
+        .collect()
+}
+
+fn json_body<T: JsonSchema>() -> Value {
+    json!({"required": true, "content": {"application/json": {"schema": schema_ref::<T>()}}})
+}
+
+fn problem(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {(problem::CONTENT_TYPE): {"schema": schema_ref::<Problem>()}}
+    })
+}
+
+/// A single user, tagged with its version.
+fn user(description: &str) -> Value {
+    json!({
+        "description": description,
+        "headers": {
+            "ETag": {"schema": {"type": "string"}, "description": "Version of the user"},
+            "Location": {"schema": {"type": "string"}}
+        },
+        "content": {"application/json": {"schema": schema_ref::<User>()}}
+    })
+}
+
+fn param(location: &str, name: &str, schema: Value, description: &str) -> Value {
+    json!({
+        "in": location,
+        "name": name,
+        "required": location == "path",
+        "schema": schema,
+        "description": description
+    })
+}
+
+fn user_id() -> Value {
+    param(
+        "path",
+        "id",
+        json!({"type": "string", "format": "uuid"}),
+        "Id of the user",
+    )
+}
+
+/// The parameters of the reads of a single user.
+fn read_parameters() -> Vec<Value> {
+    let mut parameters = query::<ReadQuery>();
+    parameters.push(param(
+        "header",
+        "If-None-Match",
+        json!({"type": "string"}),
+        "Skips the body if the user is still at this ETag",
+    ));
+    parameters
+}
+
+fn if_match() -> Value {
+    param(
+        "header",
+        "If-Match",
+        json!({"type": "string"}),
+        "Only writes the user if it's still at this ETag",
+    )
+}
+
+fn paths() -> Value {
+    let users = format!("{}/users", v1::PATH);
+    let user_path = format!("{}/{{id}}", users);
+    let mut paths = Map::new();
+    paths.insert(
+        users.clone(),
+        json!({
+            "get": {
+                "operationId": "listUsers",
+                "summary": "Lists a page of users",
+                "parameters": query::<ListQuery>(),
+                "responses": {
+                    "200": {
+                        "description": "A page of users",
+                        "headers": {
+                            "Link": {"schema": {"type": "string"}, "description": "Next and previous pages"},
+                            "X-Total-Count": {"schema": {"type": "integer"}, "description": "Only with include_total"}
+                        },
+                        "content": {"application/json": {"schema": schema_ref::<UserPage>()}}
+                    },
+                    "400": problem("Invalid query"),
+                    "401": problem("The caller can't list users")
+                }
+            },
+            "post": {
+                "operationId": "createUser",
+                "summary": "Creates a user",
+                "parameters": [
+                    param("header", idempotency::HEADER, json!({"type": "string", "maxLength": idempotency::MAX_KEY_LENGTH}), "Makes retries return the first response")
+                ],
+                "requestBody": json_body::<User>(),
+                "responses": {
+                    "201": user("The user was created"),
+                    "400": problem("Invalid body or idempotency key"),
+                    "409": problem("The name is taken"),
+                    "422": problem("Invalid user, or idempotency key used with another body")
+                }
+            }
+        }),
+    );
+    paths.insert(
+        user_path.clone(),
+        json!({
+            "parameters": [user_id()],
+            "get": {
+                "operationId": "getUser",
+                "summary": "Gets a user",
+                "parameters": read_parameters(),
+                "responses": {
+                    "200": user("The user"),
+                    "304": {"description": "The user didn't change"},
+                    "404": problem("The user doesn't exist, or the caller can't read it")
+                }
+            },
+            "put": {
+                "operationId": "replaceUser",
+                "summary": "Replaces every field of a user",
+                "parameters": [if_match()],
+                "requestBody": json_body::<User>(),
+                "responses": {
+                    "200": user("The user was replaced"),
+                    "401": problem("The caller can't write the user"),
+                    "404": problem("The user doesn't exist"),
+                    "409": problem("The name is taken"),
+                    "412": problem("The user changed since it was read"),
+                    "422": problem("Invalid user")
+                }
+            },
+            "patch": {
+                "operationId": "patchUser",
+                "summary": "Changes some fields of a user",
+                "description": "Plain JSON bodies only replace the custom_data.",
+                "parameters": [if_match()],
+                "requestBody": {
+                    "required": true,
+                    "content": {
+                        "application/json": {"schema": schema_ref::<CustomData>()},
+                        "application/merge-patch+json": {"schema": {"type": "object", "description": "RFC 7396"}},
+                        "application/json-patch+json": {"schema": {"type": "array", "items": {"type": "object"}, "description": "RFC 6902"}}
+                    }
+                },
+                "responses": {
+                    "200": user("The user was patched"),
+                    "401": problem("The caller can't write the user"),
+                    "404": problem("The user doesn't exist"),
+                    "409": problem("The name is taken"),
+                    "412": problem("The user changed since it was read"),
+                    "422": problem("Invalid patch or user")
+                }
+            },
+            "delete": {
+                "operationId": "deleteUser",
+                "summary": "Deletes a user, which can be restored until it's purged",
+                "parameters": [if_match()],
+                "responses": {
+                    "200": user("The user was deleted"),
+                    "401": problem("The caller can't write the user"),
+                    "404": problem("The user doesn't exist"),
+                    "412": problem("The user changed since it was read")
+                }
+            }
+        }),
+    );
+    paths.insert(
+        format!("{}/restore", user_path),
+        json!({
+            "parameters": [user_id()],
+            "post": {
+                "operationId": "restoreUser",
+                "summary": "Restores a deleted user",
+                "responses": {
+                    "200": user("The user was restored"),
+                    "401": problem("Only the user and the admins can restore it"),
+                    "404": problem("There's no deleted user with this id"),
+                    "409": problem("The name was taken meanwhile")
+                }
+            }
+        }),
+    );
+    paths.insert(
+        format!("{}/history", user_path),
+        json!({
+            "parameters": [user_id()],
+            "get": {
+                "operationId": "getUserHistory",
+                "summary": "Lists the changes of a user, the latest first",
+                "parameters": query::<HistoryQuery>(),
+                "responses": {
+                    "200": {
+                        "description": "A page of the history",
+                        "content": {"application/json": {"schema": schema_ref::<HistoryPage>()}}
+                    },
+                    "400": problem("Invalid query"),
+                    "404": problem("The user doesn't exist, or the caller can't read it")
+                }
+            }
+        }),
+    );
+    let patch = paths[&user_path]["patch"].clone();
+    paths.insert(
+        format!("{}/me", v1::PATH),
+        json!({
+            "get": {
+                "operationId": "getMe",
+                "summary": "Gets the user of the caller, whose id is the subject of the caller",
+                "parameters": read_parameters(),
+                "responses": {
+                    "200": user("The user of the caller"),
+                    "304": {"description": "The user didn't change"},
+                    "404": problem("The caller didn't sign up, or can't have a user")
+                }
+            },
+            "post": {
+                "operationId": "signUp",
+                "summary": "Creates the user of the caller, with the subject of the caller as id",
+                "requestBody": json_body::<User>(),
+                "responses": {
+                    "201": user("The caller signed up"),
+                    "400": problem("Invalid body"),
+                    "401": problem("The caller can't have a user, like the API keys"),
+                    "409": problem("The caller already signed up, or the name is taken"),
+                    "422": problem("Invalid user")
+                }
+            },
+            // the same bodies and responses as the patch of any user
+            "patch": {
+                "operationId": "patchMe",
+                "summary": "Changes some fields of the user of the caller",
+                "description": patch["description"],
+                "parameters": [if_match()],
+                "requestBody": patch["requestBody"],
+                "responses": patch["responses"]
+            },
+            "delete": {
+                "operationId": "deleteMe",
+                "summary": "Deletes the user of the caller, which can be restored until it's purged",
+                "parameters": [if_match()],
+                "responses": {
+                    "200": user("The user was deleted"),
+                    "404": problem("The caller didn't sign up, or can't have a user"),
+                    "412": problem("The user changed since it was read")
+                }
+            }
+        }),
+    );
+    Value::Object(paths)
+}
+
+/// The OpenAPI document of the API.
+pub fn document() -> Value {
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "rpts02",
+            "description": "Users of the Rust practical sessions",
+            "version": env!("CARGO_PKG_VERSION")
+        },
+        "paths": paths(),
+        "components": {
+            "schemas": schemas(),            "securitySchemes": {
+                "bearer": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "bearerFormat": "JWT",
+                    "description": "Token of the Cognito user pool or the OpenID Connect provider"
+                },
+                "apiKey": {
+                    "type": "apiKey",
+                    "in": "header",
+                    "name": auth::API_KEY_HEADER,
+                    "description": "Static API key, when the `api_keys` provider is configured"
+                }
+            }
+        },
+        "security": [{"bearer": []}, {"apiKey": []}]
+    })
+}
+
+/// The Swagger UI page. Its assets are vendored from `swagger-ui-dist` 5.17.14 in
+/// `assets/swagger-ui` and served by the API itself, so the page loads nothing from another origin.
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8" />
+  <title>rpts02</title>
+  <link rel="stylesheet" href="/docs/swagger-ui.css" />
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="/docs/swagger-ui-bundle.js"></script>
+  <script>
+    window.onload = () => {
+      window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui", validatorUrl: null });
+    };
+  </script>
+</body>
+</html>
+"##;
+
+const SWAGGER_UI_CSS: &str = include_str!("../assets/swagger-ui/swagger-ui.css");
+const SWAGGER_UI_BUNDLE: &str = include_str!("../assets/swagger-ui/swagger-ui-bundle.js");
+
+/// OpenAPI document and Swagger UI endpoints
+pub fn endpoint(cfg: &mut web::ServiceConfig) {
+    cfg.route("/openapi.json", web::get().to(openapi_json))
+        .route("/docs", web::get().to(swagger_ui))
+        .route("/docs/swagger-ui.css", web::get().to(swagger_ui_css))
+        .route(
+            "/docs/swagger-ui-bundle.js",
+            web::get().to(swagger_ui_bundle),
+        );
+}
+
+async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(document())
+}
+
+async fn swagger_ui() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(SWAGGER_UI)
+}
+
+async fn swagger_ui_css() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/css; charset=utf-8")
+        .body(SWAGGER_UI_CSS)
+}
+
+async fn swagger_ui_bundle() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/javascript; charset=utf-8")
+        .body(SWAGGER_UI_BUNDLE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{AuditRecord, Operation, Violation};
+    use crate::v1::mocks::MockSvc;
+    use actix_web::{http::StatusCode, test, App};
+    use std::collections::BTreeSet;
+
+    /// The routes as `METHOD /path`, ignoring the trailing slashes.
+    fn operations(routes: Vec<(String, String)>) -> BTreeSet<String> {
+        routes
+            .into_iter()
+            .map(|(method, path)| format!("{} {}", method, path.trim_end_matches('/')))
+            .collect()
+    }
+
+    fn documented() -> BTreeSet<String> {
+        let doc = document();
+        let routes = doc["paths"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .flat_map(|(path, item)| {
+                item.as_object()
+                    .unwrap()
+                    .keys()
+                    .filter(|key| *key != "parameters")
+                    .map(move |method| (method.to_uppercase(), path.clone()))
+            })
+            .collect();
+        operations(routes)
+    }
+
+    fn served() -> BTreeSet<String> {
+        let routes = v1::routes::<MockSvc>()
+            .into_iter()
+            .map(|(method, path)| (method.to_string(), path))
+            .collect();
+        operations(routes)
+    }
+
+    /// The fields of a model, as serialized.
+    fn fields(value: &impl serde::Serialize) -> BTreeSet<String> {
+        serde_json::to_value(value)
+            .unwrap()
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    fn properties<T: JsonSchema>() -> BTreeSet<String> {
+        schemas()[T::schema_name()]["properties"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn every_route_is_documented() {
+        let undocumented: Vec<_> = served().difference(&documented()).cloned().collect();
+        assert!(
+            undocumented.is_empty(),
+            "Undocumented routes: {:?}",
+            undocumented
+        );
+    }
+
+    #[test]
+    fn every_documented_route_exists() {
+        let missing: Vec<_> = documented().difference(&served()).cloned().collect();
+        assert!(
+            missing.is_empty(),
+            "Documented routes not served: {:?}",
+            missing
+        );
+    }
+
+    /// The targets of the `$ref` in a part of the document.
+    fn references(value: &Value) -> BTreeSet<String> {
+        match value {
+            Value::Object(object) => object
+                .iter()
+                .flat_map(|(key, value)| match (key.as_str(), value) {
+                    ("$ref", Value::String(target)) => vec![target.clone()].into_iter().collect(),
+                    _ => references(value),
+                })
+                .collect(),
+            Value::Array(values) => values.iter().flat_map(references).collect(),
+            _ => BTreeSet::new(),
+        }
+    }
+
+    #[test]
+    fn every_reference_has_a_schema() {
+        let doc = document();
+        let dangling: Vec<_> = references(&doc)
+            .into_iter()
+            .filter(|target| {
+                let name = target.trim_start_matches("#/components/schemas/");
+                doc["components"]["schemas"].get(name).is_none()
+            })
+            .collect();
+        assert!(dangling.is_empty(), "Missing schemas: {:?}", dangling);
+    }
+
+    #[test]
+    fn query_parameters_are_the_fields_of_the_queries() {
+        let names = |parameters: Vec<Value>| -> BTreeSet<String> {
+            parameters
+                .iter()
+                .map(|parameter| parameter["name"].as_str().unwrap().to_string())
+                .collect()
+        };
+        let list = ListQuery {
+            limit: Some(1),
+            cursor: Some("cursor".to_string()),
+            sort: Some(crate::models::SortField::Name),
+            order: Some(crate::models::SortOrder::Desc),
+            name_prefix: Some("Rob".to_string()),
+            born_after: Some(chrono::NaiveDate::from_ymd(1977, 3, 10)),
+            born_before: Some(chrono::NaiveDate::from_ymd(1977, 3, 10)),
+            include_total: true,
+            include_deleted: true,
+        };
+
+        assert_eq!(names(query::<ListQuery>()), fields(&list));
+        assert_eq!(names(query::<ReadQuery>()), fields(&ReadQuery::default()));
+        let doc = document();
+        let parameters = doc["paths"]["/v1/users"]["get"]["parameters"]
+            .as_array()
+            .unwrap();
+        let limit = parameters.iter().find(|p| p["name"] == "limit").unwrap();
+        assert_eq!(
+            limit["schema"]["maximum"].as_f64(),
+            Some(f64::from(v1::listing::MAX_LIMIT))
+        );
+    }
+
+    #[test]
+    fn schemas_have_the_fields_of_the_models() {
+        let mut user = User::default();
+        user.id = Some(uuid::Uuid::new_v4());
+        user.created_at = Some(chrono::Utc::now());
+        user.updated_at = user.created_at;
+        user.custom_data = Some(CustomData { random: 1 });
+        user.deleted_at = user.created_at;
+        let record = AuditRecord {
+            id: 1,
+            user_id: uuid::Uuid::new_v4(),
+            actor: None,
+            operation: Operation::Create,
+            changes: json!({}),
+            request_id: None,
+            created_at: chrono::Utc::now(),
+        };
+        let page = UserPage {
+            total: Some(1),
+            ..UserPage::default()
+        };
+        let violation = Violation {
+            field: "name".to_string(),
+            message: "is required".to_string(),
+        };
+        let mut problem = Problem::from(crate::v1::service::ServiceError::Unauthorized);
+        problem.instance = Some("/v1/users".to_string());
+        problem.request_id = Some("request".to_string());
+        problem.trace_id = Some("trace".to_string());
+        problem.violations = vec![violation.clone()];
+
+        assert_eq!(fields(&user), properties::<User>());
+        assert_eq!(fields(&CustomData::default()), properties::<CustomData>());
+        assert_eq!(fields(&page), properties::<UserPage>());
+        assert_eq!(fields(&record), properties::<AuditRecord>());
+        assert_eq!(fields(&HistoryPage::default()), properties::<HistoryPage>());
+        assert_eq!(fields(&violation), properties::<Violation>());
+        assert_eq!(fields(&problem), properties::<Problem>());
+    }
+
+    #[actix_rt::test]
+    async fn openapi_endpoints_integration_works() {
+        let mut app = test::init_service(App::new().configure(endpoint)).await;
+
+        let req = test::TestRequest::get().uri("/openapi.json").to_request();
+        let doc: Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(doc["openapi"], "3.0.3");
+        assert!(doc["components"]["securitySchemes"]["bearer"].is_object());
+        assert!(doc["components"]["securitySchemes"]["apiKey"].is_object());
+
+        let req = test::TestRequest::get().uri("/docs").to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn swagger_ui_assets_are_served_by_the_api() {
+        let mut app = test::init_service(App::new().configure(endpoint)).await;
+        assert!(
+            !SWAGGER_UI.contains("//"),
+            "The page loads assets from another origin"
+        );
+
+        for (uri, content_type) in &[
+            ("/docs/swagger-ui.css", "text/css; charset=utf-8"),
+            (
+                "/docs/swagger-ui-bundle.js",
+                "application/javascript; charset=utf-8",
+            ),
+        ] {
+            assert!(SWAGGER_UI.contains(&format!("\"{}\"", uri)));
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let res = test::call_service(&mut app, req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers().get("content-type").unwrap(), content_type);
+        }
+    }
+}
@@ -0,0 +1,319 @@
+//! Errors of the REST API, rendered as `application/problem+json` (RFC 7807).
+use crate::models::Violation;
+use crate::telemetry::TraceId;
+use crate::v1::service::{ServiceError, USER_NOT_FOUND};
+use actix_web::{
+    error::{JsonPayloadError, PathError, QueryPayloadError},
+    http::{HeaderMap, StatusCode},
+    HttpMessage, HttpRequest, HttpResponse, ResponseError,
+};
+use rpts_domain::DomainError;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+pub const CONTENT_TYPE: &str = "application/problem+json";
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+pub type Result<T> = std::result::Result<T, Problem>;
+
+/// The single error type of the handlers, as RFC 7807 problem details.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Problem {
+    /// URI reference identifying the kind of problem, like `/problems/not-found`.
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// Path of the request that caused the problem.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Id of the trace of the request, to find it in the tracing backend.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+    /// The fields at fault, for the validation errors.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<Violation>,
+}
+
+impl Problem {
+    pub fn new(status: StatusCode, kind: &str, detail: impl ToString) -> Self {
+        Self {
+            problem_type: format!("/problems/{}", kind),
+            title: status.canonical_reason().unwrap_or("Error").to_string(),
+            status: status.as_u16(),
+            detail: Some(detail.to_string()),
+            instance: None,
+            request_id: None,
+            trace_id: None,
+            violations: vec![],
+        }
+    }
+
+    /// Points the problem to the request that caused it.
+    pub fn for_request(mut self, req: &HttpRequest) -> Self {
+        self.instance = Some(req.path().to_string());
+        self.request_id = Some(request_id(req));
+        self.trace_id = req
+            .extensions()
+            .get::<TraceId>()
+            .map(|TraceId(trace_id)| trace_id.clone());
+        self
+    }
+
+    /// Error handler for the JSON payloads.
+    pub fn from_json(err: JsonPayloadError, req: &HttpRequest) -> actix_web::Error {
+        let problem = match err {
+            JsonPayloadError::ContentType => Problem::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported-media-type",
+                "The body must be JSON",
+            ),
+            JsonPayloadError::Overflow => {
+                Problem::new(StatusCode::PAYLOAD_TOO_LARGE, "payload-too-large", err)
+            }
+            _ => Problem::new(StatusCode::BAD_REQUEST, "invalid-body", err),
+        };
+        problem.for_request(req).into()
+    }
+
+    /// Error handler for the path parameters, like the user ids.
+    pub fn from_path(err: PathError, req: &HttpRequest) -> actix_web::Error {
+        Problem::new(StatusCode::NOT_FOUND, "invalid-path", err)
+            .for_request(req)
+            .into()
+    }
+
+    /// Error handler for the query strings.
+    pub fn from_query(err: QueryPayloadError, req: &HttpRequest) -> actix_web::Error {
+        Problem::new(StatusCode::BAD_REQUEST, "invalid-query", err)
+            .for_request(req)
+            .into()
+    }
+}
+
+/// The id of a request, assigned once by the [Tracing](crate::telemetry::Tracing) middleware
+/// and kept in its extensions, so its logs, problems and audit records share it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestId(pub String);
+
+/// Longest request id taken from the callers.
+pub const REQUEST_ID_MAX_LEN: usize = 128;
+
+impl RequestId {
+    /// The id set by the client or a proxy, if it's a valid one, or a new one otherwise.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(Self::parse)
+            .unwrap_or_else(Self::generate)
+    }
+
+    /// The id sent by a caller, unless it's longer than [REQUEST_ID_MAX_LEN] or has other
+    /// characters than letters, digits and `._-`, since it ends up in the logs and the audit.
+    pub fn parse(id: &str) -> Option<Self> {
+        let is_valid_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-');
+        Some(id)
+            .filter(|id| !id.is_empty() && id.len() <= REQUEST_ID_MAX_LEN)
+            .filter(|id| id.chars().all(is_valid_char))
+            .map(|id| Self(id.to_string()))
+    }
+
+    /// A new random id.
+    pub fn generate() -> Self {
+        Self(uuid::Uuid::new_v4().to_string())
+    }
+}
+
+/// The id of the request, as assigned by the middleware, or read from its headers without it.
+pub fn request_id(req: &HttpRequest) -> String {
+    match req.extensions().get::<RequestId>() {
+        Some(RequestId(id)) => id.clone(),
+        None => RequestId::from_headers(req.headers()).0,
+    }
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.detail {
+            Some(detail) => write!(f, "{}: {}", self.title, detail),
+            None => f.write_str(&self.title),
+        }
+    }
+}
+
+impl ResponseError for Problem {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        response.content_type(CONTENT_TYPE);
+        if let Some(request_id) = &self.request_id {
+            response.header(REQUEST_ID_HEADER, request_id.as_str());
+        }
+        if self.status_code() == StatusCode::SERVICE_UNAVAILABLE {
+            // the conflicting transaction is usually done by then
+            response.header("Retry-After", "1");
+        }
+        response.json(self)
+    }
+}
+
+impl From<ServiceError> for Problem {
+    fn from(err: ServiceError) -> Self {
+        let (status, kind) = match &err {
+            ServiceError::Unauthorized => (StatusCode::UNAUTHORIZED, "unauthorized"),
+            ServiceError::DuplicateName => (StatusCode::CONFLICT, "duplicate-name"),
+            ServiceError::DbError(sqlx::Error::RowNotFound) => {
+                return Problem::new(StatusCode::NOT_FOUND, "not-found", USER_NOT_FOUND);
+            }
+            ServiceError::DbError(_) => {
+                // the details of the database are not for the callers to know
+                return Problem::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "database-error",
+                    "Database Error",
+                );
+            }
+            ServiceError::InvalidUser(err) => return validation_failed(err),
+            ServiceError::InvalidQuery(_) => (StatusCode::BAD_REQUEST, "invalid-query"),
+            ServiceError::InvalidPatch(_) => (StatusCode::UNPROCESSABLE_ENTITY, "invalid-patch"),
+            ServiceError::PreconditionFailed => {
+                (StatusCode::PRECONDITION_FAILED, "precondition-failed")
+            }
+            ServiceError::ForeignKeyViolation => (StatusCode::CONFLICT, "foreign-key-violation"),
+            ServiceError::CheckViolation => (StatusCode::UNPROCESSABLE_ENTITY, "check-violation"),
+            ServiceError::SerializationFailure => {
+                (StatusCode::SERVICE_UNAVAILABLE, "serialization-failure")
+            }
+            ServiceError::IdempotencyKeyReused => {
+                (StatusCode::UNPROCESSABLE_ENTITY, "idempotency-key-reused")
+            }
+            ServiceError::AlreadySignedUp => (StatusCode::CONFLICT, "already-signed-up"),
+        };
+        Problem::new(status, kind, err)
+    }
+}
+
+impl From<DomainError> for Problem {
+    fn from(err: DomainError) -> Self {
+        validation_failed(&err)
+    }
+}
+
+fn validation_failed(err: &DomainError) -> Problem {
+    Problem {
+        violations: err.violations(),
+        ..Problem::new(StatusCode::UNPROCESSABLE_ENTITY, "validation-failed", err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{dev::Body, test::TestRequest};
+
+    #[test]
+    fn for_request_uses_the_request_id_header() {
+        let req = TestRequest::with_uri("/v1/users/1")
+            .header(REQUEST_ID_HEADER, "my-request")
+            .to_http_request();
+        let problem = Problem::new(StatusCode::NOT_FOUND, "not-found", "Nope").for_request(&req);
+
+        assert_eq!(problem.instance.as_deref(), Some("/v1/users/1"));
+        assert_eq!(problem.request_id.as_deref(), Some("my-request"));
+    }
+
+    #[test]
+    fn request_ids_of_the_callers_are_checked() {
+        assert_eq!(
+            RequestId::parse("my-request_1.2"),
+            Some(RequestId("my-request_1.2".to_string()))
+        );
+        assert_eq!(RequestId::parse(""), None);
+        assert_eq!(RequestId::parse("my request"), None);
+        assert_eq!(RequestId::parse("my-request\",\"admin\":true"), None);
+        assert_eq!(RequestId::parse(&"a".repeat(REQUEST_ID_MAX_LEN + 1)), None);
+    }
+
+    #[test]
+    fn for_request_replaces_invalid_request_ids() {
+        let req = TestRequest::default()
+            .header(REQUEST_ID_HEADER, "not a valid id")
+            .to_http_request();
+        let problem = Problem::new(StatusCode::NOT_FOUND, "not-found", "Nope").for_request(&req);
+
+        let request_id = problem.request_id.unwrap();
+        assert_ne!(request_id, "not a valid id");
+        assert!(RequestId::parse(&request_id).is_some());
+    }
+
+    #[test]
+    fn for_request_generates_a_request_id() {
+        let req = TestRequest::default().to_http_request();
+        let problem = Problem::new(StatusCode::NOT_FOUND, "not-found", "Nope").for_request(&req);
+        assert!(problem.request_id.is_some());
+    }
+
+    #[test]
+    fn error_response_renders_problem_json() {
+        let problem = Problem::from(ServiceError::PreconditionFailed);
+        let mut res = problem.error_response();
+
+        let content_type = res.headers().get("Content-Type").unwrap().clone();
+        let body = res
+            .take_body()
+            .as_ref()
+            .map(|b| match b {
+                Body::Bytes(x) => serde_json::from_slice::<'_, serde_json::Value>(x).ok(),
+                _ => None,
+            })
+            .flatten()
+            .unwrap();
+
+        assert_eq!(res.status().as_u16(), 412);
+        assert_eq!(content_type, CONTENT_TYPE);
+        assert_eq!(body["type"], "/problems/precondition-failed");
+        assert_eq!(body["title"], "Precondition Failed");
+        assert_eq!(body["status"], 412);
+        assert_eq!(body["detail"], ServiceError::PreconditionFailed.to_string());
+    }
+
+    #[test]
+    fn database_errors_are_not_detailed() {
+        let problem = Problem::from(ServiceError::DbError(sqlx::Error::PoolTimedOut));
+        assert_eq!(problem.status, 500);
+        assert_eq!(problem.detail.as_deref(), Some("Database Error"));
+    }
+
+    #[test]
+    fn invalid_users_list_the_violations() {
+        let err = DomainError::MissingField("name");
+        let problem = Problem::from(ServiceError::InvalidUser(err));
+
+        assert_eq!(problem.status, 422);
+        assert_eq!(problem.problem_type, "/problems/validation-failed");
+        assert_eq!(problem.violations.len(), 1);
+        assert_eq!(problem.violations[0].field, "name");
+    }
+
+    #[test]
+    fn serialization_failures_can_be_retried() {
+        let res = Problem::from(ServiceError::SerializationFailure).error_response();
+        assert_eq!(res.status().as_u16(), 503);
+        assert_eq!(res.headers().get("Retry-After").unwrap(), "1");
+    }
+
+    #[test]
+    fn json_errors_are_problems() {
+        let req = TestRequest::default().to_http_request();
+        let err = Problem::from_json(JsonPayloadError::ContentType, &req);
+        assert_eq!(err.as_response_error().status_code().as_u16(), 415);
+    }
+}
@@ -0,0 +1,61 @@
+use super::{Bucket, Decision, Limit, RateLimitStore};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::{collections::HashMap, sync::Mutex};
+
+/// Keeps the buckets in the memory of the instance, so each instance limits on its own.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryStore {
+    async fn take(&self, key: &str, limit: &Limit) -> sqlx::Result<Decision> {
+        let now = Utc::now();
+        let mut buckets = self.buckets.lock().expect("Poisoned rate limit buckets");
+        Ok(buckets
+            .entry(key.to_string())
+            .or_insert_with(|| Bucket::full(limit, now))
+            .take(limit, now))
+    }
+
+    async fn sweep(&self, unused_since: DateTime<Utc>) -> sqlx::Result<u64> {
+        let mut buckets = self.buckets.lock().expect("Poisoned rate limit buckets");
+        let before = buckets.len();
+        buckets.retain(|_, bucket| bucket.updated_at >= unused_since);
+        Ok((before - buckets.len()) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn memory_store_keeps_a_bucket_per_key() {
+        let store = MemoryStore::default();
+        let limit: Limit = "1/60".parse().unwrap();
+
+        let first = store.take("a", &limit).await.unwrap();
+        let second = store.take("a", &limit).await.unwrap();
+        let other = store.take("b", &limit).await.unwrap();
+
+        assert!(first.allowed);
+        assert!(!second.allowed);
+        assert!(other.allowed);
+    }
+
+    #[actix_rt::test]
+    async fn memory_store_sweeps_the_unused_buckets() {
+        let store = MemoryStore::default();
+        let limit: Limit = "1/60".parse().unwrap();
+        store.take("a", &limit).await.unwrap();
+
+        let kept = store.sweep(Utc::now() - chrono::Duration::minutes(1)).await;
+        let swept = store.sweep(Utc::now() + chrono::Duration::minutes(1)).await;
+
+        assert_eq!(kept.unwrap(), 0);
+        assert_eq!(swept.unwrap(), 1);
+    }
+}
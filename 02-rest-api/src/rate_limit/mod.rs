@@ -0,0 +1,665 @@
+//! Rate limiting of the callers of the API, with token buckets.
+//!
+//! Every caller has a bucket per [Rule], keyed by its tenant and id, or by its IP address
+//! when it isn't authenticated. Each request takes a token out of the bucket, which
+//! is refilled at the pace of the [Limit] of the rule. Requests to an empty bucket
+//! are answered with `429 Too Many Requests`.
+//!
+//! Every response carries the `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset`
+//! headers of the bucket, and the rejected ones a `Retry-After` too.
+//!
+//! The buckets are kept in memory, or in Postgres to share them between the instances
+//! of the API, as picked by the `RATE_LIMIT_STORE` env var.
+mod memory;
+mod postgres;
+
+use crate::auth::{Authenticator, Credentials};
+use crate::problem::Problem;
+use actix_web::{
+    dev::{Body, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    Error, HttpMessage, ResponseError,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use sqlx::PgPool;
+use std::{
+    cell::RefCell,
+    env,
+    net::SocketAddr,
+    rc::Rc,
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+pub use memory::MemoryStore;
+pub use postgres::PostgresStore;
+
+/// Limit of the routes without a rule, unless `RATE_LIMIT_DEFAULT` says otherwise.
+pub const DEFAULT_LIMIT: &str = "100/60";
+
+/// A number of requests allowed per period, at once or spread over the period.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limit {
+    pub requests: u32,
+    pub period: Duration,
+}
+
+impl Limit {
+    /// The tokens refilled per second.
+    fn rate(&self) -> f64 {
+        f64::from(self.requests) / self.period.as_secs_f64()
+    }
+}
+
+/// Parses limits like `100/60`, for 100 requests every 60 seconds.
+impl FromStr for Limit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid limit {}, expected requests/seconds", s);
+        let (requests, seconds) = match s.trim().splitn(2, '/').collect::<Vec<_>>()[..] {
+            [requests, seconds] => (requests, seconds),
+            _ => return Err(invalid()),
+        };
+        let requests: u32 = requests.parse().map_err(|_| invalid())?;
+        let seconds: u64 = seconds.parse().map_err(|_| invalid())?;
+        if requests == 0 || seconds == 0 {
+            return Err(invalid());
+        }
+        Ok(Self {
+            requests,
+            period: Duration::from_secs(seconds),
+        })
+    }
+}
+
+/// The limit of the requests with a method, if any, to the paths starting with a prefix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub method: Option<Method>,
+    pub path: String,
+    pub limit: Limit,
+}
+
+impl Rule {
+    /// Whether the rule applies to the request.
+    /// The path of the rule matches itself and the paths below it, `/v1/users` matches
+    /// `/v1/users/1` but not `/v1/users_export`.
+    fn matches(&self, method: &Method, path: &str) -> bool {
+        let below = match path.strip_prefix(self.path.as_str()) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/') || self.path.ends_with('/'),
+            None => false,
+        };
+        self.method.as_ref().map_or(true, |m| m == method) && below
+    }
+
+    /// Name of the rule in the keys of its buckets.
+    fn name(&self) -> String {
+        match &self.method {
+            Some(method) => format!("{} {}", method, self.path),
+            None => format!("* {}", self.path),
+        }
+    }
+}
+
+/// Parses rules like `POST /v1/users=10/60`, or `* /v1/me=30/60` for every method.
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid rule {}, expected METHOD /path=requests/seconds", s);
+        let (route, limit) = match s.trim().splitn(2, '=').collect::<Vec<_>>()[..] {
+            [route, limit] => (route, limit),
+            _ => return Err(invalid()),
+        };
+        let (method, path) = match route.split_whitespace().collect::<Vec<_>>()[..] {
+            ["*", path] => (None, path),
+            [method, path] => (
+                Some(Method::from_str(&method.to_uppercase()).map_err(|_| invalid())?),
+                path,
+            ),
+            _ => return Err(invalid()),
+        };
+        Ok(Self {
+            method,
+            path: path.to_string(),
+            limit: limit.parse()?,
+        })
+    }
+}
+
+/// The outcome of taking a token out of a bucket.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    /// Tokens left in the bucket.
+    pub remaining: u32,
+    /// Seconds until the bucket is full again.
+    pub reset: u64,
+    /// Seconds until the next token, for the rejected requests.
+    pub retry_after: Option<u64>,
+}
+
+/// The tokens of a caller, as they were the last time it made a request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bucket {
+    pub tokens: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Bucket {
+    pub fn full(limit: &Limit, now: DateTime<Utc>) -> Self {
+        Self {
+            tokens: f64::from(limit.requests),
+            updated_at: now,
+        }
+    }
+
+    /// Refills the bucket for the time elapsed since it was last used,
+    /// and takes a token out of it if there's any left.
+    pub fn take(&mut self, limit: &Limit, now: DateTime<Utc>) -> Decision {
+        // the clocks of the instances can be a bit off
+        let elapsed = (now - self.updated_at).to_std().unwrap_or_default();
+        let capacity = f64::from(limit.requests);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * limit.rate()).min(capacity);
+        self.updated_at = self.updated_at.max(now);
+        let allowed = self.tokens >= 1.0;
+        if allowed {
+            self.tokens -= 1.0;
+        }
+        let seconds = |tokens: f64| (tokens * limit.period.as_secs_f64() / capacity).ceil() as u64;
+        Decision {
+            allowed,
+            limit: limit.requests,
+            remaining: self.tokens.floor() as u32,
+            reset: seconds(capacity - self.tokens),
+            retry_after: if allowed {
+                None
+            } else {
+                Some(seconds(1.0 - self.tokens))
+            },
+        }
+    }
+}
+
+/// Keeps the buckets of the callers.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync + std::fmt::Debug {
+    /// Takes a token out of the bucket of the key, which starts full.
+    async fn take(&self, key: &str, limit: &Limit) -> sqlx::Result<Decision>;
+    /// Forgets the buckets unused since then, which are full again anyway.
+    /// Returns how many buckets were removed.
+    async fn sweep(&self, unused_since: DateTime<Utc>) -> sqlx::Result<u64>;
+}
+
+/// Middleware limiting the requests of each caller to the routes it wraps.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    /// The first rule matching a request applies, or the default limit if none does.
+    rules: Arc<Vec<Rule>>,
+    default: Limit,
+    /// The middleware runs before the scope data is available, so it has its own.
+    authenticator: Authenticator,
+    /// Whether the client IP can be read from the `Forwarded` and `X-Forwarded-For` headers.
+    trust_proxy: bool,
+    enabled: bool,
+}
+
+impl RateLimiter {
+    pub fn new(
+        store: impl RateLimitStore + 'static,
+        rules: Vec<Rule>,
+        default: Limit,
+        authenticator: Authenticator,
+    ) -> Self {
+        Self {
+            store: Arc::new(store),
+            rules: Arc::new(rules),
+            default,
+            authenticator,
+            trust_proxy: false,
+            enabled: true,
+        }
+    }
+
+    /// Lets every request through.
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::new(
+                MemoryStore::default(),
+                vec![],
+                DEFAULT_LIMIT.parse().unwrap(),
+                Authenticator::disabled(),
+            )
+        }
+    }
+
+    /// Reads the client IP from the headers of the proxies, which must be trusted to set them.
+    pub fn trust_proxy(mut self, trust_proxy: bool) -> Self {
+        self.trust_proxy = trust_proxy;
+        self
+    }
+
+    /// Builds the limiter from the env variables:
+    /// [RATE_LIMIT_STORE] (`memory` or `postgres`), [RATE_LIMIT_DEFAULT],
+    /// [RATE_LIMITS] as comma separated rules and [RATE_LIMIT_TRUST_PROXY].
+    /// `RATE_LIMIT_ENABLED=false` disables it.
+    pub fn from_env(pool: &PgPool, authenticator: Authenticator) -> Result<Self, String> {
+        if env::var("RATE_LIMIT_ENABLED").map_or(false, |v| v == "false") {
+            return Ok(Self::disabled());
+        }
+        let default = env::var("RATE_LIMIT_DEFAULT")
+            .unwrap_or_else(|_| DEFAULT_LIMIT.to_string())
+            .parse()?;
+        let rules = env::var("RATE_LIMITS")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|rule| !rule.trim().is_empty())
+            .map(Rule::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+        let trust_proxy = env::var("RATE_LIMIT_TRUST_PROXY").map_or(false, |v| v == "true");
+        let store = env::var("RATE_LIMIT_STORE").unwrap_or_else(|_| "memory".to_string());
+        let limiter = match store.as_str() {
+            "memory" => Self::new(MemoryStore::default(), rules, default, authenticator),
+            "postgres" => Self::new(
+                PostgresStore::new(pool.clone()),
+                rules,
+                default,
+                authenticator,
+            ),
+            other => return Err(format!("Unknown RATE_LIMIT_STORE: {}", other)),
+        };
+        Ok(limiter.trust_proxy(trust_proxy))
+    }
+
+    /// The rule of the request, by its name, and its limit.
+    fn limit(&self, method: &Method, path: &str) -> (String, Limit) {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(method, path))
+            .map_or(("default".to_string(), self.default), |rule| {
+                (rule.name(), rule.limit)
+            })
+    }
+
+    /// Who is making the request, to pick its bucket.
+    /// The authenticated callers are kept in the request, so they aren't authenticated twice.
+    async fn client(&self, req: &ServiceRequest) -> String {
+        let credentials = Credentials::from_headers(req.headers());
+        match self.authenticator.authenticate(&credentials).await {
+            Ok(caller) => {
+                let client = caller
+                    .user
+                    .as_ref()
+                    .map(|user| format!("caller:{}:{}", caller.tenant, user));
+                req.extensions_mut().insert(caller);
+                client.unwrap_or_else(|| self.ip(req))
+            }
+            // the handlers will reject them anyway
+            Err(_) => self.ip(req),
+        }
+    }
+
+    fn ip(&self, req: &ServiceRequest) -> String {
+        let ip = if self.trust_proxy {
+            // without proxy headers, it's the peer address, port included
+            req.connection_info().realip_remote_addr().map(|addr| {
+                addr.parse::<SocketAddr>()
+                    .map_or_else(|_| addr.to_string(), |addr| addr.ip().to_string())
+            })
+        } else {
+            req.peer_addr().map(|addr| addr.ip().to_string())
+        };
+        format!("ip:{}", ip.unwrap_or_else(|| "unknown".to_string()))
+    }
+
+    /// Takes a token for the request, or lets it through if the store fails.
+    async fn check(&self, req: &ServiceRequest) -> Option<Decision> {
+        if !self.enabled {
+            return None;
+        }
+        let (rule, limit) = self.limit(req.method(), req.path());
+        let key = format!("{}|{}", rule, self.client(req).await);
+        match self.store.take(&key, &limit).await {
+            Ok(decision) => Some(decision),
+            Err(e) => {
+                tracing::error!("🔥 Rate limit store error, letting {} through: {}", key, e);
+                None
+            }
+        }
+    }
+
+    /// Forgets the buckets unused for longer than the longest period, as they're full again.
+    pub async fn sweep(&self) -> sqlx::Result<u64> {
+        let longest = self
+            .rules
+            .iter()
+            .map(|rule| rule.limit.period)
+            .chain(std::iter::once(self.default.period))
+            .max()
+            .unwrap_or_default();
+        let longest =
+            chrono::Duration::from_std(longest).unwrap_or_else(|_| chrono::Duration::zero());
+        self.store.sweep(Utc::now() - longest).await
+    }
+}
+
+fn set_headers(headers: &mut HeaderMap, decision: &Decision) {
+    let mut set = |name: &'static str, value: u64| {
+        headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+    };
+    set("ratelimit-limit", u64::from(decision.limit));
+    set("ratelimit-remaining", u64::from(decision.remaining));
+    set("ratelimit-reset", decision.reset);
+    if let Some(retry_after) = decision.retry_after {
+        set("retry-after", retry_after);
+    }
+}
+
+/// The response to the requests over the limit.
+#[derive(Debug)]
+struct TooManyRequests {
+    problem: Problem,
+    decision: Decision,
+}
+
+impl std::fmt::Display for TooManyRequests {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.problem)
+    }
+}
+
+impl ResponseError for TooManyRequests {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::TOO_MANY_REQUESTS
+    }
+
+    fn error_response(&self) -> actix_web::HttpResponse {
+        let mut response = self.problem.error_response();
+        set_headers(response.headers_mut(), &self.decision);
+        response
+    }
+}
+
+impl<S> Transform<S> for RateLimiter
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<Body>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimiterMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RateLimiterMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            limiter: self.clone(),
+        })
+    }
+}
+
+pub struct RateLimiterMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    limiter: RateLimiter,
+}
+
+impl<S> Service for RateLimiterMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<Body>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let limiter = self.limiter.clone();
+        async move {
+            let decision = limiter.check(&req).await;
+            match decision {
+                Some(decision) if !decision.allowed => {
+                    tracing::warn!("Too many requests to {} {}", req.method(), req.path());
+                    let (req, _) = req.into_parts();
+                    let problem = Problem::new(
+                        StatusCode::TOO_MANY_REQUESTS,
+                        "rate-limited",
+                        "Too many requests, try again later",
+                    );
+                    let error = TooManyRequests {
+                        problem: problem.for_request(&req),
+                        decision,
+                    };
+                    Ok(ServiceResponse::new(req, error.error_response()))
+                }
+                _ => {
+                    let mut res = service.borrow_mut().call(req).await?;
+                    if let Some(decision) = decision {
+                        set_headers(res.headers_mut(), &decision);
+                    }
+                    Ok(res)
+                }
+            }
+        }
+        .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{ApiKeysProvider, Caller, DevProvider, API_KEY_HEADER};
+    use actix_web::{test, web, App, HttpResponse};
+
+    fn limit(s: &str) -> Limit {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn limits_and_rules_are_parsed() {
+        assert_eq!(
+            limit("10/60"),
+            Limit {
+                requests: 10,
+                period: Duration::from_secs(60)
+            }
+        );
+        assert!("10".parse::<Limit>().is_err());
+        assert!("0/60".parse::<Limit>().is_err());
+
+        let rule: Rule = "post /v1/users=10/60".parse().unwrap();
+        assert_eq!(rule.method, Some(Method::POST));
+        assert_eq!(rule.path, "/v1/users");
+        assert_eq!(rule.limit, limit("10/60"));
+        assert_eq!("* /v1=1/1".parse::<Rule>().unwrap().method, None);
+        assert!("/v1=1/1".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn buckets_allow_bursts_up_to_the_limit() {
+        let limit = limit("3/60");
+        let now = Utc::now();
+        let mut bucket = Bucket::full(&limit, now);
+
+        let decisions: Vec<_> = (0..4).map(|_| bucket.take(&limit, now)).collect();
+
+        assert!(decisions[..3].iter().all(|d| d.allowed));
+        assert_eq!(decisions[2].remaining, 0);
+        assert_eq!(decisions[2].reset, 60);
+        assert!(!decisions[3].allowed);
+        assert_eq!(decisions[3].retry_after, Some(20));
+    }
+
+    #[test]
+    fn buckets_are_refilled_over_time() {
+        let limit = limit("60/60");
+        let now = Utc::now();
+        let mut bucket = Bucket {
+            tokens: 0.0,
+            updated_at: now,
+        };
+
+        let early = bucket.take(&limit, now + chrono::Duration::milliseconds(500));
+        let refilled = bucket.take(&limit, now + chrono::Duration::seconds(1));
+        let full = bucket.take(&limit, now + chrono::Duration::hours(1));
+
+        assert!(!early.allowed);
+        assert!(refilled.allowed);
+        assert_eq!(full.remaining, 59);
+    }
+
+    #[test]
+    fn buckets_ignore_clocks_going_backwards() {
+        let limit = limit("3/60");
+        let now = Utc::now();
+        let mut bucket = Bucket {
+            tokens: 0.0,
+            updated_at: now,
+        };
+
+        let decision = bucket.take(&limit, now - chrono::Duration::seconds(30));
+
+        assert!(!decision.allowed);
+        assert_eq!(bucket.updated_at, now);
+    }
+
+    #[actix_rt::test]
+    async fn disabled_limiters_let_everything_through() {
+        let limiter = RateLimiter::disabled();
+        let req = test::TestRequest::default().to_srv_request();
+
+        assert_eq!(limiter.check(&req).await, None);
+    }
+
+    #[test]
+    fn the_first_matching_rule_applies() {
+        let limiter = RateLimiter::new(
+            MemoryStore::default(),
+            vec![
+                "POST /v1/users=1/60".parse().unwrap(),
+                "* /v1/users=2/60".parse().unwrap(),
+            ],
+            limit("3/60"),
+            Authenticator::disabled(),
+        );
+
+        assert_eq!(
+            limiter.limit(&Method::POST, "/v1/users/"),
+            ("POST /v1/users".to_string(), limit("1/60"))
+        );
+        assert_eq!(
+            limiter.limit(&Method::GET, "/v1/users/1"),
+            ("* /v1/users".to_string(), limit("2/60"))
+        );
+        assert_eq!(
+            limiter.limit(&Method::GET, "/v1/me"),
+            ("default".to_string(), limit("3/60"))
+        );
+    }
+
+    #[test]
+    fn rules_match_on_segment_boundaries() {
+        let rule: Rule = "* /v1/users=1/60".parse().unwrap();
+
+        assert!(rule.matches(&Method::GET, "/v1/users"));
+        assert!(rule.matches(&Method::GET, "/v1/users/"));
+        assert!(rule.matches(&Method::GET, "/v1/users/1/history"));
+        assert!(!rule.matches(&Method::GET, "/v1/usersX"));
+        assert!(!rule.matches(&Method::GET, "/v1/user"));
+
+        let rule: Rule = "* /v1/=1/60".parse().unwrap();
+        assert!(rule.matches(&Method::GET, "/v1/me"));
+    }
+
+    async fn handler(caller: Caller) -> HttpResponse {
+        HttpResponse::Ok().body(caller.user.unwrap_or_default())
+    }
+
+    #[actix_rt::test]
+    async fn rate_limiter_integration_works() {
+        let authenticator = Authenticator::new(ApiKeysProvider::new(vec![
+            ("key-1".to_string(), Caller::new("one")),
+            ("key-2".to_string(), Caller::new("two")),
+        ]));
+        let limiter =
+            RateLimiter::new(MemoryStore::default(), vec![], limit("2/60"), authenticator);
+        // no authenticator in the scope, the callers come from the limiter
+        let mut app = test::init_service(
+            App::new().service(
+                web::scope("/v1")
+                    .wrap(limiter)
+                    .route("/users", web::get().to(handler)),
+            ),
+        )
+        .await;
+        let request = |key: &str| {
+            test::TestRequest::get()
+                .uri("/v1/users")
+                .header(API_KEY_HEADER, key)
+                .to_request()
+        };
+
+        let first = test::call_service(&mut app, request("key-1")).await;
+        let _ = test::call_service(&mut app, request("key-1")).await;
+        let limited = test::call_service(&mut app, request("key-1")).await;
+        let other = test::call_service(&mut app, request("key-2")).await;
+
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(first.headers().get("RateLimit-Limit").unwrap(), "2");
+        assert_eq!(first.headers().get("RateLimit-Remaining").unwrap(), "1");
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(limited.headers().get("Retry-After").unwrap(), "30");
+        assert_eq!(
+            limited.headers().get("Content-Type").unwrap(),
+            crate::problem::CONTENT_TYPE
+        );
+        assert_eq!(other.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn anonymous_callers_are_limited_by_ip() {
+        let limiter = RateLimiter::new(
+            MemoryStore::default(),
+            vec![],
+            limit("1/60"),
+            Authenticator::new(DevProvider::new(Caller::default())),
+        );
+        let mut app = test::init_service(
+            App::new().service(
+                web::scope("/v1")
+                    .wrap(limiter)
+                    .route("/users", web::get().to(handler)),
+            ),
+        )
+        .await;
+        let request = |ip: &str| {
+            test::TestRequest::get()
+                .uri("/v1/users")
+                .peer_addr(format!("{}:1234", ip).parse().unwrap())
+                .to_request()
+        };
+
+        let first = test::call_service(&mut app, request("10.0.0.1")).await;
+        let limited = test::call_service(&mut app, request("10.0.0.1")).await;
+        let other = test::call_service(&mut app, request("10.0.0.2")).await;
+
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(other.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,128 @@
+use super::{Bucket, Decision, Limit, RateLimitStore};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+/// Keeps the buckets in the `rate_limit_buckets` table, shared by every instance of the API.
+#[derive(Debug)]
+pub struct PostgresStore {
+    pool: PgPool,
+}
+
+impl PostgresStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for PostgresStore {
+    async fn take(&self, key: &str, limit: &Limit) -> sqlx::Result<Decision> {
+        let now = Utc::now();
+        let full = Bucket::full(limit, now);
+        let mut tx = self.pool.begin().await?;
+        // the upsert locks the bucket until the transaction ends, even if it already existed
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO rate_limit_buckets (key, tokens, updated_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (key) DO UPDATE SET key = EXCLUDED.key
+            RETURNING tokens, updated_at
+            "#,
+            key,
+            full.tokens,
+            full.updated_at,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        let mut bucket = Bucket {
+            tokens: row.tokens,
+            updated_at: row.updated_at,
+        };
+        let decision = bucket.take(limit, now);
+        sqlx::query!(
+            "UPDATE rate_limit_buckets SET tokens = $2, updated_at = $3 WHERE key = $1",
+            key,
+            bucket.tokens,
+            bucket.updated_at,
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(decision)
+    }
+
+    async fn sweep(&self, unused_since: DateTime<Utc>) -> sqlx::Result<u64> {
+        sqlx::query!(
+            "DELETE FROM rate_limit_buckets WHERE updated_at < $1",
+            unused_since,
+        )
+        .execute(&self.pool)
+        .await
+        .map(|done| done.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::join_all;
+    use uuid::Uuid;
+
+    /// The store on the database of `DATABASE_URL`, migrated with `cargo make db-migrate`.
+    async fn store() -> PostgresStore {
+        dotenv::dotenv().ok();
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        PostgresStore::new(PgPool::connect(&url).await.unwrap())
+    }
+
+    /// A key no other run of the tests uses.
+    fn key() -> String {
+        format!("test:{}", Uuid::new_v4())
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs the database, run with `cargo test -- --include-ignored`"]
+    async fn postgres_store_keeps_a_bucket_per_key() {
+        let store = store().await;
+        let limit: Limit = "1/60".parse().unwrap();
+        let (key, other_key) = (key(), key());
+
+        let first = store.take(&key, &limit).await.unwrap();
+        let second = store.take(&key, &limit).await.unwrap();
+        let other = store.take(&other_key, &limit).await.unwrap();
+
+        assert!(first.allowed);
+        assert!(!second.allowed);
+        assert!(other.allowed);
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs the database, run with `cargo test -- --include-ignored`"]
+    async fn postgres_store_takes_every_token_once() {
+        let store = store().await;
+        let limit: Limit = "3/60".parse().unwrap();
+        let key = key();
+
+        // the bucket is locked while a token is taken, so concurrent requests can't share one
+        let decisions = join_all((0..6).map(|_| store.take(&key, &limit))).await;
+
+        let allowed = decisions
+            .into_iter()
+            .filter(|decision| decision.as_ref().unwrap().allowed)
+            .count();
+        assert_eq!(allowed, 3);
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs the database, run with `cargo test -- --include-ignored`"]
+    async fn postgres_store_sweeps_the_unused_buckets() {
+        let store = store().await;
+        let limit: Limit = "1/60".parse().unwrap();
+        store.take(&key(), &limit).await.unwrap();
+
+        let swept = store.sweep(Utc::now() + chrono::Duration::minutes(1)).await;
+
+        assert!(swept.unwrap() >= 1);
+    }
+}
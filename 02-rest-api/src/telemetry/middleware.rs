@@ -0,0 +1,272 @@
+use super::{extract, redact, TraceId};
+use crate::problem::{RequestId, REQUEST_ID_HEADER};
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::{header, HeaderValue, Version},
+    Error, HttpMessage,
+};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use std::task::{Context, Poll};
+use tracing::{field::Empty, Span};
+use tracing_futures::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Middleware running every request in a span with the semantic HTTP attributes,
+/// continuing the trace of the caller. It also assigns the [RequestId] of the request,
+/// returned in the `X-Request-Id` header of the response.
+#[derive(Debug, Clone, Default)]
+pub struct Tracing;
+
+impl<S, B> Transform<S> for Tracing
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = TracingMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(TracingMiddleware { service })
+    }
+}
+
+pub struct TracingMiddleware<S> {
+    service: S,
+}
+
+/// The protocol version, as in the `http.flavor` attribute.
+fn flavor(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "0.9",
+        Version::HTTP_10 => "1.0",
+        Version::HTTP_11 => "1.1",
+        Version::HTTP_2 => "2.0",
+        _ => "other",
+    }
+}
+
+/// The span of the request. Its route, status and trace id are recorded later.
+fn request_span(req: &ServiceRequest, request_id: &RequestId) -> Span {
+    let info = req.connection_info().clone();
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+    };
+    tracing::info_span!(
+        "HTTP request",
+        otel.name = Empty,
+        otel.kind = "server",
+        otel.status_code = Empty,
+        http.method = %req.method(),
+        http.target = %redact::target(req.uri()),
+        http.route = Empty,
+        http.scheme = %info.scheme(),
+        http.host = %info.host(),
+        http.flavor = flavor(req.version()),
+        http.user_agent = header(header::USER_AGENT.as_str()),
+        http.client_ip = info.realip_remote_addr().unwrap_or_default(),
+        http.status_code = Empty,
+        request_id = request_id.0.as_str(),
+        trace_id = Empty,
+    )
+}
+
+impl<S, B> Service for TracingMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let request_id = RequestId::from_headers(req.headers());
+        let span = request_span(&req, &request_id);
+        let parent = extract(|name| req.headers().get(name).and_then(|v| v.to_str().ok()));
+        span.set_parent(&parent);
+        // without an OpenTelemetry subscriber the span has no trace, so the caller's is kept
+        if let Some(trace_id) = TraceId::of(&span.context()).or_else(|| TraceId::of(&parent)) {
+            span.record("trace_id", &trace_id.0.as_str());
+            req.extensions_mut().insert(trace_id);
+        }
+        let response_id = HeaderValue::from_str(&request_id.0).ok();
+        req.extensions_mut().insert(request_id);
+        let method = req.method().clone();
+        let path = req.path().to_string();
+        let fut = span.in_scope(|| self.service.call(req));
+        let recorded = span.clone();
+        Box::pin(
+            async move {
+                let mut res = fut.await;
+                let span = recorded;
+                if let (Ok(res), Some(id)) = (&mut res, response_id) {
+                    res.headers_mut()
+                        .insert(header::HeaderName::from_static("x-request-id"), id);
+                }
+                let status = match &res {
+                    Ok(res) => {
+                        let route = res.request().match_pattern().unwrap_or(path);
+                        span.record("http.route", &route.as_str());
+                        span.record("otel.name", &format!("{} {}", method, route).as_str());
+                        res.status()
+                    }
+                    Err(e) => {
+                        span.record("otel.name", &format!("{} {}", method, path).as_str());
+                        e.as_response_error().status_code()
+                    }
+                };
+                span.record("http.status_code", &status.as_u16());
+                if status.is_server_error() {
+                    span.record("otel.status_code", &"ERROR");
+                }
+                res
+            }
+            .instrument(span),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::problem::{request_id, Problem};
+    use crate::telemetry::local_tracer;
+    use actix_web::{http::StatusCode, test, web, App, HttpRequest, HttpResponse};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    const TRACEPARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+    async fn trace_id(req: HttpRequest) -> HttpResponse {
+        match req.extensions().get::<TraceId>() {
+            Some(TraceId(id)) => HttpResponse::Ok().body(id.clone()),
+            None => HttpResponse::Ok().finish(),
+        }
+    }
+
+    async fn id(req: HttpRequest) -> HttpResponse {
+        HttpResponse::Ok().body(request_id(&req))
+    }
+
+    async fn fail(req: HttpRequest) -> Result<HttpResponse, Problem> {
+        Err(Problem::new(StatusCode::INTERNAL_SERVER_ERROR, "failed", "Boom").for_request(&req))
+    }
+
+    async fn call(req: test::TestRequest) -> (StatusCode, String) {
+        let (status, _, body) = call_with_id(req).await;
+        (status, body)
+    }
+
+    /// Calls the app, returning the request id of the response too.
+    async fn call_with_id(req: test::TestRequest) -> (StatusCode, String, String) {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(Tracing)
+                .route("/trace", web::get().to(trace_id))
+                .route("/id", web::get().to(id))
+                .route("/fail", web::get().to(fail)),
+        )
+        .await;
+        let res = test::call_service(&mut app, req.to_request()).await;
+        let status = res.status();
+        let id = res
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|id| id.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let body = test::read_body(res).await;
+        (status, id, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[actix_rt::test]
+    async fn requests_continue_the_trace_of_the_caller() {
+        let (tracer, _provider) = local_tracer();
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        let _subscriber = tracing::subscriber::set_default(subscriber);
+        let req = test::TestRequest::get()
+            .uri("/trace")
+            .header("traceparent", TRACEPARENT);
+
+        let (_, trace_id) = call(req).await;
+
+        assert_eq!(trace_id, "0af7651916cd43dd8448eb211c80319c");
+    }
+
+    #[actix_rt::test]
+    async fn requests_start_a_trace_otherwise() {
+        let (tracer, _provider) = local_tracer();
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        let _subscriber = tracing::subscriber::set_default(subscriber);
+
+        let (_, first) = call(test::TestRequest::get().uri("/trace")).await;
+        let (_, second) = call(test::TestRequest::get().uri("/trace")).await;
+
+        assert_eq!(first.len(), 32);
+        assert_ne!(first, "0".repeat(32));
+        assert_ne!(first, second);
+    }
+
+    #[actix_rt::test]
+    async fn the_trace_of_the_caller_is_kept_without_subscriber() {
+        let req = test::TestRequest::get()
+            .uri("/trace")
+            .header("traceparent", TRACEPARENT);
+
+        let (_, trace_id) = call(req).await;
+
+        assert_eq!(trace_id, "0af7651916cd43dd8448eb211c80319c");
+    }
+
+    #[actix_rt::test]
+    async fn problems_carry_the_trace_id() {
+        let req = test::TestRequest::get()
+            .uri("/fail")
+            .header("traceparent", TRACEPARENT);
+
+        let (status, body) = call(req).await;
+        let problem: Problem = serde_json::from_str(&body).unwrap();
+
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            problem.trace_id.as_deref(),
+            Some("0af7651916cd43dd8448eb211c80319c")
+        );
+    }
+
+    #[actix_rt::test]
+    async fn requests_get_a_single_id() {
+        let (_, returned, seen) = call_with_id(test::TestRequest::get().uri("/id")).await;
+        let (_, other, _) = call_with_id(test::TestRequest::get().uri("/id")).await;
+
+        assert_eq!(returned.len(), 36);
+        assert_eq!(returned, seen);
+        assert_ne!(returned, other);
+    }
+
+    #[actix_rt::test]
+    async fn requests_keep_the_id_of_the_caller() {
+        let req = test::TestRequest::get()
+            .uri("/fail")
+            .header(REQUEST_ID_HEADER, "my-request");
+
+        let (_, returned, body) = call_with_id(req).await;
+        let problem: Problem = serde_json::from_str(&body).unwrap();
+
+        assert_eq!(returned, "my-request");
+        assert_eq!(problem.request_id.as_deref(), Some("my-request"));
+    }
+}
@@ -0,0 +1,311 @@
+//! Logs and distributed tracing.
+//!
+//! Every span is also an OpenTelemetry span, exported with OTLP to the collector
+//! in `OTEL_EXPORTER_OTLP_ENDPOINT`, if any. The trace of a request continues the one
+//! of its caller from the W3C `traceparent` and `tracestate` headers, and its id is
+//! logged and returned in the errors.
+//!
+//! The personal data is redacted wherever it's logged or recorded in a span,
+//! as the policy in `LOG_REDACTION` says, and the credentials are never shown.
+mod middleware;
+pub mod redact;
+
+pub use middleware::Tracing;
+
+use actix_web::middleware::Logger;
+use opentelemetry::{
+    propagation::TextMapPropagator,
+    sdk::{
+        propagation::TraceContextPropagator,
+        trace::{self as sdktrace, TracerProvider},
+        Resource,
+    },
+    trace::{TraceContextExt, TracerProvider as _},
+    Context, KeyValue,
+};
+use std::{collections::HashMap, env};
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+pub const TRACESTATE_HEADER: &str = "tracestate";
+/// Name of the API in the exported spans.
+pub const SERVICE_NAME: &str = "rpts02";
+
+/// Id of the trace of a request, as 32 hex digits. Kept in the extensions of the request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceId(pub String);
+
+impl TraceId {
+    /// The id of the trace of the context, from its span or the one of the caller,
+    /// unless it has none.
+    pub fn of(cx: &Context) -> Option<Self> {
+        let span_context = cx.span().span_context().clone();
+        Some(span_context)
+            .filter(|span_context| span_context.is_valid())
+            .or_else(|| cx.remote_span_context().cloned())
+            .filter(|span_context| span_context.is_valid())
+            .map(|span_context| Self(format!("{:032x}", span_context.trace_id().to_u128())))
+    }
+}
+
+/// Keeps the tracing running, and flushes the pending spans when dropped.
+pub struct Guard {
+    _uninstall: Option<opentelemetry_otlp::Uninstall>,
+    _provider: Option<TracerProvider>,
+}
+
+/// Sets up the JSON logs and the OpenTelemetry spans, with the personal data redacted.
+/// Spans are only exported if [OTEL_EXPORTER_OTLP_ENDPOINT] is set, like `http://localhost:4317`,
+/// but they always have ids, so requests can be followed in the logs.
+pub fn init() -> Result<Guard, String> {
+    redact::init_from_env()?;
+    let (tracer, guard) = match env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        Ok(endpoint) => {
+            let (tracer, uninstall) = otlp_tracer(endpoint)?;
+            let guard = Guard {
+                _uninstall: Some(uninstall),
+                _provider: None,
+            };
+            (tracer, guard)
+        }
+        Err(_) => {
+            let (tracer, provider) = local_tracer();
+            let guard = Guard {
+                _uninstall: None,
+                _provider: Some(provider),
+            };
+            (tracer, guard)
+        }
+    };
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(
+            fmt::layer()
+                .with_ansi(true)
+                .json()
+                .flatten_event(true)
+                .with_target(true)
+                .with_span_list(true)
+                .with_timer(fmt::time::ChronoUtc::rfc3339()),
+        )
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()
+        .map_err(|e| e.to_string())?;
+    Ok(guard)
+}
+
+/// The access log of the requests, with their sensitive query parameters redacted.
+/// Unlike the default one, it leaves out the referer, whose query string can't be told apart.
+pub fn access_log() -> Logger {
+    Logger::new(r#"%a "%{request}xi" %s %b "%{User-Agent}i" %T"#).custom_request_replace(
+        "request",
+        |req| {
+            format!(
+                "{} {} {:?}",
+                req.method(),
+                redact::target(req.uri()),
+                req.version()
+            )
+        },
+    )
+}
+
+/// A tracer exporting the spans with OTLP to the collector at `endpoint`.
+/// The pending spans are flushed when the [opentelemetry_otlp::Uninstall] is dropped.
+fn otlp_tracer(
+    endpoint: String,
+) -> Result<(sdktrace::Tracer, opentelemetry_otlp::Uninstall), String> {
+    opentelemetry_otlp::new_pipeline()
+        .with_endpoint(endpoint)
+        .with_trace_config(
+            sdktrace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                SERVICE_NAME,
+            )])),
+        )
+        .install()
+        .map_err(|e| format!("Unable to export the spans: {}", e))
+}
+
+/// A tracer giving ids to the spans without exporting them.
+/// The provider must be kept alive as long as the tracer is used.
+pub fn local_tracer() -> (sdktrace::Tracer, TracerProvider) {
+    let provider = TracerProvider::builder().build();
+    let tracer = provider.get_tracer(SERVICE_NAME, None);
+    (tracer, provider)
+}
+
+/// The trace context of the caller, from the headers read by `header`.
+/// Empty if the caller sent none, or an invalid one.
+pub fn extract<'a>(header: impl Fn(&str) -> Option<&'a str>) -> Context {
+    let carrier: HashMap<String, String> = [TRACEPARENT_HEADER, TRACESTATE_HEADER]
+        .iter()
+        .filter_map(|&name| header(name).map(|value| (name.to_string(), value.to_string())))
+        .collect();
+    TraceContextPropagator::new().extract(&carrier)
+}
+
+/// The `traceparent` and `tracestate` headers carrying the context to another service.
+pub fn inject(cx: &Context) -> HashMap<String, String> {
+    let mut carrier = HashMap::new();
+    TraceContextPropagator::new().inject_context(cx, &mut carrier);
+    carrier
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+    use opentelemetry::trace::Tracer;
+    use otlp::{
+        trace_service_server::{TraceService, TraceServiceServer},
+        ExportTraceServiceRequest, ExportTraceServiceResponse,
+    };
+    use std::{
+        net::Ipv4Addr,
+        sync::{mpsc, Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    mod otlp {
+        #![allow(clippy::all, clippy::pedantic, clippy::nursery)]
+        tonic::include_proto!("opentelemetry.proto.collector.trace.v1");
+    }
+
+    const TRACEPARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// An OTLP collector keeping the trace and parent span ids of the spans it receives.
+    #[derive(Clone, Default)]
+    struct Collector {
+        spans: Arc<Mutex<Vec<(String, String)>>>,
+    }
+
+    impl Collector {
+        /// Starts the collector on a thread of its own, where the tracer exporting to it
+        /// is built too, so the exports never wait for the thread of the test.
+        fn start() -> (Self, sdktrace::Tracer, opentelemetry_otlp::Uninstall) {
+            let collector = Self::default();
+            let service = TraceServiceServer::new(collector.clone());
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || {
+                actix_rt::System::new("collector").block_on(async move {
+                    let mut listener = actix_rt::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+                        .await
+                        .unwrap();
+                    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+                    actix_rt::spawn(async move {
+                        tonic::transport::Server::builder()
+                            .add_service(service)
+                            .serve_with_incoming(listener.incoming())
+                            .await
+                            .unwrap();
+                    });
+                    tx.send(otlp_tracer(endpoint).unwrap()).unwrap();
+                    futures::future::pending::<()>().await;
+                })
+            });
+            let (tracer, uninstall) = rx.recv().unwrap();
+            (collector, tracer, uninstall)
+        }
+
+        /// The spans received, waiting a bit for the first ones.
+        fn received(&self) -> Vec<(String, String)> {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            loop {
+                let spans = self.spans.lock().unwrap().clone();
+                if !spans.is_empty() || Instant::now() > deadline {
+                    return spans;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+
+    #[tonic::async_trait]
+    impl TraceService for Collector {
+        async fn export(
+            &self,
+            request: tonic::Request<ExportTraceServiceRequest>,
+        ) -> Result<tonic::Response<ExportTraceServiceResponse>, tonic::Status> {
+            let spans = request
+                .into_inner()
+                .resource_spans
+                .into_iter()
+                .flat_map(|resource| resource.instrumentation_library_spans)
+                .flat_map(|library| library.spans)
+                .map(|span| (hex(&span.trace_id), hex(&span.parent_span_id)));
+            self.spans.lock().unwrap().extend(spans);
+            Ok(tonic::Response::new(ExportTraceServiceResponse {}))
+        }
+    }
+
+    #[test]
+    fn extract_reads_the_traceparent() {
+        let cx = extract(|name| match name {
+            TRACEPARENT_HEADER => Some(TRACEPARENT),
+            _ => None,
+        });
+
+        assert_eq!(
+            TraceId::of(&cx),
+            Some(TraceId("0af7651916cd43dd8448eb211c80319c".to_string()))
+        );
+    }
+
+    #[test]
+    fn extract_ignores_invalid_traceparents() {
+        let cx = extract(|name| match name {
+            TRACEPARENT_HEADER => Some("00-not-a-trace-01"),
+            _ => None,
+        });
+
+        assert_eq!(TraceId::of(&cx), None);
+    }
+
+    #[test]
+    fn inject_writes_the_trace_of_the_span() {
+        let (tracer, _provider) = local_tracer();
+        let cx = Context::new().with_span(tracer.start("fetch"));
+
+        let headers = inject(&cx);
+        let TraceId(trace_id) = TraceId::of(&cx).unwrap();
+
+        assert!(headers[TRACEPARENT_HEADER].starts_with(&format!("00-{}-", trace_id)));
+    }
+
+    async fn ok() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_rt::test]
+    async fn spans_are_exported_with_the_trace_of_the_caller() {
+        let (collector, tracer, uninstall) = Collector::start();
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        let _subscriber = tracing::subscriber::set_default(subscriber);
+        let mut app =
+            test::init_service(App::new().wrap(Tracing).route("/", web::get().to(ok))).await;
+        let req = test::TestRequest::get()
+            .header(TRACEPARENT_HEADER, TRACEPARENT)
+            .to_request();
+
+        test::call_service(&mut app, req).await;
+        // flushes the pending spans
+        drop(uninstall);
+
+        let spans = collector.received();
+        assert!(
+            spans.contains(&(
+                "0af7651916cd43dd8448eb211c80319c".to_string(),
+                "b7ad6b7169203331".to_string()
+            )),
+            "{:?}",
+            spans
+        );
+    }
+}
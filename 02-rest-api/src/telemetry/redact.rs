@@ -0,0 +1,195 @@
+use actix_web::http::Uri;
+use percent_encoding::percent_decode_str;
+use rpts_domain::redact::{self, Redaction};
+use std::env;
+
+/// Parameters of the query strings holding personal data.
+/// The cursors carry the sort key of a user, like its name.
+pub const SENSITIVE_QUERY_PARAMS: &[&str] = &["name_prefix", "born_after", "born_before", "cursor"];
+
+/// Sets the redaction policy of the process in [LOG_REDACTION]: `mask` (default), `hash` or `off`.
+/// `hash` needs the secret key of its digests in [LOG_REDACTION_KEY].
+pub fn init_from_env() -> Result<(), String> {
+    let policy = match env::var("LOG_REDACTION") {
+        Ok(v) => v.parse()?,
+        Err(_) => Redaction::Mask,
+    };
+    if policy == Redaction::Hash {
+        let key = env::var("LOG_REDACTION_KEY")
+            .ok()
+            .filter(|key| !key.is_empty())
+            .ok_or("LOG_REDACTION=hash needs a secret key in LOG_REDACTION_KEY")?;
+        redact::set_hash_key(key)?;
+    }
+    redact::set_policy(policy);
+    Ok(())
+}
+
+/// The path and query of a request, with the values of the sensitive parameters redacted.
+pub fn target(uri: &Uri) -> String {
+    target_with(redact::policy(), uri)
+}
+
+fn target_with(policy: Redaction, uri: &Uri) -> String {
+    match uri.query() {
+        None => uri.path().to_string(),
+        Some(query) if policy == Redaction::Off => format!("{}?{}", uri.path(), query),
+        Some(query) => {
+            let params: Vec<String> = query
+                .split('&')
+                .map(|param| {
+                    let mut parts = param.splitn(2, '=');
+                    let name = parts.next().unwrap_or_default();
+                    // decoded as the handlers do, so `name%5Fprefix` is `name_prefix` too
+                    let decoded = percent_decode_str(name).decode_utf8_lossy();
+                    match parts.next() {
+                        Some(value) if SENSITIVE_QUERY_PARAMS.contains(&decoded.as_ref()) => {
+                            format!("{}={}", name, policy.show(&value))
+                        }
+                        _ => param.to_string(),
+                    }
+                })
+                .collect();
+            format!("{}?{}", uri.path(), params.join("&"))
+        }
+    }
+}
+
+/// Captures what's logged, spans included, to check what's left in it.
+#[cfg(test)]
+pub mod capture {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+    use tracing::subscriber::DefaultGuard;
+    use tracing_subscriber::fmt::{format::FmtSpan, MakeWriter};
+
+    #[derive(Debug, Clone, Default)]
+    pub struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Captured {
+        pub fn output(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+        }
+    }
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl MakeWriter for Captured {
+        type Writer = Self;
+
+        fn make_writer(&self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    /// Captures the logs of the thread until the guard is dropped.
+    pub fn start() -> (Captured, DefaultGuard) {
+        let captured = Captured::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(captured.clone())
+            .with_span_events(FmtSpan::NEW)
+            .with_max_level(tracing::Level::TRACE)
+            .finish();
+        (captured, tracing::subscriber::set_default(subscriber))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Credentials;
+    use crate::models::{CustomData, ListQuery, User};
+    use chrono::NaiveDate;
+    use tracing::instrument;
+
+    fn uri(uri: &str) -> Uri {
+        uri.parse().unwrap()
+    }
+
+    #[test]
+    fn targets_without_query_are_kept() {
+        assert_eq!(target(&uri("/v1/users/42")), "/v1/users/42");
+    }
+
+    #[test]
+    fn sensitive_parameters_are_masked() {
+        assert_eq!(
+            target(&uri(
+                "/v1/users?limit=5&name_prefix=Jose&born_after=1990-05-17"
+            )),
+            "/v1/users?limit=5&name_prefix=[REDACTED]&born_after=[REDACTED]"
+        );
+    }
+
+    #[test]
+    fn encoded_sensitive_parameters_are_masked() {
+        assert_eq!(
+            target(&uri("/v1/users?name%5Fprefix=Jose&%63ursor=abc")),
+            "/v1/users?name%5Fprefix=[REDACTED]&%63ursor=[REDACTED]"
+        );
+    }
+
+    #[test]
+    fn sensitive_parameters_follow_the_policy() {
+        let query = uri("/v1/users?name_prefix=Jose&sort=name");
+
+        let hashed = target_with(Redaction::Hash, &query);
+
+        assert!(!hashed.contains("Jose"));
+        assert!(hashed.ends_with("&sort=name"));
+        assert_eq!(
+            target_with(Redaction::Off, &query),
+            "/v1/users?name_prefix=Jose&sort=name"
+        );
+    }
+
+    #[instrument]
+    fn handle(user: &User, query: &ListQuery, credentials: &Credentials) {
+        tracing::info!("handled");
+    }
+
+    #[test]
+    fn spans_leave_no_personal_data_nor_secrets() {
+        let user = User {
+            name: "Jose Smith".to_string(),
+            birth_date: NaiveDate::from_ymd(1990, 5, 17),
+            custom_data: Some(CustomData { random: 31337 }),
+            ..User::default()
+        };
+        let query = ListQuery {
+            name_prefix: Some("Jose".to_string()),
+            limit: Some(5),
+            ..ListQuery::default()
+        };
+        let credentials = Credentials {
+            bearer: Some("my.token".to_string()),
+            api_key: Some("my-key".to_string()),
+        };
+        let (captured, _guard) = capture::start();
+
+        handle(&user, &query, &credentials);
+
+        let logs = captured.output();
+        assert!(logs.contains("handled"));
+        assert!(logs.contains("limit: Some(5)"));
+        for sensitive in &["Jose", "1990-05-17", "31337", "my.token", "my-key"] {
+            assert!(
+                !logs.contains(sensitive),
+                "{} was logged: {}",
+                sensitive,
+                logs
+            );
+        }
+    }
+}
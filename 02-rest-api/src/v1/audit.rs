@@ -0,0 +1,178 @@
+//! Audit trail of the users.
+//!
+//! Every write of a user appends a record to the `user_audit` table, in the same transaction,
+//! with who made it, from which request and the fields it changed.
+use super::listing::{DEFAULT_LIMIT, MAX_LIMIT};
+use super::service::ServiceError;
+use crate::models::{AuditRecord, HistoryPage, HistoryQuery, User};
+use serde_json::{json, Map, Value};
+
+/// Who makes a change, as recorded in the audit trail.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Actor {
+    /// Id of the caller, if authenticated.
+    pub user_id: Option<String>,
+    /// Id of the request that made the change, to find it in the logs.
+    pub request_id: Option<String>,
+}
+
+impl Actor {
+    pub fn new(user_id: Option<String>, request_id: Option<String>) -> Self {
+        Self {
+            user_id,
+            request_id,
+        }
+    }
+}
+
+/// The fields that differ between two versions of a user, as
+/// `{"field": {"before": ..., "after": ...}}`.
+/// A missing version, like the one before a creation, has every field set to `null`.
+pub fn changes(before: Option<&User>, after: &User) -> Value {
+    let fields = |user: Option<&User>| match user.map(serde_json::to_value) {
+        Some(Ok(Value::Object(fields))) => fields,
+        _ => Map::new(),
+    };
+    let (before, after) = (fields(before), fields(Some(after)));
+    let mut names: Vec<&String> = before.keys().chain(after.keys()).collect();
+    names.sort();
+    names.dedup();
+    let changes: Map<String, Value> = names
+        .into_iter()
+        .filter_map(|name| {
+            let old = before.get(name).unwrap_or(&Value::Null);
+            let new = after.get(name).unwrap_or(&Value::Null);
+            if old == new {
+                None
+            } else {
+                Some((name.clone(), json!({ "before": old, "after": new })))
+            }
+        })
+        .collect();
+    Value::Object(changes)
+}
+
+/// A validated [HistoryQuery], ready to be handed to the repository.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryListing {
+    /// Only the records older than this one, if any.
+    pub before: Option<i64>,
+    pub limit: u32,
+}
+
+impl HistoryListing {
+    pub fn from_query(query: &HistoryQuery) -> Result<Self, ServiceError> {
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+        if limit == 0 || limit > MAX_LIMIT {
+            return Err(ServiceError::InvalidQuery(format!(
+                "limit must be between 1 and {}",
+                MAX_LIMIT
+            )));
+        }
+        let before = query.cursor.as_deref().map(decode_cursor).transpose()?;
+        Ok(Self { before, limit })
+    }
+
+    /// Builds the page out of the records read by the repository.
+    /// The repository reads one more record than the limit to know whether there are more.
+    pub fn page(&self, mut records: Vec<AuditRecord>) -> HistoryPage {
+        let has_more = records.len() > self.limit as usize;
+        records.truncate(self.limit as usize);
+        HistoryPage {
+            next_cursor: records
+                .last()
+                .filter(|_| has_more)
+                .map(|record| encode_cursor(record.id)),
+            items: records,
+        }
+    }
+}
+
+fn encode_cursor(id: i64) -> String {
+    base64::encode_config(id.to_string(), base64::URL_SAFE_NO_PAD)
+}
+
+fn decode_cursor(cursor: &str) -> Result<i64, ServiceError> {
+    base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+        .ok()
+        .and_then(|id| String::from_utf8(id).ok())
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| ServiceError::InvalidQuery("Invalid cursor".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{CustomData, Operation};
+    use chrono::Utc;
+
+    fn record(id: i64) -> AuditRecord {
+        AuditRecord {
+            id,
+            user_id: uuid::Uuid::new_v4(),
+            actor: None,
+            operation: Operation::Update,
+            changes: json!({}),
+            request_id: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn changes_only_has_the_changed_fields() {
+        let before = User::default();
+        let mut after = before.clone();
+        after.custom_data = Some(CustomData { random: 3 });
+
+        assert_eq!(
+            changes(Some(&before), &after),
+            json!({"custom_data": {"before": null, "after": {"random": 3}}})
+        );
+        assert_eq!(changes(Some(&before), &before), json!({}));
+    }
+
+    #[test]
+    fn changes_of_a_creation_have_every_field() {
+        let mut user = User::default();
+        user.name = "Roberto".to_string();
+
+        let changes = changes(None, &user);
+
+        assert_eq!(changes["name"], json!({"before": null, "after": "Roberto"}));
+        assert!(changes.get("birth_date").is_some());
+    }
+
+    #[test]
+    fn history_pages_continue_from_the_last_record() {
+        let listing = HistoryListing::from_query(&HistoryQuery {
+            limit: Some(2),
+            cursor: None,
+        })
+        .unwrap();
+
+        let page = listing.page(vec![record(9), record(7), record(4)]);
+
+        assert_eq!(page.items.len(), 2);
+        let next = HistoryListing::from_query(&HistoryQuery {
+            limit: Some(2),
+            cursor: page.next_cursor,
+        })
+        .unwrap();
+        assert_eq!(next.before, Some(7));
+        assert_eq!(next.page(vec![record(4)]).next_cursor, None);
+    }
+
+    #[test]
+    fn history_queries_are_validated() {
+        let invalid = |limit, cursor: Option<&str>| {
+            HistoryListing::from_query(&HistoryQuery {
+                limit,
+                cursor: cursor.map(|c| c.to_string()),
+            })
+            .is_err()
+        };
+        assert!(invalid(Some(0), None));
+        assert!(invalid(Some(MAX_LIMIT + 1), None));
+        assert!(invalid(None, Some("not a cursor")));
+    }
+}
@@ -0,0 +1,647 @@
+//! Read-through cache of the users, in front of any [Repository].
+//!
+//! Only [Repository::get_user] is cached, including the users that weren't found.
+//! Every write through the cache invalidates the user it wrote, but the writes of other
+//! instances aren't seen until the entries expire, so keep the TTL short.
+use super::audit::{Actor, HistoryListing};
+use super::etag::Version;
+use super::idempotency::{IdempotencyKey, Idempotent};
+use super::listing::{UserFilter, UserListing};
+use super::repository::Repository;
+use crate::models::{AuditRecord, CustomData, User};
+use async_trait::async_trait;
+use prometheus::{IntCounterVec, Opts, Registry};
+use sqlx::{
+    types::chrono::{DateTime, Utc},
+    Result,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+/// Users kept at most, unless `USERS_CACHE_CAPACITY` says otherwise.
+pub const DEFAULT_CAPACITY: usize = 10_000;
+/// Seconds a user is kept, unless `USERS_CACHE_TTL_SECS` says otherwise.
+pub const DEFAULT_TTL_SECS: u64 = 30;
+/// Seconds a user that wasn't found is kept, unless `USERS_CACHE_NEGATIVE_TTL_SECS` says otherwise.
+pub const DEFAULT_NEGATIVE_TTL_SECS: u64 = 5;
+
+/// Size and lifetime of the cached users.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheConfig {
+    pub capacity: usize,
+    pub ttl: Duration,
+    pub negative_ttl: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_CAPACITY,
+            ttl: Duration::from_secs(DEFAULT_TTL_SECS),
+            negative_ttl: Duration::from_secs(DEFAULT_NEGATIVE_TTL_SECS),
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Reads the config from the [USERS_CACHE_CAPACITY], [USERS_CACHE_TTL_SECS]
+    /// and [USERS_CACHE_NEGATIVE_TTL_SECS] env vars.
+    /// `USERS_CACHE_ENABLED=false` disables the cache, so there's no config.
+    pub fn from_env() -> std::result::Result<Option<Self>, String> {
+        if env::var("USERS_CACHE_ENABLED").map_or(false, |v| v == "false") {
+            return Ok(None);
+        }
+        let defaults = Self::default();
+        Ok(Some(Self {
+            capacity: env_var("USERS_CACHE_CAPACITY")?.unwrap_or(defaults.capacity),
+            ttl: env_var("USERS_CACHE_TTL_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.ttl),
+            negative_ttl: env_var("USERS_CACHE_NEGATIVE_TTL_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.negative_ttl),
+        }))
+    }
+}
+
+/// Parses the env var, if set.
+fn env_var<T: std::str::FromStr>(name: &str) -> std::result::Result<Option<T>, String> {
+    env::var(name)
+        .ok()
+        .map(|v| {
+            v.parse()
+                .map_err(|_| format!("{} must be a positive number, not {}", name, v))
+        })
+        .transpose()
+}
+
+/// Counts the reads answered by the cache, and the ones that went to the repository.
+#[derive(Clone)]
+pub struct CacheMetrics {
+    requests: IntCounterVec,
+}
+
+impl CacheMetrics {
+    /// Registers the `rpts02_api_users_cache_requests_total` counter, labeled with the `result`.
+    pub fn register(registry: &Registry) -> prometheus::Result<Self> {
+        let requests = IntCounterVec::new(
+            Opts::new(
+                "users_cache_requests_total",
+                "Reads of users answered by the cache (hit) or by the database (miss)",
+            )
+            .namespace("rpts02_api"),
+            &["result"],
+        )?;
+        registry.register(Box::new(requests.clone()))?;
+        Ok(Self { requests })
+    }
+
+    fn hit(&self) {
+        self.requests.with_label_values(&["hit"]).inc();
+    }
+
+    fn miss(&self) {
+        self.requests.with_label_values(&["miss"]).inc();
+    }
+}
+
+/// A user of a tenant, read with or without the deleted ones.
+type Key = (String, Uuid, bool);
+
+/// A cached read, `None` if the user wasn't found.
+#[derive(Debug)]
+struct Entry {
+    user: Option<User>,
+    expires_at: Instant,
+    used_at: u64,
+}
+
+/// Least recently used cache, whose entries also expire.
+#[derive(Debug)]
+struct Lru {
+    capacity: usize,
+    entries: HashMap<Key, Entry>,
+    /// Keys by their last use, the least recent first.
+    recency: BTreeMap<u64, Key>,
+    clock: u64,
+    /// Bumped on every invalidation, so the reads that started before don't cache what they read.
+    generation: u64,
+}
+
+impl Lru {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            generation: 0,
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// The cached read of the key, unless it's missing or expired.
+    fn get(&mut self, key: &Key, now: Instant) -> Option<Option<User>> {
+        let used_at = self.tick();
+        let entry = self.entries.get_mut(key)?;
+        if entry.expires_at <= now {
+            let used_at = entry.used_at;
+            self.entries.remove(key);
+            self.recency.remove(&used_at);
+            return None;
+        }
+        self.recency.remove(&entry.used_at);
+        self.recency.insert(used_at, key.clone());
+        entry.used_at = used_at;
+        Some(entry.user.clone())
+    }
+
+    /// Caches the read, evicting the least recently used entries if full.
+    fn insert(&mut self, key: Key, user: Option<User>, expires_at: Instant) {
+        let used_at = self.tick();
+        if let Some(old) = self.entries.remove(&key) {
+            self.recency.remove(&old.used_at);
+        }
+        while self.entries.len() >= self.capacity {
+            let lru = match self.recency.keys().next() {
+                Some(used_at) => *used_at,
+                None => break,
+            };
+            if let Some(key) = self.recency.remove(&lru) {
+                self.entries.remove(&key);
+            }
+        }
+        if self.capacity == 0 {
+            return;
+        }
+        self.recency.insert(used_at, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                user,
+                expires_at,
+                used_at,
+            },
+        );
+    }
+
+    /// Forgets every read of the user.
+    fn invalidate(&mut self, tenant: &str, id: &Uuid) {
+        self.generation += 1;
+        for &include_deleted in &[false, true] {
+            if let Some(entry) = self
+                .entries
+                .remove(&(tenant.to_string(), *id, include_deleted))
+            {
+                self.recency.remove(&entry.used_at);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.generation += 1;
+        self.entries.clear();
+        self.recency.clear();
+    }
+}
+
+/// The cache itself, missing when disabled.
+struct Cache {
+    lru: Mutex<Lru>,
+    config: CacheConfig,
+    metrics: CacheMetrics,
+}
+
+/// Repository caching the users read from the `inner` one.
+pub struct CachingRepository<R: Repository> {
+    pub inner: R,
+    cache: Option<Cache>,
+}
+
+impl<R: Repository> std::fmt::Debug for CachingRepository<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CachingRepository")
+            .field("inner", &self.inner)
+            .field("config", &self.cache.as_ref().map(|cache| &cache.config))
+            .finish()
+    }
+}
+
+impl<R: Repository> CachingRepository<R> {
+    pub fn new(inner: R, config: CacheConfig, metrics: CacheMetrics) -> Self {
+        Self {
+            inner,
+            cache: Some(Cache {
+                lru: Mutex::new(Lru::new(config.capacity)),
+                config,
+                metrics,
+            }),
+        }
+    }
+
+    /// Sends every read to the `inner` repository.
+    pub fn disabled(inner: R) -> Self {
+        Self { inner, cache: None }
+    }
+
+    /// Builds the cache as configured by [CacheConfig::from_env],
+    /// with its metrics in the registry.
+    pub fn from_env(inner: R, registry: &Registry) -> std::result::Result<Self, String> {
+        match CacheConfig::from_env()? {
+            Some(config) => {
+                let metrics = CacheMetrics::register(registry).map_err(|e| e.to_string())?;
+                Ok(Self::new(inner, config, metrics))
+            }
+            None => Ok(Self::disabled(inner)),
+        }
+    }
+
+    fn lru(cache: &Cache) -> std::sync::MutexGuard<'_, Lru> {
+        cache.lru.lock().expect("Poisoned users cache")
+    }
+
+    /// Forgets the user once written, whether the write succeeded or not.
+    fn invalidate<T>(&self, tenant: &str, id: &Uuid, result: Result<T>) -> Result<T> {
+        if let Some(cache) = &self.cache {
+            Self::lru(cache).invalidate(tenant, id);
+        }
+        result
+    }
+
+    /// Forgets the user that was just created, which may have been cached as not found.
+    fn created(&self, tenant: &str, user: Result<User>) -> Result<User> {
+        match user.as_ref().ok().and_then(|user| user.id) {
+            Some(id) => self.invalidate(tenant, &id, user),
+            None => user,
+        }
+    }
+}
+
+#[async_trait]
+impl<R: Repository + Send + Sync> Repository for CachingRepository<R> {
+    async fn get_user(&self, id: &Uuid, include_deleted: bool, tenant: &str) -> Result<User> {
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => return self.inner.get_user(id, include_deleted, tenant).await,
+        };
+        let key = (tenant.to_string(), *id, include_deleted);
+        let generation = {
+            let mut lru = Self::lru(cache);
+            if let Some(user) = lru.get(&key, Instant::now()) {
+                cache.metrics.hit();
+                return user.ok_or(sqlx::Error::RowNotFound);
+            }
+            lru.generation
+        };
+        cache.metrics.miss();
+        let result = self.inner.get_user(id, include_deleted, tenant).await;
+        let (user, ttl) = match &result {
+            Ok(user) => (Some(user.clone()), cache.config.ttl),
+            Err(sqlx::Error::RowNotFound) => (None, cache.config.negative_ttl),
+            Err(_) => return result,
+        };
+        let mut lru = Self::lru(cache);
+        // a write in the meantime may have made the read stale
+        if lru.generation == generation {
+            lru.insert(key, user, Instant::now() + ttl);
+        }
+        result
+    }
+
+    async fn create_user(&self, user: User, actor: &Actor, tenant: &str) -> Result<User> {
+        let user = self.inner.create_user(user, actor, tenant).await;
+        self.created(tenant, user)
+    }
+
+    async fn create_user_idempotent(
+        &self,
+        user: User,
+        key: &IdempotencyKey,
+        actor: &Actor,
+        tenant: &str,
+    ) -> Result<Idempotent<User>> {
+        let result = self
+            .inner
+            .create_user_idempotent(user, key, actor, tenant)
+            .await;
+        if let Ok(Idempotent::Created(user)) = &result {
+            if let Some(id) = user.id {
+                return self.invalidate(tenant, &id, result);
+            }
+        }
+        result
+    }
+
+    async fn update_user(
+        &self,
+        id: &Uuid,
+        custom_data: CustomData,
+        expected: Option<Version>,
+        actor: &Actor,
+        tenant: &str,
+    ) -> Result<User> {
+        let user = self
+            .inner
+            .update_user(id, custom_data, expected, actor, tenant)
+            .await;
+        self.invalidate(tenant, id, user)
+    }
+
+    async fn replace_user(
+        &self,
+        id: &Uuid,
+        user: User,
+        expected: Option<Version>,
+        actor: &Actor,
+        tenant: &str,
+    ) -> Result<User> {
+        let user = self
+            .inner
+            .replace_user(id, user, expected, actor, tenant)
+            .await;
+        self.invalidate(tenant, id, user)
+    }
+
+    async fn delete_user(
+        &self,
+        id: &Uuid,
+        expected: Option<Version>,
+        actor: &Actor,
+        tenant: &str,
+    ) -> Result<User> {
+        let user = self.inner.delete_user(id, expected, actor, tenant).await;
+        self.invalidate(tenant, id, user)
+    }
+
+    async fn restore_user(&self, id: &Uuid, actor: &Actor, tenant: &str) -> Result<User> {
+        let user = self.inner.restore_user(id, actor, tenant).await;
+        self.invalidate(tenant, id, user)
+    }
+
+    async fn purge_users(&self, deleted_before: DateTime<Utc>) -> Result<u64> {
+        let purged = self.inner.purge_users(deleted_before).await;
+        // the purged users may be in any tenant
+        if let Some(cache) = &self.cache {
+            Self::lru(cache).clear();
+        }
+        purged
+    }
+
+    async fn list_users(&self, listing: &UserListing, tenant: &str) -> Result<Vec<User>> {
+        self.inner.list_users(listing, tenant).await
+    }
+
+    async fn count_users(&self, filter: &UserFilter, tenant: &str) -> Result<i64> {
+        self.inner.count_users(filter, tenant).await
+    }
+
+    async fn user_history(
+        &self,
+        id: &Uuid,
+        listing: &HistoryListing,
+        tenant: &str,
+    ) -> Result<Vec<AuditRecord>> {
+        self.inner.user_history(id, listing, tenant).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::mocks::MockRepo;
+    use mockall::predicate::*;
+
+    const TENANT: &str = "acme";
+
+    fn user(id: Uuid, name: &str) -> User {
+        User {
+            id: Some(id),
+            name: name.to_string(),
+            ..User::default()
+        }
+    }
+
+    fn cached(mock: MockRepo, config: CacheConfig) -> (CachingRepository<MockRepo>, Registry) {
+        let registry = Registry::new();
+        let metrics = CacheMetrics::register(&registry).unwrap();
+        (CachingRepository::new(mock, config, metrics), registry)
+    }
+
+    fn counter(registry: &Registry, result: &str) -> u64 {
+        registry
+            .gather()
+            .iter()
+            .filter(|family| family.get_name() == "rpts02_api_users_cache_requests_total")
+            .flat_map(|family| family.get_metric())
+            .filter(|metric| metric.get_label()[0].get_value() == result)
+            .map(|metric| metric.get_counter().get_value() as u64)
+            .sum()
+    }
+
+    #[actix_rt::test]
+    async fn get_user_is_read_once() {
+        let mut mock = MockRepo::default();
+        let id = Uuid::new_v4();
+        mock.expect_sync_get_user()
+            .with(eq(id), eq(false), function(|tenant: &str| tenant == TENANT))
+            .times(1)
+            .returning(|id, _, _| Ok(user(*id, "cached")));
+        let (repo, registry) = cached(mock, CacheConfig::default());
+
+        let first = repo.get_user(&id, false, TENANT).await.unwrap();
+        let second = repo.get_user(&id, false, TENANT).await.unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(counter(&registry, "miss"), 1);
+        assert_eq!(counter(&registry, "hit"), 1);
+    }
+
+    #[actix_rt::test]
+    async fn get_user_is_cached_per_tenant_and_deleted_flag() {
+        let mut mock = MockRepo::default();
+        let id = Uuid::new_v4();
+        mock.expect_sync_get_user()
+            .times(3)
+            .returning(|id, _, _| Ok(user(*id, "cached")));
+        let (repo, _) = cached(mock, CacheConfig::default());
+
+        repo.get_user(&id, false, TENANT).await.unwrap();
+        repo.get_user(&id, true, TENANT).await.unwrap();
+        repo.get_user(&id, false, "other").await.unwrap();
+        repo.get_user(&id, false, TENANT).await.unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn users_not_found_are_cached_too() {
+        let mut mock = MockRepo::default();
+        let id = Uuid::new_v4();
+        mock.expect_sync_get_user()
+            .times(1)
+            .returning(|_, _, _| Err(sqlx::Error::RowNotFound));
+        let (repo, registry) = cached(mock, CacheConfig::default());
+
+        let first = repo.get_user(&id, false, TENANT).await;
+        let second = repo.get_user(&id, false, TENANT).await;
+
+        assert!(matches!(first, Err(sqlx::Error::RowNotFound)));
+        assert!(matches!(second, Err(sqlx::Error::RowNotFound)));
+        assert_eq!(counter(&registry, "hit"), 1);
+    }
+
+    #[actix_rt::test]
+    async fn other_errors_arent_cached() {
+        let mut mock = MockRepo::default();
+        let id = Uuid::new_v4();
+        mock.expect_sync_get_user()
+            .times(2)
+            .returning(|_, _, _| Err(sqlx::Error::PoolTimedOut));
+        let (repo, _) = cached(mock, CacheConfig::default());
+
+        assert!(repo.get_user(&id, false, TENANT).await.is_err());
+        assert!(repo.get_user(&id, false, TENANT).await.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn expired_users_are_read_again() {
+        let mut mock = MockRepo::default();
+        let id = Uuid::new_v4();
+        mock.expect_sync_get_user()
+            .times(2)
+            .returning(|id, _, _| Ok(user(*id, "cached")));
+        let config = CacheConfig {
+            ttl: Duration::from_secs(0),
+            ..CacheConfig::default()
+        };
+        let (repo, _) = cached(mock, config);
+
+        repo.get_user(&id, false, TENANT).await.unwrap();
+        repo.get_user(&id, false, TENANT).await.unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn least_recently_used_users_are_evicted() {
+        let mut mock = MockRepo::default();
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        mock.expect_sync_get_user()
+            .with(eq(a), always(), always())
+            .times(1)
+            .returning(|id, _, _| Ok(user(*id, "a")));
+        mock.expect_sync_get_user()
+            .with(eq(b), always(), always())
+            .times(2)
+            .returning(|id, _, _| Ok(user(*id, "b")));
+        mock.expect_sync_get_user()
+            .with(eq(c), always(), always())
+            .times(1)
+            .returning(|id, _, _| Ok(user(*id, "c")));
+        let config = CacheConfig {
+            capacity: 2,
+            ..CacheConfig::default()
+        };
+        let (repo, _) = cached(mock, config);
+
+        repo.get_user(&a, false, TENANT).await.unwrap();
+        repo.get_user(&b, false, TENANT).await.unwrap();
+        // a is used again, so b is the one evicted by c
+        repo.get_user(&a, false, TENANT).await.unwrap();
+        repo.get_user(&c, false, TENANT).await.unwrap();
+        repo.get_user(&a, false, TENANT).await.unwrap();
+        repo.get_user(&b, false, TENANT).await.unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn writes_invalidate_the_user() {
+        let mut mock = MockRepo::default();
+        let id = Uuid::new_v4();
+        let mut seq = mockall::Sequence::new();
+        mock.expect_sync_get_user()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|id, _, _| Ok(user(*id, "before")));
+        mock.expect_sync_update_user()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|id, _, _, _, _| Ok(user(*id, "after")));
+        mock.expect_sync_get_user()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|id, _, _| Ok(user(*id, "after")));
+        mock.expect_sync_delete_user()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _, _, _| Err(sqlx::Error::RowNotFound));
+        mock.expect_sync_get_user()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|id, _, _| Ok(user(*id, "after")));
+        let (repo, _) = cached(mock, CacheConfig::default());
+        let actor = Actor::new(Some("admin".to_string()), None);
+
+        repo.get_user(&id, false, TENANT).await.unwrap();
+        repo.update_user(&id, CustomData::default(), None, &actor, TENANT)
+            .await
+            .unwrap();
+        let updated = repo.get_user(&id, false, TENANT).await.unwrap();
+        // even failed writes invalidate, as they may have found a newer user
+        repo.delete_user(&id, None, &actor, TENANT)
+            .await
+            .unwrap_err();
+        repo.get_user(&id, false, TENANT).await.unwrap();
+
+        assert_eq!(updated.name, "after");
+    }
+
+    #[actix_rt::test]
+    async fn created_users_arent_cached_as_not_found_anymore() {
+        let mut mock = MockRepo::default();
+        let id = Uuid::new_v4();
+        let mut seq = mockall::Sequence::new();
+        mock.expect_sync_get_user()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _, _| Err(sqlx::Error::RowNotFound));
+        mock.expect_sync_create_user()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|user, _, _| Ok(user));
+        mock.expect_sync_get_user()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|id, _, _| Ok(user(*id, "signed_up")));
+        let (repo, _) = cached(mock, CacheConfig::default());
+
+        repo.get_user(&id, false, TENANT).await.unwrap_err();
+        repo.create_user(
+            user(id, "signed_up"),
+            &Actor::new(Some("admin".to_string()), None),
+            TENANT,
+        )
+        .await
+        .unwrap();
+        let created = repo.get_user(&id, false, TENANT).await.unwrap();
+
+        assert_eq!(created.name, "signed_up");
+    }
+
+    #[actix_rt::test]
+    async fn disabled_cache_reads_every_time() {
+        let mut mock = MockRepo::default();
+        let id = Uuid::new_v4();
+        mock.expect_sync_get_user()
+            .times(2)
+            .returning(|id, _, _| Ok(user(*id, "uncached")));
+        let repo = CachingRepository::disabled(mock);
+
+        repo.get_user(&id, false, TENANT).await.unwrap();
+        repo.get_user(&id, false, TENANT).await.unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+//! Entity tags of the users, for conditional requests.
+//!
+//! The version of a user is the time of its last change, so the tag changes on every write
+//! and can be checked by Postgres in the same statement that writes the user.
+use crate::models::User;
+use crate::problem::Problem;
+use actix_web::{
+    http::{header, StatusCode},
+    HttpRequest,
+};
+use chrono::{DateTime, TimeZone, Utc};
+
+/// Version of a user, exposed as a strong `ETag`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Version(DateTime<Utc>);
+
+impl Version {
+    pub fn of(user: &User) -> Self {
+        Self(
+            user.updated_at
+                .or(user.created_at)
+                .unwrap_or_else(|| Utc.timestamp(0, 0)),
+        )
+    }
+
+    /// The value of `COALESCE(updated_at, created_at)` for this version.
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        self.0
+    }
+
+    /// Postgres keeps microseconds, so they're enough to tell versions apart.
+    pub fn etag(&self) -> String {
+        let micros = self.0.timestamp() * 1_000_000 + i64::from(self.0.timestamp_subsec_micros());
+        format!("\"{}\"", micros)
+    }
+
+    /// Parses a strong entity tag. Weak ones never match a write precondition.
+    pub fn from_etag(etag: &str) -> Option<Self> {
+        let micros: i64 = etag
+            .trim()
+            .strip_prefix('"')?
+            .strip_suffix('"')?
+            .parse()
+            .ok()?;
+        let nanos = (micros.rem_euclid(1_000_000) * 1_000) as u32;
+        Some(Self(Utc.timestamp(micros.div_euclid(1_000_000), nanos)))
+    }
+}
+
+/// Reads the `If-Match` precondition of a write.
+/// There's nothing to check if the header is missing or `*`, as the user must exist anyway.
+/// Tags that can never match fail right away with `412 Precondition Failed`.
+pub fn if_match(req: &HttpRequest) -> Result<Option<Version>, Problem> {
+    let value = match req.headers().get(header::IF_MATCH) {
+        Some(value) => value.to_str().unwrap_or_default().trim(),
+        None => return Ok(None),
+    };
+    if value == "*" {
+        return Ok(None);
+    }
+    Version::from_etag(value).map(Some).ok_or_else(|| {
+        Problem::new(
+            StatusCode::PRECONDITION_FAILED,
+            "precondition-failed",
+            "The If-Match header doesn't match",
+        )
+        .for_request(req)
+    })
+}
+
+/// Whether the `If-None-Match` header of a read matches the current version.
+pub fn if_none_match(req: &HttpRequest, version: &Version) -> bool {
+    let etag = version.etag();
+    req.headers()
+        .get_all(header::IF_NONE_MATCH)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn version() -> Version {
+        let mut user = User::default();
+        user.created_at = Some(Utc.timestamp(1_608_000_000, 123_456_000));
+        Version::of(&user)
+    }
+
+    #[test]
+    fn version_uses_the_last_change() {
+        let mut user = User::default();
+        user.created_at = Some(Utc.timestamp(1, 0));
+        user.updated_at = Some(Utc.timestamp(2, 0));
+        assert_eq!(Version::of(&user).timestamp(), Utc.timestamp(2, 0));
+    }
+
+    #[test]
+    fn etag_roundtrip_works() {
+        let version = version();
+        assert_eq!(version.etag(), "\"1608000000123456\"");
+        assert_eq!(Version::from_etag(&version.etag()), Some(version));
+    }
+
+    #[test]
+    fn from_etag_rejects_weak_and_malformed_tags() {
+        assert_eq!(Version::from_etag("W/\"1608000000123456\""), None);
+        assert_eq!(Version::from_etag("1608000000123456"), None);
+        assert_eq!(Version::from_etag("\"abc\""), None);
+    }
+
+    #[test]
+    fn if_match_works() {
+        let req = TestRequest::with_header("If-Match", version().etag()).to_http_request();
+        assert_eq!(if_match(&req).unwrap(), Some(version()));
+    }
+
+    #[test]
+    fn if_match_is_optional() {
+        let req = TestRequest::default().to_http_request();
+        assert_eq!(if_match(&req).unwrap(), None);
+        let req = TestRequest::with_header("If-Match", "*").to_http_request();
+        assert_eq!(if_match(&req).unwrap(), None);
+    }
+
+    #[test]
+    fn if_match_fails_with_weak_tags() {
+        let req = TestRequest::with_header("If-Match", "W/\"1\"").to_http_request();
+        let problem = if_match(&req).err().unwrap();
+        assert_eq!(problem.status, 412);
+    }
+
+    #[test]
+    fn if_none_match_works() {
+        let etags = format!("\"1\", W/{}", version().etag());
+        let req = TestRequest::with_header("If-None-Match", etags).to_http_request();
+        assert!(if_none_match(&req, &version()));
+
+        let req = TestRequest::with_header("If-None-Match", "\"1\"").to_http_request();
+        assert!(!if_none_match(&req, &version()));
+    }
+}
@@ -1,7 +1,12 @@
-use super::service::ServiceError;
-use crate::models::{CustomData, User};
-use actix_web::{error, web, HttpRequest, HttpResponse, Result};
-use actix_web_middleware_cognito::CognitoInfo;
+use super::etag::{self, Version};
+use super::idempotency::{self, IdempotencyKey, Idempotent};
+use super::listing;
+use super::validated::Validated;
+use crate::auth::Caller;
+use crate::models::{CustomData, HistoryQuery, ListQuery, ReadQuery, User, UserPatch};
+use crate::problem::{request_id, Problem, Result};
+use actix_web::{web, HttpRequest, HttpResponse};
+use rpts_domain::redact::Sensitive;
 use tracing::{self as log, instrument};
 use uuid::Uuid;
 
@@ -9,138 +14,309 @@ pub mod users {
     use super::*;
 
     pub const PATH: &str = "/users";
+    pub const RESTORE_PATH: &str = "/restore";
+    pub const HISTORY_PATH: &str = "/history";
 
-    #[instrument]
+    #[instrument(
+        skip(req, svc),
+        fields(http.method = %req.method(), http.route = "/v1/users/{id}", enduser.id = ?auth.user)
+    )]
     pub async fn get<S: crate::v1::service::Service>(
         id: web::Path<Uuid>,
+        query: web::Query<ReadQuery>,
         req: HttpRequest,
-        auth: CognitoInfo,
+        auth: Caller,
         svc: web::Data<S>,
     ) -> Result<HttpResponse> {
+        let user = svc
+            .as_ref()
+            .get_user(&id, auth, query.include_deleted)
+            .await;
+        if let Ok(user) = &user {
+            let version = Version::of(user);
+            if etag::if_none_match(&req, &version) {
+                return Ok(HttpResponse::NotModified()
+                    .header("ETag", version.etag())
+                    .finish());
+            }
+        }
         svc_response!(
-            svc.as_ref().get_user(&id, auth.user).await,
+            user,
             HttpResponse::Ok(),
-            req.path(),
+            req,
             format!("Error getting user {}", id)
         )
     }
 
-    #[instrument]
+    #[instrument(
+        skip(req, svc),
+        fields(http.method = %req.method(), http.route = "/v1/users", enduser.id = ?auth.user)
+    )]
+    pub async fn list<S: crate::v1::service::Service>(
+        query: web::Query<ListQuery>,
+        req: HttpRequest,
+        auth: Caller,
+        svc: web::Data<S>,
+    ) -> Result<HttpResponse> {
+        let query = query.into_inner();
+        match svc.as_ref().list_users(auth, query.clone()).await {
+            Ok(page) => {
+                let mut response = HttpResponse::Ok();
+                if let Some(link) = listing::link_header(req.path(), &query, &page) {
+                    response.header("Link", link);
+                }
+                if let Some(total) = page.total {
+                    response.header("X-Total-Count", total.to_string());
+                }
+                Ok(response.json(page))
+            }
+            Err(err) => {
+                log::error!("Error listing users: {}", err);
+                Err(Problem::from(err).for_request(&req))
+            }
+        }
+    }
+
+    #[instrument(
+        skip(req, svc),
+        fields(http.method = %req.method(), http.route = "/v1/users", enduser.id = ?auth.user)
+    )]
     pub async fn post<S: crate::v1::service::Service>(
-        user: web::Json<User>,
+        user: Validated<User>,
         req: HttpRequest,
+        auth: Caller,
         svc: web::Data<S>,
     ) -> Result<HttpResponse> {
-        match svc.as_ref().create_user(user.into_inner()).await {
-            Ok(usr) => {
-                let response = HttpResponse::Created()
+        let user = user.into_inner();
+        let request_id = Some(request_id(&req));
+        let created = match IdempotencyKey::from_request(&req, &user)? {
+            Some(key) => {
+                svc.as_ref()
+                    .create_user_idempotent(user, key, auth, request_id)
+                    .await
+            }
+            None => svc
+                .as_ref()
+                .create_user(user, auth, request_id)
+                .await
+                .map(Idempotent::Created),
+        };
+        match created {
+            Ok(created) => {
+                let mut response = HttpResponse::Created();
+                if created.is_replayed() {
+                    response.header(idempotency::REPLAYED_HEADER, "true");
+                }
+                let usr = created.into_inner();
+                let response = response
                     .header("Location", format!("{}/{}", req.path(), usr.id.unwrap()))
                     .json(usr);
                 Ok(response)
             }
             Err(err) => {
                 log::error!("Error creating user: {}", err);
-                svc_err!(err)
+                Err(Problem::from(err).for_request(&req))
             }
         }
     }
 
-    #[instrument]
+    #[instrument(
+        skip(req, svc),
+        fields(http.method = %req.method(), http.route = "/v1/users/{id}", enduser.id = ?auth.user)
+    )]
     pub async fn patch<S: crate::v1::service::Service>(
         id: web::Path<uuid::Uuid>,
-        custom_data: web::Json<CustomData>,
+        custom_data: Validated<CustomData>,
         req: HttpRequest,
-        auth: CognitoInfo,
+        auth: Caller,
         svc: web::Data<S>,
     ) -> Result<HttpResponse> {
+        let expected = etag::if_match(&req)?;
         svc_response!(
             svc.as_ref()
-                .update_user(&id, auth.user, custom_data.into_inner())
+                .update_user(
+                    &id,
+                    auth,
+                    custom_data.into_inner(),
+                    expected,
+                    Some(request_id(&req)),
+                )
                 .await,
             HttpResponse::Ok(),
-            req.path(),
+            req,
             format!("Error updating user: {}", id)
         )
     }
 
-    #[instrument]
-    pub async fn delete<S: crate::v1::service::Service>(
+    #[instrument(
+        skip(req, svc),
+        fields(http.method = %req.method(), http.route = "/v1/users/{id}", enduser.id = ?auth.user)
+    )]
+    pub async fn put<S: crate::v1::service::Service>(
         id: web::Path<uuid::Uuid>,
+        user: Validated<User>,
         req: HttpRequest,
-        auth: CognitoInfo,
+        auth: Caller,
         svc: web::Data<S>,
     ) -> Result<HttpResponse> {
+        let expected = etag::if_match(&req)?;
         svc_response!(
-            svc.as_ref().delete_user(&id, auth.user).await,
+            svc.as_ref()
+                .replace_user(
+                    &id,
+                    auth,
+                    user.into_inner(),
+                    expected,
+                    Some(request_id(&req)),
+                )
+                .await,
             HttpResponse::Ok(),
-            req.path(),
-            format!("Error getting user {}", id)
+            req,
+            format!("Error replacing user: {}", id)
         )
     }
 
-    #[cfg(test)]
-    mod tests {
-        use super::*;
-        use crate::v1::service::{Result as ServiceResult, Service};
-        use actix_web::{dev::Body, test};
-        use async_trait::async_trait;
-        use mockall::predicate::*;
-        use mockall::*;
-
-        mock! {
-            pub Svc {
-                fn sync_get_user(&self, user_id: &Uuid, caller_id: Option<String>) -> ServiceResult<User> {}
-                fn sync_update_user(
-                    &self, user_id: &Uuid,
-                    caller_id: Option<String>,
-                    custom_data: CustomData,
-                ) -> ServiceResult<User> {}
-                fn sync_create_user(
-                    &self,
-                    user: User,
-                ) -> ServiceResult<User> {}
-                fn sync_delete_user(
-                    &self,
-                    user_id: &Uuid,
-                    caller_id: Option<String>,
-                ) -> ServiceResult<User> {}
-            }
-        }
+    /// PATCH with an `application/merge-patch+json` body.
+    #[instrument(
+        skip(req, svc, patch),
+        fields(
+            http.method = %req.method(),
+            http.route = "/v1/users/{id}",
+            enduser.id = ?auth.user,
+            patch = ?Sensitive(&patch.0)
+        )
+    )]
+    pub async fn merge_patch<S: crate::v1::service::Service>(
+        id: web::Path<uuid::Uuid>,
+        patch: web::Json<serde_json::Value>,
+        req: HttpRequest,
+        auth: Caller,
+        svc: web::Data<S>,
+    ) -> Result<HttpResponse> {
+        let expected = etag::if_match(&req)?;
+        let patch = UserPatch::Merge(patch.into_inner());
+        svc_response!(
+            svc.as_ref()
+                .patch_user(&id, auth, patch, expected, Some(request_id(&req)))
+                .await,
+            HttpResponse::Ok(),
+            req,
+            format!("Error patching user: {}", id)
+        )
+    }
 
-        impl std::fmt::Debug for MockSvc {
-            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                f.debug_struct("MockSvc").finish()
-            }
-        }
+    /// PATCH with an `application/json-patch+json` body.
+    #[instrument(
+        skip(req, svc, patch),
+        fields(
+            http.method = %req.method(),
+            http.route = "/v1/users/{id}",
+            enduser.id = ?auth.user,
+            patch = ?Sensitive(&patch.0)
+        )
+    )]
+    pub async fn json_patch<S: crate::v1::service::Service>(
+        id: web::Path<uuid::Uuid>,
+        patch: web::Json<json_patch::Patch>,
+        req: HttpRequest,
+        auth: Caller,
+        svc: web::Data<S>,
+    ) -> Result<HttpResponse> {
+        let expected = etag::if_match(&req)?;
+        let patch = UserPatch::Json(patch.into_inner());
+        svc_response!(
+            svc.as_ref()
+                .patch_user(&id, auth, patch, expected, Some(request_id(&req)))
+                .await,
+            HttpResponse::Ok(),
+            req,
+            format!("Error patching user: {}", id)
+        )
+    }
 
-        #[async_trait]
-        impl Service for MockSvc {
-            async fn get_user(
-                &self,
-                user_id: &Uuid,
-                caller_id: Option<String>,
-            ) -> ServiceResult<User> {
-                self.sync_get_user(&user_id, caller_id)
-            }
-            async fn update_user(
-                &self,
-                user_id: &Uuid,
-                caller_id: Option<String>,
-                custom_data: CustomData,
-            ) -> ServiceResult<User> {
-                self.sync_update_user(&user_id, caller_id, custom_data)
+    #[instrument(
+        skip(req, svc),
+        fields(http.method = %req.method(), http.route = "/v1/users/{id}/restore", enduser.id = ?auth.user)
+    )]
+    pub async fn restore<S: crate::v1::service::Service>(
+        id: web::Path<uuid::Uuid>,
+        req: HttpRequest,
+        auth: Caller,
+        svc: web::Data<S>,
+    ) -> Result<HttpResponse> {
+        match svc
+            .as_ref()
+            .restore_user(&id, auth, Some(request_id(&req)))
+            .await
+        {
+            Ok(user) => {
+                // the restored user is at its own path, not at the restore one
+                let response = HttpResponse::Ok()
+                    .header("Location", req.path().trim_end_matches(RESTORE_PATH))
+                    .header("ETag", Version::of(&user).etag())
+                    .json(user);
+                Ok(response)
             }
-            async fn create_user(&self, user: User) -> ServiceResult<User> {
-                self.sync_create_user(user)
+            Err(err) => {
+                log::error!("Error restoring user {}: {}", id, err);
+                Err(Problem::from(err).for_request(&req))
             }
-            async fn delete_user(
-                &self,
-                user_id: &Uuid,
-                caller_id: Option<String>,
-            ) -> ServiceResult<User> {
-                self.sync_delete_user(&user_id, caller_id)
+        }
+    }
+
+    /// The changes of a user, the latest first.
+    #[instrument(
+        skip(req, svc),
+        fields(http.method = %req.method(), http.route = "/v1/users/{id}/history", enduser.id = ?auth.user)
+    )]
+    pub async fn history<S: crate::v1::service::Service>(
+        id: web::Path<uuid::Uuid>,
+        query: web::Query<HistoryQuery>,
+        req: HttpRequest,
+        auth: Caller,
+        svc: web::Data<S>,
+    ) -> Result<HttpResponse> {
+        match svc
+            .as_ref()
+            .user_history(&id, auth, query.into_inner())
+            .await
+        {
+            Ok(page) => Ok(HttpResponse::Ok().json(page)),
+            Err(err) => {
+                log::error!("Error getting the history of user {}: {}", id, err);
+                Err(Problem::from(err).for_request(&req))
             }
         }
+    }
+
+    #[instrument(
+        skip(req, svc),
+        fields(http.method = %req.method(), http.route = "/v1/users/{id}", enduser.id = ?auth.user)
+    )]
+    pub async fn delete<S: crate::v1::service::Service>(
+        id: web::Path<uuid::Uuid>,
+        req: HttpRequest,
+        auth: Caller,
+        svc: web::Data<S>,
+    ) -> Result<HttpResponse> {
+        let expected = etag::if_match(&req)?;
+        svc_response!(
+            svc.as_ref()
+                .delete_user(&id, auth, expected, Some(request_id(&req)))
+                .await,
+            HttpResponse::Ok(),
+            req,
+            format!("Error getting user {}", id)
+        )
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::models::{HistoryPage, UserPage};
+        use crate::v1::mocks::MockSvc;
+        use crate::v1::service::{ServiceError, USER_NOT_FOUND};
+        use actix_web::{dev::Body, test};
 
         // get handler
 
@@ -153,7 +329,7 @@ pub mod users {
             let mut mock_svc = MockSvc::default();
             mock_svc
                 .expect_sync_get_user()
-                .returning(move |user_id, _caller_id| {
+                .returning(move |user_id, _caller, _| {
                     let mut user = User::default();
                     user.id = Some(*user_id);
                     user.name = user_name.to_string();
@@ -162,9 +338,11 @@ pub mod users {
 
             let svc = web::Data::new(mock_svc);
             let id = web::Path::from(user_id);
-            let auth = CognitoInfo::disabled();
+            let auth = Caller::disabled();
             let req = test::TestRequest::with_uri(path.as_ref()).to_http_request();
-            let mut res: HttpResponse = get(id, req, auth, svc).await.unwrap();
+            let mut res: HttpResponse = get(id, web::Query(ReadQuery::default()), req, auth, svc)
+                .await
+                .unwrap();
 
             let user = res
                 .take_body()
@@ -185,285 +363,271 @@ pub mod users {
         }
 
         #[actix_rt::test]
-        async fn get_users_handler_maps_err_to_unauthorized() {
+        async fn get_users_handler_returns_etag() {
             let mut mock_svc = MockSvc::default();
-            let err_svc = || ServiceError::Unauthorized;
             mock_svc
                 .expect_sync_get_user()
-                .returning(move |_, _| Err(err_svc()));
+                .returning(move |_, _, _| Ok(User::default()));
 
             let svc = web::Data::new(mock_svc);
             let id = web::Path::from(Uuid::new_v4());
-            let auth = CognitoInfo::disabled();
+            let auth = Caller::disabled();
             let req = test::TestRequest::default().to_http_request();
-            let res = get(id, req, auth, svc).await.err().unwrap();
-            let err = res.as_response_error();
+            let res: HttpResponse = get(id, web::Query(ReadQuery::default()), req, auth, svc)
+                .await
+                .unwrap();
 
-            assert_eq!(err.to_string(), err_svc().to_string());
-            assert_eq!(err.status_code().as_u16(), 401);
+            let etag = res.headers().get("ETag").unwrap().to_str().unwrap();
+
+            assert_eq!(etag, Version::of(&User::default()).etag());
+            assert!(res.status().is_success());
         }
 
         #[actix_rt::test]
-        async fn get_users_handler_maps_err_to_not_found() {
+        async fn get_users_handler_returns_not_modified_if_none_match() {
             let mut mock_svc = MockSvc::default();
-            let err_svc = || ServiceError::DbError(sqlx::Error::RowNotFound);
             mock_svc
                 .expect_sync_get_user()
-                .returning(move |_, _| Err(err_svc()));
+                .returning(move |_, _, _| Ok(User::default()));
 
             let svc = web::Data::new(mock_svc);
             let id = web::Path::from(Uuid::new_v4());
-            let auth = CognitoInfo::disabled();
-            let req = test::TestRequest::default().to_http_request();
-            let res = get(id, req, auth, svc).await.err().unwrap();
-            let err = res.as_response_error();
+            let auth = Caller::disabled();
+            let etag = Version::of(&User::default()).etag();
+            let req = test::TestRequest::with_header("If-None-Match", etag).to_http_request();
+            let res: HttpResponse = get(id, web::Query(ReadQuery::default()), req, auth, svc)
+                .await
+                .unwrap();
 
-            assert_eq!(err.to_string(), err_svc().to_string());
-            assert_eq!(err.status_code().as_u16(), 404);
+            assert_eq!(res.status().as_u16(), 304);
+            assert!(res.headers().get("ETag").is_some());
         }
 
         #[actix_rt::test]
-        async fn get_users_handler_maps_err_to_internal() {
+        async fn get_users_handler_passes_include_deleted_to_the_service() {
             let mut mock_svc = MockSvc::default();
-            let err_svc = || ServiceError::DbError(sqlx::Error::PoolTimedOut);
             mock_svc
                 .expect_sync_get_user()
-                .returning(move |_, _| Err(err_svc()));
+                .withf(|_, _, include_deleted| *include_deleted)
+                .returning(|_, _, _| Ok(User::default()));
 
             let svc = web::Data::new(mock_svc);
             let id = web::Path::from(Uuid::new_v4());
-            let auth = CognitoInfo::disabled();
+            let query = web::Query(ReadQuery {
+                include_deleted: true,
+            });
+            let auth = Caller::disabled();
             let req = test::TestRequest::default().to_http_request();
-            let res = get(id, req, auth, svc).await.err().unwrap();
-            let err = res.as_response_error();
+            let res: HttpResponse = get(id, query, req, auth, svc).await.unwrap();
 
-            assert_eq!(err.to_string(), "Database Error");
-            assert_eq!(err.status_code().as_u16(), 500);
+            assert!(res.status().is_success());
         }
 
-        // post handler
-
         #[actix_rt::test]
-        async fn post_users_handler_works() {
-            let user_id = Uuid::new_v4();
-            let user_name = "my_name";
-            let path = format!("/v1/users/{}", user_id);
-
-            let mut user = User::default();
-            user.id = Some(user_id);
-            user.name = user_name.to_string();
-
+        async fn get_users_handler_maps_err_to_unauthorized() {
             let mut mock_svc = MockSvc::default();
+            let err_svc = || ServiceError::Unauthorized;
             mock_svc
-                .expect_sync_create_user()
-                .returning(move |user| Ok(user));
+                .expect_sync_get_user()
+                .returning(move |_, _, _| Err(err_svc()));
 
             let svc = web::Data::new(mock_svc);
-            let usr = web::Json(user);
-            let req = test::TestRequest::with_uri("/v1/users").to_http_request();
-            let mut res: HttpResponse = post(usr, req, svc).await.unwrap();
-
-            let user = res
-                .take_body()
-                .as_ref()
-                .map(|b| match b {
-                    Body::Bytes(x) => serde_json::from_slice::<'_, User>(x).ok(),
-                    _ => None,
-                })
-                .flatten()
+            let id = web::Path::from(Uuid::new_v4());
+            let auth = Caller::disabled();
+            let req = test::TestRequest::default().to_http_request();
+            let problem = get(id, web::Query(ReadQuery::default()), req, auth, svc)
+                .await
+                .err()
                 .unwrap();
 
-            let location = res.headers().get("Location").unwrap().to_str().unwrap();
-
-            assert_eq!(user.id.unwrap(), user_id);
-            assert_eq!(user.name, user_name);
-            assert_eq!(location, path);
-            assert!(res.status().is_success());
+            assert_eq!(problem.detail, Some(err_svc().to_string()));
+            assert_eq!(problem.status, 401);
         }
 
         #[actix_rt::test]
-        async fn post_users_handler_maps_err_to_unauthorized() {
+        async fn get_users_handler_maps_err_to_not_found() {
             let mut mock_svc = MockSvc::default();
-            let err_svc = || ServiceError::Unauthorized;
+            let err_svc = || ServiceError::DbError(sqlx::Error::RowNotFound);
             mock_svc
-                .expect_sync_create_user()
-                .returning(move |_| Err(err_svc()));
+                .expect_sync_get_user()
+                .returning(move |_, _, _| Err(err_svc()));
 
             let svc = web::Data::new(mock_svc);
-            let usr = web::Json(User::default());
+            let id = web::Path::from(Uuid::new_v4());
+            let auth = Caller::disabled();
             let req = test::TestRequest::default().to_http_request();
-            let res = post(usr, req, svc).await.err().unwrap();
-            let err = res.as_response_error();
+            let problem = get(id, web::Query(ReadQuery::default()), req, auth, svc)
+                .await
+                .err()
+                .unwrap();
 
-            assert_eq!(err.to_string(), err_svc().to_string());
-            assert_eq!(err.status_code().as_u16(), 401);
+            assert_eq!(problem.detail.as_deref(), Some(USER_NOT_FOUND));
+            assert_eq!(problem.status, 404);
         }
 
         #[actix_rt::test]
-        async fn post_users_handler_maps_err_to_not_found() {
+        async fn get_users_handler_problems_point_to_the_request() {
             let mut mock_svc = MockSvc::default();
-            let err_svc = || ServiceError::DbError(sqlx::Error::RowNotFound);
             mock_svc
-                .expect_sync_create_user()
-                .returning(move |_| Err(err_svc()));
+                .expect_sync_get_user()
+                .returning(move |_, _, _| Err(ServiceError::DbError(sqlx::Error::RowNotFound)));
 
             let svc = web::Data::new(mock_svc);
-            let usr = web::Json(User::default());
-            let req = test::TestRequest::default().to_http_request();
-            let res = post(usr, req, svc).await.err().unwrap();
-            let err = res.as_response_error();
-
-            assert_eq!(err.to_string(), err_svc().to_string());
-            assert_eq!(err.status_code().as_u16(), 404);
+            let user_id = Uuid::new_v4();
+            let path = format!("/v1/users/{}", user_id);
+            let auth = Caller::disabled();
+            let req = test::TestRequest::with_uri(path.as_ref())
+                .header("X-Request-Id", "my-request")
+                .to_http_request();
+            let problem = get(
+                web::Path::from(user_id),
+                web::Query(ReadQuery::default()),
+                req,
+                auth,
+                svc,
+            )
+            .await
+            .err()
+            .unwrap();
+
+            assert_eq!(problem.problem_type, "/problems/not-found");
+            assert_eq!(problem.title, "Not Found");
+            assert_eq!(problem.detail.as_deref(), Some(USER_NOT_FOUND));
+            assert_eq!(problem.instance, Some(path));
+            assert_eq!(problem.request_id, Some("my-request".to_string()));
         }
 
         #[actix_rt::test]
-        async fn post_users_handler_maps_err_to_internal() {
+        async fn get_users_handler_maps_err_to_internal() {
             let mut mock_svc = MockSvc::default();
             let err_svc = || ServiceError::DbError(sqlx::Error::PoolTimedOut);
             mock_svc
-                .expect_sync_create_user()
-                .returning(move |_| Err(err_svc()));
+                .expect_sync_get_user()
+                .returning(move |_, _, _| Err(err_svc()));
 
             let svc = web::Data::new(mock_svc);
-            let usr = web::Json(User::default());
+            let id = web::Path::from(Uuid::new_v4());
+            let auth = Caller::disabled();
             let req = test::TestRequest::default().to_http_request();
-            let res = post(usr, req, svc).await.err().unwrap();
-            let err = res.as_response_error();
+            let problem = get(id, web::Query(ReadQuery::default()), req, auth, svc)
+                .await
+                .err()
+                .unwrap();
 
-            assert_eq!(err.to_string(), "Database Error");
-            assert_eq!(err.status_code().as_u16(), 500);
+            assert_eq!(problem.detail, Some("Database Error".to_string()));
+            assert_eq!(problem.status, 500);
         }
 
-        // patch handler
+        // list handler
 
         #[actix_rt::test]
-        async fn patch_users_handler_works() {
-            let user_id = Uuid::new_v4();
-            let user_name = "my_name";
-            let random = 74444;
-            let path = format!("/v1/users/{}", user_id);
-
+        async fn list_users_handler_works() {
             let mut mock_svc = MockSvc::default();
             mock_svc
-                .expect_sync_update_user()
-                .returning(move |user_id, _caller, custom_data| {
-                    let mut user = User::default();
-                    user.id = Some(*user_id);
-                    user.name = user_name.to_string();
-                    user.custom_data = Some(sqlx::types::Json(custom_data));
-                    Ok(user)
+                .expect_sync_list_users()
+                .returning(move |_caller, _query| {
+                    Ok(UserPage {
+                        items: vec![User::default()],
+                        next_cursor: Some("next".to_string()),
+                        prev_cursor: None,
+                        total: Some(3),
+                    })
                 });
 
             let svc = web::Data::new(mock_svc);
-            let id = web::Path::from(user_id);
-            let fields = web::Json(CustomData { random });
-            let auth = CognitoInfo::disabled();
-            let req = test::TestRequest::with_uri(path.as_ref()).to_http_request();
-            let mut res: HttpResponse = patch(id, fields, req, auth, svc).await.unwrap();
+            let query = web::Query(ListQuery {
+                limit: Some(1),
+                include_total: true,
+                ..ListQuery::default()
+            });
+            let auth = Caller::disabled();
+            let req = test::TestRequest::with_uri("/v1/users").to_http_request();
+            let mut res: HttpResponse = list(query, req, auth, svc).await.unwrap();
 
-            let user = res
+            let page = res
                 .take_body()
                 .as_ref()
                 .map(|b| match b {
-                    Body::Bytes(x) => serde_json::from_slice::<'_, User>(x).ok(),
+                    Body::Bytes(x) => serde_json::from_slice::<'_, UserPage>(x).ok(),
                     _ => None,
                 })
                 .flatten()
                 .unwrap();
 
-            let location = res.headers().get("Location").unwrap().to_str().unwrap();
+            let link = res.headers().get("Link").unwrap().to_str().unwrap();
+            let total = res
+                .headers()
+                .get("X-Total-Count")
+                .unwrap()
+                .to_str()
+                .unwrap();
 
-            assert_eq!(user.id.unwrap(), user_id);
-            assert_eq!(user.name, user_name);
-            assert_eq!(user.custom_data.unwrap().random, random);
-            assert_eq!(location, path);
+            assert_eq!(page.items.len(), 1);
+            assert_eq!(
+                link,
+                "</v1/users?limit=1&cursor=next&include_total=true>; rel=\"next\""
+            );
+            assert_eq!(total, "3");
             assert!(res.status().is_success());
         }
 
         #[actix_rt::test]
-        async fn patch_users_handler_maps_err_to_unauthorized() {
+        async fn list_users_handler_maps_err_to_unauthorized() {
             let mut mock_svc = MockSvc::default();
             let err_svc = || ServiceError::Unauthorized;
             mock_svc
-                .expect_sync_update_user()
-                .returning(move |_, _, _| Err(err_svc()));
-
-            let svc = web::Data::new(mock_svc);
-            let id = web::Path::from(Uuid::new_v4());
-            let custom_data = web::Json(CustomData::default());
-            let auth = CognitoInfo::disabled();
-            let req = test::TestRequest::default().to_http_request();
-            let res = patch(id, custom_data, req, auth, svc).await.err().unwrap();
-            let err = res.as_response_error();
-
-            assert_eq!(err.to_string(), err_svc().to_string());
-            assert_eq!(err.status_code().as_u16(), 401);
-        }
-
-        #[actix_rt::test]
-        async fn patch_users_handler_maps_err_to_not_found() {
-            let mut mock_svc = MockSvc::default();
-            let err_svc = || ServiceError::DbError(sqlx::Error::RowNotFound);
-            mock_svc
-                .expect_sync_update_user()
-                .returning(move |_, _, _| Err(err_svc()));
+                .expect_sync_list_users()
+                .returning(move |_, _| Err(err_svc()));
 
             let svc = web::Data::new(mock_svc);
-            let id = web::Path::from(Uuid::new_v4());
-            let custom_data = web::Json(CustomData::default());
-            let auth = CognitoInfo::disabled();
+            let query = web::Query(ListQuery::default());
+            let auth = Caller::disabled();
             let req = test::TestRequest::default().to_http_request();
-            let res = patch(id, custom_data, req, auth, svc).await.err().unwrap();
-            let err = res.as_response_error();
+            let problem = list(query, req, auth, svc).await.err().unwrap();
 
-            assert_eq!(err.to_string(), err_svc().to_string());
-            assert_eq!(err.status_code().as_u16(), 404);
+            assert_eq!(problem.detail, Some(err_svc().to_string()));
+            assert_eq!(problem.status, 401);
         }
 
         #[actix_rt::test]
-        async fn patch_users_handler_maps_err_to_internal() {
+        async fn list_users_handler_maps_err_to_bad_request() {
             let mut mock_svc = MockSvc::default();
-            let err_svc = || ServiceError::DbError(sqlx::Error::PoolTimedOut);
+            let err_svc = || ServiceError::InvalidQuery("Invalid cursor".to_string());
             mock_svc
-                .expect_sync_update_user()
-                .returning(move |_, _, _| Err(err_svc()));
+                .expect_sync_list_users()
+                .returning(move |_, _| Err(err_svc()));
 
             let svc = web::Data::new(mock_svc);
-            let id = web::Path::from(Uuid::new_v4());
-            let custom_data = web::Json(CustomData::default());
-            let auth = CognitoInfo::disabled();
+            let query = web::Query(ListQuery::default());
+            let auth = Caller::disabled();
             let req = test::TestRequest::default().to_http_request();
-            let res = patch(id, custom_data, req, auth, svc).await.err().unwrap();
-            let err = res.as_response_error();
+            let problem = list(query, req, auth, svc).await.err().unwrap();
 
-            assert_eq!(err.to_string(), "Database Error");
-            assert_eq!(err.status_code().as_u16(), 500);
+            assert_eq!(problem.detail, Some(err_svc().to_string()));
+            assert_eq!(problem.status, 400);
         }
 
-        // delete handler
+        // post handler
 
         #[actix_rt::test]
-        async fn delete_users_handler_works() {
+        async fn post_users_handler_works() {
             let user_id = Uuid::new_v4();
             let user_name = "my_name";
             let path = format!("/v1/users/{}", user_id);
 
+            let mut user = User::default();
+            user.id = Some(user_id);
+            user.name = user_name.to_string();
+
             let mut mock_svc = MockSvc::default();
             mock_svc
-                .expect_sync_delete_user()
-                .returning(move |user_id, _caller_id| {
-                    let mut user = User::default();
-                    user.id = Some(*user_id);
-                    user.name = user_name.to_string();
-                    Ok(user)
-                });
+                .expect_sync_create_user()
+                .returning(move |user, _, _| Ok(user));
 
             let svc = web::Data::new(mock_svc);
-            let id = web::Path::from(user_id);
-            let auth = CognitoInfo::disabled();
-            let req = test::TestRequest::with_uri(path.as_ref()).to_http_request();
-            let mut res: HttpResponse = delete(id, req, auth, svc).await.unwrap();
+            let usr = Validated(user);
+            let req = test::TestRequest::with_uri("/v1/users").to_http_request();
+            let mut res: HttpResponse = post(usr, req, Caller::disabled(), svc).await.unwrap();
 
             let user = res
                 .take_body()
@@ -484,60 +648,925 @@ pub mod users {
         }
 
         #[actix_rt::test]
-        async fn delete_users_handler_maps_err_to_unauthorized() {
+        async fn post_users_handler_replays_idempotent_requests() {
+            let user_id = Uuid::new_v4();
+            let path = format!("/v1/users/{}", user_id);
+
+            let mut user = User::default();
+            user.name = "my_name".to_string();
+            let key = IdempotencyKey::new("my_key", &user);
+            let request_hash = key.request_hash.clone();
+
             let mut mock_svc = MockSvc::default();
-            let err_svc = || ServiceError::Unauthorized;
+            mock_svc.expect_sync_create_user().never();
             mock_svc
-                .expect_sync_delete_user()
-                .returning(move |_, _| Err(err_svc()));
+                .expect_sync_create_user_idempotent()
+                .withf(move |_user, k, _, _| *k == key)
+                .returning(move |mut user, _key, _, _| {
+                    user.id = Some(user_id);
+                    Ok(Idempotent::Replayed {
+                        request_hash: request_hash.clone(),
+                        response: user,
+                    })
+                });
 
             let svc = web::Data::new(mock_svc);
-            let id = web::Path::from(Uuid::new_v4());
-            let auth = CognitoInfo::disabled();
-            let req = test::TestRequest::default().to_http_request();
-            let res = delete(id, req, auth, svc).await.err().unwrap();
-            let err = res.as_response_error();
+            let req = test::TestRequest::with_uri("/v1/users")
+                .header(idempotency::HEADER, "my_key")
+                .to_http_request();
+            let res: HttpResponse = post(Validated(user), req, Caller::disabled(), svc)
+                .await
+                .unwrap();
 
-            assert_eq!(err.to_string(), err_svc().to_string());
-            assert_eq!(err.status_code().as_u16(), 401);
+            let location = res.headers().get("Location").unwrap().to_str().unwrap();
+            let replayed = res.headers().get(idempotency::REPLAYED_HEADER).unwrap();
+
+            assert_eq!(res.status().as_u16(), 201);
+            assert_eq!(location, path);
+            assert_eq!(replayed, "true");
         }
 
         #[actix_rt::test]
-        async fn delete_users_handler_maps_err_to_not_found() {
+        async fn post_users_handler_maps_reused_keys_to_unprocessable_entity() {
             let mut mock_svc = MockSvc::default();
-            let err_svc = || ServiceError::DbError(sqlx::Error::RowNotFound);
+            let err_svc = || ServiceError::IdempotencyKeyReused;
             mock_svc
-                .expect_sync_delete_user()
-                .returning(move |_, _| Err(err_svc()));
+                .expect_sync_create_user_idempotent()
+                .returning(move |_, _, _, _| Err(err_svc()));
 
             let svc = web::Data::new(mock_svc);
-            let id = web::Path::from(Uuid::new_v4());
-            let auth = CognitoInfo::disabled();
-            let req = test::TestRequest::default().to_http_request();
-            let res = delete(id, req, auth, svc).await.err().unwrap();
-            let err = res.as_response_error();
+            let req = test::TestRequest::default()
+                .header(idempotency::HEADER, "my_key")
+                .to_http_request();
+            let problem = post(Validated(User::default()), req, Caller::disabled(), svc)
+                .await
+                .err()
+                .unwrap();
 
-            assert_eq!(err.to_string(), err_svc().to_string());
-            assert_eq!(err.status_code().as_u16(), 404);
+            assert_eq!(problem.detail, Some(err_svc().to_string()));
+            assert_eq!(problem.status, 422);
         }
 
         #[actix_rt::test]
-        async fn delete_users_handler_maps_err_to_internal() {
+        async fn post_users_handler_maps_err_to_unauthorized() {
             let mut mock_svc = MockSvc::default();
-            let err_svc = || ServiceError::DbError(sqlx::Error::PoolTimedOut);
+            let err_svc = || ServiceError::Unauthorized;
             mock_svc
-                .expect_sync_delete_user()
-                .returning(move |_, _| Err(err_svc()));
+                .expect_sync_create_user()
+                .returning(move |_, _, _| Err(err_svc()));
 
             let svc = web::Data::new(mock_svc);
-            let id = web::Path::from(Uuid::new_v4());
-            let auth = CognitoInfo::disabled();
+            let usr = Validated(User::default());
             let req = test::TestRequest::default().to_http_request();
-            let res = delete(id, req, auth, svc).await.err().unwrap();
-            let err = res.as_response_error();
+            let problem = post(usr, req, Caller::disabled(), svc).await.err().unwrap();
 
-            assert_eq!(err.to_string(), "Database Error");
-            assert_eq!(err.status_code().as_u16(), 500);
+            assert_eq!(problem.detail, Some(err_svc().to_string()));
+            assert_eq!(problem.status, 401);
+        }
+
+        #[actix_rt::test]
+        async fn post_users_handler_maps_err_to_not_found() {
+            let mut mock_svc = MockSvc::default();
+            let err_svc = || ServiceError::DbError(sqlx::Error::RowNotFound);
+            mock_svc
+                .expect_sync_create_user()
+                .returning(move |_, _, _| Err(err_svc()));
+
+            let svc = web::Data::new(mock_svc);
+            let usr = Validated(User::default());
+            let req = test::TestRequest::default().to_http_request();
+            let problem = post(usr, req, Caller::disabled(), svc).await.err().unwrap();
+
+            assert_eq!(problem.detail.as_deref(), Some(USER_NOT_FOUND));
+            assert_eq!(problem.status, 404);
+        }
+
+        #[actix_rt::test]
+        async fn post_users_handler_maps_err_to_internal() {
+            let mut mock_svc = MockSvc::default();
+            let err_svc = || ServiceError::DbError(sqlx::Error::PoolTimedOut);
+            mock_svc
+                .expect_sync_create_user()
+                .returning(move |_, _, _| Err(err_svc()));
+
+            let svc = web::Data::new(mock_svc);
+            let usr = Validated(User::default());
+            let req = test::TestRequest::default().to_http_request();
+            let problem = post(usr, req, Caller::disabled(), svc).await.err().unwrap();
+
+            assert_eq!(problem.detail, Some("Database Error".to_string()));
+            assert_eq!(problem.status, 500);
+        }
+
+        #[actix_rt::test]
+        async fn post_users_handler_maps_err_to_unprocessable_entity() {
+            let mut mock_svc = MockSvc::default();
+            let err_svc =
+                || ServiceError::InvalidUser(rpts_domain::DomainError::MissingField("name"));
+            mock_svc
+                .expect_sync_create_user()
+                .returning(move |_, _, _| Err(err_svc()));
+
+            let svc = web::Data::new(mock_svc);
+            let usr = Validated(User::default());
+            let req = test::TestRequest::default().to_http_request();
+            let problem = post(usr, req, Caller::disabled(), svc).await.err().unwrap();
+
+            assert_eq!(problem.detail, Some(err_svc().to_string()));
+            assert_eq!(problem.status, 422);
+            assert_eq!(problem.violations[0].field, "name");
+        }
+
+        #[actix_rt::test]
+        async fn post_users_handler_maps_err_to_conflict_on_foreign_keys() {
+            let mut mock_svc = MockSvc::default();
+            let err_svc = || ServiceError::ForeignKeyViolation;
+            mock_svc
+                .expect_sync_create_user()
+                .returning(move |_, _, _| Err(err_svc()));
+
+            let svc = web::Data::new(mock_svc);
+            let usr = Validated(User::default());
+            let req = test::TestRequest::default().to_http_request();
+            let problem = post(usr, req, Caller::disabled(), svc).await.err().unwrap();
+
+            assert_eq!(problem.detail, Some(err_svc().to_string()));
+            assert_eq!(problem.status, 409);
+        }
+
+        #[actix_rt::test]
+        async fn post_users_handler_maps_err_to_unprocessable_entity_on_checks() {
+            let mut mock_svc = MockSvc::default();
+            let err_svc = || ServiceError::CheckViolation;
+            mock_svc
+                .expect_sync_create_user()
+                .returning(move |_, _, _| Err(err_svc()));
+
+            let svc = web::Data::new(mock_svc);
+            let usr = Validated(User::default());
+            let req = test::TestRequest::default().to_http_request();
+            let problem = post(usr, req, Caller::disabled(), svc).await.err().unwrap();
+
+            assert_eq!(problem.detail, Some(err_svc().to_string()));
+            assert_eq!(problem.status, 422);
+        }
+
+        #[actix_rt::test]
+        async fn post_users_handler_maps_err_to_service_unavailable_on_serialization_failures() {
+            let mut mock_svc = MockSvc::default();
+            let err_svc = || ServiceError::SerializationFailure;
+            mock_svc
+                .expect_sync_create_user()
+                .returning(move |_, _, _| Err(err_svc()));
+
+            let svc = web::Data::new(mock_svc);
+            let usr = Validated(User::default());
+            let req = test::TestRequest::default().to_http_request();
+            let problem = post(usr, req, Caller::disabled(), svc).await.err().unwrap();
+
+            assert_eq!(problem.detail, Some(err_svc().to_string()));
+            assert_eq!(problem.status, 503);
+        }
+
+        // patch handler
+
+        #[actix_rt::test]
+        async fn patch_users_handler_works() {
+            let user_id = Uuid::new_v4();
+            let user_name = "my_name";
+            let random = 74444;
+            let path = format!("/v1/users/{}", user_id);
+
+            let mut mock_svc = MockSvc::default();
+            mock_svc.expect_sync_update_user().returning(
+                move |user_id, _caller, custom_data, _expected, _| {
+                    let mut user = User::default();
+                    user.id = Some(*user_id);
+                    user.name = user_name.to_string();
+                    user.custom_data = Some(custom_data);
+                    Ok(user)
+                },
+            );
+
+            let svc = web::Data::new(mock_svc);
+            let id = web::Path::from(user_id);
+            let fields = Validated(CustomData { random });
+            let auth = Caller::disabled();
+            let req = test::TestRequest::with_uri(path.as_ref()).to_http_request();
+            let mut res: HttpResponse = patch(id, fields, req, auth, svc).await.unwrap();
+
+            let user = res
+                .take_body()
+                .as_ref()
+                .map(|b| match b {
+                    Body::Bytes(x) => serde_json::from_slice::<'_, User>(x).ok(),
+                    _ => None,
+                })
+                .flatten()
+                .unwrap();
+
+            let location = res.headers().get("Location").unwrap().to_str().unwrap();
+
+            assert_eq!(user.id.unwrap(), user_id);
+            assert_eq!(user.name, user_name);
+            assert_eq!(user.custom_data.unwrap().random, random);
+            assert_eq!(location, path);
+            assert!(res.status().is_success());
+        }
+
+        #[actix_rt::test]
+        async fn patch_users_handler_passes_if_match_to_the_service() {
+            let expected = Version::of(&User::default());
+
+            let mut mock_svc = MockSvc::default();
+            mock_svc
+                .expect_sync_update_user()
+                .withf(move |_, _, _, e, _| *e == Some(expected))
+                .returning(move |_, _, _, _, _| Ok(User::default()));
+
+            let svc = web::Data::new(mock_svc);
+            let id = web::Path::from(Uuid::new_v4());
+            let custom_data = Validated(CustomData::default());
+            let auth = Caller::disabled();
+            let req = test::TestRequest::with_header("If-Match", expected.etag()).to_http_request();
+            let res = patch(id, custom_data, req, auth, svc).await.unwrap();
+
+            assert!(res.status().is_success());
+        }
+
+        #[actix_rt::test]
+        async fn patch_users_handler_rejects_weak_if_match() {
+            let mut mock_svc = MockSvc::default();
+            mock_svc.expect_sync_update_user().never();
+
+            let svc = web::Data::new(mock_svc);
+            let id = web::Path::from(Uuid::new_v4());
+            let custom_data = Validated(CustomData::default());
+            let auth = Caller::disabled();
+            let req = test::TestRequest::with_header("If-Match", "W/\"1\"").to_http_request();
+            let problem = patch(id, custom_data, req, auth, svc).await.err().unwrap();
+
+            assert_eq!(problem.status, 412);
+        }
+
+        #[actix_rt::test]
+        async fn patch_users_handler_maps_err_to_precondition_failed() {
+            let mut mock_svc = MockSvc::default();
+            let err_svc = || ServiceError::PreconditionFailed;
+            mock_svc
+                .expect_sync_update_user()
+                .returning(move |_, _, _, _, _| Err(err_svc()));
+
+            let svc = web::Data::new(mock_svc);
+            let id = web::Path::from(Uuid::new_v4());
+            let custom_data = Validated(CustomData::default());
+            let auth = Caller::disabled();
+            let req = test::TestRequest::default().to_http_request();
+            let problem = patch(id, custom_data, req, auth, svc).await.err().unwrap();
+
+            assert_eq!(problem.detail, Some(err_svc().to_string()));
+            assert_eq!(problem.status, 412);
+        }
+
+        #[actix_rt::test]
+        async fn patch_users_handler_maps_err_to_unauthorized() {
+            let mut mock_svc = MockSvc::default();
+            let err_svc = || ServiceError::Unauthorized;
+            mock_svc
+                .expect_sync_update_user()
+                .returning(move |_, _, _, _, _| Err(err_svc()));
+
+            let svc = web::Data::new(mock_svc);
+            let id = web::Path::from(Uuid::new_v4());
+            let custom_data = Validated(CustomData::default());
+            let auth = Caller::disabled();
+            let req = test::TestRequest::default().to_http_request();
+            let problem = patch(id, custom_data, req, auth, svc).await.err().unwrap();
+
+            assert_eq!(problem.detail, Some(err_svc().to_string()));
+            assert_eq!(problem.status, 401);
+        }
+
+        #[actix_rt::test]
+        async fn patch_users_handler_maps_err_to_not_found() {
+            let mut mock_svc = MockSvc::default();
+            let err_svc = || ServiceError::DbError(sqlx::Error::RowNotFound);
+            mock_svc
+                .expect_sync_update_user()
+                .returning(move |_, _, _, _, _| Err(err_svc()));
+
+            let svc = web::Data::new(mock_svc);
+            let id = web::Path::from(Uuid::new_v4());
+            let custom_data = Validated(CustomData::default());
+            let auth = Caller::disabled();
+            let req = test::TestRequest::default().to_http_request();
+            let problem = patch(id, custom_data, req, auth, svc).await.err().unwrap();
+
+            assert_eq!(problem.detail.as_deref(), Some(USER_NOT_FOUND));
+            assert_eq!(problem.status, 404);
+        }
+
+        #[actix_rt::test]
+        async fn patch_users_handler_maps_err_to_internal() {
+            let mut mock_svc = MockSvc::default();
+            let err_svc = || ServiceError::DbError(sqlx::Error::PoolTimedOut);
+            mock_svc
+                .expect_sync_update_user()
+                .returning(move |_, _, _, _, _| Err(err_svc()));
+
+            let svc = web::Data::new(mock_svc);
+            let id = web::Path::from(Uuid::new_v4());
+            let custom_data = Validated(CustomData::default());
+            let auth = Caller::disabled();
+            let req = test::TestRequest::default().to_http_request();
+            let problem = patch(id, custom_data, req, auth, svc).await.err().unwrap();
+
+            assert_eq!(problem.detail, Some("Database Error".to_string()));
+            assert_eq!(problem.status, 500);
+        }
+
+        // put handler
+
+        #[actix_rt::test]
+        async fn put_users_handler_works() {
+            let user_id = Uuid::new_v4();
+            let path = format!("/v1/users/{}", user_id);
+
+            let mut user = User::default();
+            user.name = "new_name".to_string();
+
+            let mut mock_svc = MockSvc::default();
+            mock_svc.expect_sync_replace_user().returning(
+                move |user_id, _caller, mut user, _expected, _| {
+                    user.id = Some(*user_id);
+                    Ok(user)
+                },
+            );
+
+            let svc = web::Data::new(mock_svc);
+            let id = web::Path::from(user_id);
+            let auth = Caller::disabled();
+            let req = test::TestRequest::with_uri(path.as_ref()).to_http_request();
+            let mut res: HttpResponse = put(id, Validated(user), req, auth, svc).await.unwrap();
+
+            let user = res
+                .take_body()
+                .as_ref()
+                .map(|b| match b {
+                    Body::Bytes(x) => serde_json::from_slice::<'_, User>(x).ok(),
+                    _ => None,
+                })
+                .flatten()
+                .unwrap();
+
+            assert_eq!(user.id.unwrap(), user_id);
+            assert_eq!(user.name, "new_name");
+            assert!(res.status().is_success());
+        }
+
+        #[actix_rt::test]
+        async fn put_users_handler_maps_err_to_conflict() {
+            let mut mock_svc = MockSvc::default();
+            let err_svc = || ServiceError::DuplicateName;
+            mock_svc
+                .expect_sync_replace_user()
+                .returning(move |_, _, _, _, _| Err(err_svc()));
+
+            let svc = web::Data::new(mock_svc);
+            let id = web::Path::from(Uuid::new_v4());
+            let user = Validated(User::default());
+            let auth = Caller::disabled();
+            let req = test::TestRequest::default().to_http_request();
+            let problem = put(id, user, req, auth, svc).await.err().unwrap();
+
+            assert_eq!(problem.detail, Some(err_svc().to_string()));
+            assert_eq!(problem.status, 409);
+        }
+
+        // merge patch & json patch handlers
+
+        #[actix_rt::test]
+        async fn merge_patch_users_handler_works() {
+            let user_id = Uuid::new_v4();
+            let path = format!("/v1/users/{}", user_id);
+            let patch = serde_json::json!({ "name": "new_name" });
+
+            let mut mock_svc = MockSvc::default();
+            mock_svc
+                .expect_sync_patch_user()
+                .withf(move |_, _, p, _, _| {
+                    *p == UserPatch::Merge(serde_json::json!({ "name": "new_name" }))
+                })
+                .returning(move |user_id, _caller, _patch, _expected, _| {
+                    let mut user = User::default();
+                    user.id = Some(*user_id);
+                    user.name = "new_name".to_string();
+                    Ok(user)
+                });
+
+            let svc = web::Data::new(mock_svc);
+            let id = web::Path::from(user_id);
+            let auth = Caller::disabled();
+            let req = test::TestRequest::with_uri(path.as_ref()).to_http_request();
+            let res: HttpResponse = merge_patch(id, web::Json(patch), req, auth, svc)
+                .await
+                .unwrap();
+
+            let location = res.headers().get("Location").unwrap().to_str().unwrap();
+
+            assert_eq!(location, path);
+            assert!(res.status().is_success());
+        }
+
+        #[actix_rt::test]
+        async fn json_patch_users_handler_maps_err_to_unprocessable_entity() {
+            let mut mock_svc = MockSvc::default();
+            let err_svc = || ServiceError::InvalidPatch("test failed".to_string());
+            mock_svc
+                .expect_sync_patch_user()
+                .returning(move |_, _, _, _, _| Err(err_svc()));
+
+            let svc = web::Data::new(mock_svc);
+            let id = web::Path::from(Uuid::new_v4());
+            let patch = web::Json(json_patch::Patch(vec![]));
+            let auth = Caller::disabled();
+            let req = test::TestRequest::default().to_http_request();
+            let problem = json_patch(id, patch, req, auth, svc).await.err().unwrap();
+
+            assert_eq!(problem.detail, Some(err_svc().to_string()));
+            assert_eq!(problem.status, 422);
+        }
+
+        #[actix_rt::test]
+        async fn patch_handlers_leave_no_personal_data_in_the_spans() {
+            let mut mock_svc = MockSvc::default();
+            mock_svc
+                .expect_sync_patch_user()
+                .returning(|_, _, _, _, _| Ok(User::default()));
+            let svc = web::Data::new(mock_svc);
+            let merge = web::Json(serde_json::json!({ "name": "Jose Smith" }));
+            let json: json_patch::Patch = serde_json::from_value(serde_json::json!([
+                { "op": "replace", "path": "/birth_date", "value": "1990-05-17" }
+            ]))
+            .unwrap();
+            let (captured, _guard) = crate::telemetry::redact::capture::start();
+
+            let id = web::Path::from(Uuid::new_v4());
+            let req = test::TestRequest::default().to_http_request();
+            merge_patch(id, merge, req, Caller::disabled(), svc.clone())
+                .await
+                .unwrap();
+            let id = web::Path::from(Uuid::new_v4());
+            let req = test::TestRequest::default().to_http_request();
+            json_patch(id, web::Json(json), req, Caller::disabled(), svc)
+                .await
+                .unwrap();
+
+            let logs = captured.output();
+            assert!(logs.contains("merge_patch"));
+            assert!(logs.contains("json_patch"));
+            for sensitive in &["Jose", "1990-05-17"] {
+                assert!(
+                    !logs.contains(sensitive),
+                    "{} was logged: {}",
+                    sensitive,
+                    logs
+                );
+            }
+        }
+
+        // restore handler
+
+        #[actix_rt::test]
+        async fn writes_forward_the_request_id_to_the_service() {
+            let mut mock_svc = MockSvc::default();
+            mock_svc
+                .expect_sync_restore_user()
+                .withf(|_, _, request_id| request_id.as_deref() == Some("my-request"))
+                .returning(|_, _, _| Ok(User::default()));
+
+            let svc = web::Data::new(mock_svc);
+            let id = web::Path::from(Uuid::new_v4());
+            let auth = Caller::disabled();
+            let req =
+                test::TestRequest::with_header(crate::problem::REQUEST_ID_HEADER, "my-request")
+                    .to_http_request();
+            let res: HttpResponse = restore(id, req, auth, svc).await.unwrap();
+
+            assert!(res.status().is_success());
+        }
+
+        #[actix_rt::test]
+        async fn restore_users_handler_works() {
+            let user_id = Uuid::new_v4();
+
+            let mut mock_svc = MockSvc::default();
+            mock_svc
+                .expect_sync_restore_user()
+                .returning(|user_id, _caller, _| {
+                    let mut user = User::default();
+                    user.id = Some(*user_id);
+                    Ok(user)
+                });
+
+            let svc = web::Data::new(mock_svc);
+            let id = web::Path::from(user_id);
+            let auth = Caller::disabled();
+            let path = format!("/v1/users/{}/restore", user_id);
+            let req = test::TestRequest::with_uri(&path).to_http_request();
+            let res: HttpResponse = restore(id, req, auth, svc).await.unwrap();
+
+            let location = res.headers().get("Location").unwrap().to_str().unwrap();
+
+            assert!(res.status().is_success());
+            assert_eq!(location, format!("/v1/users/{}", user_id));
+        }
+
+        #[actix_rt::test]
+        async fn restore_users_handler_maps_err_to_not_found() {
+            let mut mock_svc = MockSvc::default();
+            let err_svc = || ServiceError::DbError(sqlx::Error::RowNotFound);
+            mock_svc
+                .expect_sync_restore_user()
+                .returning(move |_, _, _| Err(err_svc()));
+
+            let svc = web::Data::new(mock_svc);
+            let id = web::Path::from(Uuid::new_v4());
+            let auth = Caller::disabled();
+            let req = test::TestRequest::default().to_http_request();
+            let problem = restore(id, req, auth, svc).await.err().unwrap();
+
+            assert_eq!(problem.detail.as_deref(), Some(USER_NOT_FOUND));
+            assert_eq!(problem.status, 404);
+        }
+
+        // history handler
+
+        #[actix_rt::test]
+        async fn history_users_handler_works() {
+            let mut mock_svc = MockSvc::default();
+            mock_svc
+                .expect_sync_user_history()
+                .withf(|_, _, query| query.limit == Some(5))
+                .returning(|_, _, _| Ok(HistoryPage::default()));
+
+            let svc = web::Data::new(mock_svc);
+            let id = web::Path::from(Uuid::new_v4());
+            let query = web::Query(HistoryQuery {
+                limit: Some(5),
+                cursor: None,
+            });
+            let auth = Caller::disabled();
+            let req = test::TestRequest::default().to_http_request();
+            let res: HttpResponse = history(id, query, req, auth, svc).await.unwrap();
+
+            assert!(res.status().is_success());
+        }
+
+        #[actix_rt::test]
+        async fn history_users_handler_maps_err_to_unauthorized() {
+            let mut mock_svc = MockSvc::default();
+            mock_svc
+                .expect_sync_user_history()
+                .returning(|_, _, _| Err(ServiceError::Unauthorized));
+
+            let svc = web::Data::new(mock_svc);
+            let id = web::Path::from(Uuid::new_v4());
+            let query = web::Query(HistoryQuery::default());
+            let auth = Caller::disabled();
+            let req = test::TestRequest::default().to_http_request();
+            let problem = history(id, query, req, auth, svc).await.err().unwrap();
+
+            assert_eq!(problem.status, 401);
+        }
+
+        // delete handler
+
+        #[actix_rt::test]
+        async fn delete_users_handler_works() {
+            let user_id = Uuid::new_v4();
+            let user_name = "my_name";
+            let path = format!("/v1/users/{}", user_id);
+
+            let mut mock_svc = MockSvc::default();
+            mock_svc
+                .expect_sync_delete_user()
+                .returning(move |user_id, _caller, _expected, _| {
+                    let mut user = User::default();
+                    user.id = Some(*user_id);
+                    user.name = user_name.to_string();
+                    Ok(user)
+                });
+
+            let svc = web::Data::new(mock_svc);
+            let id = web::Path::from(user_id);
+            let auth = Caller::disabled();
+            let req = test::TestRequest::with_uri(path.as_ref()).to_http_request();
+            let mut res: HttpResponse = delete(id, req, auth, svc).await.unwrap();
+
+            let user = res
+                .take_body()
+                .as_ref()
+                .map(|b| match b {
+                    Body::Bytes(x) => serde_json::from_slice::<'_, User>(x).ok(),
+                    _ => None,
+                })
+                .flatten()
+                .unwrap();
+
+            let location = res.headers().get("Location").unwrap().to_str().unwrap();
+
+            assert_eq!(user.id.unwrap(), user_id);
+            assert_eq!(user.name, user_name);
+            assert_eq!(location, path);
+            assert!(res.status().is_success());
+        }
+
+        #[actix_rt::test]
+        async fn delete_users_handler_maps_err_to_unauthorized() {
+            let mut mock_svc = MockSvc::default();
+            let err_svc = || ServiceError::Unauthorized;
+            mock_svc
+                .expect_sync_delete_user()
+                .returning(move |_, _, _, _| Err(err_svc()));
+
+            let svc = web::Data::new(mock_svc);
+            let id = web::Path::from(Uuid::new_v4());
+            let auth = Caller::disabled();
+            let req = test::TestRequest::default().to_http_request();
+            let problem = delete(id, req, auth, svc).await.err().unwrap();
+
+            assert_eq!(problem.detail, Some(err_svc().to_string()));
+            assert_eq!(problem.status, 401);
+        }
+
+        #[actix_rt::test]
+        async fn delete_users_handler_maps_err_to_not_found() {
+            let mut mock_svc = MockSvc::default();
+            let err_svc = || ServiceError::DbError(sqlx::Error::RowNotFound);
+            mock_svc
+                .expect_sync_delete_user()
+                .returning(move |_, _, _, _| Err(err_svc()));
+
+            let svc = web::Data::new(mock_svc);
+            let id = web::Path::from(Uuid::new_v4());
+            let auth = Caller::disabled();
+            let req = test::TestRequest::default().to_http_request();
+            let problem = delete(id, req, auth, svc).await.err().unwrap();
+
+            assert_eq!(problem.detail.as_deref(), Some(USER_NOT_FOUND));
+            assert_eq!(problem.status, 404);
+        }
+
+        #[actix_rt::test]
+        async fn delete_users_handler_maps_err_to_internal() {
+            let mut mock_svc = MockSvc::default();
+            let err_svc = || ServiceError::DbError(sqlx::Error::PoolTimedOut);
+            mock_svc
+                .expect_sync_delete_user()
+                .returning(move |_, _, _, _| Err(err_svc()));
+
+            let svc = web::Data::new(mock_svc);
+            let id = web::Path::from(Uuid::new_v4());
+            let auth = Caller::disabled();
+            let req = test::TestRequest::default().to_http_request();
+            let problem = delete(id, req, auth, svc).await.err().unwrap();
+
+            assert_eq!(problem.detail, Some("Database Error".to_string()));
+            assert_eq!(problem.status, 500);
+        }
+    }
+}
+
+/// The caller's own user, whose id is the subject of the caller.
+pub mod me {
+    use super::*;
+    use actix_web::http::StatusCode;
+
+    pub const PATH: &str = "/me";
+
+    /// The id of the caller's user, which can only exist for UUID subjects.
+    fn user_id(auth: &Caller, req: &HttpRequest) -> Result<Uuid> {
+        auth.user_id().ok_or_else(|| {
+            Problem::new(
+                StatusCode::NOT_FOUND,
+                "not-found",
+                "The caller has no user of its own",
+            )
+            .for_request(req)
+        })
+    }
+
+    #[instrument(
+        skip(req, svc),
+        fields(http.method = %req.method(), http.route = "/v1/me", enduser.id = ?auth.user)
+    )]
+    pub async fn get<S: crate::v1::service::Service>(
+        query: web::Query<ReadQuery>,
+        req: HttpRequest,
+        auth: Caller,
+        svc: web::Data<S>,
+    ) -> Result<HttpResponse> {
+        let id = user_id(&auth, &req)?;
+        users::get(web::Path::from(id), query, req, auth, svc).await
+    }
+
+    /// Creates the caller's user, once.
+    #[instrument(
+        skip(req, svc),
+        fields(http.method = %req.method(), http.route = "/v1/me", enduser.id = ?auth.user)
+    )]
+    pub async fn sign_up<S: crate::v1::service::Service>(
+        user: Validated<User>,
+        req: HttpRequest,
+        auth: Caller,
+        svc: web::Data<S>,
+    ) -> Result<HttpResponse> {
+        svc_response!(
+            svc.as_ref()
+                .sign_up(user.into_inner(), auth, Some(request_id(&req)))
+                .await,
+            HttpResponse::Created(),
+            req,
+            "Error signing up"
+        )
+    }
+
+    #[instrument(
+        skip(req, svc),
+        fields(http.method = %req.method(), http.route = "/v1/me", enduser.id = ?auth.user)
+    )]
+    pub async fn patch<S: crate::v1::service::Service>(
+        custom_data: Validated<CustomData>,
+        req: HttpRequest,
+        auth: Caller,
+        svc: web::Data<S>,
+    ) -> Result<HttpResponse> {
+        let id = user_id(&auth, &req)?;
+        users::patch(web::Path::from(id), custom_data, req, auth, svc).await
+    }
+
+    #[instrument(
+        skip(req, svc, patch),
+        fields(
+            http.method = %req.method(),
+            http.route = "/v1/me",
+            enduser.id = ?auth.user,
+            patch = ?Sensitive(&patch.0)
+        )
+    )]
+    pub async fn merge_patch<S: crate::v1::service::Service>(
+        patch: web::Json<serde_json::Value>,
+        req: HttpRequest,
+        auth: Caller,
+        svc: web::Data<S>,
+    ) -> Result<HttpResponse> {
+        let id = user_id(&auth, &req)?;
+        users::merge_patch(web::Path::from(id), patch, req, auth, svc).await
+    }
+
+    #[instrument(
+        skip(req, svc, patch),
+        fields(
+            http.method = %req.method(),
+            http.route = "/v1/me",
+            enduser.id = ?auth.user,
+            patch = ?Sensitive(&patch.0)
+        )
+    )]
+    pub async fn json_patch<S: crate::v1::service::Service>(
+        patch: web::Json<json_patch::Patch>,
+        req: HttpRequest,
+        auth: Caller,
+        svc: web::Data<S>,
+    ) -> Result<HttpResponse> {
+        let id = user_id(&auth, &req)?;
+        users::json_patch(web::Path::from(id), patch, req, auth, svc).await
+    }
+
+    #[instrument(
+        skip(req, svc),
+        fields(http.method = %req.method(), http.route = "/v1/me", enduser.id = ?auth.user)
+    )]
+    pub async fn delete<S: crate::v1::service::Service>(
+        req: HttpRequest,
+        auth: Caller,
+        svc: web::Data<S>,
+    ) -> Result<HttpResponse> {
+        let id = user_id(&auth, &req)?;
+        users::delete(web::Path::from(id), req, auth, svc).await
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::v1::mocks::MockSvc;
+        use crate::v1::service::ServiceError;
+        use actix_web::test;
+
+        #[actix_rt::test]
+        async fn get_me_handler_gets_the_user_of_the_caller() {
+            let caller_id = Uuid::new_v4();
+            let mut mock_svc = MockSvc::default();
+            mock_svc
+                .expect_sync_get_user()
+                .withf(move |user_id, _, _| *user_id == caller_id)
+                .returning(|user_id, _, _| {
+                    let mut user = User::default();
+                    user.id = Some(*user_id);
+                    Ok(user)
+                });
+
+            let svc = web::Data::new(mock_svc);
+            let auth = Caller::new(caller_id.to_string());
+            let req = test::TestRequest::with_uri("/v1/me").to_http_request();
+            let res = get(web::Query(ReadQuery::default()), req, auth, svc)
+                .await
+                .unwrap();
+
+            let location = res.headers().get("Location").unwrap().to_str().unwrap();
+
+            assert!(res.status().is_success());
+            assert_eq!(location, "/v1/me");
+        }
+
+        #[actix_rt::test]
+        async fn get_me_handler_returns_not_found_if_the_caller_cant_have_a_user() {
+            let mut mock_svc = MockSvc::default();
+            mock_svc.expect_sync_get_user().never();
+
+            let svc = web::Data::new(mock_svc);
+            let req = test::TestRequest::with_uri("/v1/me").to_http_request();
+            let problem = get(
+                web::Query(ReadQuery::default()),
+                req,
+                Caller::new("reports"),
+                svc,
+            )
+            .await
+            .err()
+            .unwrap();
+
+            assert_eq!(problem.status, 404);
+            assert_eq!(problem.instance, Some("/v1/me".to_string()));
+        }
+
+        #[actix_rt::test]
+        async fn sign_up_handler_returns_created() {
+            let caller_id = Uuid::new_v4();
+            let mut mock_svc = MockSvc::default();
+            mock_svc
+                .expect_sync_sign_up()
+                .returning(move |mut user, _, _| {
+                    user.id = Some(caller_id);
+                    Ok(user)
+                });
+
+            let svc = web::Data::new(mock_svc);
+            let auth = Caller::new(caller_id.to_string());
+            let req = test::TestRequest::with_uri("/v1/me").to_http_request();
+            let res = sign_up(Validated(User::default()), req, auth, svc)
+                .await
+                .unwrap();
+
+            assert_eq!(res.status().as_u16(), 201);
+            assert!(res.headers().get("ETag").is_some());
+        }
+
+        #[actix_rt::test]
+        async fn sign_up_handler_maps_err_to_conflict() {
+            let mut mock_svc = MockSvc::default();
+            mock_svc
+                .expect_sync_sign_up()
+                .returning(|_, _, _| Err(ServiceError::AlreadySignedUp));
+
+            let svc = web::Data::new(mock_svc);
+            let auth = Caller::new(Uuid::new_v4().to_string());
+            let req = test::TestRequest::with_uri("/v1/me").to_http_request();
+            let problem = sign_up(Validated(User::default()), req, auth, svc)
+                .await
+                .err()
+                .unwrap();
+
+            assert_eq!(problem.status, 409);
+            assert_eq!(problem.problem_type, "/problems/already-signed-up");
+        }
+
+        #[actix_rt::test]
+        async fn delete_me_handler_deletes_the_user_of_the_caller() {
+            let caller_id = Uuid::new_v4();
+            let mut mock_svc = MockSvc::default();
+            mock_svc
+                .expect_sync_delete_user()
+                .withf(move |user_id, _, _, _| *user_id == caller_id)
+                .returning(|_, _, _, _| Ok(User::default()));
+
+            let svc = web::Data::new(mock_svc);
+            let auth = Caller::new(caller_id.to_string());
+            let req = test::TestRequest::with_uri("/v1/me").to_http_request();
+            let res = delete(req, auth, svc).await.unwrap();
+
+            assert!(res.status().is_success());
         }
     }
 }
@@ -0,0 +1,128 @@
+//! Idempotency keys, so clients can safely retry the creation of users.
+//!
+//! The key, a hash of the request and the response are stored together with the user,
+//! in the same transaction. A retry either waits for the first request to finish or gets
+//! its response back.
+use crate::problem::Problem;
+use actix_web::{http::StatusCode, HttpRequest};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+pub const HEADER: &str = "Idempotency-Key";
+/// Tells the clients the response comes from a previous request.
+pub const REPLAYED_HEADER: &str = "Idempotent-Replayed";
+pub const MAX_KEY_LENGTH: usize = 255;
+
+/// How long the responses are kept for the retries.
+pub fn ttl() -> chrono::Duration {
+    chrono::Duration::hours(24)
+}
+
+/// An idempotency key sent by a client, along with the hash of its request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdempotencyKey {
+    pub key: String,
+    pub request_hash: String,
+}
+
+impl IdempotencyKey {
+    pub fn new(key: impl Into<String>, request: &impl Serialize) -> Self {
+        let request = serde_json::to_vec(request).expect("Requests are always serializable");
+        Self {
+            key: key.into(),
+            request_hash: format!("{:x}", Sha256::digest(&request)),
+        }
+    }
+
+    /// Reads the key of a request, if any.
+    /// Keys must be made of 1 to 255 visible ASCII characters, like a UUID.
+    pub fn from_request(
+        req: &HttpRequest,
+        request: &impl Serialize,
+    ) -> Result<Option<Self>, Problem> {
+        let value = match req.headers().get(HEADER) {
+            Some(value) => value.to_str().unwrap_or_default(),
+            None => return Ok(None),
+        };
+        let is_valid = !value.is_empty()
+            && value.len() <= MAX_KEY_LENGTH
+            && value.chars().all(|c| c.is_ascii_graphic());
+        if !is_valid {
+            return Err(Problem::new(
+                StatusCode::BAD_REQUEST,
+                "invalid-idempotency-key",
+                format!(
+                    "The {} header must have from 1 to {} visible ASCII characters",
+                    HEADER, MAX_KEY_LENGTH
+                ),
+            )
+            .for_request(req));
+        }
+        Ok(Some(Self::new(value, request)))
+    }
+}
+
+/// Result of a request with an idempotency key.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Idempotent<T> {
+    /// The key is new, so the request was processed.
+    Created(T),
+    /// The key was already used. This is what the first request returned.
+    Replayed { request_hash: String, response: T },
+}
+
+impl<T> Idempotent<T> {
+    pub fn is_replayed(&self) -> bool {
+        matches!(self, Idempotent::Replayed { .. })
+    }
+
+    pub fn into_inner(self) -> T {
+        match self {
+            Idempotent::Created(response) => response,
+            Idempotent::Replayed { response, .. } => response,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::User;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn same_requests_have_the_same_hash() {
+        let mut user = User::default();
+        let key = IdempotencyKey::new("key", &user);
+        assert_eq!(IdempotencyKey::new("key", &user), key);
+        assert_eq!(key.request_hash.len(), 64);
+
+        user.name = "other".to_string();
+        assert_ne!(IdempotencyKey::new("key", &user), key);
+    }
+
+    #[test]
+    fn from_request_works() {
+        let req = TestRequest::with_header(HEADER, "8e0c3a4e-key").to_http_request();
+        let key = IdempotencyKey::from_request(&req, &User::default()).unwrap();
+        assert_eq!(key.unwrap().key, "8e0c3a4e-key");
+
+        let req = TestRequest::default().to_http_request();
+        assert_eq!(
+            IdempotencyKey::from_request(&req, &User::default()).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn from_request_rejects_invalid_keys() {
+        let long = "a".repeat(MAX_KEY_LENGTH + 1);
+        for key in &["", "with spaces", long.as_str()] {
+            let req = TestRequest::with_header(HEADER, *key).to_http_request();
+            let problem = IdempotencyKey::from_request(&req, &User::default())
+                .err()
+                .unwrap();
+            assert_eq!(problem.status, 400);
+        }
+    }
+}
@@ -0,0 +1,353 @@
+//! Cursor based pagination of the users collection.
+//!
+//! Pages are positioned with keyset pagination: the cursor holds the sort key and id
+//! of the last (or first) user seen, so pages stay consistent while users are added or removed.
+use super::service::ServiceError;
+use crate::models::{ListQuery, SortField, SortOrder, User, UserPage};
+use chrono::{NaiveDate, SecondsFormat};
+use rpts_domain::redacted_debug;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub const DEFAULT_LIMIT: u32 = 20;
+pub const MAX_LIMIT: u32 = 100;
+
+/// Which side of the cursor the page is on.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Next,
+    Prev,
+}
+
+/// Position in the users collection.
+/// It's bound to the sorting it was created with.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cursor {
+    pub sort: SortField,
+    pub order: SortOrder,
+    pub direction: Direction,
+    /// Sort key of the user, as understood by Postgres.
+    pub key: String,
+    pub id: Uuid,
+}
+
+redacted_debug!(Cursor {
+    sort,
+    order,
+    direction,
+    #[sensitive]
+    key,
+    id,
+});
+
+impl Cursor {
+    /// Builds the cursor pointing to a user.
+    fn at(user: &User, sort: SortField, order: SortOrder, direction: Direction) -> Option<Self> {
+        let key = match sort {
+            SortField::Name => user.name.clone(),
+            SortField::BirthDate => user.birth_date.to_string(),
+            SortField::CreatedAt => user.created_at.map_or_else(
+                || "-infinity".to_string(),
+                |created_at| created_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+            ),
+        };
+        Some(Self {
+            sort,
+            order,
+            direction,
+            key,
+            id: user.id?,
+        })
+    }
+
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("Cursors are always serializable");
+        base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, ServiceError> {
+        let invalid = || ServiceError::InvalidQuery("Invalid cursor".to_string());
+        let json = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).map_err(|_| invalid())?;
+        serde_json::from_slice(&json).map_err(|_| invalid())
+    }
+}
+
+/// Filters of the users collection.
+#[derive(Clone, Default, PartialEq)]
+pub struct UserFilter {
+    pub name_prefix: Option<String>,
+    pub born_after: Option<NaiveDate>,
+    pub born_before: Option<NaiveDate>,
+    pub include_deleted: bool,
+}
+
+redacted_debug!(UserFilter {
+    #[sensitive]
+    name_prefix,
+    #[sensitive]
+    born_after,
+    #[sensitive]
+    born_before,
+    include_deleted,
+});
+
+/// A validated [ListQuery], ready to be handed to the repository.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserListing {
+    pub filter: UserFilter,
+    pub sort: SortField,
+    pub order: SortOrder,
+    pub cursor: Option<Cursor>,
+    pub limit: u32,
+}
+
+impl UserListing {
+    pub fn from_query(query: &ListQuery) -> Result<Self, ServiceError> {
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+        if limit == 0 || limit > MAX_LIMIT {
+            return Err(ServiceError::InvalidQuery(format!(
+                "limit must be between 1 and {}",
+                MAX_LIMIT
+            )));
+        }
+        let sort = query.sort.unwrap_or_default();
+        let order = query.order.unwrap_or_default();
+        let cursor = query.cursor.as_deref().map(Cursor::decode).transpose()?;
+        if let Some(cursor) = &cursor {
+            if cursor.sort != sort || cursor.order != order {
+                return Err(ServiceError::InvalidQuery(
+                    "The cursor was created with a different sorting".to_string(),
+                ));
+            }
+        }
+        Ok(Self {
+            filter: UserFilter {
+                name_prefix: query.name_prefix.clone(),
+                born_after: query.born_after,
+                born_before: query.born_before,
+                include_deleted: query.include_deleted,
+            },
+            sort,
+            order,
+            cursor,
+            limit,
+        })
+    }
+
+    /// Whether the repository must read the collection backwards, from the cursor.
+    pub fn is_backwards(&self) -> bool {
+        matches!(
+            self.cursor,
+            Some(Cursor {
+                direction: Direction::Prev,
+                ..
+            })
+        )
+    }
+
+    /// Builds the page out of the users read by the repository.
+    /// The repository reads one more user than the limit to know whether there are more.
+    pub fn page(&self, mut users: Vec<User>, total: Option<i64>) -> UserPage {
+        let has_more = users.len() > self.limit as usize;
+        users.truncate(self.limit as usize);
+        if self.is_backwards() {
+            users.reverse();
+        }
+        let cursor = |user: Option<&User>, direction| {
+            user.and_then(|user| Cursor::at(user, self.sort, self.order, direction))
+                .map(|cursor| cursor.encode())
+        };
+        let (has_next, has_prev) = match &self.cursor {
+            None => (has_more, false),
+            Some(cursor) if cursor.direction == Direction::Next => (has_more, true),
+            Some(_) => (true, has_more),
+        };
+        UserPage {
+            next_cursor: cursor(users.last().filter(|_| has_next), Direction::Next),
+            prev_cursor: cursor(users.first().filter(|_| has_prev), Direction::Prev),
+            items: users,
+            total,
+        }
+    }
+}
+
+/// Builds the `Link` header pointing to the next and previous pages.
+pub fn link_header(path: &str, query: &ListQuery, page: &UserPage) -> Option<String> {
+    let link = |cursor: &Option<String>, rel: &str| {
+        cursor.as_ref().map(|cursor| {
+            let query = ListQuery {
+                cursor: Some(cursor.clone()),
+                ..query.clone()
+            };
+            let query = serde_urlencoded::to_string(&query).unwrap_or_default();
+            format!("<{}?{}>; rel=\"{}\"", path, query, rel)
+        })
+    };
+    let links: Vec<String> = vec![
+        link(&page.next_cursor, "next"),
+        link(&page.prev_cursor, "prev"),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if links.is_empty() {
+        None
+    } else {
+        Some(links.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn users(count: usize) -> Vec<User> {
+        (0..count)
+            .map(|i| {
+                let mut user = User::default();
+                user.id = Some(Uuid::new_v4());
+                user.name = format!("user_{}", i);
+                user
+            })
+            .collect()
+    }
+
+    fn listing(query: ListQuery) -> UserListing {
+        UserListing::from_query(&query).unwrap()
+    }
+
+    fn cursor(direction: Direction, sort: SortField) -> String {
+        Cursor {
+            sort,
+            order: SortOrder::Asc,
+            direction,
+            key: "user_0".to_string(),
+            id: Uuid::new_v4(),
+        }
+        .encode()
+    }
+
+    #[test]
+    fn cursor_roundtrip_works() {
+        let cursor = Cursor::at(
+            &users(1)[0],
+            SortField::Name,
+            SortOrder::Desc,
+            Direction::Next,
+        )
+        .unwrap();
+        assert_eq!(Cursor::decode(&cursor.encode()).unwrap(), cursor);
+    }
+
+    #[test]
+    fn from_query_uses_defaults() {
+        let listing = listing(ListQuery::default());
+        assert_eq!(listing.limit, DEFAULT_LIMIT);
+        assert_eq!(listing.sort, SortField::CreatedAt);
+        assert_eq!(listing.order, SortOrder::Asc);
+        assert_eq!(listing.cursor, None);
+    }
+
+    #[test]
+    fn from_query_rejects_invalid_limits() {
+        for limit in &[0, MAX_LIMIT + 1] {
+            let query = ListQuery {
+                limit: Some(*limit),
+                ..ListQuery::default()
+            };
+            let error = UserListing::from_query(&query).err().unwrap();
+            assert!(matches!(error, ServiceError::InvalidQuery(_)));
+        }
+    }
+
+    #[test]
+    fn from_query_rejects_invalid_cursors() {
+        let query = ListQuery {
+            cursor: Some("not-a-cursor".to_string()),
+            ..ListQuery::default()
+        };
+        let error = UserListing::from_query(&query).err().unwrap();
+        assert!(matches!(error, ServiceError::InvalidQuery(_)));
+    }
+
+    #[test]
+    fn from_query_rejects_cursors_of_another_sorting() {
+        let query = ListQuery {
+            cursor: Some(cursor(Direction::Next, SortField::Name)),
+            sort: Some(SortField::BirthDate),
+            ..ListQuery::default()
+        };
+        let error = UserListing::from_query(&query).err().unwrap();
+        assert!(matches!(error, ServiceError::InvalidQuery(_)));
+    }
+
+    #[test]
+    fn first_page_has_only_next() {
+        let listing = listing(ListQuery {
+            limit: Some(2),
+            ..ListQuery::default()
+        });
+        let page = listing.page(users(3), None);
+
+        assert_eq!(page.items.len(), 2);
+        assert!(page.next_cursor.is_some());
+        assert!(page.prev_cursor.is_none());
+    }
+
+    #[test]
+    fn last_page_has_only_prev() {
+        let listing = listing(ListQuery {
+            limit: Some(2),
+            sort: Some(SortField::Name),
+            cursor: Some(cursor(Direction::Next, SortField::Name)),
+            ..ListQuery::default()
+        });
+        let page = listing.page(users(2), Some(4));
+
+        assert_eq!(page.items.len(), 2);
+        assert!(page.next_cursor.is_none());
+        assert_eq!(page.total, Some(4));
+
+        let prev = Cursor::decode(&page.prev_cursor.unwrap()).unwrap();
+        assert_eq!(prev.direction, Direction::Prev);
+        assert_eq!(prev.key, page.items[0].name);
+    }
+
+    #[test]
+    fn backwards_pages_are_reversed() {
+        let listing = listing(ListQuery {
+            limit: Some(2),
+            sort: Some(SortField::Name),
+            cursor: Some(cursor(Direction::Prev, SortField::Name)),
+            ..ListQuery::default()
+        });
+        let read = users(3);
+        let page = listing.page(read.clone(), None);
+
+        assert_eq!(page.items, vec![read[1].clone(), read[0].clone()]);
+        assert!(page.next_cursor.is_some());
+        assert!(page.prev_cursor.is_some());
+    }
+
+    #[test]
+    fn link_header_keeps_the_query() {
+        let query = ListQuery {
+            limit: Some(2),
+            name_prefix: Some("rob".to_string()),
+            ..ListQuery::default()
+        };
+        let page = UserPage {
+            next_cursor: Some("abc".to_string()),
+            ..UserPage::default()
+        };
+
+        let link = link_header("/v1/users", &query, &page).unwrap();
+
+        assert_eq!(
+            link,
+            "</v1/users?limit=2&cursor=abc&name_prefix=rob>; rel=\"next\""
+        );
+        assert_eq!(link_header("/v1/users", &query, &UserPage::default()), None);
+    }
+}
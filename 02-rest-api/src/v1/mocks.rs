@@ -0,0 +1,319 @@
+//! Mocks shared by the tests of the different front-ends and layers.
+use super::audit::{Actor, HistoryListing};
+use super::etag::Version;
+use super::idempotency::{IdempotencyKey, Idempotent};
+use super::listing::{UserFilter, UserListing};
+use super::repository::Repository;
+use super::service::{Result as ServiceResult, Service};
+use crate::auth::Caller;
+use crate::models::{
+    AuditRecord, CustomData, HistoryPage, HistoryQuery, ListQuery, User, UserPage, UserPatch,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use mockall::*;
+use sqlx::Result;
+use uuid::Uuid;
+
+mock! {
+    pub Svc {
+        fn sync_get_user(
+            &self,
+            user_id: &Uuid,
+            caller: Caller,
+            include_deleted: bool,
+        ) -> ServiceResult<User> {}
+        fn sync_update_user(
+            &self, user_id: &Uuid,
+            caller: Caller,
+            custom_data: CustomData,
+            expected: Option<Version>,
+            request_id: Option<String>,
+        ) -> ServiceResult<User> {}
+        fn sync_create_user(
+            &self,
+            user: User,
+            caller: Caller,
+            request_id: Option<String>,
+        ) -> ServiceResult<User> {}
+        fn sync_create_user_idempotent(
+            &self,
+            user: User,
+            key: IdempotencyKey,
+            caller: Caller,
+            request_id: Option<String>,
+        ) -> ServiceResult<Idempotent<User>> {}
+        fn sync_sign_up(
+            &self,
+            user: User,
+            caller: Caller,
+            request_id: Option<String>,
+        ) -> ServiceResult<User> {}
+        fn sync_replace_user(
+            &self,
+            user_id: &Uuid,
+            caller: Caller,
+            user: User,
+            expected: Option<Version>,
+            request_id: Option<String>,
+        ) -> ServiceResult<User> {}
+        fn sync_patch_user(
+            &self,
+            user_id: &Uuid,
+            caller: Caller,
+            patch: UserPatch,
+            expected: Option<Version>,
+            request_id: Option<String>,
+        ) -> ServiceResult<User> {}
+        fn sync_delete_user(
+            &self,
+            user_id: &Uuid,
+            caller: Caller,
+            expected: Option<Version>,
+            request_id: Option<String>,
+        ) -> ServiceResult<User> {}
+        fn sync_list_users(
+            &self,
+            caller: Caller,
+            query: ListQuery,
+        ) -> ServiceResult<UserPage> {}
+        fn sync_restore_user(
+            &self,
+            user_id: &Uuid,
+            caller: Caller,
+            request_id: Option<String>,
+        ) -> ServiceResult<User> {}
+        fn sync_user_history(
+            &self,
+            user_id: &Uuid,
+            caller: Caller,
+            query: HistoryQuery,
+        ) -> ServiceResult<HistoryPage> {}
+        fn sync_purge_deleted_users(&self, retention: Duration) -> ServiceResult<u64> {}
+    }
+}
+
+impl std::fmt::Debug for MockSvc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockSvc").finish()
+    }
+}
+
+#[async_trait]
+impl Service for MockSvc {
+    async fn get_user(
+        &self,
+        user_id: &Uuid,
+        caller: Caller,
+        include_deleted: bool,
+    ) -> ServiceResult<User> {
+        self.sync_get_user(&user_id, caller, include_deleted)
+    }
+    async fn update_user(
+        &self,
+        user_id: &Uuid,
+        caller: Caller,
+        custom_data: CustomData,
+        expected: Option<Version>,
+        request_id: Option<String>,
+    ) -> ServiceResult<User> {
+        self.sync_update_user(&user_id, caller, custom_data, expected, request_id)
+    }
+    async fn create_user(
+        &self,
+        user: User,
+        caller: Caller,
+        request_id: Option<String>,
+    ) -> ServiceResult<User> {
+        self.sync_create_user(user, caller, request_id)
+    }
+    async fn create_user_idempotent(
+        &self,
+        user: User,
+        key: IdempotencyKey,
+        caller: Caller,
+        request_id: Option<String>,
+    ) -> ServiceResult<Idempotent<User>> {
+        self.sync_create_user_idempotent(user, key, caller, request_id)
+    }
+    async fn sign_up(
+        &self,
+        user: User,
+        caller: Caller,
+        request_id: Option<String>,
+    ) -> ServiceResult<User> {
+        self.sync_sign_up(user, caller, request_id)
+    }
+    async fn replace_user(
+        &self,
+        user_id: &Uuid,
+        caller: Caller,
+        user: User,
+        expected: Option<Version>,
+        request_id: Option<String>,
+    ) -> ServiceResult<User> {
+        self.sync_replace_user(&user_id, caller, user, expected, request_id)
+    }
+    async fn patch_user(
+        &self,
+        user_id: &Uuid,
+        caller: Caller,
+        patch: UserPatch,
+        expected: Option<Version>,
+        request_id: Option<String>,
+    ) -> ServiceResult<User> {
+        self.sync_patch_user(&user_id, caller, patch, expected, request_id)
+    }
+    async fn delete_user(
+        &self,
+        user_id: &Uuid,
+        caller: Caller,
+        expected: Option<Version>,
+        request_id: Option<String>,
+    ) -> ServiceResult<User> {
+        self.sync_delete_user(&user_id, caller, expected, request_id)
+    }
+    async fn list_users(&self, caller: Caller, query: ListQuery) -> ServiceResult<UserPage> {
+        self.sync_list_users(caller, query)
+    }
+    async fn restore_user(
+        &self,
+        user_id: &Uuid,
+        caller: Caller,
+        request_id: Option<String>,
+    ) -> ServiceResult<User> {
+        self.sync_restore_user(&user_id, caller, request_id)
+    }
+    async fn user_history(
+        &self,
+        user_id: &Uuid,
+        caller: Caller,
+        query: HistoryQuery,
+    ) -> ServiceResult<HistoryPage> {
+        self.sync_user_history(&user_id, caller, query)
+    }
+    async fn purge_deleted_users(&self, retention: Duration) -> ServiceResult<u64> {
+        self.sync_purge_deleted_users(retention)
+    }
+}
+
+mock! {
+    pub Repo {
+        fn sync_get_user(&self, id: &Uuid, include_deleted: bool, tenant: &str) -> Result<User> {}
+        fn sync_update_user(
+            &self,
+            id: &Uuid,
+            custom_data: CustomData,
+            expected: Option<Version>,
+            actor: &Actor,
+            tenant: &str,
+        ) -> Result<User> {}
+        fn sync_create_user(&self, user: User, actor: &Actor, tenant: &str) -> Result<User> {}
+        fn sync_create_user_idempotent(
+            &self,
+            user: User,
+            key: &IdempotencyKey,
+            actor: &Actor,
+            tenant: &str,
+        ) -> Result<Idempotent<User>> {}
+        fn sync_replace_user(
+            &self,
+            id: &Uuid,
+            user: User,
+            expected: Option<Version>,
+            actor: &Actor,
+            tenant: &str,
+        ) -> Result<User> {}
+        fn sync_delete_user(
+            &self,
+            id: &Uuid,
+            expected: Option<Version>,
+            actor: &Actor,
+            tenant: &str,
+        ) -> Result<User> {}
+        fn sync_list_users(&self, listing: &UserListing, tenant: &str) -> Result<Vec<User>> {}
+        fn sync_count_users(&self, filter: &UserFilter, tenant: &str) -> Result<i64> {}
+        fn sync_restore_user(&self, id: &Uuid, actor: &Actor, tenant: &str) -> Result<User> {}
+        fn sync_purge_users(&self, deleted_before: DateTime<Utc>) -> Result<u64> {}
+        fn sync_user_history(
+            &self,
+            id: &Uuid,
+            listing: &HistoryListing,
+            tenant: &str,
+        ) -> Result<Vec<AuditRecord>> {}
+    }
+}
+
+impl std::fmt::Debug for MockRepo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockRepo").finish()
+    }
+}
+
+#[async_trait]
+impl Repository for MockRepo {
+    async fn get_user(&self, user: &Uuid, include_deleted: bool, tenant: &str) -> Result<User> {
+        self.sync_get_user(user, include_deleted, tenant)
+    }
+    async fn create_user(&self, user: User, actor: &Actor, tenant: &str) -> Result<User> {
+        self.sync_create_user(user, actor, tenant)
+    }
+    async fn create_user_idempotent(
+        &self,
+        user: User,
+        key: &IdempotencyKey,
+        actor: &Actor,
+        tenant: &str,
+    ) -> Result<Idempotent<User>> {
+        self.sync_create_user_idempotent(user, key, actor, tenant)
+    }
+    async fn update_user(
+        &self,
+        id: &Uuid,
+        custom_data: CustomData,
+        expected: Option<Version>,
+        actor: &Actor,
+        tenant: &str,
+    ) -> Result<User> {
+        self.sync_update_user(id, custom_data, expected, actor, tenant)
+    }
+    async fn replace_user(
+        &self,
+        id: &Uuid,
+        user: User,
+        expected: Option<Version>,
+        actor: &Actor,
+        tenant: &str,
+    ) -> Result<User> {
+        self.sync_replace_user(id, user, expected, actor, tenant)
+    }
+    async fn delete_user(
+        &self,
+        id: &Uuid,
+        expected: Option<Version>,
+        actor: &Actor,
+        tenant: &str,
+    ) -> Result<User> {
+        self.sync_delete_user(id, expected, actor, tenant)
+    }
+    async fn list_users(&self, listing: &UserListing, tenant: &str) -> Result<Vec<User>> {
+        self.sync_list_users(listing, tenant)
+    }
+    async fn count_users(&self, filter: &UserFilter, tenant: &str) -> Result<i64> {
+        self.sync_count_users(filter, tenant)
+    }
+    async fn restore_user(&self, id: &Uuid, actor: &Actor, tenant: &str) -> Result<User> {
+        self.sync_restore_user(id, actor, tenant)
+    }
+    async fn purge_users(&self, deleted_before: DateTime<Utc>) -> Result<u64> {
+        self.sync_purge_users(deleted_before)
+    }
+    async fn user_history(
+        &self,
+        id: &Uuid,
+        listing: &HistoryListing,
+        tenant: &str,
+    ) -> Result<Vec<AuditRecord>> {
+        self.sync_user_history(id, listing, tenant)
+    }
+}
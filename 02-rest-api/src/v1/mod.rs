@@ -1,27 +1,134 @@
+pub mod audit;
+pub mod cache;
+pub mod etag;
 mod handlers;
+pub mod idempotency;
+pub mod listing;
+#[cfg(test)]
+pub mod mocks;
 pub mod repository;
 pub mod service;
+pub mod validated;
 
-use actix_web::web;
-use handlers::users;
+use crate::models::UserPatch;
+use crate::problem::Problem;
+use actix_web::{
+    guard,
+    http::{header, Method},
+    web, Route, Scope,
+};
+use handlers::{me, users};
+
+pub const PATH: &str = "/v1";
 
 /// Configures the API
 pub fn api<S: service::Service + 'static>(cfg: &mut web::ServiceConfig) {
-    configure_users::<S>(cfg);
+    cfg.service(users_router::<S>().scope)
+        .service(me_router::<S>().scope);
+}
+
+/// Every route of the API, as its method and full path, like `GET /v1/users/{id}`.
+pub fn routes<S: service::Service + 'static>() -> Vec<(Method, String)> {
+    users_router::<S>()
+        .routes
+        .into_iter()
+        .chain(me_router::<S>().routes)
+        .map(|(method, path)| (method, format!("{}{}", PATH, path)))
+        .collect()
 }
 
-/// Configures the user endpoints
-fn configure_users<S: service::Service + 'static>(cfg: &mut web::ServiceConfig) {
+/// A scope that keeps track of its routes, so they can be checked against the OpenAPI document.
+struct Router {
+    path: &'static str,
+    scope: Scope,
+    routes: Vec<(Method, String)>,
+}
+
+impl Router {
+    fn new(path: &'static str) -> Self {
+        Self {
+            path,
+            scope: web::scope(path),
+            routes: vec![],
+        }
+    }
+
+    fn app_data<U: 'static>(mut self, data: U) -> Self {
+        self.scope = self.scope.app_data(data);
+        self
+    }
+
+    /// Adds a route for the method, finished by `to` with its guards and handler.
+    fn route(mut self, method: Method, path: &str, to: impl FnOnce(Route) -> Route) -> Self {
+        self.routes
+            .push((method.clone(), format!("{}{}", self.path, path)));
+        self.scope = self.scope.route(path, to(web::method(method)));
+        self
+    }
+}
+
+/// Routes of the user endpoints
+fn users_router<S: service::Service + 'static>() -> Router {
     let path_user_id = "/{id}";
-    cfg.service(
-        web::scope(users::PATH)
-            // GET
-            .route(path_user_id, web::get().to(users::get::<S>))
-            // POST
-            .route("/", web::post().to(users::post::<S>))
-            // PATCH
-            .route(&path_user_id, web::patch().to(users::patch::<S>))
-            // DELETE
-            .route(&path_user_id, web::delete().to(users::delete::<S>)),
-    );
+    let path_restore = format!("{}{}", path_user_id, users::RESTORE_PATH);
+    let path_history = format!("{}{}", path_user_id, users::HISTORY_PATH);
+    Router::new(users::PATH)
+        // extraction errors are problems too
+        .app_data(web::JsonConfig::default().error_handler(Problem::from_json))
+        .app_data(web::PathConfig::default().error_handler(Problem::from_path))
+        .app_data(web::QueryConfig::default().error_handler(Problem::from_query))
+        // GET
+        .route(Method::GET, "", |r| r.to(users::list::<S>))
+        .route(Method::GET, "/", |r| r.to(users::list::<S>))
+        .route(Method::GET, path_user_id, |r| r.to(users::get::<S>))
+        .route(Method::GET, &path_history, |r| r.to(users::history::<S>))
+        // POST
+        .route(Method::POST, "/", |r| r.to(users::post::<S>))
+        .route(Method::POST, &path_restore, |r| r.to(users::restore::<S>))
+        // PUT
+        .route(Method::PUT, path_user_id, |r| r.to(users::put::<S>))
+        // PATCH
+        .route(Method::PATCH, path_user_id, |r| {
+            r.guard(content_type(UserPatch::MERGE_CONTENT_TYPE))
+                .to(users::merge_patch::<S>)
+        })
+        .route(Method::PATCH, path_user_id, |r| {
+            r.guard(content_type(UserPatch::JSON_CONTENT_TYPE))
+                .to(users::json_patch::<S>)
+        })
+        // plain JSON bodies only update the custom_data
+        .route(Method::PATCH, path_user_id, |r| r.to(users::patch::<S>))
+        // DELETE
+        .route(Method::DELETE, path_user_id, |r| r.to(users::delete::<S>))
+}
+
+/// Routes of the caller's own user
+fn me_router<S: service::Service + 'static>() -> Router {
+    Router::new(me::PATH)
+        .app_data(web::JsonConfig::default().error_handler(Problem::from_json))
+        .app_data(web::QueryConfig::default().error_handler(Problem::from_query))
+        .route(Method::GET, "", |r| r.to(me::get::<S>))
+        // sign-up
+        .route(Method::POST, "", |r| r.to(me::sign_up::<S>))
+        .route(Method::PATCH, "", |r| {
+            r.guard(content_type(UserPatch::MERGE_CONTENT_TYPE))
+                .to(me::merge_patch::<S>)
+        })
+        .route(Method::PATCH, "", |r| {
+            r.guard(content_type(UserPatch::JSON_CONTENT_TYPE))
+                .to(me::json_patch::<S>)
+        })
+        .route(Method::PATCH, "", |r| r.to(me::patch::<S>))
+        .route(Method::DELETE, "", |r| r.to(me::delete::<S>))
+}
+
+/// Matches the requests with this content type, whatever its parameters.
+fn content_type(expected: &'static str) -> impl guard::Guard {
+    guard::fn_guard(move |req| {
+        req.headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .map_or(false, |v| v.trim().eq_ignore_ascii_case(expected))
+    })
 }
@@ -1,32 +1,268 @@
-use crate::models::{CustomData, User};
+use super::audit::{self, Actor, HistoryListing};
+use super::etag::Version;
+use super::idempotency::{self, IdempotencyKey, Idempotent};
+use super::listing::{UserFilter, UserListing};
+use crate::metrics::QueryMetrics;
+use crate::models::{AuditRecord, CustomData, Operation, SortField, SortOrder, User};
 use async_trait::async_trait;
-use sqlx::{types::chrono::Utc, types::Json, PgPool, Result};
+use sqlx::{
+    postgres::PgPoolOptions,
+    types::chrono::{DateTime, NaiveDate, Utc},
+    types::Json,
+    Done, PgPool, Postgres, Result, Transaction,
+};
 use std::time::Instant;
 use tracing::{self as log, instrument};
 
+/// Every operation but the purge is scoped by the `tenant`:
+/// the users of other tenants are never seen, as if they didn't exist.
 #[async_trait]
 pub trait Repository: std::fmt::Debug {
     /// Gets a user by id from the database.
-    async fn get_user(&self, id: &uuid::Uuid) -> Result<User>;
-    /// Creates a new user in the database.
-    async fn create_user(&self, user: User) -> Result<User>;
+    /// Deleted users are only found if `include_deleted`.
+    async fn get_user(&self, id: &uuid::Uuid, include_deleted: bool, tenant: &str) -> Result<User>;
+    /// Creates a new user in the database, with its id if it has one.
+    /// Every write records the change, made by the `actor`, in the audit trail of the user.
+    async fn create_user(&self, user: User, actor: &Actor, tenant: &str) -> Result<User>;
+    /// Creates a new user unless the idempotency key was already used by the same caller.
+    /// Otherwise returns the user created with the key, along with the hash of its request.
+    /// Requests with the same key wait for each other, so only one creates the user.
+    async fn create_user_idempotent(
+        &self,
+        user: User,
+        key: &IdempotencyKey,
+        actor: &Actor,
+        tenant: &str,
+    ) -> Result<Idempotent<User>>;
     /// Updates the user's custom_data field.
-    async fn update_user(&self, id: &uuid::Uuid, custom_data: CustomData) -> Result<User>;
-    /// Deletes a user.
-    async fn delete_user(&self, id: &uuid::Uuid) -> Result<User>;
+    /// Writes only succeed if the user is still at the `expected` version, if any.
+    /// Otherwise they fail with [sqlx::Error::RowNotFound], as if the user didn't exist.
+    /// Deleted users can't be written.
+    async fn update_user(
+        &self,
+        id: &uuid::Uuid,
+        custom_data: CustomData,
+        expected: Option<Version>,
+        actor: &Actor,
+        tenant: &str,
+    ) -> Result<User>;
+    /// Replaces every field of the user but the id and creation date.
+    async fn replace_user(
+        &self,
+        id: &uuid::Uuid,
+        user: User,
+        expected: Option<Version>,
+        actor: &Actor,
+        tenant: &str,
+    ) -> Result<User>;
+    /// Soft deletes a user, which is kept until purged.
+    async fn delete_user(
+        &self,
+        id: &uuid::Uuid,
+        expected: Option<Version>,
+        actor: &Actor,
+        tenant: &str,
+    ) -> Result<User>;
+    /// Restores a deleted user.
+    async fn restore_user(&self, id: &uuid::Uuid, actor: &Actor, tenant: &str) -> Result<User>;
+    /// Removes for good the users deleted before the given time, in every tenant.
+    /// Returns how many users were purged.
+    async fn purge_users(&self, deleted_before: DateTime<Utc>) -> Result<u64>;
+    /// Lists the users matching the filter, from the cursor on.
+    /// Reads up to `limit + 1` users so the caller knows whether there are more.
+    async fn list_users(&self, listing: &UserListing, tenant: &str) -> Result<Vec<User>>;
+    /// Counts the users matching the filter.
+    async fn count_users(&self, filter: &UserFilter, tenant: &str) -> Result<i64>;
+    /// Lists the audit records of a user, the latest first.
+    /// Reads up to `limit + 1` records so the caller knows whether there are more.
+    async fn user_history(
+        &self,
+        id: &uuid::Uuid,
+        listing: &HistoryListing,
+        tenant: &str,
+    ) -> Result<Vec<AuditRecord>>;
 }
 
+/// Conditions shared by the listing and the counting of users.
+const USERS_FILTER: &str = r#"
+    ($1::text IS NULL OR left(name, length($1)) = $1)
+    AND ($2::date IS NULL OR birth_date >= $2)
+    AND ($3::date IS NULL OR birth_date <= $3)
+    AND ($4::bool OR deleted_at IS NULL)
+    AND tenant_id = $5
+"#;
+
+/// Sort expression and Postgres type of a sort field.
+/// Users without creation date go first, as `-infinity`.
+fn sort_column(sort: SortField) -> (&'static str, &'static str) {
+    match sort {
+        SortField::Name => ("name", "text"),
+        SortField::CreatedAt => (
+            "COALESCE(created_at, '-infinity'::timestamptz)",
+            "timestamptz",
+        ),
+        SortField::BirthDate => ("birth_date", "date"),
+    }
+}
+
+/// A row of the users table.
+/// The domain [User] knows nothing about the database, so queries map into this first.
+#[derive(Debug, sqlx::FromRow)]
+struct UserRow {
+    id: Option<uuid::Uuid>,
+    name: String,
+    birth_date: NaiveDate,
+    created_at: Option<DateTime<Utc>>,
+    updated_at: Option<DateTime<Utc>>,
+    custom_data: Option<Json<CustomData>>,
+    deleted_at: Option<DateTime<Utc>>,
+}
+
+impl From<UserRow> for User {
+    fn from(row: UserRow) -> Self {
+        Self {
+            id: row.id,
+            name: row.name,
+            birth_date: row.birth_date,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+            custom_data: row.custom_data.map(|Json(custom_data)| custom_data),
+            deleted_at: row.deleted_at,
+        }
+    }
+}
+
+/// Inserts a user of the tenant, either on its own or as part of a transaction.
+async fn insert_user<'e, E>(executor: E, user: User, tenant: &str) -> Result<User>
+where
+    E: sqlx::Executor<'e, Database = Postgres>,
+{
+    sqlx::query_as!(
+        UserRow,
+        r#"
+            INSERT INTO users (id, name, birth_date, custom_data, tenant_id)
+            VALUES (COALESCE($1, uuid_generate_v1()), $2, $3, $4, $5)
+            RETURNING id as "id?", name, birth_date, custom_data as "custom_data: Json<CustomData>", created_at, updated_at, deleted_at
+            "#,
+        user.id,
+        user.name,
+        user.birth_date,
+        user.custom_data.map(Json) as _,
+        tenant,
+    )
+    .fetch_one(executor)
+    .await
+    .map(User::from)
+}
+
+/// Reads a user to be written in the same transaction, so nobody changes it meanwhile.
+async fn lock_user(
+    tx: &mut Transaction<'_, Postgres>,
+    id: &uuid::Uuid,
+    tenant: &str,
+) -> Result<User> {
+    sqlx::query_as!(
+        UserRow,
+        r#"
+            SELECT id as "id?", name, birth_date, custom_data as "custom_data: Json<CustomData>", created_at, updated_at, deleted_at
+            FROM users
+            WHERE id = $1 AND tenant_id = $2
+            FOR UPDATE
+            "#,
+        id,
+        tenant,
+    )
+    .fetch_one(tx)
+    .await
+    .map(User::from)
+}
+
+/// Appends a change of a user to its audit trail.
+async fn record_change(
+    tx: &mut Transaction<'_, Postgres>,
+    operation: Operation,
+    before: Option<&User>,
+    after: &User,
+    actor: &Actor,
+    tenant: &str,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+            INSERT INTO user_audit (user_id, actor, operation, changes, request_id, tenant_id)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+        after.id,
+        actor.user_id,
+        operation.as_str(),
+        audit::changes(before, after),
+        actor.request_id,
+        tenant,
+    )
+    .execute(tx)
+    .await
+    .map(|_| ())
+}
+
+/// A row of the user_audit table.
+#[derive(Debug, sqlx::FromRow)]
+struct AuditRow {
+    id: i64,
+    user_id: uuid::Uuid,
+    actor: Option<String>,
+    operation: String,
+    changes: serde_json::Value,
+    request_id: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+impl std::convert::TryFrom<AuditRow> for AuditRecord {
+    type Error = sqlx::Error;
+
+    fn try_from(row: AuditRow) -> Result<Self> {
+        Ok(Self {
+            id: row.id,
+            user_id: row.user_id,
+            actor: row.actor,
+            operation: row
+                .operation
+                .parse()
+                .map_err(|e: String| sqlx::Error::Decode(e.into()))?,
+            changes: row.changes,
+            request_id: row.request_id,
+            created_at: row.created_at,
+        })
+    }
+}
+
+/// Connections of the pool at most.
+pub const MAX_CONNECTIONS: u32 = 10;
+
 /// Postgres repository implementation
 #[derive(Debug)]
 pub struct PostgresRepository {
     pub pool: PgPool,
+    metrics: Option<QueryMetrics>,
 }
 
 impl PostgresRepository {
     /// Generates a connection pool for a Postgres database
     pub async fn build(conn_str: &str) -> Result<Self> {
-        let pool = PgPool::connect(conn_str).await?;
-        Ok(Self { pool })
+        let pool = PgPoolOptions::new()
+            .max_connections(MAX_CONNECTIONS)
+            .connect(conn_str)
+            .await?;
+        Ok(Self {
+            pool,
+            metrics: None,
+        })
+    }
+
+    /// Times the queries in the metrics.
+    pub fn with_metrics(self, metrics: QueryMetrics) -> Self {
+        Self {
+            metrics: Some(metrics),
+            ..self
+        }
     }
 
     /// Generates a connection pool for a Postgres database
@@ -37,80 +273,495 @@ impl PostgresRepository {
             std::env::var("DATABASE_URL").map_err(|e| sqlx::Error::Configuration(Box::new(e)))?;
         PostgresRepository::build(&conn_str).await
     }
+
+    /// Starts a transaction that only sees the rows of the tenant,
+    /// as enforced by the row-level security of the tables.
+    /// The wait for its connection is observed by the metrics, if any.
+    async fn begin(&self, tenant: &str) -> Result<Transaction<'_, Postgres>> {
+        let start = Instant::now();
+        let tx = self.pool.begin().await;
+        if let Some(metrics) = &self.metrics {
+            metrics.acquired(start.elapsed());
+        }
+        let mut tx = tx?;
+        sqlx::query!("SELECT set_config('app.tenant_id', $1, true)", tenant)
+            .execute(&mut tx)
+            .await?;
+        Ok(tx)
+    }
 }
 
 #[async_trait]
 impl Repository for PostgresRepository {
-    #[instrument]
-    async fn get_user(&self, id: &uuid::Uuid) -> Result<User> {
-        measure_query!("Get", {
-            sqlx::query_as!(
-                User,
+    #[instrument(
+        skip(self),
+        fields(
+            otel.kind = "client",
+            db.system = "postgresql",
+            db.operation = "SELECT",
+            db.sql.table = "users"
+        )
+    )]
+    async fn get_user(&self, id: &uuid::Uuid, include_deleted: bool, tenant: &str) -> Result<User> {
+        measure_query!(self.metrics, "get", {
+            let mut tx = self.begin(tenant).await?;
+            let user = sqlx::query_as!(
+                UserRow,
                 r#"
-                SELECT id as "id?", name, birth_date, custom_data as "custom_data: Json<CustomData>", created_at, updated_at
+                SELECT id as "id?", name, birth_date, custom_data as "custom_data: Json<CustomData>", created_at, updated_at, deleted_at
                 FROM users
-                WHERE id = $1
+                WHERE id = $1 AND ($2 OR deleted_at IS NULL) AND tenant_id = $3
                 "#,
                 id,
+                include_deleted,
+                tenant,
             )
-            .fetch_one(&self.pool)
+            .fetch_one(&mut tx)
             .await
+            .map(User::from)?;
+            tx.commit().await?;
+            Ok(user)
+        })
+    }
+
+    #[instrument(
+        skip(self),
+        fields(
+            otel.kind = "client",
+            db.system = "postgresql",
+            db.operation = "INSERT",
+            db.sql.table = "users"
+        )
+    )]
+    async fn create_user(&self, user: User, actor: &Actor, tenant: &str) -> Result<User> {
+        measure_query!(self.metrics, "create", {
+            let mut tx = self.begin(tenant).await?;
+            let user = insert_user(&mut tx, user, tenant).await?;
+            record_change(&mut tx, Operation::Create, None, &user, actor, tenant).await?;
+            tx.commit().await?;
+            Ok(user)
         })
     }
 
-    #[instrument]
-    async fn create_user(&self, user: User) -> Result<User> {
-        measure_query!("Create", {
-            sqlx::query_as!(
-                User,
+    #[instrument(
+        skip(self),
+        fields(
+            otel.kind = "client",
+            db.system = "postgresql",
+            db.operation = "INSERT",
+            db.sql.table = "users"
+        )
+    )]
+    async fn create_user_idempotent(
+        &self,
+        user: User,
+        key: &IdempotencyKey,
+        actor: &Actor,
+        tenant: &str,
+    ) -> Result<Idempotent<User>> {
+        // callers without id, like the ones of the disabled authentication, share the keys
+        let caller = actor.user_id.as_deref().unwrap_or_default();
+        measure_query!(self.metrics, "create_idempotent", {
+            let mut tx = self.begin(tenant).await?;
+            sqlx::query!(
+                "DELETE FROM idempotency_keys WHERE expires_at <= now() AND tenant_id = $1",
+                tenant,
+            )
+            .execute(&mut tx)
+            .await?;
+            // a concurrent request with the same key holds the row until it's done,
+            // so this waits for it and then finds its response
+            let claimed = sqlx::query!(
                 r#"
-            INSERT INTO users (name, birth_date, custom_data)
-            VALUES ($1, $2, $3)
-            RETURNING id as "id?", name, birth_date, custom_data as "custom_data: Json<CustomData>", created_at, updated_at
+            INSERT INTO idempotency_keys (key, request_hash, expires_at, tenant_id, caller)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (tenant_id, caller, key) DO NOTHING
+            RETURNING key
+            "#,
+                key.key,
+                key.request_hash,
+                Utc::now() + idempotency::ttl(),
+                tenant,
+                caller,
+            )
+            .fetch_optional(&mut tx)
+            .await?;
+            let result = if claimed.is_some() {
+                let user = insert_user(&mut tx, user, tenant).await?;
+                record_change(&mut tx, Operation::Create, None, &user, actor, tenant).await?;
+                sqlx::query!(
+                    r#"
+            UPDATE idempotency_keys SET response = $1
+            WHERE key = $2 AND tenant_id = $3 AND caller = $4
+            "#,
+                    Json(&user) as _,
+                    key.key,
+                    tenant,
+                    caller,
+                )
+                .execute(&mut tx)
+                .await?;
+                Idempotent::Created(user)
+            } else {
+                let stored = sqlx::query!(
+                    r#"
+            SELECT request_hash, response as "response: Json<User>"
+            FROM idempotency_keys
+            WHERE key = $1 AND tenant_id = $2 AND caller = $3
+            "#,
+                    key.key,
+                    tenant,
+                    caller,
+                )
+                .fetch_one(&mut tx)
+                .await?;
+                // the response is stored in the same transaction that claims the key
+                let Json(response) = stored.response.ok_or(sqlx::Error::RowNotFound)?;
+                Idempotent::Replayed {
+                    request_hash: stored.request_hash,
+                    response,
+                }
+            };
+            tx.commit().await?;
+            Ok(result)
+        })
+    }
+
+    #[instrument(
+        skip(self),
+        fields(
+            otel.kind = "client",
+            db.system = "postgresql",
+            db.operation = "UPDATE",
+            db.sql.table = "users"
+        )
+    )]
+    async fn update_user(
+        &self,
+        id: &uuid::Uuid,
+        custom_data: CustomData,
+        expected: Option<Version>,
+        actor: &Actor,
+        tenant: &str,
+    ) -> Result<User> {
+        measure_query!(self.metrics, "update", {
+            let mut tx = self.begin(tenant).await?;
+            let before = lock_user(&mut tx, id, tenant).await?;
+            let user = sqlx::query_as!(
+                UserRow,
+                r#"
+            UPDATE users
+            SET custom_data = $1, updated_at = $2
+            WHERE id = $3 AND deleted_at IS NULL AND tenant_id = $5
+            AND ($4::timestamptz IS NULL OR COALESCE(updated_at, created_at) = $4)
+            RETURNING id  as "id?", name, birth_date, custom_data as "custom_data: Json<CustomData>", created_at, updated_at, deleted_at
+            "#,
+                Json(custom_data) as _,
+                Utc::now(),
+                id,
+                expected.map(|v| v.timestamp()),
+                tenant,
+            )
+            .fetch_one(&mut tx)
+            .await
+            .map(User::from)?;
+            record_change(
+                &mut tx,
+                Operation::Update,
+                Some(&before),
+                &user,
+                actor,
+                tenant,
+            )
+            .await?;
+            tx.commit().await?;
+            Ok(user)
+        })
+    }
+
+    #[instrument(
+        skip(self),
+        fields(
+            otel.kind = "client",
+            db.system = "postgresql",
+            db.operation = "UPDATE",
+            db.sql.table = "users"
+        )
+    )]
+    async fn replace_user(
+        &self,
+        id: &uuid::Uuid,
+        user: User,
+        expected: Option<Version>,
+        actor: &Actor,
+        tenant: &str,
+    ) -> Result<User> {
+        measure_query!(self.metrics, "replace", {
+            let mut tx = self.begin(tenant).await?;
+            let before = lock_user(&mut tx, id, tenant).await?;
+            let user = sqlx::query_as!(
+                UserRow,
+                r#"
+            UPDATE users
+            SET name = $1, birth_date = $2, custom_data = $3, updated_at = $4
+            WHERE id = $5 AND deleted_at IS NULL AND tenant_id = $7
+            AND ($6::timestamptz IS NULL OR COALESCE(updated_at, created_at) = $6)
+            RETURNING id  as "id?", name, birth_date, custom_data as "custom_data: Json<CustomData>", created_at, updated_at, deleted_at
             "#,
                 user.name,
                 user.birth_date,
-                user.custom_data as _,
+                user.custom_data.map(Json) as _,
+                Utc::now(),
+                id,
+                expected.map(|v| v.timestamp()),
+                tenant,
             )
-            .fetch_one(&self.pool)
+            .fetch_one(&mut tx)
             .await
+            .map(User::from)?;
+            record_change(
+                &mut tx,
+                Operation::Update,
+                Some(&before),
+                &user,
+                actor,
+                tenant,
+            )
+            .await?;
+            tx.commit().await?;
+            Ok(user)
         })
     }
 
-    #[instrument]
-    async fn update_user(&self, id: &uuid::Uuid, custom_data: CustomData) -> Result<User> {
-        measure_query!("Update", {
-            sqlx::query_as!(
-                User,
+    #[instrument(
+        skip(self),
+        fields(
+            otel.kind = "client",
+            db.system = "postgresql",
+            db.operation = "UPDATE",
+            db.sql.table = "users"
+        )
+    )]
+    async fn delete_user(
+        &self,
+        id: &uuid::Uuid,
+        expected: Option<Version>,
+        actor: &Actor,
+        tenant: &str,
+    ) -> Result<User> {
+        measure_query!(self.metrics, "delete", {
+            let mut tx = self.begin(tenant).await?;
+            let before = lock_user(&mut tx, id, tenant).await?;
+            let user = sqlx::query_as!(
+                UserRow,
                 r#"
             UPDATE users
-            SET custom_data = $1, updated_at = $2
-            WHERE id = $3
-            RETURNING id  as "id?", name, birth_date, custom_data as "custom_data: Json<CustomData>", created_at, updated_at
+            SET deleted_at = $1, updated_at = $1
+            WHERE id = $2 AND deleted_at IS NULL AND tenant_id = $4
+            AND ($3::timestamptz IS NULL OR COALESCE(updated_at, created_at) = $3)
+            RETURNING id  as "id?", name, birth_date, custom_data as "custom_data: Json<CustomData>", created_at, updated_at, deleted_at
             "#,
-                Json(custom_data) as _,
                 Utc::now(),
                 id,
+                expected.map(|v| v.timestamp()),
+                tenant,
             )
-            .fetch_one(&self.pool)
+            .fetch_one(&mut tx)
             .await
+            .map(User::from)?;
+            record_change(
+                &mut tx,
+                Operation::Delete,
+                Some(&before),
+                &user,
+                actor,
+                tenant,
+            )
+            .await?;
+            tx.commit().await?;
+            Ok(user)
         })
     }
 
-    #[instrument]
-    async fn delete_user(&self, id: &uuid::Uuid) -> Result<User> {
-        measure_query!("Delete", {
-            sqlx::query_as!(
-                User,
+    #[instrument(
+        skip(self),
+        fields(
+            otel.kind = "client",
+            db.system = "postgresql",
+            db.operation = "UPDATE",
+            db.sql.table = "users"
+        )
+    )]
+    async fn restore_user(&self, id: &uuid::Uuid, actor: &Actor, tenant: &str) -> Result<User> {
+        measure_query!(self.metrics, "restore", {
+            let mut tx = self.begin(tenant).await?;
+            let before = lock_user(&mut tx, id, tenant).await?;
+            let user = sqlx::query_as!(
+                UserRow,
                 r#"
-            DELETE FROM users
-            WHERE id = $1
-            RETURNING id  as "id?", name, birth_date, custom_data as "custom_data: Json<CustomData>", created_at, updated_at
+            UPDATE users
+            SET deleted_at = NULL, updated_at = $1
+            WHERE id = $2 AND deleted_at IS NOT NULL AND tenant_id = $3
+            RETURNING id  as "id?", name, birth_date, custom_data as "custom_data: Json<CustomData>", created_at, updated_at, deleted_at
             "#,
+                Utc::now(),
                 id,
+                tenant,
             )
-            .fetch_one(&self.pool)
+            .fetch_one(&mut tx)
             .await
+            .map(User::from)?;
+            record_change(
+                &mut tx,
+                Operation::Restore,
+                Some(&before),
+                &user,
+                actor,
+                tenant,
+            )
+            .await?;
+            tx.commit().await?;
+            Ok(user)
+        })
+    }
+
+    #[instrument(
+        skip(self),
+        fields(
+            otel.kind = "client",
+            db.system = "postgresql",
+            db.operation = "DELETE",
+            db.sql.table = "users"
+        )
+    )]
+    async fn purge_users(&self, deleted_before: DateTime<Utc>) -> Result<u64> {
+        measure_query!(self.metrics, "purge", {
+            // the only query across tenants, run by a function bypassing the row-level security
+            let purged = sqlx::query!(
+                r#"SELECT purge_deleted_users($1) as "purged!""#,
+                deleted_before
+            )
+            .fetch_one(&self.pool)
+            .await?
+            .purged;
+            Ok(purged as u64)
+        })
+    }
+
+    #[instrument(
+        skip(self),
+        fields(
+            otel.kind = "client",
+            db.system = "postgresql",
+            db.operation = "SELECT",
+            db.sql.table = "users"
+        )
+    )]
+    async fn list_users(&self, listing: &UserListing, tenant: &str) -> Result<Vec<User>> {
+        let (column, column_type) = sort_column(listing.sort);
+        let ascending = (listing.order == SortOrder::Asc) != listing.is_backwards();
+        let (direction, comparison) = if ascending {
+            ("ASC", ">")
+        } else {
+            ("DESC", "<")
+        };
+        // the sort column can't be a bind parameter, but it only comes from [sort_column]
+        let query = format!(
+            r#"
+            SELECT id, name, birth_date, custom_data, created_at, updated_at, deleted_at
+            FROM users
+            WHERE {filter}
+            AND ($6::text IS NULL OR ({column}, id) {comparison} ($6::{column_type}, $7))
+            ORDER BY {column} {direction}, id {direction}
+            LIMIT $8
+            "#,
+            filter = USERS_FILTER,
+            column = column,
+            column_type = column_type,
+            comparison = comparison,
+            direction = direction,
+        );
+        let filter = &listing.filter;
+        let cursor = listing.cursor.as_ref();
+        measure_query!(self.metrics, "list", {
+            let mut tx = self.begin(tenant).await?;
+            let rows = sqlx::query_as::<_, UserRow>(&query)
+                .bind(&filter.name_prefix)
+                .bind(filter.born_after)
+                .bind(filter.born_before)
+                .bind(filter.include_deleted)
+                .bind(tenant)
+                .bind(cursor.map(|c| &c.key))
+                .bind(cursor.map(|c| c.id))
+                .bind(i64::from(listing.limit) + 1)
+                .fetch_all(&mut tx)
+                .await?;
+            tx.commit().await?;
+            Ok(rows.into_iter().map(User::from).collect())
+        })
+    }
+
+    #[instrument(
+        skip(self),
+        fields(
+            otel.kind = "client",
+            db.system = "postgresql",
+            db.operation = "SELECT",
+            db.sql.table = "users"
+        )
+    )]
+    async fn count_users(&self, filter: &UserFilter, tenant: &str) -> Result<i64> {
+        let query = format!("SELECT COUNT(*) FROM users WHERE {}", USERS_FILTER);
+        measure_query!(self.metrics, "count", {
+            let mut tx = self.begin(tenant).await?;
+            let (count,) = sqlx::query_as::<_, (i64,)>(&query)
+                .bind(&filter.name_prefix)
+                .bind(filter.born_after)
+                .bind(filter.born_before)
+                .bind(filter.include_deleted)
+                .bind(tenant)
+                .fetch_one(&mut tx)
+                .await?;
+            tx.commit().await?;
+            Ok(count)
+        })
+    }
+
+    #[instrument(
+        skip(self),
+        fields(
+            otel.kind = "client",
+            db.system = "postgresql",
+            db.operation = "SELECT",
+            db.sql.table = "user_audit"
+        )
+    )]
+    async fn user_history(
+        &self,
+        id: &uuid::Uuid,
+        listing: &HistoryListing,
+        tenant: &str,
+    ) -> Result<Vec<AuditRecord>> {
+        measure_query!(self.metrics, "history", {
+            let mut tx = self.begin(tenant).await?;
+            let rows = sqlx::query_as!(
+                AuditRow,
+                r#"
+            SELECT id, user_id, actor, operation, changes, request_id, created_at
+            FROM user_audit
+            WHERE user_id = $1 AND tenant_id = $4 AND ($2::bigint IS NULL OR id < $2)
+            ORDER BY id DESC
+            LIMIT $3
+            "#,
+                id,
+                listing.before,
+                i64::from(listing.limit) + 1,
+                tenant,
+            )
+            .fetch_all(&mut tx)
+            .await?;
+            tx.commit().await?;
+            rows.into_iter().map(AuditRecord::try_from).collect()
         })
     }
 }
@@ -1,6 +1,18 @@
+pub mod policy;
+
+use super::audit::{Actor, HistoryListing};
+use super::etag::Version;
+use super::idempotency::{IdempotencyKey, Idempotent};
+use super::listing::UserListing;
 use super::repository::Repository;
-use crate::models::{CustomData, User};
+use crate::auth::Caller;
+use crate::metrics::UserMetrics;
+use crate::models::{
+    CustomData, HistoryPage, HistoryQuery, ListQuery, User, UserPage, UserPatch, Validate,
+};
 use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use policy::{authorize, Action};
 use tracing::instrument;
 use uuid::Uuid;
 
@@ -11,84 +23,527 @@ pub type Result<T> = std::result::Result<T, ServiceError>;
 pub enum ServiceError {
     #[error("User is not authorized to access this resource")]
     Unauthorized,
+    #[error("A user with this name already exists")]
+    DuplicateName,
+    #[error(transparent)]
+    DbError(sqlx::Error),
     #[error(transparent)]
-    DbError(#[from] sqlx::Error),
+    InvalidUser(#[from] rpts_domain::DomainError),
+    #[error("Invalid query: {0}")]
+    InvalidQuery(String),
+    #[error("The patch can't be applied: {0}")]
+    InvalidPatch(String),
+    #[error("The user has changed since it was read")]
+    PreconditionFailed,
+    #[error("The user refers to data that doesn't exist")]
+    ForeignKeyViolation,
+    #[error("The user breaks a constraint of the database")]
+    CheckViolation,
+    #[error("The user was changed by a concurrent request, try again")]
+    SerializationFailure,
+    #[error("The idempotency key was already used with a different request")]
+    IdempotencyKeyReused,
+    #[error("The caller already signed up")]
+    AlreadySignedUp,
+}
+
+/// What the callers are told when a user doesn't exist, instead of the error of the database.
+pub const USER_NOT_FOUND: &str = "User not found";
+
+/// Postgres error codes (SQLSTATE) with a meaning for the callers.
+mod sqlstate {
+    pub const NOT_NULL_VIOLATION: &str = "23502";
+    pub const FOREIGN_KEY_VIOLATION: &str = "23503";
+    pub const UNIQUE_VIOLATION: &str = "23505";
+    pub const CHECK_VIOLATION: &str = "23514";
+    pub const SERIALIZATION_FAILURE: &str = "40001";
+    pub const DEADLOCK_DETECTED: &str = "40P01";
+}
+
+impl From<sqlx::Error> for ServiceError {
+    fn from(err: sqlx::Error) -> Self {
+        let code = match &err {
+            sqlx::Error::Database(db_err) => db_err.code(),
+            _ => None,
+        };
+        match code.as_deref() {
+            // names are the only unique field users can set, sign-ups tell the ids apart
+            Some(sqlstate::UNIQUE_VIOLATION) => ServiceError::DuplicateName,
+            Some(sqlstate::FOREIGN_KEY_VIOLATION) => ServiceError::ForeignKeyViolation,
+            Some(sqlstate::CHECK_VIOLATION) | Some(sqlstate::NOT_NULL_VIOLATION) => {
+                ServiceError::CheckViolation
+            }
+            // both are solved by retrying the transaction
+            Some(sqlstate::SERIALIZATION_FAILURE) | Some(sqlstate::DEADLOCK_DETECTED) => {
+                ServiceError::SerializationFailure
+            }
+            _ => ServiceError::DbError(err),
+        }
+    }
 }
 
 #[async_trait]
 pub trait Service: Send + Sync + std::fmt::Debug {
     /// Gets a user by id.
-    /// The caller_id is optional and it's used for validation purposes.
-    async fn get_user(&self, user_id: &Uuid, caller_id: Option<String>) -> Result<User>;
+    /// The caller is checked against the [policy].
+    /// Only admins can read the deleted users, with `include_deleted`.
+    async fn get_user(&self, user_id: &Uuid, caller: Caller, include_deleted: bool)
+        -> Result<User>;
 
     /// Updates the custom_data of a user by id.
-    /// The caller_id is optional and it's used for validation purposes.
+    /// The caller is checked against the [policy].
+    /// Writes fail with [ServiceError::PreconditionFailed] unless the user is
+    /// at the `expected` version, if any.
+    /// Every write is recorded in the history of the user, along with the caller and request_id.
     async fn update_user(
         &self,
         user_id: &Uuid,
-        caller_id: Option<String>,
+        caller: Caller,
         custom_data: CustomData,
+        expected: Option<Version>,
+        request_id: Option<String>,
+    ) -> Result<User>;
+
+    /// Creates a new user, with an id picked by the database.
+    /// The caller is checked against the [policy].
+    /// The user is validated before being stored.
+    async fn create_user(
+        &self,
+        user: User,
+        caller: Caller,
+        request_id: Option<String>,
+    ) -> Result<User>;
+
+    /// Creates a new user once per idempotency key.
+    /// Retries with the same key and request get the user created the first time.
+    async fn create_user_idempotent(
+        &self,
+        user: User,
+        key: IdempotencyKey,
+        caller: Caller,
+        request_id: Option<String>,
+    ) -> Result<Idempotent<User>>;
+
+    /// Creates the caller's own user, with its subject as id, so the caller owns it.
+    /// Each caller signs up once: the second time fails with [ServiceError::AlreadySignedUp],
+    /// even if its user was deleted since.
+    async fn sign_up(&self, user: User, caller: Caller, request_id: Option<String>)
+        -> Result<User>;
+
+    /// Replaces every field of a user but the id and timestamps.
+    /// The caller is checked against the [policy].
+    async fn replace_user(
+        &self,
+        user_id: &Uuid,
+        caller: Caller,
+        user: User,
+        expected: Option<Version>,
+        request_id: Option<String>,
     ) -> Result<User>;
 
-    /// Creates a new user.
-    async fn create_user(&self, user: User) -> Result<User>;
+    /// Applies a patch to any field of a user but the id and timestamps.
+    /// The caller is checked against the [policy].
+    /// The user can't change between being read and being patched.
+    async fn patch_user(
+        &self,
+        user_id: &Uuid,
+        caller: Caller,
+        patch: UserPatch,
+        expected: Option<Version>,
+        request_id: Option<String>,
+    ) -> Result<User>;
 
     /// Deletes a user.
-    /// The caller_id is optional and it's used for validation purposes.
-    async fn delete_user(&self, user_id: &Uuid, caller_id: Option<String>) -> Result<User>;
+    /// The caller is checked against the [policy].
+    async fn delete_user(
+        &self,
+        user_id: &Uuid,
+        caller: Caller,
+        expected: Option<Version>,
+        request_id: Option<String>,
+    ) -> Result<User>;
+
+    /// Lists a page of users.
+    /// The caller is checked against the [policy].
+    async fn list_users(&self, caller: Caller, query: ListQuery) -> Result<UserPage>;
+
+    /// Restores a deleted user.
+    /// The caller is checked against the [policy].
+    async fn restore_user(
+        &self,
+        user_id: &Uuid,
+        caller: Caller,
+        request_id: Option<String>,
+    ) -> Result<User>;
+
+    /// Lists a page of the changes of a user, the latest first.
+    /// The caller is checked against the [policy].
+    async fn user_history(
+        &self,
+        user_id: &Uuid,
+        caller: Caller,
+        query: HistoryQuery,
+    ) -> Result<HistoryPage>;
+
+    /// Removes for good the users deleted longer than `retention` ago.
+    /// Returns how many users were purged.
+    async fn purge_deleted_users(&self, retention: Duration) -> Result<u64>;
 }
 
 /// Our custom Service implementing the Service trait.
 #[derive(Debug)]
 pub struct Rpts02Service<T: Repository> {
     pub repository: T,
+    metrics: Option<UserMetrics>,
 }
 
 impl<T: Repository> Rpts02Service<T> {
     /// Builds a new Rpts02Service
     pub fn new(repository: T) -> Self {
-        Self { repository }
+        Self {
+            repository,
+            metrics: None,
+        }
+    }
+
+    /// Counts the users created and deleted, and the authorization failures, in the metrics.
+    pub fn with_metrics(self, metrics: UserMetrics) -> Self {
+        Self {
+            metrics: Some(metrics),
+            ..self
+        }
+    }
+
+    /// Authorizes an action, counting the failures.
+    fn authorize(&self, caller: &Caller, action: Action, user_id: Option<&Uuid>) -> Result<()> {
+        let result = authorize(caller, action, user_id);
+        if let (Err(ServiceError::Unauthorized), Some(metrics)) = (&result, &self.metrics) {
+            metrics.denied(action.as_str());
+        }
+        result
+    }
+
+    /// Counts the user in the metrics once written by the operation.
+    fn count<R>(&self, result: Result<R>, counter: impl Fn(&UserMetrics)) -> Result<R> {
+        if let (Ok(_), Some(metrics)) = (&result, &self.metrics) {
+            counter(metrics);
+        }
+        result
+    }
+
+    /// Maps the result of a conditional write.
+    /// The repository can't tell a missing user from one at another version,
+    /// so it's read again to report the right error.
+    async fn checked<R>(
+        &self,
+        user_id: &Uuid,
+        tenant: &str,
+        expected: Option<Version>,
+        result: sqlx::Result<R>,
+    ) -> Result<R> {
+        match result {
+            Err(sqlx::Error::RowNotFound) if expected.is_some() => {
+                match self.repository.get_user(user_id, false, tenant).await {
+                    Ok(_) => Err(ServiceError::PreconditionFailed),
+                    Err(e) => Err(e.into()),
+                }
+            }
+            result => result.map_err(|e| e.into()),
+        }
+    }
+
+    /// Authorizes an action on a user.
+    /// The denied reads are reported as not found, whether the user exists or not,
+    /// so they can't be used to probe the ids of the tenant.
+    fn authorize_on(&self, caller: &Caller, action: Action, user_id: &Uuid) -> Result<()> {
+        match self.authorize(caller, action, Some(user_id)) {
+            Err(ServiceError::Unauthorized) if action.is_read() => {
+                Err(ServiceError::DbError(sqlx::Error::RowNotFound))
+            }
+            result => result,
+        }
     }
 }
 
 #[async_trait]
 impl<T: Repository + Send + Sync + 'static> Service for Rpts02Service<T> {
-    #[instrument]
-    async fn get_user(&self, user_id: &Uuid, caller_id: Option<String>) -> Result<User> {
-        authorized!(user_id, caller_id);
+    #[instrument(
+        skip(self),
+        fields(enduser.id = ?caller.user, enduser.role = ?caller.roles, tenant = %caller.tenant)
+    )]
+    async fn get_user(
+        &self,
+        user_id: &Uuid,
+        caller: Caller,
+        include_deleted: bool,
+    ) -> Result<User> {
+        let action = if include_deleted {
+            Action::ReadDeleted
+        } else {
+            Action::Read
+        };
+        self.authorize_on(&caller, action, user_id)?;
         self.repository
-            .get_user(&user_id)
+            .get_user(&user_id, include_deleted, &caller.tenant)
             .await
             .map_err(|e| e.into())
     }
 
-    #[instrument]
+    #[instrument(
+        skip(self),
+        fields(enduser.id = ?caller.user, enduser.role = ?caller.roles, tenant = %caller.tenant)
+    )]
     async fn update_user(
         &self,
         user_id: &Uuid,
-        caller_id: Option<String>,
+        caller: Caller,
         custom_data: CustomData,
+        expected: Option<Version>,
+        request_id: Option<String>,
     ) -> Result<User> {
-        authorized!(user_id, caller_id);
-        self.repository
-            .update_user(user_id, custom_data)
+        let actor = Actor::new(caller.user.clone(), request_id);
+        self.authorize_on(&caller, Action::Update, user_id)?;
+        custom_data.validate()?;
+        let result = self
+            .repository
+            .update_user(user_id, custom_data, expected, &actor, &caller.tenant)
+            .await;
+        self.checked(user_id, &caller.tenant, expected, result)
             .await
-            .map_err(|e| e.into())
     }
 
-    #[instrument]
-    async fn create_user(&self, user: User) -> Result<User> {
+    #[instrument(
+        skip(self),
+        fields(enduser.id = ?caller.user, enduser.role = ?caller.roles, tenant = %caller.tenant)
+    )]
+    async fn create_user(
+        &self,
+        user: User,
+        caller: Caller,
+        request_id: Option<String>,
+    ) -> Result<User> {
+        self.authorize(&caller, Action::Create, None)?;
+        user.validate()?;
+        // only the sign-ups choose their ids
+        let user = User { id: None, ..user };
+        let result = self
+            .repository
+            .create_user(user, &Actor::new(caller.user, request_id), &caller.tenant)
+            .await
+            .map_err(|e| e.into());
+        self.count(result, UserMetrics::created)
+    }
+
+    #[instrument(
+        skip(self),
+        fields(enduser.id = ?caller.user, enduser.role = ?caller.roles, tenant = %caller.tenant)
+    )]
+    async fn create_user_idempotent(
+        &self,
+        user: User,
+        key: IdempotencyKey,
+        caller: Caller,
+        request_id: Option<String>,
+    ) -> Result<Idempotent<User>> {
+        self.authorize(&caller, Action::Create, None)?;
+        user.validate()?;
+        let user = User { id: None, ..user };
+        let actor = Actor::new(caller.user, request_id);
+        match self
+            .repository
+            .create_user_idempotent(user, &key, &actor, &caller.tenant)
+            .await?
+        {
+            Idempotent::Replayed { request_hash, .. } if request_hash != key.request_hash => {
+                Err(ServiceError::IdempotencyKeyReused)
+            }
+            replayed @ Idempotent::Replayed { .. } => Ok(replayed),
+            created => self.count(Ok(created), UserMetrics::created),
+        }
+    }
+
+    #[instrument(
+        skip(self),
+        fields(enduser.id = ?caller.user, enduser.role = ?caller.roles, tenant = %caller.tenant)
+    )]
+    async fn sign_up(
+        &self,
+        user: User,
+        caller: Caller,
+        request_id: Option<String>,
+    ) -> Result<User> {
+        self.authorize(&caller, Action::Create, None)?;
+        // the callers without a UUID subject, like the API keys, can't own a user
+        let id = caller.user_id().ok_or(ServiceError::Unauthorized)?;
+        user.validate()?;
+        let user = User {
+            id: Some(id),
+            ..user
+        };
+        let actor = Actor::new(caller.user.clone(), request_id);
+        let result = self
+            .repository
+            .create_user(user, &actor, &caller.tenant)
+            .await
+            .map_err(ServiceError::from);
+        match self.count(result, UserMetrics::created) {
+            // the id is unique too, so the user is read again to tell which one is taken
+            Err(ServiceError::DuplicateName) => {
+                match self.repository.get_user(&id, true, &caller.tenant).await {
+                    Ok(_) => Err(ServiceError::AlreadySignedUp),
+                    Err(_) => Err(ServiceError::DuplicateName),
+                }
+            }
+            result => result,
+        }
+    }
+
+    #[instrument(
+        skip(self),
+        fields(enduser.id = ?caller.user, enduser.role = ?caller.roles, tenant = %caller.tenant)
+    )]
+    async fn replace_user(
+        &self,
+        user_id: &Uuid,
+        caller: Caller,
+        user: User,
+        expected: Option<Version>,
+        request_id: Option<String>,
+    ) -> Result<User> {
+        let actor = Actor::new(caller.user.clone(), request_id);
+        self.authorize_on(&caller, Action::Update, user_id)?;
+        user.validate()?;
+        let result = self
+            .repository
+            .replace_user(user_id, user, expected, &actor, &caller.tenant)
+            .await;
+        self.checked(user_id, &caller.tenant, expected, result)
+            .await
+    }
+
+    #[instrument(
+        skip(self),
+        fields(enduser.id = ?caller.user, enduser.role = ?caller.roles, tenant = %caller.tenant)
+    )]
+    async fn patch_user(
+        &self,
+        user_id: &Uuid,
+        caller: Caller,
+        patch: UserPatch,
+        expected: Option<Version>,
+        request_id: Option<String>,
+    ) -> Result<User> {
+        let actor = Actor::new(caller.user.clone(), request_id);
+        self.authorize_on(&caller, Action::Update, user_id)?;
+        let user = self
+            .repository
+            .get_user(user_id, false, &caller.tenant)
+            .await?;
+        let version = Version::of(&user);
+        if expected.map_or(false, |expected| expected != version) {
+            return Err(ServiceError::PreconditionFailed);
+        }
+        let patched = patch.apply(&user).map_err(ServiceError::InvalidPatch)?;
+        patched.validate()?;
+        // the patch is only valid for the version it was applied to
+        let result = self
+            .repository
+            .replace_user(user_id, patched, Some(version), &actor, &caller.tenant)
+            .await;
+        self.checked(user_id, &caller.tenant, Some(version), result)
+            .await
+    }
+
+    #[instrument(
+        skip(self),
+        fields(enduser.id = ?caller.user, enduser.role = ?caller.roles, tenant = %caller.tenant)
+    )]
+    async fn delete_user(
+        &self,
+        user_id: &Uuid,
+        caller: Caller,
+        expected: Option<Version>,
+        request_id: Option<String>,
+    ) -> Result<User> {
+        let actor = Actor::new(caller.user.clone(), request_id);
+        self.authorize_on(&caller, Action::Delete, user_id)?;
+        let result = self
+            .repository
+            .delete_user(user_id, expected, &actor, &caller.tenant)
+            .await;
+        let result = self
+            .checked(user_id, &caller.tenant, expected, result)
+            .await;
+        self.count(result, UserMetrics::deleted)
+    }
+
+    #[instrument(
+        skip(self),
+        fields(enduser.id = ?caller.user, enduser.role = ?caller.roles, tenant = %caller.tenant)
+    )]
+    async fn list_users(&self, caller: Caller, query: ListQuery) -> Result<UserPage> {
+        let action = if query.include_deleted {
+            Action::ListDeleted
+        } else {
+            Action::List
+        };
+        self.authorize(&caller, action, None)?;
+        let listing = UserListing::from_query(&query)?;
+        let users = self.repository.list_users(&listing, &caller.tenant).await?;
+        let total = if query.include_total {
+            Some(
+                self.repository
+                    .count_users(&listing.filter, &caller.tenant)
+                    .await?,
+            )
+        } else {
+            None
+        };
+        Ok(listing.page(users, total))
+    }
+
+    #[instrument(
+        skip(self),
+        fields(enduser.id = ?caller.user, enduser.role = ?caller.roles, tenant = %caller.tenant)
+    )]
+    async fn restore_user(
+        &self,
+        user_id: &Uuid,
+        caller: Caller,
+        request_id: Option<String>,
+    ) -> Result<User> {
+        // the users can undo their own deletions too
+        self.authorize_on(&caller, Action::Restore, user_id)?;
+        let actor = Actor::new(caller.user, request_id);
         self.repository
-            .create_user(user)
+            .restore_user(user_id, &actor, &caller.tenant)
             .await
             .map_err(|e| e.into())
     }
 
-    #[instrument]
-    async fn delete_user(&self, user_id: &Uuid, caller_id: Option<String>) -> Result<User> {
-        authorized!(user_id, caller_id);
+    #[instrument(
+        skip(self),
+        fields(enduser.id = ?caller.user, enduser.role = ?caller.roles, tenant = %caller.tenant)
+    )]
+    async fn user_history(
+        &self,
+        user_id: &Uuid,
+        caller: Caller,
+        query: HistoryQuery,
+    ) -> Result<HistoryPage> {
+        self.authorize_on(&caller, Action::ReadHistory, user_id)?;
+        let listing = HistoryListing::from_query(&query)?;
+        let records = self
+            .repository
+            .user_history(user_id, &listing, &caller.tenant)
+            .await?;
+        Ok(listing.page(records))
+    }
+
+    #[instrument(skip(self))]
+    async fn purge_deleted_users(&self, retention: Duration) -> Result<u64> {
         self.repository
-            .delete_user(user_id)
+            .purge_users(Utc::now() - retention)
             .await
             .map_err(|e| e.into())
     }
@@ -97,45 +552,54 @@ impl<T: Repository + Send + Sync + 'static> Service for Rpts02Service<T> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::auth::Role;
+    use crate::models::AuditRecord;
+    use crate::v1::listing::UserFilter;
+    use crate::v1::mocks::MockRepo;
     use mockall::predicate::*;
-    use mockall::*;
-    use sqlx::{types::Json, Result};
-
-    mock! {
-        pub Repo {
-            fn sync_get_user(&self, id: &Uuid) -> Result<User> {}
-            fn sync_update_user(
-                &self,
-                id: &Uuid,
-                custom_data: CustomData,
-            ) -> Result<User> {}
-            fn sync_create_user(&self, user: User) -> Result<User> {}
-            fn sync_delete_user(&self, id: &Uuid) -> Result<User> {}
-        }
-    }
 
-    impl std::fmt::Debug for MockRepo {
+    /// A database error carrying a Postgres error code.
+    #[derive(Debug)]
+    struct PgError(&'static str);
+
+    impl std::fmt::Display for PgError {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            f.debug_struct("MockRepo").finish()
+            write!(f, "Postgres error {}", self.0)
         }
     }
 
-    #[async_trait]
-    impl Repository for MockRepo {
-        async fn get_user(&self, user: &Uuid) -> Result<User> {
-            self.sync_get_user(user)
+    impl std::error::Error for PgError {}
+
+    impl sqlx::error::DatabaseError for PgError {
+        fn message(&self) -> &str {
+            "Postgres error"
+        }
+        fn code(&self) -> Option<std::borrow::Cow<'_, str>> {
+            Some(self.0.into())
         }
-        async fn create_user(&self, user: User) -> Result<User> {
-            self.sync_create_user(user)
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
         }
-        async fn update_user(&self, id: &Uuid, custom_data: CustomData) -> Result<User> {
-            self.sync_update_user(id, custom_data)
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
         }
-        async fn delete_user(&self, id: &Uuid) -> Result<User> {
-            self.sync_delete_user(id)
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
         }
     }
 
+    fn pg_error(code: &'static str) -> sqlx::Error {
+        sqlx::Error::Database(Box::new(PgError(code)))
+    }
+
+    fn admin() -> Caller {
+        Caller::new("admin").with_roles(vec![Role::Admin])
+    }
+
+    fn support() -> Caller {
+        Caller::new("support").with_roles(vec![Role::Support])
+    }
+
     // get user tests
 
     #[actix_rt::test]
@@ -144,7 +608,7 @@ mod tests {
         let user_id = Uuid::new_v4();
         let user_name = "my_name";
 
-        mock.expect_sync_get_user().returning(move |id| {
+        mock.expect_sync_get_user().returning(move |id, _, _| {
             let mut user = User::default();
             user.id = Some(*id);
             user.name = user_name.to_string();
@@ -154,7 +618,7 @@ mod tests {
         let svc = Rpts02Service::new(mock);
 
         let result = svc
-            .get_user(&user_id, Some(user_id.to_string()))
+            .get_user(&user_id, Caller::new(user_id.to_string()), false)
             .await
             .unwrap();
 
@@ -163,12 +627,12 @@ mod tests {
     }
 
     #[actix_rt::test]
-    async fn get_user_returns_if_caller_is_none() {
+    async fn get_user_returns_if_caller_is_an_admin() {
         let mut mock = MockRepo::default();
         let user_id = Uuid::new_v4();
         let user_name = "my_name";
 
-        mock.expect_sync_get_user().returning(move |id| {
+        mock.expect_sync_get_user().returning(move |id, _, _| {
             let mut user = User::default();
             user.id = Some(*id);
             user.name = user_name.to_string();
@@ -177,34 +641,97 @@ mod tests {
 
         let svc = Rpts02Service::new(mock);
 
-        let result = svc.get_user(&user_id, None).await.unwrap();
+        let result = svc.get_user(&user_id, admin(), false).await.unwrap();
 
         assert_eq!(result.name, user_name);
         assert_eq!(result.id.unwrap(), user_id);
     }
 
     #[actix_rt::test]
-    async fn get_user_returns_unauthorized_if_userid_not_equal_caller() {
+    async fn get_user_returns_not_found_if_userid_not_equal_caller() {
         let mut mock = MockRepo::default();
         let user_id = Uuid::new_v4();
 
-        mock.expect_sync_get_user()
-            .returning(|_| Ok(User::default()));
+        mock.expect_sync_get_user().never();
 
         let svc = Rpts02Service::new(mock);
 
         let error = svc
-            .get_user(&user_id, Some("2".to_string()))
+            .get_user(&user_id, Caller::new("2"), false)
             .await
             .err()
             .unwrap();
 
-        let is_unauthorized = match error {
-            ServiceError::Unauthorized => true,
-            _ => false,
-        };
+        assert!(matches!(
+            error,
+            ServiceError::DbError(sqlx::Error::RowNotFound)
+        ));
+    }
 
-        assert!(is_unauthorized);
+    #[actix_rt::test]
+    async fn update_user_returns_unauthorized_without_reading_the_user() {
+        let mut mock = MockRepo::default();
+        let user_id = Uuid::new_v4();
+
+        mock.expect_sync_get_user().never();
+        mock.expect_sync_update_user().never();
+
+        let svc = Rpts02Service::new(mock);
+
+        let error = svc
+            .update_user(
+                &user_id,
+                Caller::new("2"),
+                CustomData::default(),
+                None,
+                None,
+            )
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(error, ServiceError::Unauthorized));
+    }
+
+    #[actix_rt::test]
+    async fn get_user_is_scoped_by_the_tenant_of_the_caller() {
+        let mut mock = MockRepo::default();
+        let user_id = Uuid::new_v4();
+
+        mock.expect_sync_get_user()
+            .withf(|_, _, tenant| tenant == "acme")
+            .times(1)
+            .returning(|_, _, _| Ok(User::default()));
+
+        let svc = Rpts02Service::new(mock);
+
+        let result = svc
+            .get_user(&user_id, admin().with_tenant("acme"), false)
+            .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn get_user_returns_not_found_for_users_of_other_tenants() {
+        let mut mock = MockRepo::default();
+        let user_id = Uuid::new_v4();
+
+        mock.expect_sync_get_user()
+            .returning(|_, _, _| Err(sqlx::Error::RowNotFound));
+
+        let svc = Rpts02Service::new(mock);
+
+        let error = svc
+            .get_user(&user_id, Caller::new("2").with_tenant("acme"), false)
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(
+            error,
+            ServiceError::DbError(sqlx::Error::RowNotFound)
+        ));
     }
 
     #[actix_rt::test]
@@ -213,12 +740,12 @@ mod tests {
         let user_id = Uuid::new_v4();
 
         mock.expect_sync_get_user()
-            .returning(|_| Err(sqlx::Error::RowNotFound));
+            .returning(|_, _, _| Err(sqlx::Error::RowNotFound));
 
         let svc = Rpts02Service::new(mock);
 
         let error = svc
-            .get_user(&user_id, Some(user_id.to_string()))
+            .get_user(&user_id, Caller::new(user_id.to_string()), false)
             .await
             .err()
             .unwrap();
@@ -241,18 +768,24 @@ mod tests {
         let random = 78900;
 
         mock.expect_sync_update_user()
-            .returning(move |id, custom_data| {
+            .returning(move |id, custom_data, _expected, _, _| {
                 let mut user = User::default();
                 user.id = Some(*id);
                 user.name = user_name.to_string();
-                user.custom_data = Some(Json(custom_data));
+                user.custom_data = Some(custom_data);
                 Ok(user)
             });
 
         let svc = Rpts02Service::new(mock);
 
         let result = svc
-            .update_user(&user_id, Some(user_id.to_string()), CustomData { random })
+            .update_user(
+                &user_id,
+                Caller::new(user_id.to_string()),
+                CustomData { random },
+                None,
+                None,
+            )
             .await
             .unwrap();
 
@@ -262,25 +795,25 @@ mod tests {
     }
 
     #[actix_rt::test]
-    async fn update_user_returns_if_caller_is_none() {
+    async fn update_user_returns_if_caller_is_an_admin() {
         let mut mock = MockRepo::default();
         let user_id = Uuid::new_v4();
         let user_name = "my_name";
         let random = 78900;
 
         mock.expect_sync_update_user()
-            .returning(move |id, custom_data| {
+            .returning(move |id, custom_data, _expected, _, _| {
                 let mut user = User::default();
                 user.id = Some(*id);
                 user.name = user_name.to_string();
-                user.custom_data = Some(Json(custom_data));
+                user.custom_data = Some(custom_data);
                 Ok(user)
             });
 
         let svc = Rpts02Service::new(mock);
 
         let result = svc
-            .update_user(&user_id, None, CustomData { random })
+            .update_user(&user_id, admin(), CustomData { random }, None, None)
             .await
             .unwrap();
 
@@ -289,18 +822,47 @@ mod tests {
         assert_eq!(result.custom_data.unwrap().random, random);
     }
 
+    #[actix_rt::test]
+    async fn update_user_returns_invalid_user_if_validation_fails() {
+        let mut mock = MockRepo::default();
+
+        mock.expect_sync_update_user().never();
+
+        let svc = Rpts02Service::new(mock);
+
+        let custom_data = CustomData {
+            random: rpts_domain::RANDOM_MAX + 1,
+        };
+        let error = svc
+            .update_user(&Uuid::new_v4(), admin(), custom_data, None, None)
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(error, ServiceError::InvalidUser(_)));
+    }
+
     #[actix_rt::test]
     async fn update_user_returns_unauthorized_if_userid_not_equal_caller() {
         let mut mock = MockRepo::default();
+        // the user exists in the tenant of the caller
+        mock.expect_sync_get_user()
+            .returning(|_, _, _| Ok(User::default()));
         let user_id = Uuid::new_v4();
 
         mock.expect_sync_update_user()
-            .returning(|_, _| Ok(User::default()));
+            .returning(|_, _, _, _, _| Ok(User::default()));
 
         let svc = Rpts02Service::new(mock);
 
         let error = svc
-            .update_user(&user_id, Some("2".to_string()), CustomData::default())
+            .update_user(
+                &user_id,
+                Caller::new("2"),
+                CustomData::default(),
+                None,
+                None,
+            )
             .await
             .err()
             .unwrap();
@@ -319,12 +881,18 @@ mod tests {
         let user_id = Uuid::new_v4();
 
         mock.expect_sync_update_user()
-            .returning(|_, _| Err(sqlx::Error::RowNotFound));
+            .returning(|_, _, _, _, _| Err(sqlx::Error::RowNotFound));
 
         let svc = Rpts02Service::new(mock);
 
         let error = svc
-            .update_user(&user_id, Some(user_id.to_string()), CustomData::default())
+            .update_user(
+                &user_id,
+                Caller::new(user_id.to_string()),
+                CustomData::default(),
+                None,
+                None,
+            )
             .await
             .err()
             .unwrap();
@@ -337,139 +905,1132 @@ mod tests {
         assert!(is_mapped_error);
     }
 
-    // create user tests
-
     #[actix_rt::test]
-    async fn create_user_returns_if_userid_equals_caller() {
+    async fn update_user_returns_precondition_failed_if_version_differs() {
         let mut mock = MockRepo::default();
         let user_id = Uuid::new_v4();
-        let user_name = "my_name";
+        let expected = Version::of(&User::default());
 
-        let mut user = User::default();
-        user.name = user_name.to_string();
-        user.id = Some(user_id);
-
-        mock.expect_sync_create_user().returning(|usr| Ok(usr));
+        mock.expect_sync_update_user()
+            .with(always(), always(), eq(Some(expected)), always(), always())
+            .returning(|_, _, _, _, _| Err(sqlx::Error::RowNotFound));
+        mock.expect_sync_get_user()
+            .returning(|_, _, _| Ok(User::default()));
 
         let svc = Rpts02Service::new(mock);
 
-        let result = svc.create_user(user).await.unwrap();
+        let error = svc
+            .update_user(
+                &user_id,
+                admin(),
+                CustomData::default(),
+                Some(expected),
+                None,
+            )
+            .await
+            .err()
+            .unwrap();
 
-        assert_eq!(result.id.unwrap(), user_id);
-        assert_eq!(result.name, user_name);
+        assert!(matches!(error, ServiceError::PreconditionFailed));
     }
 
     #[actix_rt::test]
-    async fn create_user_returns_mapped_error() {
+    async fn update_user_returns_not_found_if_user_is_missing_with_precondition() {
         let mut mock = MockRepo::default();
+        let user_id = Uuid::new_v4();
+        let expected = Version::of(&User::default());
 
-        mock.expect_sync_create_user()
-            .returning(|_| Err(sqlx::Error::RowNotFound));
+        mock.expect_sync_update_user()
+            .returning(|_, _, _, _, _| Err(sqlx::Error::RowNotFound));
+        mock.expect_sync_get_user()
+            .returning(|_, _, _| Err(sqlx::Error::RowNotFound));
 
         let svc = Rpts02Service::new(mock);
 
-        let error = svc.create_user(User::default()).await.err().unwrap();
-
-        let is_mapped_error = match error {
-            ServiceError::DbError(sqlx::Error::RowNotFound) => true,
-            _ => false,
-        };
+        let error = svc
+            .update_user(
+                &user_id,
+                admin(),
+                CustomData::default(),
+                Some(expected),
+                None,
+            )
+            .await
+            .err()
+            .unwrap();
 
-        assert!(is_mapped_error);
+        assert!(matches!(
+            error,
+            ServiceError::DbError(sqlx::Error::RowNotFound)
+        ));
     }
 
-    // delete user tests
+    // create user tests
 
     #[actix_rt::test]
-    async fn delete_user_returns_if_userid_equals_caller() {
+    async fn create_user_ignores_the_id_of_the_user() {
         let mut mock = MockRepo::default();
         let user_id = Uuid::new_v4();
         let user_name = "my_name";
 
-        mock.expect_sync_delete_user().returning(move |id| {
-            let mut user = User::default();
-            user.id = Some(*id);
-            user.name = user_name.to_string();
-            Ok(user)
-        });
+        let mut user = User::default();
+        user.name = user_name.to_string();
+        user.id = Some(user_id);
+
+        mock.expect_sync_create_user()
+            .withf(|usr, _, _| usr.id.is_none())
+            .returning(|usr, _, _| Ok(usr));
 
         let svc = Rpts02Service::new(mock);
 
-        let result = svc
-            .delete_user(&user_id, Some(user_id.to_string()))
-            .await
-            .unwrap();
+        let result = svc.create_user(user, admin(), None).await.unwrap();
 
-        assert_eq!(result.id.unwrap(), user_id);
+        assert_eq!(result.id, None);
         assert_eq!(result.name, user_name);
     }
 
     #[actix_rt::test]
-    async fn delete_user_returns_if_caller_is_none() {
+    async fn create_user_returns_unauthorized_if_caller_is_anonymous() {
         let mut mock = MockRepo::default();
-        let user_id = Uuid::new_v4();
-        let user_name = "my_name";
 
-        mock.expect_sync_delete_user().returning(move |id| {
-            let mut user = User::default();
-            user.id = Some(*id);
-            user.name = user_name.to_string();
-            Ok(user)
-        });
+        mock.expect_sync_create_user().never();
 
         let svc = Rpts02Service::new(mock);
 
-        let result = svc.delete_user(&user_id, None).await.unwrap();
+        let error = svc
+            .create_user(User::default(), Caller::default(), None)
+            .await
+            .err()
+            .unwrap();
 
-        assert_eq!(result.id.unwrap(), user_id);
-        assert_eq!(result.name, user_name);
+        assert!(matches!(error, ServiceError::Unauthorized));
     }
 
     #[actix_rt::test]
-    async fn delete_user_returns_unauthorized_if_userid_not_equal_caller() {
+    async fn create_user_returns_invalid_user_if_validation_fails() {
         let mut mock = MockRepo::default();
-        let user_id = Uuid::new_v4();
 
-        mock.expect_sync_delete_user()
-            .returning(|_| Ok(User::default()));
+        mock.expect_sync_create_user().never();
 
         let svc = Rpts02Service::new(mock);
 
         let error = svc
-            .delete_user(&user_id, Some("2".to_string()))
+            .create_user(User::default(), admin(), None)
             .await
             .err()
             .unwrap();
 
-        let is_unauthorized = match error {
-            ServiceError::Unauthorized => true,
+        let is_invalid_user = match error {
+            ServiceError::InvalidUser(_) => true,
             _ => false,
         };
 
-        assert!(is_unauthorized);
+        assert!(is_invalid_user);
     }
 
     #[actix_rt::test]
-    async fn delete_user_returns_mapped_error() {
+    async fn create_user_returns_duplicate_name_on_unique_violations() {
         let mut mock = MockRepo::default();
-        let user_id = Uuid::new_v4();
 
-        mock.expect_sync_delete_user()
-            .returning(|_| Err(sqlx::Error::RowNotFound));
+        let mut user = User::default();
+        user.name = "my_name".to_string();
+
+        mock.expect_sync_create_user()
+            .returning(|_, _, _| Err(pg_error(sqlstate::UNIQUE_VIOLATION)));
 
         let svc = Rpts02Service::new(mock);
 
-        let error = svc
-            .delete_user(&user_id, Some(user_id.to_string()))
-            .await
-            .err()
-            .unwrap();
+        let error = svc.create_user(user, admin(), None).await.err().unwrap();
 
-        let is_mapped_error = match error {
-            ServiceError::DbError(sqlx::Error::RowNotFound) => true,
-            _ => false,
-        };
+        assert!(matches!(error, ServiceError::DuplicateName));
+    }
 
-        assert!(is_mapped_error);
+    // sign up tests
+
+    #[actix_rt::test]
+    async fn sign_up_creates_the_user_with_the_id_of_the_caller() {
+        let mut mock = MockRepo::default();
+        let caller_id = Uuid::new_v4();
+
+        mock.expect_sync_create_user()
+            .withf(move |user, actor, _| {
+                user.id == Some(caller_id) && actor.user_id == Some(caller_id.to_string())
+            })
+            .returning(|usr, _, _| Ok(usr));
+
+        let svc = Rpts02Service::new(mock);
+
+        let mut user = User::default();
+        user.name = "my_name".to_string();
+        user.id = Some(Uuid::new_v4());
+
+        let result = svc
+            .sign_up(user, Caller::new(caller_id.to_string()), None)
+            .await
+            .unwrap();
+
+        assert_eq!(result.id, Some(caller_id));
+    }
+
+    #[actix_rt::test]
+    async fn sign_up_returns_already_signed_up_the_second_time() {
+        let mut mock = MockRepo::default();
+        let caller_id = Uuid::new_v4();
+
+        mock.expect_sync_create_user()
+            .returning(|_, _, _| Err(pg_error(sqlstate::UNIQUE_VIOLATION)));
+        // even deleted, the user of the caller exists
+        mock.expect_sync_get_user()
+            .with(eq(caller_id), eq(true), always())
+            .returning(|_, _, _| Ok(User::default()));
+
+        let svc = Rpts02Service::new(mock);
+
+        let mut user = User::default();
+        user.name = "my_name".to_string();
+
+        let error = svc
+            .sign_up(user, Caller::new(caller_id.to_string()), None)
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(error, ServiceError::AlreadySignedUp));
+    }
+
+    #[actix_rt::test]
+    async fn sign_up_returns_duplicate_name_if_the_name_is_taken() {
+        let mut mock = MockRepo::default();
+
+        mock.expect_sync_create_user()
+            .returning(|_, _, _| Err(pg_error(sqlstate::UNIQUE_VIOLATION)));
+        mock.expect_sync_get_user()
+            .returning(|_, _, _| Err(sqlx::Error::RowNotFound));
+
+        let svc = Rpts02Service::new(mock);
+
+        let mut user = User::default();
+        user.name = "my_name".to_string();
+
+        let error = svc
+            .sign_up(user, Caller::new(Uuid::new_v4().to_string()), None)
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(error, ServiceError::DuplicateName));
+    }
+
+    #[actix_rt::test]
+    async fn sign_up_returns_unauthorized_if_the_caller_cant_own_a_user() {
+        let mut mock = MockRepo::default();
+
+        mock.expect_sync_create_user().never();
+
+        let svc = Rpts02Service::new(mock);
+
+        let mut user = User::default();
+        user.name = "my_name".to_string();
+
+        for caller in vec![Caller::new("reports"), Caller::default()] {
+            let error = svc.sign_up(user.clone(), caller, None).await.err().unwrap();
+            assert!(matches!(error, ServiceError::Unauthorized));
+        }
+    }
+
+    #[actix_rt::test]
+    async fn create_user_idempotent_works() {
+        let mut mock = MockRepo::default();
+
+        let mut user = User::default();
+        user.name = "my_name".to_string();
+        let key = IdempotencyKey::new("my_key", &user);
+
+        mock.expect_sync_create_user_idempotent()
+            .with(always(), eq(key.clone()), always(), always())
+            .returning(|mut user, _, _, _| {
+                user.id = Some(Uuid::new_v4());
+                Ok(Idempotent::Created(user))
+            });
+
+        let svc = Rpts02Service::new(mock);
+
+        let result = svc
+            .create_user_idempotent(user, key, admin(), None)
+            .await
+            .unwrap();
+
+        assert!(!result.is_replayed());
+        assert!(result.into_inner().id.is_some());
+    }
+
+    #[actix_rt::test]
+    async fn create_user_idempotent_replays_the_same_request() {
+        let mut mock = MockRepo::default();
+
+        let mut user = User::default();
+        user.name = "my_name".to_string();
+        let key = IdempotencyKey::new("my_key", &user);
+        let request_hash = key.request_hash.clone();
+
+        mock.expect_sync_create_user_idempotent()
+            .returning(move |user, _, _, _| {
+                Ok(Idempotent::Replayed {
+                    request_hash: request_hash.clone(),
+                    response: user,
+                })
+            });
+
+        let svc = Rpts02Service::new(mock);
+
+        let result = svc
+            .create_user_idempotent(user, key, admin(), None)
+            .await
+            .unwrap();
+
+        assert!(result.is_replayed());
+    }
+
+    #[actix_rt::test]
+    async fn create_user_idempotent_rejects_reused_keys() {
+        let mut mock = MockRepo::default();
+
+        let mut user = User::default();
+        user.name = "my_name".to_string();
+        let key = IdempotencyKey::new("my_key", &user);
+
+        mock.expect_sync_create_user_idempotent()
+            .returning(|user, _, _, _| {
+                Ok(Idempotent::Replayed {
+                    request_hash: "another request".to_string(),
+                    response: user,
+                })
+            });
+
+        let svc = Rpts02Service::new(mock);
+
+        let error = svc
+            .create_user_idempotent(user, key, admin(), None)
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(error, ServiceError::IdempotencyKeyReused));
+    }
+
+    #[actix_rt::test]
+    async fn create_user_idempotent_validates_first() {
+        let mut mock = MockRepo::default();
+
+        mock.expect_sync_create_user_idempotent().never();
+
+        let svc = Rpts02Service::new(mock);
+
+        let key = IdempotencyKey::new("my_key", &User::default());
+        let error = svc
+            .create_user_idempotent(User::default(), key, admin(), None)
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(error, ServiceError::InvalidUser(_)));
+    }
+
+    #[actix_rt::test]
+    async fn create_user_maps_constraint_violations() {
+        let cases: Vec<(&'static str, fn(&ServiceError) -> bool)> = vec![
+            (sqlstate::FOREIGN_KEY_VIOLATION, |e| {
+                matches!(e, ServiceError::ForeignKeyViolation)
+            }),
+            (sqlstate::CHECK_VIOLATION, |e| {
+                matches!(e, ServiceError::CheckViolation)
+            }),
+            (sqlstate::NOT_NULL_VIOLATION, |e| {
+                matches!(e, ServiceError::CheckViolation)
+            }),
+            (sqlstate::SERIALIZATION_FAILURE, |e| {
+                matches!(e, ServiceError::SerializationFailure)
+            }),
+            (sqlstate::DEADLOCK_DETECTED, |e| {
+                matches!(e, ServiceError::SerializationFailure)
+            }),
+            // undefined table
+            ("42P01", |e| {
+                matches!(e, ServiceError::DbError(sqlx::Error::Database(_)))
+            }),
+        ];
+
+        for (code, is_expected) in cases {
+            let mut mock = MockRepo::default();
+
+            let mut user = User::default();
+            user.name = "my_name".to_string();
+
+            mock.expect_sync_create_user()
+                .returning(move |_, _, _| Err(pg_error(code)));
+
+            let svc = Rpts02Service::new(mock);
+
+            let error = svc.create_user(user, admin(), None).await.err().unwrap();
+
+            assert!(is_expected(&error), "{} mapped to {:?}", code, error);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn replace_user_returns_serialization_failure_on_concurrent_writes() {
+        let mut mock = MockRepo::default();
+
+        let mut user = User::default();
+        user.name = "my_name".to_string();
+
+        mock.expect_sync_replace_user()
+            .returning(|_, _, _, _, _| Err(pg_error(sqlstate::SERIALIZATION_FAILURE)));
+        mock.expect_sync_get_user().never();
+
+        let svc = Rpts02Service::new(mock);
+
+        let version = Version::of(&user);
+        let error = svc
+            .replace_user(&Uuid::new_v4(), admin(), user, Some(version), None)
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(error, ServiceError::SerializationFailure));
+    }
+
+    #[actix_rt::test]
+    async fn create_user_returns_mapped_error() {
+        let mut mock = MockRepo::default();
+
+        let mut user = User::default();
+        user.name = "my_name".to_string();
+
+        mock.expect_sync_create_user()
+            .returning(|_, _, _| Err(sqlx::Error::RowNotFound));
+
+        let svc = Rpts02Service::new(mock);
+
+        let error = svc.create_user(user, admin(), None).await.err().unwrap();
+
+        let is_mapped_error = match error {
+            ServiceError::DbError(sqlx::Error::RowNotFound) => true,
+            _ => false,
+        };
+
+        assert!(is_mapped_error);
+    }
+
+    // replace user tests
+
+    #[actix_rt::test]
+    async fn replace_user_returns_if_userid_equals_caller() {
+        let mut mock = MockRepo::default();
+        let user_id = Uuid::new_v4();
+
+        let mut user = User::default();
+        user.name = "my_name".to_string();
+
+        mock.expect_sync_replace_user()
+            .returning(|id, mut user, _, _, _| {
+                user.id = Some(*id);
+                Ok(user)
+            });
+
+        let svc = Rpts02Service::new(mock);
+
+        let result = svc
+            .replace_user(&user_id, Caller::new(user_id.to_string()), user, None, None)
+            .await
+            .unwrap();
+
+        assert_eq!(result.id.unwrap(), user_id);
+        assert_eq!(result.name, "my_name");
+    }
+
+    #[actix_rt::test]
+    async fn replace_user_returns_unauthorized_if_userid_not_equal_caller() {
+        let mut mock = MockRepo::default();
+        // the user exists in the tenant of the caller
+        mock.expect_sync_get_user()
+            .returning(|_, _, _| Ok(User::default()));
+
+        mock.expect_sync_replace_user().never();
+
+        let svc = Rpts02Service::new(mock);
+
+        let error = svc
+            .replace_user(
+                &Uuid::new_v4(),
+                Caller::new("2"),
+                User::default(),
+                None,
+                None,
+            )
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(error, ServiceError::Unauthorized));
+    }
+
+    #[actix_rt::test]
+    async fn replace_user_returns_invalid_user_if_validation_fails() {
+        let mut mock = MockRepo::default();
+
+        mock.expect_sync_replace_user().never();
+
+        let svc = Rpts02Service::new(mock);
+
+        let error = svc
+            .replace_user(&Uuid::new_v4(), admin(), User::default(), None, None)
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(error, ServiceError::InvalidUser(_)));
+    }
+
+    // patch user tests
+
+    #[actix_rt::test]
+    async fn patch_user_applies_the_patch_to_the_stored_user() {
+        let mut mock = MockRepo::default();
+        let user_id = Uuid::new_v4();
+
+        mock.expect_sync_get_user().returning(|id, _, _| {
+            let mut user = User::default();
+            user.id = Some(*id);
+            user.name = "my_name".to_string();
+            user.custom_data = Some(CustomData { random: 1 });
+            Ok(user)
+        });
+        mock.expect_sync_replace_user()
+            .withf(|_, user, expected, _, _| {
+                user.name == "new_name" && user.custom_data.is_some() && expected.is_some()
+            })
+            .returning(|_, user, _, _, _| Ok(user));
+
+        let svc = Rpts02Service::new(mock);
+
+        let patch = UserPatch::Merge(serde_json::json!({ "name": "new_name" }));
+        let result = svc
+            .patch_user(
+                &user_id,
+                Caller::new(user_id.to_string()),
+                patch,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(result.id.unwrap(), user_id);
+        assert_eq!(result.name, "new_name");
+    }
+
+    #[actix_rt::test]
+    async fn patch_user_returns_precondition_failed_if_version_differs() {
+        let mut mock = MockRepo::default();
+
+        mock.expect_sync_get_user().returning(|_, _, _| {
+            let mut user = User::default();
+            user.name = "my_name".to_string();
+            user.updated_at = Some(chrono::Utc::now());
+            Ok(user)
+        });
+        mock.expect_sync_replace_user().never();
+
+        let svc = Rpts02Service::new(mock);
+
+        let patch = UserPatch::Merge(serde_json::json!({ "name": "new_name" }));
+        let expected = Version::of(&User::default());
+        let error = svc
+            .patch_user(&Uuid::new_v4(), admin(), patch, Some(expected), None)
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(error, ServiceError::PreconditionFailed));
+    }
+
+    #[actix_rt::test]
+    async fn patch_user_returns_invalid_patch_if_it_cant_be_applied() {
+        let mut mock = MockRepo::default();
+
+        mock.expect_sync_get_user().returning(|_, _, _| {
+            let mut user = User::default();
+            user.name = "my_name".to_string();
+            Ok(user)
+        });
+        mock.expect_sync_replace_user().never();
+
+        let svc = Rpts02Service::new(mock);
+
+        let patch = UserPatch::Merge(serde_json::json!({ "birth_date": 42 }));
+        let error = svc
+            .patch_user(&Uuid::new_v4(), admin(), patch, None, None)
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(error, ServiceError::InvalidPatch(_)));
+    }
+
+    #[actix_rt::test]
+    async fn patch_user_returns_invalid_user_if_validation_fails() {
+        let mut mock = MockRepo::default();
+
+        mock.expect_sync_get_user().returning(|_, _, _| {
+            let mut user = User::default();
+            user.name = "my_name".to_string();
+            Ok(user)
+        });
+        mock.expect_sync_replace_user().never();
+
+        let svc = Rpts02Service::new(mock);
+
+        let patch = UserPatch::Merge(serde_json::json!({ "name": " " }));
+        let error = svc
+            .patch_user(&Uuid::new_v4(), admin(), patch, None, None)
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(error, ServiceError::InvalidUser(_)));
+    }
+
+    // delete user tests
+
+    #[actix_rt::test]
+    async fn delete_user_returns_if_userid_equals_caller() {
+        let mut mock = MockRepo::default();
+        let user_id = Uuid::new_v4();
+        let user_name = "my_name";
+
+        mock.expect_sync_delete_user()
+            .returning(move |id, _, _, _| {
+                let mut user = User::default();
+                user.id = Some(*id);
+                user.name = user_name.to_string();
+                Ok(user)
+            });
+
+        let svc = Rpts02Service::new(mock);
+
+        let result = svc
+            .delete_user(&user_id, Caller::new(user_id.to_string()), None, None)
+            .await
+            .unwrap();
+
+        assert_eq!(result.id.unwrap(), user_id);
+        assert_eq!(result.name, user_name);
+    }
+
+    #[actix_rt::test]
+    async fn delete_user_returns_if_caller_is_an_admin() {
+        let mut mock = MockRepo::default();
+        let user_id = Uuid::new_v4();
+        let user_name = "my_name";
+
+        mock.expect_sync_delete_user()
+            .returning(move |id, _, _, _| {
+                let mut user = User::default();
+                user.id = Some(*id);
+                user.name = user_name.to_string();
+                Ok(user)
+            });
+
+        let svc = Rpts02Service::new(mock);
+
+        let result = svc
+            .delete_user(&user_id, admin(), None, None)
+            .await
+            .unwrap();
+
+        assert_eq!(result.id.unwrap(), user_id);
+        assert_eq!(result.name, user_name);
+    }
+
+    #[actix_rt::test]
+    async fn delete_user_returns_unauthorized_if_userid_not_equal_caller() {
+        let mut mock = MockRepo::default();
+        // the user exists in the tenant of the caller
+        mock.expect_sync_get_user()
+            .returning(|_, _, _| Ok(User::default()));
+        let user_id = Uuid::new_v4();
+
+        mock.expect_sync_delete_user()
+            .returning(|_, _, _, _| Ok(User::default()));
+
+        let svc = Rpts02Service::new(mock);
+
+        let error = svc
+            .delete_user(&user_id, Caller::new("2"), None, None)
+            .await
+            .err()
+            .unwrap();
+
+        let is_unauthorized = match error {
+            ServiceError::Unauthorized => true,
+            _ => false,
+        };
+
+        assert!(is_unauthorized);
+    }
+
+    #[actix_rt::test]
+    async fn delete_user_returns_mapped_error() {
+        let mut mock = MockRepo::default();
+        let user_id = Uuid::new_v4();
+
+        mock.expect_sync_delete_user()
+            .returning(|_, _, _, _| Err(sqlx::Error::RowNotFound));
+
+        let svc = Rpts02Service::new(mock);
+
+        let error = svc
+            .delete_user(&user_id, Caller::new(user_id.to_string()), None, None)
+            .await
+            .err()
+            .unwrap();
+
+        let is_mapped_error = match error {
+            ServiceError::DbError(sqlx::Error::RowNotFound) => true,
+            _ => false,
+        };
+
+        assert!(is_mapped_error);
+    }
+
+    // metrics tests
+
+    /// The value of the counter, summed over its labels.
+    fn counted(registry: &prometheus::Registry, name: &str) -> f64 {
+        registry
+            .gather()
+            .iter()
+            .filter(|family| family.get_name() == name)
+            .flat_map(|family| family.get_metric().iter())
+            .map(|metric| metric.get_counter().get_value())
+            .sum()
+    }
+
+    fn with_metrics(mock: MockRepo) -> (Rpts02Service<MockRepo>, prometheus::Registry) {
+        let registry = prometheus::Registry::new();
+        let metrics = UserMetrics::register(&registry).unwrap();
+        (Rpts02Service::new(mock).with_metrics(metrics), registry)
+    }
+
+    #[actix_rt::test]
+    async fn created_users_are_counted_but_not_the_replays() {
+        let mut mock = MockRepo::default();
+        let mut user = User::default();
+        user.name = "my_name".to_string();
+        let key = IdempotencyKey::new("my_key", &user);
+        let request_hash = key.request_hash.clone();
+
+        mock.expect_sync_create_user()
+            .returning(|user, _, _| Ok(user));
+        mock.expect_sync_create_user_idempotent()
+            .returning(move |user, _, _, _| {
+                Ok(Idempotent::Replayed {
+                    request_hash: request_hash.clone(),
+                    response: user,
+                })
+            });
+
+        let (svc, registry) = with_metrics(mock);
+
+        svc.create_user(user.clone(), admin(), None).await.unwrap();
+        svc.create_user_idempotent(user, key, admin(), None)
+            .await
+            .unwrap();
+
+        assert_eq!(counted(&registry, "rpts02_api_users_created_total"), 1.0);
+    }
+
+    #[actix_rt::test]
+    async fn deleted_users_are_counted_once_deleted() {
+        let mut mock = MockRepo::default();
+        let user_id = Uuid::new_v4();
+        let missing_id = Uuid::new_v4();
+
+        mock.expect_sync_delete_user()
+            .withf(move |id, _, _, _| *id == user_id)
+            .returning(|_, _, _, _| Ok(User::default()));
+        mock.expect_sync_delete_user()
+            .withf(move |id, _, _, _| *id == missing_id)
+            .returning(|_, _, _, _| Err(sqlx::Error::RowNotFound));
+
+        let (svc, registry) = with_metrics(mock);
+
+        svc.delete_user(&user_id, admin(), None, None)
+            .await
+            .unwrap();
+        assert!(svc
+            .delete_user(&missing_id, admin(), None, None)
+            .await
+            .is_err());
+
+        assert_eq!(counted(&registry, "rpts02_api_users_deleted_total"), 1.0);
+    }
+
+    #[actix_rt::test]
+    async fn authorization_failures_are_counted() {
+        let mut mock = MockRepo::default();
+        mock.expect_sync_get_user()
+            .returning(|_, _, _| Ok(User::default()));
+
+        let (svc, registry) = with_metrics(mock);
+
+        assert!(svc
+            .get_user(&Uuid::new_v4(), Caller::new("2"), false)
+            .await
+            .is_err());
+        assert!(svc
+            .create_user(User::default(), Caller::default(), None)
+            .await
+            .is_err());
+
+        assert_eq!(
+            counted(&registry, "rpts02_api_authorization_failures_total"),
+            2.0
+        );
+    }
+
+    // redaction tests
+
+    #[actix_rt::test]
+    async fn spans_leave_no_personal_data() {
+        let mut mock = MockRepo::default();
+        mock.expect_sync_create_user()
+            .returning(|user, _, _| Ok(user));
+        mock.expect_sync_list_users().returning(|_, _| Ok(vec![]));
+        let svc = Rpts02Service::new(mock);
+        let user = User {
+            name: "Jose Smith".to_string(),
+            birth_date: chrono::NaiveDate::from_ymd(1990, 5, 17),
+            custom_data: Some(CustomData { random: 31337 }),
+            ..User::default()
+        };
+        let query = ListQuery {
+            name_prefix: Some("Jose".to_string()),
+            ..ListQuery::default()
+        };
+        let (captured, _guard) = crate::telemetry::redact::capture::start();
+
+        svc.create_user(user, admin(), None).await.unwrap();
+        svc.list_users(admin(), query).await.unwrap();
+
+        let logs = captured.output();
+        assert!(logs.contains("create_user"));
+        assert!(logs.contains("list_users"));
+        for personal in &["Jose", "1990-05-17", "31337"] {
+            assert!(
+                !logs.contains(personal),
+                "{} was logged: {}",
+                personal,
+                logs
+            );
+        }
+    }
+
+    // list users tests
+
+    #[actix_rt::test]
+    async fn list_users_returns_if_caller_is_support() {
+        let mut mock = MockRepo::default();
+
+        mock.expect_sync_list_users()
+            .returning(|_, _| Ok(vec![User::default()]));
+        mock.expect_sync_count_users().never();
+
+        let svc = Rpts02Service::new(mock);
+
+        let page = svc
+            .list_users(support(), ListQuery::default())
+            .await
+            .unwrap();
+
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.total, None);
+    }
+
+    #[actix_rt::test]
+    async fn list_users_returns_total_if_requested() {
+        let mut mock = MockRepo::default();
+
+        mock.expect_sync_list_users().returning(|_, _| Ok(vec![]));
+        mock.expect_sync_count_users()
+            .with(
+                eq(UserFilter {
+                    name_prefix: Some("rob".to_string()),
+                    ..UserFilter::default()
+                }),
+                always(),
+            )
+            .returning(|_, _| Ok(42));
+
+        let svc = Rpts02Service::new(mock);
+
+        let query = ListQuery {
+            name_prefix: Some("rob".to_string()),
+            include_total: true,
+            ..ListQuery::default()
+        };
+        let page = svc.list_users(admin(), query).await.unwrap();
+
+        assert_eq!(page.total, Some(42));
+    }
+
+    #[actix_rt::test]
+    async fn list_users_returns_unauthorized_if_caller_has_no_role() {
+        let mut mock = MockRepo::default();
+
+        mock.expect_sync_list_users().never();
+
+        let svc = Rpts02Service::new(mock);
+
+        let error = svc
+            .list_users(Caller::new("2"), ListQuery::default())
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(error, ServiceError::Unauthorized));
+    }
+
+    #[actix_rt::test]
+    async fn list_users_returns_invalid_query_if_cursor_is_invalid() {
+        let mut mock = MockRepo::default();
+
+        mock.expect_sync_list_users().never();
+
+        let svc = Rpts02Service::new(mock);
+
+        let query = ListQuery {
+            cursor: Some("not-a-cursor".to_string()),
+            ..ListQuery::default()
+        };
+        let error = svc.list_users(admin(), query).await.err().unwrap();
+
+        assert!(matches!(error, ServiceError::InvalidQuery(_)));
+    }
+
+    #[actix_rt::test]
+    async fn list_users_returns_unauthorized_if_support_includes_deleted() {
+        let mut mock = MockRepo::default();
+
+        mock.expect_sync_list_users().never();
+
+        let svc = Rpts02Service::new(mock);
+
+        let query = ListQuery {
+            include_deleted: true,
+            ..ListQuery::default()
+        };
+        let error = svc.list_users(support(), query).await.err().unwrap();
+
+        assert!(matches!(error, ServiceError::Unauthorized));
+    }
+
+    // soft delete tests
+
+    #[actix_rt::test]
+    async fn get_user_returns_deleted_users_to_admins() {
+        let mut mock = MockRepo::default();
+        let user_id = Uuid::new_v4();
+
+        mock.expect_sync_get_user()
+            .with(eq(user_id), eq(true), always())
+            .returning(|_, _, _| {
+                let mut user = User::default();
+                user.deleted_at = Some(Utc::now());
+                Ok(user)
+            });
+
+        let svc = Rpts02Service::new(mock);
+
+        let user = svc.get_user(&user_id, admin(), true).await.unwrap();
+
+        assert!(user.deleted_at.is_some());
+    }
+
+    #[actix_rt::test]
+    async fn get_user_returns_not_found_if_owner_includes_deleted() {
+        let mut mock = MockRepo::default();
+        let user_id = Uuid::new_v4();
+
+        mock.expect_sync_get_user().never();
+
+        let svc = Rpts02Service::new(mock);
+
+        let error = svc
+            .get_user(&user_id, Caller::new(user_id.to_string()), true)
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(
+            error,
+            ServiceError::DbError(sqlx::Error::RowNotFound)
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn restore_user_returns_if_userid_equals_caller() {
+        let mut mock = MockRepo::default();
+        let user_id = Uuid::new_v4();
+
+        mock.expect_sync_restore_user()
+            .with(eq(user_id), always(), always())
+            .returning(|id, _, _| {
+                let mut user = User::default();
+                user.id = Some(*id);
+                Ok(user)
+            });
+
+        let svc = Rpts02Service::new(mock);
+
+        let user = svc
+            .restore_user(&user_id, Caller::new(user_id.to_string()), None)
+            .await
+            .unwrap();
+
+        assert_eq!(user.id, Some(user_id));
+    }
+
+    #[actix_rt::test]
+    async fn restore_user_returns_if_caller_is_an_admin() {
+        let mut mock = MockRepo::default();
+
+        mock.expect_sync_restore_user()
+            .returning(|_, _, _| Ok(User::default()));
+
+        let svc = Rpts02Service::new(mock);
+
+        let result = svc.restore_user(&Uuid::new_v4(), admin(), None).await;
+
+        assert!(result.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn restore_user_returns_unauthorized_if_userid_not_equal_caller() {
+        let mut mock = MockRepo::default();
+        // the user exists in the tenant of the caller
+        mock.expect_sync_get_user()
+            .returning(|_, _, _| Ok(User::default()));
+
+        mock.expect_sync_restore_user().never();
+
+        let svc = Rpts02Service::new(mock);
+
+        let error = svc
+            .restore_user(&Uuid::new_v4(), Caller::new("2"), None)
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(error, ServiceError::Unauthorized));
+    }
+
+    #[actix_rt::test]
+    async fn purge_deleted_users_keeps_the_retention_period() {
+        let mut mock = MockRepo::default();
+        let retention = Duration::days(30);
+        let oldest = Utc::now() - retention;
+
+        mock.expect_sync_purge_users()
+            .withf(move |deleted_before| *deleted_before >= oldest)
+            .returning(|_| Ok(3));
+
+        let svc = Rpts02Service::new(mock);
+
+        let purged = svc.purge_deleted_users(retention).await.unwrap();
+
+        assert_eq!(purged, 3);
+    }
+
+    // audit tests
+
+    #[actix_rt::test]
+    async fn writes_are_recorded_with_the_caller_and_request() {
+        let mut mock = MockRepo::default();
+        let user_id = Uuid::new_v4();
+        let expected = Actor::new(Some(user_id.to_string()), Some("request".to_string()));
+
+        mock.expect_sync_delete_user()
+            .with(eq(user_id), always(), eq(expected), always())
+            .returning(|_, _, _, _| Ok(User::default()));
+
+        let svc = Rpts02Service::new(mock);
+
+        let result = svc
+            .delete_user(
+                &user_id,
+                Caller::new(user_id.to_string()),
+                None,
+                Some("request".to_string()),
+            )
+            .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn user_history_works() {
+        let mut mock = MockRepo::default();
+        let user_id = Uuid::new_v4();
+
+        mock.expect_sync_user_history()
+            .withf(move |id, listing, _| *id == user_id && listing.limit == 1)
+            .returning(|id, _, _| {
+                let record = |record_id| AuditRecord {
+                    id: record_id,
+                    user_id: *id,
+                    actor: None,
+                    operation: crate::models::Operation::Update,
+                    changes: serde_json::json!({}),
+                    request_id: None,
+                    created_at: Utc::now(),
+                };
+                Ok(vec![record(2), record(1)])
+            });
+
+        let svc = Rpts02Service::new(mock);
+
+        let query = HistoryQuery {
+            limit: Some(1),
+            cursor: None,
+        };
+        let page = svc
+            .user_history(&user_id, Caller::new(user_id.to_string()), query)
+            .await
+            .unwrap();
+
+        assert_eq!(page.items.len(), 1);
+        assert!(page.next_cursor.is_some());
+    }
+
+    #[actix_rt::test]
+    async fn user_history_returns_not_found_if_userid_not_equal_caller() {
+        let mut mock = MockRepo::default();
+        mock.expect_sync_get_user().never();
+        mock.expect_sync_user_history().never();
+
+        let svc = Rpts02Service::new(mock);
+
+        let error = svc
+            .user_history(&Uuid::new_v4(), Caller::new("2"), HistoryQuery::default())
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(
+            error,
+            ServiceError::DbError(sqlx::Error::RowNotFound)
+        ));
     }
 }
@@ -0,0 +1,170 @@
+//! Who can do what with the users.
+//!
+//! Access is denied unless one of the grants below allows it:
+//!
+//! | Caller | Actions |
+//! | --- | --- |
+//! | [Role::Admin] | Every action on every user. |
+//! | [Role::Support] | Reading and listing the users that aren't deleted, and their history. |
+//! | The user itself | Reading, writing, deleting and restoring itself, and its history. |
+//! | Any authenticated caller but [Role::Support], which only reads | Creating users. |
+use super::ServiceError;
+use crate::auth::{Caller, Role};
+use tracing as log;
+use uuid::Uuid;
+
+/// What a caller wants to do with the users.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Read,
+    /// Reading a user even if it's deleted.
+    ReadDeleted,
+    List,
+    /// Listing the deleted users too.
+    ListDeleted,
+    Create,
+    Update,
+    Delete,
+    Restore,
+    ReadHistory,
+}
+
+use Action::*;
+
+impl Action {
+    /// Name of the action in the metrics.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Read => "read",
+            ReadDeleted => "read_deleted",
+            List => "list",
+            ListDeleted => "list_deleted",
+            Create => "create",
+            Update => "update",
+            Delete => "delete",
+            Restore => "restore",
+            ReadHistory => "read_history",
+        }
+    }
+
+    /// Whether the action only reads a user.
+    pub fn is_read(self) -> bool {
+        matches!(self, Read | ReadDeleted | ReadHistory)
+    }
+}
+
+const SUPPORT: &[Action] = &[Read, List, ReadHistory];
+const OWNER: &[Action] = &[Read, Update, Delete, Restore, ReadHistory];
+const AUTHENTICATED: &[Action] = &[Create];
+
+/// Whether the caller can perform the action on the user, if the action has one.
+pub fn allows(caller: &Caller, action: Action, user_id: Option<&Uuid>) -> bool {
+    let is_owner = match (caller.user_id(), user_id) {
+        (Some(caller_id), Some(user_id)) => caller_id == *user_id,
+        _ => false,
+    };
+    caller.has_role(Role::Admin)
+        || (caller.has_role(Role::Support) && SUPPORT.contains(&action))
+        || (is_owner && OWNER.contains(&action))
+        || (caller.user.is_some()
+            && !caller.has_role(Role::Support)
+            && AUTHENTICATED.contains(&action))
+}
+
+/// Fails with [ServiceError::Unauthorized] unless the caller can perform the action.
+pub fn authorize(
+    caller: &Caller,
+    action: Action,
+    user_id: Option<&Uuid>,
+) -> Result<(), ServiceError> {
+    if allows(caller, action, user_id) {
+        Ok(())
+    } else {
+        log::warn!(
+            "{:?} is not allowed to {:?} {:?}",
+            caller.user,
+            action,
+            user_id
+        );
+        Err(ServiceError::Unauthorized)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: &[Action] = &[
+        Read,
+        ReadDeleted,
+        List,
+        ListDeleted,
+        Create,
+        Update,
+        Delete,
+        Restore,
+        ReadHistory,
+    ];
+
+    /// The actions the caller is allowed to perform on the user.
+    fn allowed(caller: &Caller, user_id: &Uuid) -> Vec<Action> {
+        ALL.iter()
+            .copied()
+            .filter(|action| allows(caller, *action, Some(user_id)))
+            .collect()
+    }
+
+    #[test]
+    fn anonymous_callers_cant_do_anything() {
+        let user_id = Uuid::new_v4();
+
+        assert_eq!(allowed(&Caller::default(), &user_id), vec![]);
+        assert!(!allows(&Caller::default(), Create, None));
+    }
+
+    #[test]
+    fn authenticated_callers_can_only_create_users() {
+        let caller = Caller::new("someone");
+
+        assert_eq!(allowed(&caller, &Uuid::new_v4()), vec![Create]);
+        assert!(allows(&caller, Create, None));
+    }
+
+    #[test]
+    fn owners_can_manage_themselves() {
+        let user_id = Uuid::new_v4();
+        let caller = Caller::new(user_id.to_string());
+
+        assert_eq!(
+            allowed(&caller, &user_id),
+            vec![Read, Create, Update, Delete, Restore, ReadHistory]
+        );
+    }
+
+    #[test]
+    fn support_can_only_read() {
+        let caller = Caller::new("support").with_roles(vec![Role::Support]);
+
+        assert_eq!(
+            allowed(&caller, &Uuid::new_v4()),
+            vec![Read, List, ReadHistory]
+        );
+        assert!(!allows(&caller, ListDeleted, None));
+        assert!(!allows(&caller, Create, None));
+    }
+
+    #[test]
+    fn admins_can_do_anything() {
+        let caller = Caller::new("admin").with_roles(vec![Role::Admin]);
+
+        assert_eq!(allowed(&caller, &Uuid::new_v4()), ALL.to_vec());
+        assert_eq!(allowed(&Caller::disabled(), &Uuid::new_v4()), ALL.to_vec());
+    }
+
+    #[test]
+    fn unauthorized_actions_are_service_errors() {
+        let result = authorize(&Caller::new("someone"), Delete, Some(&Uuid::new_v4()));
+
+        assert!(matches!(result, Err(ServiceError::Unauthorized)));
+    }
+}
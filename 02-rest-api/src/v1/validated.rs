@@ -0,0 +1,84 @@
+//! Extractor of JSON bodies that are validated before reaching the handlers.
+use crate::models::Validate;
+use crate::problem::Problem;
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use futures::future::{FutureExt, LocalBoxFuture};
+use serde::de::DeserializeOwned;
+
+/// A JSON body that follows the input constraints of its type,
+/// so the read-only fields are rejected along with any invalid value.
+/// Otherwise the request fails with `422 Unprocessable Entity` and the list of violations.
+#[derive(Debug)]
+pub struct Validated<T>(pub T);
+
+impl<T> Validated<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> FromRequest for Validated<T>
+where
+    T: DeserializeOwned + Validate + 'static,
+{
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = web::JsonConfig;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        web::Json::<T>::from_request(&req, payload)
+            .map(move |json| {
+                let value = json?.into_inner();
+                value
+                    .validate_input()
+                    .map_err(|err| Problem::from(err).for_request(&req))?;
+                Ok(Validated(value))
+            })
+            .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{CustomData, User};
+    use actix_web::test::TestRequest;
+
+    #[actix_rt::test]
+    async fn valid_bodies_are_extracted() {
+        let (req, mut payload) = TestRequest::default()
+            .set_json(&CustomData { random: 7 })
+            .to_http_parts();
+
+        let custom_data = Validated::<CustomData>::from_request(&req, &mut payload)
+            .await
+            .unwrap();
+
+        assert_eq!(custom_data.into_inner().random, 7);
+    }
+
+    #[actix_rt::test]
+    async fn invalid_bodies_are_rejected_with_the_violations() {
+        let mut user = User::default();
+        user.id = Some(uuid::Uuid::new_v4());
+        let (req, mut payload) = TestRequest::with_uri("/v1/users")
+            .set_json(&user)
+            .to_http_parts();
+
+        let err = Validated::<User>::from_request(&req, &mut payload)
+            .await
+            .err()
+            .unwrap();
+        let problem = err.as_error::<Problem>().unwrap();
+
+        assert_eq!(problem.status, 422);
+        assert_eq!(problem.instance.as_deref(), Some("/v1/users"));
+        let fields: Vec<&str> = problem
+            .violations
+            .iter()
+            .map(|v| v.field.as_str())
+            .collect();
+        assert_eq!(fields, vec!["name", "id"]);
+    }
+}
@@ -0,0 +1,7 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // only the messages are generated here. The services living in other crates
+    // reuse them by declaring an `extern_path` to `rpts_domain::proto`.
+    prost_build::compile_protos(&["proto/user.proto"], &["proto"])?;
+    println!("## Domain proto files have been compiled");
+    Ok(())
+}
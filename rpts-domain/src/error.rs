@@ -0,0 +1,37 @@
+use crate::validation::Violation;
+
+/// Errors produced while validating or converting domain types.
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum DomainError {
+    #[error("The field {0} is required")]
+    MissingField(&'static str),
+    #[error("The field {0} is invalid: {1}")]
+    InvalidField(&'static str, String),
+    #[error("The data is invalid: {}", join(.0))]
+    Invalid(Vec<Violation>),
+}
+
+impl DomainError {
+    /// The fields at fault and what's wrong with them.
+    pub fn violations(&self) -> Vec<Violation> {
+        match self {
+            DomainError::MissingField(field) => vec![Violation {
+                field: field.to_string(),
+                message: "is required".to_string(),
+            }],
+            DomainError::InvalidField(field, message) => vec![Violation {
+                field: field.to_string(),
+                message: message.clone(),
+            }],
+            DomainError::Invalid(violations) => violations.clone(),
+        }
+    }
+}
+
+fn join(violations: &[Violation]) -> String {
+    violations
+        .iter()
+        .map(Violation::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
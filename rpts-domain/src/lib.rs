@@ -0,0 +1,15 @@
+//! Domain types shared by the rpts servers.
+//!
+//! Both the gRPC server and the REST API use these types so a user is
+//! validated and serialized exactly the same way no matter the transport.
+mod error;
+pub mod proto;
+pub mod redact;
+mod user;
+pub mod validation;
+
+pub use error::DomainError;
+pub use user::{CustomData, User, NAME_MAX_CHARS, RANDOM_MAX};
+pub use validation::{Validate, Violation};
+
+pub type Result<T> = std::result::Result<T, DomainError>;
@@ -0,0 +1,186 @@
+#![allow(clippy::all, clippy::pedantic, clippy::nursery)]
+//! Protobuf messages for the domain types and their conversions.
+use crate::{CustomData, DomainError, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use prost_types::Timestamp;
+use std::{collections::HashMap, convert::TryFrom};
+use uuid::Uuid;
+
+include!(concat!(env!("OUT_DIR"), "/rpts.domain.rs"));
+
+const RANDOM_KEY: &str = "random";
+
+impl From<crate::User> for User {
+    fn from(user: crate::User) -> Self {
+        Self {
+            id: user.id.map(|id| id.to_string()).unwrap_or_default(),
+            name: user.name,
+            birth_date: Some(date_to_timestamp(user.birth_date)),
+            created_at: user.created_at.map(datetime_to_timestamp),
+            updated_at: user.updated_at.map(datetime_to_timestamp),
+            custom_data: user.custom_data.map(CustomData::into).unwrap_or_default(),
+            deleted_at: user.deleted_at.map(datetime_to_timestamp),
+        }
+    }
+}
+
+impl TryFrom<User> for crate::User {
+    type Error = DomainError;
+
+    fn try_from(user: User) -> Result<Self> {
+        let id = if user.id.is_empty() {
+            None
+        } else {
+            let id = Uuid::parse_str(&user.id)
+                .map_err(|e| DomainError::InvalidField("id", e.to_string()))?;
+            Some(id)
+        };
+        let birth_date = user
+            .birth_date
+            .ok_or(DomainError::MissingField("birth_date"))
+            .and_then(|ts| timestamp_to_datetime("birth_date", ts))?
+            .date()
+            .naive_utc();
+        let custom_data = if user.custom_data.is_empty() {
+            None
+        } else {
+            Some(CustomData::try_from(user.custom_data)?)
+        };
+
+        Ok(Self {
+            id,
+            name: user.name,
+            birth_date,
+            created_at: user
+                .created_at
+                .map(|ts| timestamp_to_datetime("created_at", ts))
+                .transpose()?,
+            updated_at: user
+                .updated_at
+                .map(|ts| timestamp_to_datetime("updated_at", ts))
+                .transpose()?,
+            custom_data,
+            deleted_at: user
+                .deleted_at
+                .map(|ts| timestamp_to_datetime("deleted_at", ts))
+                .transpose()?,
+        })
+    }
+}
+
+impl From<CustomData> for HashMap<String, i64> {
+    fn from(custom_data: CustomData) -> Self {
+        let mut map = HashMap::new();
+        map.insert(RANDOM_KEY.to_string(), i64::from(custom_data.random));
+        map
+    }
+}
+
+impl TryFrom<HashMap<String, i64>> for CustomData {
+    type Error = DomainError;
+
+    fn try_from(map: HashMap<String, i64>) -> Result<Self> {
+        let random = map
+            .get(RANDOM_KEY)
+            .ok_or(DomainError::MissingField("custom_data.random"))?;
+        let random = u32::try_from(*random)
+            .map_err(|e| DomainError::InvalidField("custom_data.random", e.to_string()))?;
+        Ok(Self { random })
+    }
+}
+
+fn datetime_to_timestamp(datetime: DateTime<Utc>) -> Timestamp {
+    Timestamp {
+        seconds: datetime.timestamp(),
+        nanos: datetime.timestamp_subsec_nanos() as i32,
+    }
+}
+
+fn date_to_timestamp(date: NaiveDate) -> Timestamp {
+    Timestamp {
+        seconds: date.and_hms(0, 0, 0).timestamp(),
+        nanos: 0,
+    }
+}
+
+fn timestamp_to_datetime(field: &'static str, ts: Timestamp) -> Result<DateTime<Utc>> {
+    let nanos =
+        u32::try_from(ts.nanos).map_err(|e| DomainError::InvalidField(field, e.to_string()))?;
+    NaiveDateTime::from_timestamp_opt(ts.seconds, nanos)
+        .map(|dt| DateTime::from_utc(dt, Utc))
+        .ok_or_else(|| DomainError::InvalidField(field, "timestamp out of range".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn domain_user() -> crate::User {
+        crate::User {
+            id: Some(Uuid::new_v4()),
+            name: "my_name".to_string(),
+            birth_date: NaiveDate::from_ymd(1977, 3, 10),
+            created_at: Some(Utc.ymd(2020, 12, 12).and_hms_nano(22, 45, 37, 123)),
+            updated_at: None,
+            custom_data: Some(CustomData { random: 7 }),
+            deleted_at: None,
+        }
+    }
+
+    #[test]
+    fn to_proto_works() {
+        let user = domain_user();
+        let proto = User::from(user.clone());
+
+        assert_eq!(proto.id, user.id.unwrap().to_string());
+        assert_eq!(proto.name, user.name);
+        assert_eq!(proto.birth_date.unwrap().seconds, 226_800_000);
+        assert_eq!(proto.created_at.unwrap().nanos, 123);
+        assert_eq!(proto.updated_at, None);
+        assert_eq!(proto.custom_data.get(RANDOM_KEY), Some(&7));
+    }
+
+    #[test]
+    fn proto_round_trip_works() {
+        let user = domain_user();
+        let result = crate::User::try_from(User::from(user.clone())).unwrap();
+        assert_eq!(result, user);
+    }
+
+    #[test]
+    fn from_proto_without_id_or_custom_data_works() {
+        let mut user = domain_user();
+        user.id = None;
+        user.custom_data = None;
+        let result = crate::User::try_from(User::from(user.clone())).unwrap();
+        assert_eq!(result, user);
+    }
+
+    #[test]
+    fn from_proto_rejects_invalid_ids() {
+        let mut proto = User::from(domain_user());
+        proto.id = "not-a-uuid".to_string();
+        let error = crate::User::try_from(proto).err().unwrap();
+        assert!(matches!(error, DomainError::InvalidField("id", _)));
+    }
+
+    #[test]
+    fn from_proto_requires_birth_date() {
+        let mut proto = User::from(domain_user());
+        proto.birth_date = None;
+        let error = crate::User::try_from(proto).err().unwrap();
+        assert_eq!(error, DomainError::MissingField("birth_date"));
+    }
+
+    #[test]
+    fn from_proto_rejects_negative_custom_data() {
+        let mut proto = User::from(domain_user());
+        proto.custom_data.insert(RANDOM_KEY.to_string(), -1);
+        let error = crate::User::try_from(proto).err().unwrap();
+        assert!(matches!(
+            error,
+            DomainError::InvalidField("custom_data.random", _)
+        ));
+    }
+}
@@ -0,0 +1,214 @@
+//! Redaction of the personal data in the logs and traces.
+//!
+//! The fields holding personal data are marked `#[sensitive]` where their type implements
+//! `Debug` with [redacted_debug], so they're shown as the [Redaction] policy of the process says
+//! wherever the type is logged or recorded in a span. Secrets, like tokens, are always masked
+//! with [Secret], whatever the policy.
+use hmac::{Hmac, Mac, NewMac};
+use once_cell::sync::OnceCell;
+use sha2::Sha256;
+use std::{
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+/// What replaces the masked values.
+pub const MASK: &str = "[REDACTED]";
+/// Hex digits of the digests shown by [Redaction::Hash].
+const DIGEST_LEN: usize = 12;
+
+/// What's shown of the sensitive values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Redaction {
+    /// Nothing but [MASK]. The default.
+    Mask,
+    /// A short digest of the value, keyed by the secret of [set_hash_key], so equal values
+    /// can be told apart without being shown nor guessed. Masked until the key is set.
+    Hash,
+    /// The value as it is. Only meant for local development.
+    Off,
+}
+
+static POLICY: AtomicU8 = AtomicU8::new(Redaction::Mask as u8);
+static HASH_KEY: OnceCell<Vec<u8>> = OnceCell::new();
+
+/// Sets the policy of the whole process.
+pub fn set_policy(policy: Redaction) {
+    POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+/// Sets the secret key of the digests of [Redaction::Hash], once per process.
+pub fn set_hash_key(key: impl Into<Vec<u8>>) -> Result<(), String> {
+    HASH_KEY
+        .set(key.into())
+        .map_err(|_| "The redaction hash key is already set".to_string())
+}
+
+/// The policy of the process.
+pub fn policy() -> Redaction {
+    match POLICY.load(Ordering::Relaxed) {
+        x if x == Redaction::Hash as u8 => Redaction::Hash,
+        x if x == Redaction::Off as u8 => Redaction::Off,
+        _ => Redaction::Mask,
+    }
+}
+
+impl Redaction {
+    /// Writes the value as the policy says.
+    pub fn write(self, f: &mut fmt::Formatter<'_>, value: &dyn fmt::Debug) -> fmt::Result {
+        match self {
+            Redaction::Mask => f.write_str(MASK),
+            Redaction::Hash => match HASH_KEY.get() {
+                Some(key) => write!(f, "[{}]", digest(key, value)),
+                None => f.write_str(MASK),
+            },
+            Redaction::Off => value.fmt(f),
+        }
+    }
+
+    /// The value as the policy shows it.
+    pub fn show(self, value: &dyn fmt::Debug) -> String {
+        format!("{:?}", Shown(self, value))
+    }
+}
+
+/// The HMAC-SHA256 of the value, truncated to [DIGEST_LEN] hex digits.
+fn digest(key: &[u8], value: &dyn fmt::Debug) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("HMAC takes keys of any length");
+    mac.update(format!("{:?}", value).as_bytes());
+    let digest = format!("{:x}", mac.finalize().into_bytes());
+    digest[..DIGEST_LEN].to_string()
+}
+
+struct Shown<'a>(Redaction, &'a dyn fmt::Debug);
+
+impl fmt::Debug for Shown<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.write(f, self.1)
+    }
+}
+
+impl FromStr for Redaction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mask" => Ok(Redaction::Mask),
+            "hash" => Ok(Redaction::Hash),
+            "off" => Ok(Redaction::Off),
+            other => Err(format!(
+                "Unknown redaction policy: {}, expected mask, hash or off",
+                other
+            )),
+        }
+    }
+}
+
+/// Personal data, shown as the policy of the process says.
+pub struct Sensitive<'a, T: ?Sized>(pub &'a T);
+
+impl<T: fmt::Debug + ?Sized> fmt::Debug for Sensitive<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        policy().write(f, &self.0)
+    }
+}
+
+/// A secret, never shown.
+pub struct Secret<'a, T: ?Sized>(pub &'a T);
+
+impl<T: ?Sized> fmt::Debug for Secret<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(MASK)
+    }
+}
+
+/// Implements `Debug` for a struct, showing its `#[sensitive]` fields as the policy says.
+/// Every field must be listed, so the new ones aren't left out.
+///
+/// ```
+/// use rpts_domain::redacted_debug;
+///
+/// struct Person {
+///     id: u32,
+///     name: String,
+/// }
+///
+/// redacted_debug!(Person {
+///     id,
+///     #[sensitive]
+///     name,
+/// });
+///
+/// let person = Person { id: 7, name: "José".to_string() };
+/// assert_eq!(format!("{:?}", person), "Person { id: 7, name: [REDACTED] }");
+/// ```
+#[macro_export]
+macro_rules! redacted_debug {
+    ($type: ident { $($(#[$attr: ident])? $field: ident),* $(,)? }) => {
+        impl ::std::fmt::Debug for $type {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                let $type { $($field),* } = self;
+                f.debug_struct(stringify!($type))
+                    $(.field(stringify!($field), &$crate::redacted_debug!(@field $field $(#[$attr])?)))*
+                    .finish()
+            }
+        }
+    };
+    (@field $field: ident) => {
+        $field
+    };
+    (@field $field: ident #[sensitive]) => {
+        $crate::redact::Sensitive($field)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_hides_the_value() {
+        assert_eq!(Redaction::Mask.show(&"José"), MASK);
+    }
+
+    #[test]
+    fn digests_are_keyed() {
+        let digested = digest(b"secret", &"José");
+
+        assert!(!digested.contains("José"));
+        assert_eq!(digested.len(), DIGEST_LEN);
+        assert_eq!(digested, digest(b"secret", &"José"));
+        assert_ne!(digested, digest(b"secret", &"Maria"));
+        assert_ne!(digested, digest(b"other secret", &"José"));
+    }
+
+    #[test]
+    fn hash_masks_until_the_key_is_set() {
+        // the tests never set the key, which is kept for the whole process
+        assert_eq!(Redaction::Hash.show(&"José"), MASK);
+    }
+
+    #[test]
+    fn off_shows_the_value() {
+        assert_eq!(Redaction::Off.show(&"José"), "\"José\"");
+    }
+
+    #[test]
+    fn secrets_are_always_masked() {
+        assert_eq!(format!("{:?}", Secret(&"token")), MASK);
+    }
+
+    #[test]
+    fn policies_are_parsed() {
+        assert_eq!("mask".parse(), Ok(Redaction::Mask));
+        assert_eq!("hash".parse(), Ok(Redaction::Hash));
+        assert_eq!("off".parse(), Ok(Redaction::Off));
+        assert!("none".parse::<Redaction>().is_err());
+    }
+
+    #[test]
+    fn the_default_policy_masks() {
+        assert_eq!(policy(), Redaction::Mask);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::redacted_debug;
+use crate::validation::{
+    AllowedChars, AtMost, MaxChars, NotBlank, PastDateSince, ReadOnly, Validate, Validator,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A user of the platform.
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct User {
+    /// Set by the server on creation.
+    pub id: Option<Uuid>,
+    /// Unique. Letters, digits, spaces and _-.'
+    #[cfg_attr(feature = "openapi", schemars(length(max = "NAME_MAX_CHARS")))]
+    pub name: String,
+    /// From 1900-01-01 to today.
+    pub birth_date: NaiveDate,
+    /// Set by the server on creation.
+    pub created_at: Option<DateTime<Utc>>,
+    /// Set by the server on every change.
+    pub updated_at: Option<DateTime<Utc>>,
+    pub custom_data: Option<CustomData>,
+    /// When the user was deleted, if it was. Deleted users can be restored until they're purged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+redacted_debug!(User {
+    id,
+    #[sensitive]
+    name,
+    #[sensitive]
+    birth_date,
+    created_at,
+    updated_at,
+    custom_data,
+    deleted_at,
+});
+
+impl Default for User {
+    fn default() -> Self {
+        Self {
+            id: None,
+            name: String::default(),
+            birth_date: NaiveDate::from_ymd(1977, 3, 10),
+            created_at: None,
+            updated_at: None,
+            custom_data: None,
+            deleted_at: None,
+        }
+    }
+}
+
+/// Longest name allowed, in characters.
+pub const NAME_MAX_CHARS: usize = 100;
+/// Largest random number allowed, so it fits a signed 32 bits integer in any client.
+pub const RANDOM_MAX: u32 = i32::MAX as u32;
+
+/// Letters and digits of any language, spaces and the usual punctuation of names.
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, ' ' | '_' | '-' | '.' | '\'')
+}
+
+fn min_birth_date() -> NaiveDate {
+    NaiveDate::from_ymd(1900, 1, 1)
+}
+
+impl Validate for User {
+    fn check(&self, validator: &mut Validator) {
+        validator
+            .field(
+                "name",
+                self.name.as_str(),
+                &[
+                    &NotBlank,
+                    &MaxChars(NAME_MAX_CHARS),
+                    &AllowedChars {
+                        allowed: is_name_char,
+                        description: "letters, digits, spaces and _-.'",
+                    },
+                ],
+            )
+            .field(
+                "birth_date",
+                &self.birth_date,
+                &[&PastDateSince(min_birth_date)],
+            )
+            .nested("custom_data", self.custom_data.as_ref());
+    }
+
+    fn check_input(&self, validator: &mut Validator) {
+        self.check(validator);
+        validator
+            .field("id", &self.id, &[&ReadOnly])
+            .field("created_at", &self.created_at, &[&ReadOnly])
+            .field("updated_at", &self.updated_at, &[&ReadOnly])
+            .field("deleted_at", &self.deleted_at, &[&ReadOnly]);
+    }
+}
+
+/// Free-form data attached to a user.
+/// Rows stored before it had a random number, like the seeded `{"points": 10}`,
+/// are still read: the number defaults to 0 and any other key is ignored.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct CustomData {
+    #[serde(default)]
+    #[cfg_attr(feature = "openapi", schemars(range(max = "RANDOM_MAX")))]
+    pub random: u32,
+}
+
+redacted_debug!(CustomData {
+    #[sensitive]
+    random,
+});
+
+impl Validate for CustomData {
+    fn check(&self, validator: &mut Validator) {
+        validator.field("random", &self.random, &[&AtMost(RANDOM_MAX)]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DomainError, Violation};
+    use chrono::Duration;
+
+    fn user() -> User {
+        let mut user = User::default();
+        user.name = "my_name".to_string();
+        user
+    }
+
+    fn fields(error: DomainError) -> Vec<String> {
+        error
+            .violations()
+            .into_iter()
+            .map(|violation| violation.field)
+            .collect()
+    }
+
+    #[test]
+    fn validate_works() {
+        let mut user = user();
+        user.name = "José O'Brien-Díaz Jr.".to_string();
+        user.custom_data = Some(CustomData { random: 7 });
+        assert_eq!(user.validate(), Ok(()));
+        assert_eq!(user.validate_input(), Ok(()));
+    }
+
+    #[test]
+    fn validate_rejects_blank_names() {
+        let mut user = user();
+        user.name = "  ".to_string();
+        assert_eq!(
+            user.validate(),
+            Err(DomainError::Invalid(vec![Violation {
+                field: "name".to_string(),
+                message: "must not be blank".to_string()
+            }]))
+        );
+    }
+
+    #[test]
+    fn validate_rejects_long_names_and_odd_characters() {
+        let mut user = user();
+        user.name = "a".repeat(NAME_MAX_CHARS + 1);
+        assert_eq!(fields(user.validate().err().unwrap()), vec!["name"]);
+        user.name = "<script>".to_string();
+        assert_eq!(fields(user.validate().err().unwrap()), vec!["name"]);
+    }
+
+    #[test]
+    fn validate_rejects_birth_dates_in_the_future() {
+        let mut user = user();
+        user.birth_date = Utc::today().naive_utc() + Duration::days(1);
+        assert_eq!(fields(user.validate().err().unwrap()), vec!["birth_date"]);
+        user.birth_date = NaiveDate::from_ymd(1899, 12, 31);
+        assert_eq!(fields(user.validate().err().unwrap()), vec!["birth_date"]);
+    }
+
+    #[test]
+    fn validate_checks_the_custom_data() {
+        let mut user = user();
+        user.custom_data = Some(CustomData {
+            random: RANDOM_MAX + 1,
+        });
+        assert_eq!(
+            fields(user.validate().err().unwrap()),
+            vec!["custom_data.random"]
+        );
+    }
+
+    #[test]
+    fn older_custom_data_is_still_read() {
+        let custom_data: CustomData = serde_json::from_str(r#"{"points": 10}"#).unwrap();
+        assert_eq!(custom_data, CustomData::default());
+        let custom_data: CustomData =
+            serde_json::from_str(r#"{"points": 10, "random": 3}"#).unwrap();
+        assert_eq!(custom_data, CustomData { random: 3 });
+    }
+
+    #[test]
+    fn validate_input_rejects_read_only_fields() {
+        let mut user = user();
+        user.id = Some(Uuid::new_v4());
+        user.created_at = Some(Utc::now());
+        assert_eq!(user.validate(), Ok(()));
+        assert_eq!(
+            fields(user.validate_input().err().unwrap()),
+            vec!["id", "created_at"]
+        );
+    }
+
+    #[test]
+    fn json_serialization_works() {
+        let mut user = User::default();
+        user.id = Some(Uuid::new_v4());
+        user.name = "my_name".to_string();
+        user.custom_data = Some(CustomData { random: 3 });
+
+        let json = serde_json::to_value(&user).unwrap();
+
+        assert_eq!(json["birth_date"], "1977-03-10");
+        assert_eq!(json["custom_data"]["random"], 3);
+        assert_eq!(serde_json::from_value::<User>(json).unwrap(), user);
+    }
+
+    #[test]
+    fn debug_hides_the_personal_data() {
+        let id = Uuid::new_v4();
+        let mut user = user();
+        user.id = Some(id);
+        user.custom_data = Some(CustomData { random: 31337 });
+
+        let debug = format!("{:?}", user);
+
+        assert!(debug.contains(&id.to_string()));
+        assert!(!debug.contains("my_name"));
+        assert!(!debug.contains("1977"));
+        assert!(!debug.contains("31337"));
+    }
+}
@@ -0,0 +1,242 @@
+//! Declarative validation of the domain types.
+//!
+//! Each type lists the constraints of its fields and every broken one is reported,
+//! so the callers can fix all of them at once.
+use crate::{DomainError, Result};
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// A constraint broken by a field.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct Violation {
+    /// Path of the field, like `custom_data.random`.
+    pub field: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// A rule the values of type `T` must follow.
+pub trait Constraint<T: ?Sized> {
+    /// Describes what's wrong with the value, if anything.
+    fn check(&self, value: &T) -> Option<String>;
+}
+
+/// Text with something else than whitespace.
+pub struct NotBlank;
+
+impl Constraint<str> for NotBlank {
+    fn check(&self, value: &str) -> Option<String> {
+        if value.trim().is_empty() {
+            Some("must not be blank".to_string())
+        } else {
+            None
+        }
+    }
+}
+
+/// Text of at most this number of characters.
+pub struct MaxChars(pub usize);
+
+impl Constraint<str> for MaxChars {
+    fn check(&self, value: &str) -> Option<String> {
+        if value.chars().count() > self.0 {
+            Some(format!("must have at most {} characters", self.0))
+        } else {
+            None
+        }
+    }
+}
+
+/// Text made only of the allowed characters.
+pub struct AllowedChars {
+    pub allowed: fn(char) -> bool,
+    /// Describes the allowed characters to the callers.
+    pub description: &'static str,
+}
+
+impl Constraint<str> for AllowedChars {
+    fn check(&self, value: &str) -> Option<String> {
+        if value.chars().all(self.allowed) {
+            None
+        } else {
+            Some(format!("must only contain {}", self.description))
+        }
+    }
+}
+
+/// Dates between this one and today, both included.
+pub struct PastDateSince(pub fn() -> NaiveDate);
+
+impl Constraint<NaiveDate> for PastDateSince {
+    fn check(&self, value: &NaiveDate) -> Option<String> {
+        let min = (self.0)();
+        if *value < min {
+            Some(format!("must not be before {}", min))
+        } else if *value > Utc::today().naive_utc() {
+            Some("must not be in the future".to_string())
+        } else {
+            None
+        }
+    }
+}
+
+/// Values up to this one, included.
+pub struct AtMost<T>(pub T);
+
+impl<T: PartialOrd + Display> Constraint<T> for AtMost<T> {
+    fn check(&self, value: &T) -> Option<String> {
+        if *value > self.0 {
+            Some(format!("must be at most {}", self.0))
+        } else {
+            None
+        }
+    }
+}
+
+/// Fields managed by the server, which the callers can't set.
+pub struct ReadOnly;
+
+impl<T> Constraint<Option<T>> for ReadOnly {
+    fn check(&self, value: &Option<T>) -> Option<String> {
+        value.as_ref().map(|_| "is read-only".to_string())
+    }
+}
+
+/// Collects the violations of the constraints of a value.
+#[derive(Debug, Default)]
+pub struct Validator {
+    prefix: String,
+    violations: Vec<Violation>,
+}
+
+impl Validator {
+    /// Checks the constraints of a field.
+    pub fn field<T: ?Sized>(
+        &mut self,
+        name: &str,
+        value: &T,
+        constraints: &[&dyn Constraint<T>],
+    ) -> &mut Self {
+        let field = format!("{}{}", self.prefix, name);
+        self.violations.extend(
+            constraints
+                .iter()
+                .filter_map(|constraint| constraint.check(value))
+                .map(|message| Violation {
+                    field: field.clone(),
+                    message,
+                }),
+        );
+        self
+    }
+
+    /// Checks the constraints of a field that's validated on its own.
+    pub fn nested<V: Validate>(&mut self, name: &str, value: Option<&V>) -> &mut Self {
+        if let Some(value) = value {
+            let parent = format!("{}{}.", self.prefix, name);
+            let parent = std::mem::replace(&mut self.prefix, parent);
+            value.check(self);
+            self.prefix = parent;
+        }
+        self
+    }
+
+    pub fn finish(self) -> Result<()> {
+        if self.violations.is_empty() {
+            Ok(())
+        } else {
+            Err(DomainError::Invalid(self.violations))
+        }
+    }
+}
+
+/// Types whose fields are checked against a list of constraints.
+pub trait Validate {
+    /// Checks the constraints of the data itself.
+    fn check(&self, validator: &mut Validator);
+
+    /// Checks the data sent by a client, which may have more constraints,
+    /// like the fields only the server can set.
+    fn check_input(&self, validator: &mut Validator) {
+        self.check(validator);
+    }
+
+    fn validate(&self) -> Result<()> {
+        let mut validator = Validator::default();
+        self.check(&mut validator);
+        validator.finish()
+    }
+
+    fn validate_input(&self) -> Result<()> {
+        let mut validator = Validator::default();
+        self.check_input(&mut validator);
+        validator.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn text_constraints_work() {
+        assert!(NotBlank.check(" \t").is_some());
+        assert!(NotBlank.check(" a ").is_none());
+        assert!(MaxChars(3).check("ñññ").is_none());
+        assert!(MaxChars(3).check("abcd").is_some());
+
+        let digits = AllowedChars {
+            allowed: |c| c.is_ascii_digit(),
+            description: "digits",
+        };
+        assert!(digits.check("123").is_none());
+        assert_eq!(digits.check("12a"), Some("must only contain digits".to_string()));
+    }
+
+    #[test]
+    fn past_date_since_works() {
+        let since = PastDateSince(|| NaiveDate::from_ymd(2000, 1, 1));
+        let today = Utc::today().naive_utc();
+        assert!(since.check(&today).is_none());
+        assert!(since.check(&NaiveDate::from_ymd(1999, 12, 31)).is_some());
+        assert!(since.check(&(today + Duration::days(1))).is_some());
+    }
+
+    #[test]
+    fn read_only_works() {
+        assert!(ReadOnly.check(&None::<u32>).is_none());
+        assert_eq!(ReadOnly.check(&Some(1)), Some("is read-only".to_string()));
+    }
+
+    #[test]
+    fn validator_collects_every_violation() {
+        let mut validator = Validator::default();
+        validator
+            .field("name", "", &[&NotBlank, &MaxChars(10)])
+            .field("age", &200, &[&AtMost(150)]);
+
+        let error = validator.finish().err().unwrap();
+
+        assert_eq!(
+            error,
+            DomainError::Invalid(vec![
+                Violation {
+                    field: "name".to_string(),
+                    message: "must not be blank".to_string()
+                },
+                Violation {
+                    field: "age".to_string(),
+                    message: "must be at most 150".to_string()
+                },
+            ])
+        );
+    }
+}
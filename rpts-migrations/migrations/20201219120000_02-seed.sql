@@ -0,0 +1,2 @@
+-- Add migration script here
+INSERT INTO public.users (id, name, birth_date, created_at, updated_at, custom_data) VALUES (DEFAULT, 'Roberto', '1977-03-10', DEFAULT, null, '{"points": 10}');
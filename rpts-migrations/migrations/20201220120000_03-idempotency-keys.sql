@@ -0,0 +1,11 @@
+-- Responses of the requests with an Idempotency-Key header, kept for the retries
+CREATE TABLE idempotency_keys
+(
+	key text NOT NULL CONSTRAINT idempotency_keys_pkey PRIMARY KEY,
+	request_hash text NOT NULL,
+	response jsonb,
+	created_at timestamp with time zone default CURRENT_TIMESTAMP NOT NULL,
+	expires_at timestamp with time zone NOT NULL
+);
+
+CREATE INDEX idempotency_keys_expires_at ON idempotency_keys (expires_at);
@@ -0,0 +1,8 @@
+-- Deleted users are kept until they're purged, so they can be restored
+ALTER TABLE users ADD COLUMN deleted_at timestamp with time zone;
+
+-- the names of the deleted users can be taken again
+DROP INDEX users_name;
+CREATE UNIQUE INDEX users_name ON users (name) WHERE deleted_at IS NULL;
+
+CREATE INDEX users_deleted_at ON users (deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,23 @@
+-- Append-only history of the changes of the users, kept even after they're purged
+CREATE TABLE user_audit
+(
+	id bigserial NOT NULL CONSTRAINT user_audit_pkey PRIMARY KEY,
+	user_id uuid NOT NULL,
+	actor text,
+	operation text NOT NULL CONSTRAINT user_audit_operation CHECK (operation IN ('create', 'update', 'delete', 'restore')),
+	changes jsonb NOT NULL,
+	request_id text,
+	created_at timestamp with time zone NOT NULL default CURRENT_TIMESTAMP
+);
+
+CREATE INDEX user_audit_user_id ON user_audit (user_id, id DESC);
+
+CREATE FUNCTION user_audit_append_only() RETURNS trigger AS $$
+BEGIN
+	RAISE EXCEPTION 'user_audit is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER user_audit_append_only
+	BEFORE UPDATE OR DELETE ON user_audit
+	FOR EACH ROW EXECUTE PROCEDURE user_audit_append_only();
@@ -0,0 +1,36 @@
+-- Every user belongs to a tenant, the client company of its callers.
+-- The existing data goes to the default tenant.
+ALTER TABLE users ADD COLUMN tenant_id text NOT NULL DEFAULT 'default';
+ALTER TABLE users ALTER COLUMN tenant_id DROP DEFAULT;
+ALTER TABLE user_audit ADD COLUMN tenant_id text NOT NULL DEFAULT 'default';
+ALTER TABLE user_audit ALTER COLUMN tenant_id DROP DEFAULT;
+ALTER TABLE idempotency_keys ADD COLUMN tenant_id text NOT NULL DEFAULT 'default';
+ALTER TABLE idempotency_keys ALTER COLUMN tenant_id DROP DEFAULT;
+
+-- names and idempotency keys are only unique within a tenant
+DROP INDEX users_name;
+CREATE UNIQUE INDEX users_name ON users (tenant_id, name) WHERE deleted_at IS NULL;
+ALTER TABLE idempotency_keys DROP CONSTRAINT idempotency_keys_pkey;
+ALTER TABLE idempotency_keys ADD CONSTRAINT idempotency_keys_pkey PRIMARY KEY (tenant_id, key);
+DROP INDEX user_audit_user_id;
+CREATE INDEX user_audit_user_id ON user_audit (tenant_id, user_id, id DESC);
+
+-- A transaction only sees the rows of the tenant in its `app.tenant_id` setting,
+-- unless `app.all_tenants` is on, like the purge of the deleted users does.
+CREATE FUNCTION tenant_visible(tenant text) RETURNS boolean AS $$
+	SELECT tenant = current_setting('app.tenant_id', true)
+		OR coalesce(current_setting('app.all_tenants', true), '') = 'on'
+$$ LANGUAGE sql STABLE;
+
+-- forced, so the policies apply to the owner of the tables too, which the API connects as
+ALTER TABLE users ENABLE ROW LEVEL SECURITY;
+ALTER TABLE users FORCE ROW LEVEL SECURITY;
+CREATE POLICY users_tenant ON users USING (tenant_visible(tenant_id));
+
+ALTER TABLE user_audit ENABLE ROW LEVEL SECURITY;
+ALTER TABLE user_audit FORCE ROW LEVEL SECURITY;
+CREATE POLICY user_audit_tenant ON user_audit USING (tenant_visible(tenant_id));
+
+ALTER TABLE idempotency_keys ENABLE ROW LEVEL SECURITY;
+ALTER TABLE idempotency_keys FORCE ROW LEVEL SECURITY;
+CREATE POLICY idempotency_keys_tenant ON idempotency_keys USING (tenant_visible(tenant_id));
@@ -0,0 +1,9 @@
+-- Token buckets of the rate limiter, when the instances of the API share them
+CREATE TABLE rate_limit_buckets
+(
+	key text NOT NULL CONSTRAINT rate_limit_buckets_pkey PRIMARY KEY,
+	tokens double precision NOT NULL,
+	updated_at timestamp with time zone NOT NULL
+);
+
+CREATE INDEX rate_limit_buckets_updated_at ON rate_limit_buckets (updated_at);
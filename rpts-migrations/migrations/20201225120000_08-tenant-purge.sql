@@ -0,0 +1,27 @@
+-- Any session could see every tenant by turning `app.all_tenants` on,
+-- so the policies now only let through the tenant in `app.tenant_id`.
+CREATE OR REPLACE FUNCTION tenant_visible(tenant text) RETURNS boolean AS $$
+	SELECT tenant = current_setting('app.tenant_id', true)
+$$ LANGUAGE sql STABLE;
+
+-- The purge of the deleted users, the only query across tenants, runs as a role
+-- bypassing the row-level security, which the API can only use through this function.
+DO $$
+BEGIN
+	IF NOT EXISTS (SELECT FROM pg_roles WHERE rolname = 'rpts_purger') THEN
+		CREATE ROLE rpts_purger NOLOGIN BYPASSRLS;
+	END IF;
+END
+$$;
+GRANT SELECT, DELETE ON users TO rpts_purger;
+
+CREATE FUNCTION purge_deleted_users(deleted_before timestamp with time zone) RETURNS bigint AS $$
+	WITH purged AS (
+		DELETE FROM public.users WHERE deleted_at <= deleted_before RETURNING 1
+	)
+	SELECT count(*) FROM purged
+$$ LANGUAGE sql SECURITY DEFINER SET search_path = pg_catalog, pg_temp;
+
+ALTER FUNCTION purge_deleted_users(timestamp with time zone) OWNER TO rpts_purger;
+REVOKE ALL ON FUNCTION purge_deleted_users(timestamp with time zone) FROM PUBLIC;
+GRANT EXECUTE ON FUNCTION purge_deleted_users(timestamp with time zone) TO CURRENT_USER;
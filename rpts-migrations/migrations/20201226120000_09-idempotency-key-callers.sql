@@ -0,0 +1,6 @@
+-- Idempotency keys are only unique per caller, so the callers of a tenant can't get back
+-- the responses of each other. The keys already stored belong to no caller, until they expire.
+ALTER TABLE idempotency_keys ADD COLUMN caller text NOT NULL DEFAULT '';
+ALTER TABLE idempotency_keys ALTER COLUMN caller DROP DEFAULT;
+ALTER TABLE idempotency_keys DROP CONSTRAINT idempotency_keys_pkey;
+ALTER TABLE idempotency_keys ADD CONSTRAINT idempotency_keys_pkey PRIMARY KEY (tenant_id, caller, key);
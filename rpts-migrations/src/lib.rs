@@ -0,0 +1,445 @@
+//! Database migrations shared by the rpts servers.
+//!
+//! The migrations in `./migrations` are the single source of truth for the schema.
+//! They're embedded in the binaries at compile time, so every binary knows exactly
+//! which schema version it expects.
+use sqlx::{
+    migrate::{MigrateError, Migrator},
+    PgPool,
+};
+use std::{fmt, str::FromStr};
+
+/// The embedded migrations.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+pub type Result<T> = std::result::Result<T, MigrationError>;
+
+/// Errors while inspecting or applying the migrations.
+#[derive(Debug, thiserror::Error)]
+pub enum MigrationError {
+    #[error("Unknown migrate command {0}. Use one of: up, status, dry-run")]
+    UnknownCommand(String),
+    #[error("Database schema is behind: found version {found:?} but expected {expected}")]
+    Behind { found: Option<i64>, expected: i64 },
+    #[error("Database schema is ahead: found version {found} but expected {expected}")]
+    Ahead { found: i64, expected: i64 },
+    #[error("Migration {0} failed or was partially applied")]
+    Dirty(i64),
+    #[error("Migration {0} has been modified after being applied")]
+    Modified(i64),
+    #[error("Migration {0} was applied to the database but is unknown to this binary")]
+    Unknown(i64),
+    #[error("The database role isn't allowed to apply the migrations ({0}). Run them as a superuser, or create the rpts_purger role beforehand and grant it to this role")]
+    InsufficientPrivilege(String),
+    #[error(transparent)]
+    DbError(#[from] sqlx::Error),
+    #[error(transparent)]
+    MigrateError(#[from] MigrateError),
+}
+
+/// Subcommands of `<binary> migrate`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Command {
+    /// Applies the pending migrations.
+    Up,
+    /// Prints the state of every migration.
+    Status,
+    /// Prints the SQL of the pending migrations without applying them.
+    DryRun,
+}
+
+impl FromStr for Command {
+    type Err = MigrationError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "up" => Ok(Command::Up),
+            "status" => Ok(Command::Status),
+            "dry-run" => Ok(Command::DryRun),
+            _ => Err(MigrationError::UnknownCommand(s.to_string())),
+        }
+    }
+}
+
+impl Command {
+    /// Reads the command from the process arguments: `<binary> migrate [up|status|dry-run]`.
+    /// Returns `None` if the binary was not asked to migrate.
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Option<Self>> {
+        let mut args = args.into_iter().skip(1);
+        match args.next().as_deref() {
+            Some("migrate") => args.next().as_deref().unwrap_or("up").parse().map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// A migration as recorded in the database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppliedMigration {
+    pub version: i64,
+    pub success: bool,
+    pub checksum: Vec<u8>,
+}
+
+/// State of a migration when comparing the binary against the database.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum State {
+    Applied,
+    Pending,
+    Dirty,
+    Modified,
+    /// Applied to the database but unknown to this binary.
+    Unknown,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            State::Applied => "applied",
+            State::Pending => "pending",
+            State::Dirty => "dirty",
+            State::Modified => "modified",
+            State::Unknown => "unknown",
+        };
+        f.write_str(state)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub state: State,
+}
+
+/// The schema version this binary expects.
+pub fn expected_version() -> i64 {
+    MIGRATOR.iter().map(|m| m.version).max().unwrap_or_default()
+}
+
+/// Gets the migrations recorded in the database.
+pub async fn applied(pool: &PgPool) -> Result<Vec<AppliedMigration>> {
+    let (table,): (Option<String>,) =
+        sqlx::query_as("SELECT to_regclass('_sqlx_migrations')::text")
+            .fetch_one(pool)
+            .await?;
+    if table.is_none() {
+        return Ok(vec![]);
+    }
+    let rows: Vec<(i64, bool, Vec<u8>)> =
+        sqlx::query_as("SELECT version, success, checksum FROM _sqlx_migrations ORDER BY version")
+            .fetch_all(pool)
+            .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(version, success, checksum)| AppliedMigration {
+            version,
+            success,
+            checksum,
+        })
+        .collect())
+}
+
+/// Compares the embedded migrations against the applied ones.
+pub fn compare(migrator: &Migrator, applied: &[AppliedMigration]) -> Vec<MigrationStatus> {
+    let mut statuses: Vec<MigrationStatus> = migrator
+        .iter()
+        .map(|m| {
+            let state = match applied.iter().find(|a| a.version == m.version) {
+                None => State::Pending,
+                Some(a) if !a.success => State::Dirty,
+                Some(a) if a.checksum[..] != m.checksum[..] => State::Modified,
+                Some(_) => State::Applied,
+            };
+            MigrationStatus {
+                version: m.version,
+                description: m.description.to_string(),
+                state,
+            }
+        })
+        .collect();
+    statuses.extend(
+        applied
+            .iter()
+            .filter(|a| migrator.iter().all(|m| m.version != a.version))
+            .map(|a| MigrationStatus {
+                version: a.version,
+                description: String::default(),
+                state: State::Unknown,
+            }),
+    );
+    statuses.sort_by_key(|s| s.version);
+    statuses
+}
+
+/// Fails unless every migration is applied and the database knows no others,
+/// whatever their version.
+/// Pending migrations are tolerated if `allow_pending` is set.
+pub fn check(statuses: &[MigrationStatus], allow_pending: bool) -> Result<()> {
+    let expected = expected_version();
+    let found = statuses
+        .iter()
+        .filter(|s| s.state != State::Pending)
+        .map(|s| s.version)
+        .max();
+    for status in statuses {
+        match status.state {
+            State::Dirty => return Err(MigrationError::Dirty(status.version)),
+            State::Modified => return Err(MigrationError::Modified(status.version)),
+            _ => {}
+        }
+    }
+    if let Some(found) = found.filter(|found| *found > expected) {
+        return Err(MigrationError::Ahead { found, expected });
+    }
+    // any other migration we don't know, even an older one, means a different schema
+    if let Some(unknown) = statuses.iter().find(|s| s.state == State::Unknown) {
+        return Err(MigrationError::Unknown(unknown.version));
+    }
+    let is_behind = statuses.iter().any(|s| s.state == State::Pending);
+    if is_behind && !allow_pending {
+        return Err(MigrationError::Behind { found, expected });
+    }
+    Ok(())
+}
+
+/// Postgres error code (SQLSTATE) of the statements the role isn't allowed to run.
+const INSUFFICIENT_PRIVILEGE: &str = "42501";
+
+/// Tells the privileges missing to apply the migrations from the other errors.
+fn applying(error: MigrateError) -> MigrationError {
+    match &error {
+        MigrateError::Execute(sqlx::Error::Database(db_err))
+            if db_err.code().as_deref() == Some(INSUFFICIENT_PRIVILEGE) =>
+        {
+            MigrationError::InsufficientPrivilege(db_err.message().to_string())
+        }
+        _ => error.into(),
+    }
+}
+
+/// Gets the state of every migration.
+pub async fn status(pool: &PgPool) -> Result<Vec<MigrationStatus>> {
+    Ok(compare(&MIGRATOR, &applied(pool).await?))
+}
+
+/// Refuses to go on unless the database is exactly at the version this binary expects.
+pub async fn ensure_up_to_date(pool: &PgPool) -> Result<()> {
+    check(&status(pool).await?, false)
+}
+
+/// Executes a migrate command, printing its results to stdout.
+pub async fn run(pool: &PgPool, command: Command) -> Result<()> {
+    let statuses = status(pool).await?;
+    match command {
+        Command::Up => {
+            check(&statuses, true)?;
+            MIGRATOR.run(pool).await.map_err(applying)?;
+            println!("Database schema is at version {}", expected_version());
+        }
+        Command::Status => {
+            for status in statuses {
+                println!(
+                    "{:<10} {} {}",
+                    status.state, status.version, status.description
+                );
+            }
+        }
+        Command::DryRun => {
+            check(&statuses, true)?;
+            let pending = MIGRATOR.iter().filter(|m| {
+                statuses
+                    .iter()
+                    .any(|s| s.version == m.version && s.state == State::Pending)
+            });
+            let mut nothing_to_apply = true;
+            for migration in pending {
+                nothing_to_apply = false;
+                println!("-- {} {}", migration.version, migration.description);
+                println!("{}", migration.sql);
+            }
+            if nothing_to_apply {
+                println!("Nothing to apply");
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn applied_all() -> Vec<AppliedMigration> {
+        MIGRATOR
+            .iter()
+            .map(|m| AppliedMigration {
+                version: m.version,
+                success: true,
+                checksum: m.checksum.to_vec(),
+            })
+            .collect()
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn from_args_works() {
+        let command = Command::from_args(args(&["rpts02", "migrate", "status"])).unwrap();
+        assert_eq!(command, Some(Command::Status));
+        let command = Command::from_args(args(&["rpts02", "migrate", "dry-run"])).unwrap();
+        assert_eq!(command, Some(Command::DryRun));
+    }
+
+    #[test]
+    fn from_args_defaults_to_up() {
+        let command = Command::from_args(args(&["rpts02", "migrate"])).unwrap();
+        assert_eq!(command, Some(Command::Up));
+    }
+
+    #[test]
+    fn from_args_returns_none_if_not_migrating() {
+        assert_eq!(Command::from_args(args(&["rpts02"])).unwrap(), None);
+    }
+
+    #[test]
+    fn from_args_rejects_unknown_commands() {
+        let error = Command::from_args(args(&["rpts02", "migrate", "down"]))
+            .err()
+            .unwrap();
+        assert!(matches!(error, MigrationError::UnknownCommand(_)));
+    }
+
+    #[test]
+    fn check_works_if_up_to_date() {
+        let statuses = compare(&MIGRATOR, &applied_all());
+        assert!(statuses.iter().all(|s| s.state == State::Applied));
+        assert!(check(&statuses, false).is_ok());
+    }
+
+    #[test]
+    fn check_returns_behind_if_migrations_are_pending() {
+        let mut applied = applied_all();
+        applied.pop();
+        let statuses = compare(&MIGRATOR, &applied);
+
+        let error = check(&statuses, false).err().unwrap();
+
+        assert!(matches!(error, MigrationError::Behind { .. }));
+        assert!(check(&statuses, true).is_ok());
+    }
+
+    #[test]
+    fn check_returns_behind_on_empty_databases() {
+        let statuses = compare(&MIGRATOR, &[]);
+        let error = check(&statuses, false).err().unwrap();
+        assert!(matches!(error, MigrationError::Behind { found: None, .. }));
+    }
+
+    #[test]
+    fn check_returns_ahead_if_database_has_unknown_migrations() {
+        let mut applied = applied_all();
+        applied.push(AppliedMigration {
+            version: expected_version() + 1,
+            success: true,
+            checksum: vec![],
+        });
+        let statuses = compare(&MIGRATOR, &applied);
+
+        let error = check(&statuses, true).err().unwrap();
+
+        assert!(matches!(error, MigrationError::Ahead { .. }));
+    }
+
+    #[test]
+    fn check_returns_unknown_if_database_has_older_unknown_migrations() {
+        let mut applied = applied_all();
+        // the first migration of rpts01, before they were shared
+        applied.push(AppliedMigration {
+            version: 20201214094417,
+            success: true,
+            checksum: vec![],
+        });
+        let statuses = compare(&MIGRATOR, &applied);
+
+        let error = check(&statuses, true).err().unwrap();
+
+        assert!(matches!(error, MigrationError::Unknown(20201214094417)));
+    }
+
+    #[test]
+    fn check_returns_dirty_if_a_migration_failed() {
+        let mut applied = applied_all();
+        applied[0].success = false;
+        let statuses = compare(&MIGRATOR, &applied);
+
+        let error = check(&statuses, true).err().unwrap();
+
+        assert!(matches!(error, MigrationError::Dirty(v) if v == applied[0].version));
+    }
+
+    #[test]
+    fn check_returns_modified_if_checksum_differs() {
+        let mut applied = applied_all();
+        applied[0].checksum = vec![0];
+        let statuses = compare(&MIGRATOR, &applied);
+
+        let error = check(&statuses, true).err().unwrap();
+
+        assert!(matches!(error, MigrationError::Modified(v) if v == applied[0].version));
+    }
+
+    /// A database error carrying a Postgres error code.
+    #[derive(Debug)]
+    struct PgError(&'static str);
+
+    impl fmt::Display for PgError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "Postgres error {}", self.0)
+        }
+    }
+
+    impl std::error::Error for PgError {}
+
+    impl sqlx::error::DatabaseError for PgError {
+        fn message(&self) -> &str {
+            "must be superuser to create bypassrls users"
+        }
+        fn code(&self) -> Option<std::borrow::Cow<'_, str>> {
+            Some(self.0.into())
+        }
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+    }
+
+    fn execute_error(code: &'static str) -> MigrateError {
+        MigrateError::Execute(sqlx::Error::Database(Box::new(PgError(code))))
+    }
+
+    #[test]
+    fn applying_reports_the_missing_privileges() {
+        let error = applying(execute_error(INSUFFICIENT_PRIVILEGE));
+
+        assert!(matches!(
+            &error,
+            MigrationError::InsufficientPrivilege(message) if message.contains("bypassrls")
+        ));
+        assert!(error.to_string().contains("rpts_purger"));
+    }
+
+    #[test]
+    fn applying_keeps_the_other_errors() {
+        let error = applying(execute_error("42P07"));
+
+        assert!(matches!(error, MigrationError::MigrateError(_)));
+    }
+}
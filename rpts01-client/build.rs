@@ -0,0 +1,12 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // we only need the client. The User message comes from the rpts-domain crate.
+    tonic_build::configure()
+        .build_server(false)
+        .extern_path(".rpts.domain", "::rpts_domain::proto")
+        .compile(
+            &["../01-grpc-server/proto/rpts01.proto"],
+            &["../01-grpc-server/proto", "../rpts-domain/proto"],
+        )?;
+    println!("## Proto files have been compiled");
+    Ok(())
+}
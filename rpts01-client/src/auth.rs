@@ -0,0 +1,25 @@
+use crate::Result;
+use async_trait::async_trait;
+
+/// Provides the bearer token sent with every call.
+#[async_trait]
+pub trait TokenProvider: Send + Sync {
+    async fn token(&self) -> Result<String>;
+}
+
+/// A token that never changes.
+#[derive(Debug, Clone)]
+pub struct StaticToken(String);
+
+impl StaticToken {
+    pub fn new(token: impl Into<String>) -> Self {
+        Self(token.into())
+    }
+}
+
+#[async_trait]
+impl TokenProvider for StaticToken {
+    async fn token(&self) -> Result<String> {
+        Ok(self.0.clone())
+    }
+}
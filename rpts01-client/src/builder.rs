@@ -0,0 +1,111 @@
+use crate::{ClientError, Result, RetryPolicy, Rpts01Client, TokenProvider};
+use std::{sync::Arc, time::Duration};
+use tonic::transport::{ClientTlsConfig, Endpoint};
+
+/// Builds a [Rpts01Client].
+#[derive(Default)]
+pub struct ClientBuilder {
+    endpoint: Option<String>,
+    tls: Option<ClientTlsConfig>,
+    token_provider: Option<Arc<dyn TokenProvider>>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    retry_policy: RetryPolicy,
+}
+
+impl ClientBuilder {
+    /// Address of the service, e.g. `http://localhost:50051`.
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Enables TLS.
+    pub fn tls(mut self, tls: ClientTlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Provides the bearer token sent with every call.
+    pub fn token_provider(mut self, provider: impl TokenProvider + 'static) -> Self {
+        self.token_provider = Some(Arc::new(provider));
+        self
+    }
+
+    /// Timeout for every call.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout for establishing the connection.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// How calls failing with `UNAVAILABLE` are retried.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    fn build_endpoint(&self) -> Result<Endpoint> {
+        let endpoint = self.endpoint.clone().ok_or(ClientError::MissingEndpoint)?;
+        let mut endpoint = Endpoint::from_shared(endpoint)
+            .map_err(|e| ClientError::InvalidEndpoint(e.to_string()))?;
+        if let Some(timeout) = self.timeout {
+            endpoint = endpoint.timeout(timeout);
+        }
+        if let Some(tls) = self.tls.clone() {
+            endpoint = endpoint.tls_config(tls)?;
+        }
+        Ok(endpoint)
+    }
+
+    /// Connects to the service.
+    pub async fn connect(self) -> Result<Rpts01Client> {
+        let endpoint = self.build_endpoint()?;
+        let channel = match self.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, endpoint.connect())
+                .await
+                .map_err(|_| ClientError::ConnectTimeout)??,
+            None => endpoint.connect().await?,
+        };
+        Ok(Rpts01Client::new(
+            channel,
+            self.token_provider,
+            self.retry_policy,
+        ))
+    }
+
+    /// Builds the client without connecting. The connection is made on the first call.
+    pub fn connect_lazy(self) -> Result<Rpts01Client> {
+        let channel = self.build_endpoint()?.connect_lazy()?;
+        Ok(Rpts01Client::new(
+            channel,
+            self.token_provider,
+            self.retry_policy,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_requires_an_endpoint() {
+        let error = ClientBuilder::default().connect_lazy().err().unwrap();
+        assert!(matches!(error, ClientError::MissingEndpoint));
+    }
+
+    #[tokio::test]
+    async fn connect_lazy_works() {
+        let client = ClientBuilder::default()
+            .endpoint("http://localhost:50051")
+            .timeout(Duration::from_secs(1))
+            .connect_lazy();
+        assert!(client.is_ok());
+    }
+}
@@ -0,0 +1,105 @@
+use crate::{
+    proto::{rpts_client::RptsClient, HiRequest, UserRequest},
+    ClientBuilder, ClientError, Result, RetryPolicy, Rpts01, TokenProvider, User,
+};
+use async_trait::async_trait;
+use std::{convert::TryFrom, sync::Arc};
+use tonic::{
+    metadata::{AsciiMetadataValue, MetadataValue},
+    transport::Channel,
+    Request,
+};
+
+/// Client of the rpts01 service.
+/// It's cheap to clone, as all the clones share the same connection.
+#[derive(Clone)]
+pub struct Rpts01Client {
+    inner: RptsClient<Channel>,
+    token_provider: Option<Arc<dyn TokenProvider>>,
+    retry_policy: RetryPolicy,
+}
+
+impl std::fmt::Debug for Rpts01Client {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Rpts01Client")
+            .field("retry_policy", &self.retry_policy)
+            .finish()
+    }
+}
+
+impl Rpts01Client {
+    /// Starts building a new client.
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+
+    pub(crate) fn new(
+        channel: Channel,
+        token_provider: Option<Arc<dyn TokenProvider>>,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        Self {
+            inner: RptsClient::new(channel),
+            token_provider,
+            retry_policy,
+        }
+    }
+
+    /// Gets the authorization header value, if there's a token provider.
+    async fn authorization(&self) -> Result<Option<AsciiMetadataValue>> {
+        match &self.token_provider {
+            Some(provider) => {
+                let token = provider.token().await?;
+                MetadataValue::from_str(&format!("Bearer {}", token))
+                    .map(Some)
+                    .map_err(|e| ClientError::InvalidToken(e.to_string()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn request<T>(message: T, authorization: &Option<AsciiMetadataValue>) -> Request<T> {
+        let mut request = Request::new(message);
+        if let Some(authorization) = authorization {
+            request
+                .metadata_mut()
+                .insert("authorization", authorization.clone());
+        }
+        request
+    }
+}
+
+#[async_trait]
+impl Rpts01 for Rpts01Client {
+    async fn say_hi(&self, hello: &str) -> Result<String> {
+        let authorization = self.authorization().await?;
+        let message = HiRequest {
+            hello: hello.to_string(),
+        };
+        let response = self
+            .retry_policy
+            .run(|| {
+                let mut client = self.inner.clone();
+                let request = Self::request(message.clone(), &authorization);
+                async move { client.say_hi(request).await }
+            })
+            .await?;
+        Ok(response.into_inner().message)
+    }
+
+    async fn get_user(&self, name: &str) -> Result<User> {
+        let authorization = self.authorization().await?;
+        let message = UserRequest {
+            name: name.to_string(),
+        };
+        let response = self
+            .retry_policy
+            .run(|| {
+                let mut client = self.inner.clone();
+                let request = Self::request(message.clone(), &authorization);
+                async move { client.get_user(request).await }
+            })
+            .await?;
+        User::try_from(response.into_inner())
+    }
+}
@@ -0,0 +1,36 @@
+use crate::proto::PermissionDeniedDetails;
+use prost::Message;
+use tonic::{Code, Status};
+
+/// Errors returned by the client.
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("No endpoint was configured")]
+    MissingEndpoint,
+    #[error("Invalid endpoint: {0}")]
+    InvalidEndpoint(String),
+    #[error("Timed out while connecting to the service")]
+    ConnectTimeout,
+    #[error(transparent)]
+    Transport(#[from] tonic::transport::Error),
+    #[error("Invalid token: {0}")]
+    InvalidToken(String),
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
+    #[error(transparent)]
+    Status(#[from] Status),
+}
+
+impl ClientError {
+    /// Scopes the caller lacks, if the call was denied because of them.
+    pub fn missing_scopes(&self) -> Option<Vec<String>> {
+        match self {
+            ClientError::Status(status) if status.code() == Code::PermissionDenied => {
+                PermissionDeniedDetails::decode(status.details())
+                    .ok()
+                    .map(|details| details.missing_scopes)
+            }
+            _ => None,
+        }
+    }
+}
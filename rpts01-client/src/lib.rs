@@ -0,0 +1,53 @@
+//! Typed client for the rpts01 gRPC service.
+//!
+//! ```no_run
+//! use rpts01_client::{Rpts01, Rpts01Client, StaticToken};
+//! use std::time::Duration;
+//!
+//! # async fn run() -> rpts01_client::Result<()> {
+//! let client = Rpts01Client::builder()
+//!     .endpoint("http://localhost:50051")
+//!     .token_provider(StaticToken::new("my_token"))
+//!     .timeout(Duration::from_secs(5))
+//!     .connect()
+//!     .await?;
+//! let user = client.get_user("Roberto").await?;
+//! println!("{} was born on {}", user.name, user.birth_date);
+//! # Ok(())
+//! # }
+//! ```
+mod auth;
+mod builder;
+mod client;
+mod error;
+mod mock;
+mod model;
+mod retry;
+
+#[allow(clippy::all, clippy::pedantic, clippy::nursery)]
+mod proto {
+    tonic::include_proto!("rpts01");
+}
+
+use async_trait::async_trait;
+
+pub use auth::{StaticToken, TokenProvider};
+pub use builder::ClientBuilder;
+pub use client::Rpts01Client;
+pub use error::ClientError;
+pub use mock::MockRpts01;
+pub use model::User;
+pub use retry::RetryPolicy;
+pub use tonic::transport::ClientTlsConfig;
+
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+/// Operations offered by the rpts01 service.
+/// Depend on this trait so you can swap the real client by [MockRpts01] in your tests.
+#[async_trait]
+pub trait Rpts01: Send + Sync {
+    /// Says hi to the service.
+    async fn say_hi(&self, hello: &str) -> Result<String>;
+    /// Gets a user by name.
+    async fn get_user(&self, name: &str) -> Result<User>;
+}
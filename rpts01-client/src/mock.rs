@@ -0,0 +1,99 @@
+use crate::{ClientError, Result, Rpts01, User};
+use async_trait::async_trait;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+use tonic::Status;
+
+/// In-memory implementation of [Rpts01] for your tests.
+/// Clones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct MockRpts01 {
+    users: Arc<Mutex<HashMap<String, User>>>,
+    failures: Arc<Mutex<VecDeque<ClientError>>>,
+}
+
+impl MockRpts01 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a user that will be found by its name.
+    pub fn with_user(self, user: User) -> Self {
+        self.users.lock().unwrap().insert(user.name.clone(), user);
+        self
+    }
+
+    /// Makes the next call fail with the given error.
+    pub fn fail_next(&self, error: ClientError) {
+        self.failures.lock().unwrap().push_back(error);
+    }
+
+    fn next_failure(&self) -> Result<()> {
+        match self.failures.lock().unwrap().pop_front() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl Rpts01 for MockRpts01 {
+    async fn say_hi(&self, hello: &str) -> Result<String> {
+        self.next_failure()?;
+        Ok(format!("Hello {}! How are you?", hello))
+    }
+
+    async fn get_user(&self, name: &str) -> Result<User> {
+        self.next_failure()?;
+        self.users
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Status::not_found(format!("No user with name {} exists", name)).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use tonic::Code;
+    use uuid::Uuid;
+
+    fn user() -> User {
+        User {
+            id: Uuid::new_v4(),
+            name: "Roberto".to_string(),
+            birth_date: NaiveDate::from_ymd(1977, 3, 10),
+            created_at: None,
+            updated_at: None,
+            custom_data: serde_json::json!({}),
+        }
+    }
+
+    #[tokio::test]
+    async fn get_user_works() {
+        let user = user();
+        let mock = MockRpts01::new().with_user(user.clone());
+        assert_eq!(mock.get_user("Roberto").await.unwrap(), user);
+    }
+
+    #[tokio::test]
+    async fn get_user_returns_not_found() {
+        let mock = MockRpts01::new();
+        let error = mock.get_user("Roberto").await.err().unwrap();
+        assert!(matches!(error, ClientError::Status(s) if s.code() == Code::NotFound));
+    }
+
+    #[tokio::test]
+    async fn fail_next_fails_only_once() {
+        let mock = MockRpts01::new();
+        mock.fail_next(Status::unavailable("down").into());
+
+        assert!(mock.say_hi("Rob").await.is_err());
+        assert_eq!(mock.say_hi("Rob").await.unwrap(), "Hello Rob! How are you?");
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{ClientError, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use prost_types::Timestamp;
+use serde_json::{Map, Value};
+use std::convert::TryFrom;
+use uuid::Uuid;
+
+/// A user as returned by the rpts01 service.
+#[derive(Debug, Clone, PartialEq)]
+pub struct User {
+    pub id: Uuid,
+    pub name: String,
+    pub birth_date: NaiveDate,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Free-form data. Always a JSON object.
+    pub custom_data: Value,
+}
+
+impl TryFrom<rpts_domain::proto::User> for User {
+    type Error = ClientError;
+
+    fn try_from(user: rpts_domain::proto::User) -> Result<Self> {
+        let id = Uuid::parse_str(&user.id)
+            .map_err(|e| ClientError::InvalidResponse(format!("invalid id: {}", e)))?;
+        let birth_date = user
+            .birth_date
+            .ok_or_else(|| ClientError::InvalidResponse("missing birth_date".to_string()))
+            .and_then(to_datetime)?
+            .date()
+            .naive_utc();
+        let custom_data = user
+            .custom_data
+            .into_iter()
+            .map(|(key, value)| (key, Value::from(value)))
+            .collect::<Map<String, Value>>();
+
+        Ok(Self {
+            id,
+            name: user.name,
+            birth_date,
+            created_at: user.created_at.map(to_datetime).transpose()?,
+            updated_at: user.updated_at.map(to_datetime).transpose()?,
+            custom_data: Value::Object(custom_data),
+        })
+    }
+}
+
+fn to_datetime(ts: Timestamp) -> Result<DateTime<Utc>> {
+    let nanos = u32::try_from(ts.nanos)
+        .map_err(|e| ClientError::InvalidResponse(format!("invalid timestamp: {}", e)))?;
+    NaiveDateTime::from_timestamp_opt(ts.seconds, nanos)
+        .map(|dt| DateTime::from_utc(dt, Utc))
+        .ok_or_else(|| ClientError::InvalidResponse("timestamp out of range".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    fn proto_user() -> rpts_domain::proto::User {
+        let mut custom_data = std::collections::HashMap::new();
+        custom_data.insert("random".to_string(), 10);
+        rpts_domain::proto::User {
+            id: Uuid::new_v4().to_string(),
+            name: "Roberto".to_string(),
+            birth_date: Some(Timestamp {
+                seconds: 226_800_000,
+                nanos: 0,
+            }),
+            created_at: Some(Timestamp {
+                seconds: 1_607_813_137,
+                nanos: 0,
+            }),
+            updated_at: None,
+            custom_data,
+            deleted_at: None,
+        }
+    }
+
+    #[test]
+    fn from_proto_works() {
+        let proto = proto_user();
+        let user = User::try_from(proto.clone()).unwrap();
+
+        assert_eq!(user.id.to_string(), proto.id);
+        assert_eq!(user.name, "Roberto");
+        assert_eq!(user.birth_date, NaiveDate::from_ymd(1977, 3, 10));
+        assert_eq!(
+            user.created_at,
+            Some(Utc.ymd(2020, 12, 12).and_hms(22, 45, 37))
+        );
+        assert_eq!(user.updated_at, None);
+        assert_eq!(user.custom_data, json!({ "random": 10 }));
+    }
+
+    #[test]
+    fn from_proto_rejects_invalid_ids() {
+        let mut proto = proto_user();
+        proto.id = "not-a-uuid".to_string();
+        let error = User::try_from(proto).err().unwrap();
+        assert!(matches!(error, ClientError::InvalidResponse(_)));
+    }
+
+    #[test]
+    fn from_proto_requires_birth_date() {
+        let mut proto = proto_user();
+        proto.birth_date = None;
+        let error = User::try_from(proto).err().unwrap();
+        assert!(matches!(error, ClientError::InvalidResponse(_)));
+    }
+}
@@ -0,0 +1,128 @@
+use std::{future::Future, time::Duration};
+use tonic::{Code, Status};
+
+/// How calls failing with `UNAVAILABLE` are retried.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt. Zero disables retries.
+    pub max_retries: u32,
+    /// Delay before the first retry. It doubles on every retry.
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between retries.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// Delay before the given retry, starting at zero.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.checked_pow(retry).unwrap_or(u32::MAX);
+        self.initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+
+    /// Runs the call, retrying it while it fails with `UNAVAILABLE`.
+    pub(crate) async fn run<T, F, Fut>(&self, mut call: F) -> Result<T, Status>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Status>>,
+    {
+        let mut retry = 0;
+        loop {
+            match call().await {
+                Err(status) if status.code() == Code::Unavailable && retry < self.max_retries => {
+                    tokio::time::delay_for(self.backoff(retry)).await;
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn fast_policy(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(2),
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_until_the_max() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(1), Duration::from_millis(200));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+        assert_eq!(policy.backoff(10), Duration::from_secs(2));
+        assert_eq!(policy.backoff(100), Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn run_retries_unavailable() {
+        let attempts = AtomicU32::new(0);
+        let result = fast_policy(3)
+            .run(|| async {
+                if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                    Err(Status::unavailable("down"))
+                } else {
+                    Ok("up")
+                }
+            })
+            .await;
+
+        assert_eq!(result.unwrap(), "up");
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn run_gives_up_after_max_retries() {
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), Status> = fast_policy(2)
+            .run(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(Status::unavailable("down"))
+            })
+            .await;
+
+        assert_eq!(result.err().unwrap().code(), Code::Unavailable);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn run_does_not_retry_other_errors() {
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), Status> = fast_policy(3)
+            .run(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(Status::not_found("nope"))
+            })
+            .await;
+
+        assert_eq!(result.err().unwrap().code(), Code::NotFound);
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+}